@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS todos_user_id_idx;
+DROP TABLE IF EXISTS todos;
+DROP TABLE IF EXISTS users;
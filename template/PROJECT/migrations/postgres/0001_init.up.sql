@@ -0,0 +1,21 @@
+-- users
+CREATE TABLE IF NOT EXISTS users (
+  id           UUID PRIMARY KEY NOT NULL,
+  email        TEXT UNIQUE NOT NULL,
+  display_name TEXT NOT NULL,
+  created_at   TIMESTAMPTZ NOT NULL
+);
+
+-- todos
+CREATE TABLE IF NOT EXISTS todos (
+  id          UUID PRIMARY KEY NOT NULL,
+  user_id     UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  title       TEXT NOT NULL,
+  notes       TEXT,
+  completed   BOOLEAN NOT NULL DEFAULT FALSE,
+  due_at      TIMESTAMPTZ,
+  created_at  TIMESTAMPTZ NOT NULL,
+  updated_at  TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS todos_user_id_idx ON todos(user_id);
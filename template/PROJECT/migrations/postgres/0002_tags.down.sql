@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS todo_tags_tag_id_idx;
+DROP TABLE IF EXISTS todo_tags;
+DROP TABLE IF EXISTS tags;
@@ -0,0 +1,19 @@
+-- tags (names are unique per user)
+CREATE TABLE IF NOT EXISTS tags (
+  id          UUID PRIMARY KEY NOT NULL,
+  user_id     UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name        TEXT NOT NULL,
+  created_at  TIMESTAMPTZ NOT NULL,
+
+  UNIQUE(user_id, name)
+);
+
+-- todo <-> tag
+CREATE TABLE IF NOT EXISTS todo_tags (
+  todo_id     UUID NOT NULL REFERENCES todos(id) ON DELETE CASCADE,
+  tag_id      UUID NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+
+  PRIMARY KEY(todo_id, tag_id)
+);
+
+CREATE INDEX IF NOT EXISTS todo_tags_tag_id_idx ON todo_tags(tag_id);
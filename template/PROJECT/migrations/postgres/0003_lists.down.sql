@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS todos_list_id_idx;
+ALTER TABLE todos DROP COLUMN list_id;
+DROP INDEX IF EXISTS lists_inbox_idx;
+DROP INDEX IF EXISTS lists_user_id_idx;
+DROP TABLE IF EXISTS lists;
@@ -0,0 +1,34 @@
+-- lists (every user has exactly one inbox list)
+CREATE TABLE IF NOT EXISTS lists (
+  id          UUID PRIMARY KEY NOT NULL,
+  user_id     UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name        TEXT NOT NULL,
+  color       TEXT,
+  archived    BOOLEAN NOT NULL DEFAULT FALSE,
+  position    BIGINT NOT NULL DEFAULT 0,
+  inbox       BOOLEAN NOT NULL DEFAULT FALSE,
+  created_at  TIMESTAMPTZ NOT NULL,
+  updated_at  TIMESTAMPTZ NOT NULL,
+
+  -- lets todos reference (list, owner) pairs
+  UNIQUE (id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS lists_user_id_idx ON lists(user_id);
+CREATE UNIQUE INDEX IF NOT EXISTS lists_inbox_idx ON lists(user_id) WHERE inbox;
+
+-- Give every existing user an inbox:
+INSERT INTO lists (id, user_id, name, inbox, created_at, updated_at)
+SELECT gen_random_uuid(), id, 'Inbox', TRUE, created_at, created_at
+FROM users;
+
+ALTER TABLE todos ADD COLUMN list_id UUID;
+UPDATE todos SET list_id = (
+  SELECT lists.id FROM lists WHERE lists.user_id = todos.user_id AND lists.inbox
+);
+ALTER TABLE todos ALTER COLUMN list_id SET NOT NULL;
+-- A todo's list must belong to the todo's user:
+ALTER TABLE todos ADD CONSTRAINT todos_list_id_fkey
+  FOREIGN KEY (list_id, user_id) REFERENCES lists(id, user_id) ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS todos_list_id_idx ON todos(list_id);
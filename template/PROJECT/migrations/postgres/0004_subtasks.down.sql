@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS todos_parent_id_idx;
+ALTER TABLE todos DROP COLUMN parent_id;
+ALTER TABLE todos DROP CONSTRAINT IF EXISTS todos_id_user_id_key;
@@ -0,0 +1,9 @@
+-- Subtasks: a todo may belong to a parent todo of the same user.
+-- Deleting a todo deletes its subtasks.
+ALTER TABLE todos ADD CONSTRAINT todos_id_user_id_key UNIQUE (id, user_id);
+ALTER TABLE todos ADD COLUMN parent_id UUID;
+ALTER TABLE todos ADD CONSTRAINT todos_parent_id_fkey
+  FOREIGN KEY (parent_id, user_id) REFERENCES todos(id, user_id) ON DELETE CASCADE;
+ALTER TABLE todos ADD CONSTRAINT todos_parent_id_check CHECK (parent_id <> id);
+
+CREATE INDEX IF NOT EXISTS todos_parent_id_idx ON todos(parent_id);
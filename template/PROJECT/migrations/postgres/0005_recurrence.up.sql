@@ -0,0 +1,4 @@
+-- Recurring todos: an RFC 5545 RRULE, and whether the next occurrence
+-- is scheduled from the completion date instead of the due date.
+ALTER TABLE todos ADD COLUMN rrule TEXT;
+ALTER TABLE todos ADD COLUMN repeat_from_completion BOOLEAN NOT NULL DEFAULT FALSE;
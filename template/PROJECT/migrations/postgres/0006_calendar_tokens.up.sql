@@ -0,0 +1,7 @@
+-- The secret token for each user's iCalendar feed, stored as a SHA-256
+-- hex digest.
+CREATE TABLE IF NOT EXISTS calendar_tokens (
+  user_id     UUID PRIMARY KEY NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  token_hash  TEXT UNIQUE NOT NULL,
+  created_at  TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,54 @@
+-- App passwords: per-device secrets for HTTP Basic auth (e.g. CalDAV
+-- clients), stored as SHA-256 hex digests.
+CREATE TABLE IF NOT EXISTS app_passwords (
+  id          UUID PRIMARY KEY NOT NULL,
+  user_id     UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name        TEXT NOT NULL,
+  token_hash  TEXT UNIQUE NOT NULL,
+  created_at  TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS app_passwords_user_id_idx ON app_passwords(user_id);
+
+-- The UIDs CalDAV clients gave todos (other todos use their id).
+CREATE TABLE IF NOT EXISTS caldav_objects (
+  todo_id     UUID PRIMARY KEY NOT NULL REFERENCES todos(id) ON DELETE CASCADE,
+  uid         TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS caldav_objects_uid_idx ON caldav_objects(uid);
+
+-- Todos that left a list (deleted or moved), for CalDAV sync. Kept for
+-- 30 days.
+CREATE TABLE IF NOT EXISTS caldav_tombstones (
+  list_id     UUID NOT NULL,
+  uid         TEXT NOT NULL,
+  removed_at  TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS caldav_tombstones_list_id_idx
+  ON caldav_tombstones(list_id, removed_at);
+
+CREATE OR REPLACE FUNCTION caldav_tombstone() RETURNS trigger AS $$
+BEGIN
+  INSERT INTO caldav_tombstones (list_id, uid, removed_at)
+  VALUES (
+    OLD.list_id,
+    COALESCE((SELECT uid FROM caldav_objects WHERE todo_id = OLD.id), OLD.id::text),
+    now()
+  );
+  IF TG_OP = 'DELETE' THEN
+    DELETE FROM caldav_tombstones WHERE removed_at < now() - interval '30 days';
+  END IF;
+  RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_caldav_delete
+BEFORE DELETE ON todos
+FOR EACH ROW EXECUTE FUNCTION caldav_tombstone();
+
+CREATE TRIGGER todos_caldav_move
+AFTER UPDATE OF list_id ON todos
+FOR EACH ROW WHEN (OLD.list_id <> NEW.list_id)
+EXECUTE FUNCTION caldav_tombstone();
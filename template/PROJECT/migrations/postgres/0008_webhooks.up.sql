@@ -0,0 +1,33 @@
+-- Webhooks: URLs that get signed POSTs for events. Those without a user
+-- are global (set up by an admin) and get every user's events.
+CREATE TABLE IF NOT EXISTS webhooks (
+  id          UUID PRIMARY KEY NOT NULL,
+  user_id     UUID REFERENCES users(id) ON DELETE CASCADE,
+  url         TEXT NOT NULL,
+  events      TEXT NOT NULL,              -- comma-separated names, or *
+  secret      TEXT NOT NULL,              -- HMAC-SHA256 signing key
+  created_at  TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS webhooks_user_id_idx ON webhooks(user_id);
+
+-- The outbox of webhook deliveries, which doubles as their log. A
+-- delivery is pending while it has a next_attempt_at.
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+  id               UUID PRIMARY KEY NOT NULL,
+  webhook_id       UUID NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+  event            TEXT NOT NULL,
+  payload          TEXT NOT NULL,              -- the JSON body
+  attempts         BIGINT NOT NULL DEFAULT 0,
+  next_attempt_at  TIMESTAMPTZ,
+  last_attempt_at  TIMESTAMPTZ,
+  last_status      BIGINT,                     -- HTTP status code
+  last_error       TEXT,
+  delivered_at     TIMESTAMPTZ,
+  created_at       TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook_id_idx
+  ON webhook_deliveries(webhook_id, created_at);
+CREATE INDEX IF NOT EXISTS webhook_deliveries_next_attempt_at_idx
+  ON webhook_deliveries(next_attempt_at);
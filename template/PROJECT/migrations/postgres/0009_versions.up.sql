@@ -0,0 +1,4 @@
+-- Record versions for optimistic concurrency: every write bumps the
+-- version, which clients see as the record's ETag.
+ALTER TABLE users ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE todos ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
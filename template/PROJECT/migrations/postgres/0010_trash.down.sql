@@ -0,0 +1,34 @@
+-- Trashed rows are deleted for good, as they would have been before.
+DROP TRIGGER IF EXISTS todos_caldav_trash ON todos;
+DROP TRIGGER IF EXISTS todos_caldav_delete ON todos;
+
+CREATE OR REPLACE FUNCTION caldav_tombstone() RETURNS trigger AS $$
+BEGIN
+  INSERT INTO caldav_tombstones (list_id, uid, removed_at)
+  VALUES (
+    OLD.list_id,
+    COALESCE((SELECT uid FROM caldav_objects WHERE todo_id = OLD.id), OLD.id::text),
+    now()
+  );
+  IF TG_OP = 'DELETE' THEN
+    DELETE FROM caldav_tombstones WHERE removed_at < now() - interval '30 days';
+  END IF;
+  RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_caldav_delete
+BEFORE DELETE ON todos
+FOR EACH ROW EXECUTE FUNCTION caldav_tombstone();
+
+DELETE FROM users WHERE deleted_at IS NOT NULL;
+DELETE FROM lists WHERE deleted_at IS NOT NULL;
+DELETE FROM todos WHERE deleted_at IS NOT NULL;
+
+DROP INDEX IF EXISTS todos_deleted_at_idx;
+DROP INDEX IF EXISTS lists_deleted_at_idx;
+DROP INDEX IF EXISTS users_deleted_at_idx;
+
+ALTER TABLE todos DROP COLUMN deleted_at;
+ALTER TABLE lists DROP COLUMN deleted_at;
+ALTER TABLE users DROP COLUMN deleted_at;
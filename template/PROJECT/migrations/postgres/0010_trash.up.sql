@@ -0,0 +1,38 @@
+-- Trash: deleting a user, list or todo only sets its deleted_at, and a
+-- background job purges rows that have been in the trash for long enough.
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE lists ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE todos ADD COLUMN deleted_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS users_deleted_at_idx ON users(deleted_at);
+CREATE INDEX IF NOT EXISTS lists_deleted_at_idx ON lists(deleted_at);
+CREATE INDEX IF NOT EXISTS todos_deleted_at_idx ON todos(deleted_at);
+
+-- For CalDAV sync, a todo leaves its list when it is trashed, not when
+-- it is purged.
+CREATE OR REPLACE FUNCTION caldav_tombstone() RETURNS trigger AS $$
+BEGIN
+  INSERT INTO caldav_tombstones (list_id, uid, removed_at)
+  VALUES (
+    OLD.list_id,
+    COALESCE((SELECT uid FROM caldav_objects WHERE todo_id = OLD.id), OLD.id::text),
+    now()
+  );
+  IF TG_OP = 'DELETE' OR NEW.deleted_at IS NOT NULL THEN
+    DELETE FROM caldav_tombstones WHERE removed_at < now() - interval '30 days';
+  END IF;
+  RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS todos_caldav_delete ON todos;
+
+CREATE TRIGGER todos_caldav_delete
+BEFORE DELETE ON todos
+FOR EACH ROW WHEN (OLD.deleted_at IS NULL)
+EXECUTE FUNCTION caldav_tombstone();
+
+CREATE TRIGGER todos_caldav_trash
+AFTER UPDATE OF deleted_at ON todos
+FOR EACH ROW WHEN (OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL)
+EXECUTE FUNCTION caldav_tombstone();
@@ -0,0 +1,28 @@
+-- Audit log: who changed what, from where. Entries are only ever
+-- appended, and outlive the records (and users) they are about.
+CREATE TABLE IF NOT EXISTS audit_log (
+  id          BIGSERIAL PRIMARY KEY,
+  at          TIMESTAMPTZ NOT NULL,
+  actor       TEXT,                       -- authenticated email
+  client_ip   TEXT,
+  request_id  TEXT,
+  entity      TEXT NOT NULL,              -- eg. todo
+  entity_id   TEXT NOT NULL,
+  action      TEXT NOT NULL,              -- create, update, delete or restore
+  before      TEXT,                       -- JSON
+  after       TEXT                        -- JSON
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_at_idx ON audit_log(at);
+CREATE INDEX IF NOT EXISTS audit_log_actor_idx ON audit_log(actor);
+CREATE INDEX IF NOT EXISTS audit_log_entity_idx ON audit_log(entity, entity_id);
+
+CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+BEGIN
+  RAISE EXCEPTION 'the audit log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only
+BEFORE UPDATE OR DELETE ON audit_log
+FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
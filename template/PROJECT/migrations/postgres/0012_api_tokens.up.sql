@@ -0,0 +1,14 @@
+-- Personal API tokens: named, scoped and expiring bearer tokens for
+-- scripts, stored as SHA-256 hex digests.
+CREATE TABLE IF NOT EXISTS api_tokens (
+  id            UUID PRIMARY KEY NOT NULL,
+  user_id       UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name          TEXT NOT NULL,
+  scopes        TEXT NOT NULL,              -- comma-separated: read, write
+  token_hash    TEXT UNIQUE NOT NULL,
+  created_at    TIMESTAMPTZ NOT NULL,
+  expires_at    TIMESTAMPTZ NOT NULL,
+  last_used_at  TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS api_tokens_user_id_idx ON api_tokens(user_id);
@@ -0,0 +1,4 @@
+-- What each user may do. Proxy groups and ADMIN_EMAILS can override it
+-- per request.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'member'
+    CHECK (role IN ('read_only', 'member', 'admin'));
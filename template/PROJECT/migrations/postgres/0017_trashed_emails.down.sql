@@ -0,0 +1,7 @@
+-- Emails are unique again, so trashed users whose email has been
+-- registered since get their id prepended to theirs.
+UPDATE users SET email = id || '+' || email
+WHERE deleted_at IS NOT NULL
+  AND EXISTS (SELECT 1 FROM users a WHERE a.email = users.email AND a.deleted_at IS NULL);
+DROP INDEX IF EXISTS users_email_idx;
+ALTER TABLE users ADD CONSTRAINT users_email_key UNIQUE (email);
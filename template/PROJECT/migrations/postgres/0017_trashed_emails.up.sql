@@ -0,0 +1,4 @@
+-- Only users outside the trash need unique emails, so a trashed user's
+-- email can be registered again.
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_email_key;
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_idx ON users(email) WHERE deleted_at IS NULL;
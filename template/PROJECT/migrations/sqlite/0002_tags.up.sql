@@ -0,0 +1,22 @@
+-- tags (names are unique per user)
+CREATE TABLE IF NOT EXISTS tags (
+  id          TEXT PRIMARY KEY NOT NULL,  -- UUID as text
+  user_id     TEXT NOT NULL,
+  name        TEXT NOT NULL,
+  created_at  INTEGER NOT NULL,           -- unix seconds
+
+  UNIQUE(user_id, name),
+  FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- todo <-> tag
+CREATE TABLE IF NOT EXISTS todo_tags (
+  todo_id     TEXT NOT NULL,
+  tag_id      TEXT NOT NULL,
+
+  PRIMARY KEY(todo_id, tag_id),
+  FOREIGN KEY(todo_id) REFERENCES todos(id) ON DELETE CASCADE,
+  FOREIGN KEY(tag_id) REFERENCES tags(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS todo_tags_tag_id_idx ON todo_tags(tag_id);
@@ -0,0 +1,58 @@
+-- lists (every user has exactly one inbox list)
+CREATE TABLE IF NOT EXISTS lists (
+  id          TEXT PRIMARY KEY NOT NULL,  -- UUID as text
+  user_id     TEXT NOT NULL,
+  name        TEXT NOT NULL,
+  color       TEXT,                       -- nullable '#rrggbb'
+  archived    INTEGER NOT NULL DEFAULT 0, -- 0/1
+  position    INTEGER NOT NULL DEFAULT 0,
+  inbox       INTEGER NOT NULL DEFAULT 0, -- 0/1
+  created_at  INTEGER NOT NULL,           -- unix seconds
+  updated_at  INTEGER NOT NULL,
+
+  FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS lists_user_id_idx ON lists(user_id);
+CREATE UNIQUE INDEX IF NOT EXISTS lists_inbox_idx ON lists(user_id) WHERE inbox;
+
+-- Give every existing user an inbox (with a random v4 UUID):
+INSERT INTO lists (id, user_id, name, inbox, created_at, updated_at)
+SELECT
+  lower(
+    hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' ||
+    substr(hex(randomblob(2)), 2) || '-' ||
+    substr('89ab', 1 + (abs(random()) % 4), 1) ||
+    substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6))
+  ),
+  id, 'Inbox', 1, created_at, created_at
+FROM users;
+
+-- todos.list_id has no FOREIGN KEY, so that it can be dropped again by
+-- the down migration. The triggers below check it instead; the
+-- application moves or deletes a list's todos when the list is deleted.
+ALTER TABLE todos ADD COLUMN list_id TEXT;
+UPDATE todos SET list_id = (
+  SELECT lists.id FROM lists WHERE lists.user_id = todos.user_id AND lists.inbox
+);
+
+CREATE INDEX IF NOT EXISTS todos_list_id_idx ON todos(list_id);
+
+-- A todo's list must exist and belong to the todo's user:
+CREATE TRIGGER IF NOT EXISTS todos_list_id_insert
+BEFORE INSERT ON todos
+WHEN NOT EXISTS (
+  SELECT 1 FROM lists WHERE lists.id = NEW.list_id AND lists.user_id = NEW.user_id
+)
+BEGIN
+  SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+
+CREATE TRIGGER IF NOT EXISTS todos_list_id_update
+BEFORE UPDATE OF list_id ON todos
+WHEN NOT EXISTS (
+  SELECT 1 FROM lists WHERE lists.id = NEW.list_id AND lists.user_id = NEW.user_id
+)
+BEGIN
+  SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
@@ -0,0 +1,4 @@
+DROP TRIGGER IF EXISTS todos_parent_id_update;
+DROP TRIGGER IF EXISTS todos_parent_id_insert;
+DROP INDEX IF EXISTS todos_parent_id_idx;
+ALTER TABLE todos DROP COLUMN parent_id;
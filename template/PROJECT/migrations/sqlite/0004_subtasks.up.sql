@@ -0,0 +1,28 @@
+-- Subtasks: a todo may belong to a parent todo of the same user.
+--
+-- Like todos.list_id, parent_id has no FOREIGN KEY so that the down
+-- migration can drop it. The triggers below check it instead, and the
+-- application deletes a todo's subtasks along with it.
+ALTER TABLE todos ADD COLUMN parent_id TEXT;
+
+CREATE INDEX IF NOT EXISTS todos_parent_id_idx ON todos(parent_id);
+
+CREATE TRIGGER IF NOT EXISTS todos_parent_id_insert
+BEFORE INSERT ON todos
+WHEN NEW.parent_id IS NOT NULL AND NOT EXISTS (
+  SELECT 1 FROM todos
+  WHERE todos.id = NEW.parent_id AND todos.user_id = NEW.user_id AND todos.id <> NEW.id
+)
+BEGIN
+  SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+
+CREATE TRIGGER IF NOT EXISTS todos_parent_id_update
+BEFORE UPDATE OF parent_id ON todos
+WHEN NEW.parent_id IS NOT NULL AND NOT EXISTS (
+  SELECT 1 FROM todos
+  WHERE todos.id = NEW.parent_id AND todos.user_id = NEW.user_id AND todos.id <> NEW.id
+)
+BEGIN
+  SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
@@ -0,0 +1,2 @@
+ALTER TABLE todos DROP COLUMN repeat_from_completion;
+ALTER TABLE todos DROP COLUMN rrule;
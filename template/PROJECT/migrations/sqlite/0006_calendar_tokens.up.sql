@@ -0,0 +1,9 @@
+-- The secret token for each user's iCalendar feed, stored as a SHA-256
+-- hex digest.
+CREATE TABLE IF NOT EXISTS calendar_tokens (
+  user_id     TEXT PRIMARY KEY NOT NULL,  -- UUID as text
+  token_hash  TEXT UNIQUE NOT NULL,
+  created_at  INTEGER NOT NULL,           -- unix seconds
+
+  FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
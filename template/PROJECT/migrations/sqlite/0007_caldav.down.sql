@@ -0,0 +1,8 @@
+DROP TRIGGER IF EXISTS todos_caldav_move;
+DROP TRIGGER IF EXISTS todos_caldav_delete;
+DROP INDEX IF EXISTS caldav_tombstones_list_id_idx;
+DROP TABLE IF EXISTS caldav_tombstones;
+DROP INDEX IF EXISTS caldav_objects_uid_idx;
+DROP TABLE IF EXISTS caldav_objects;
+DROP INDEX IF EXISTS app_passwords_user_id_idx;
+DROP TABLE IF EXISTS app_passwords;
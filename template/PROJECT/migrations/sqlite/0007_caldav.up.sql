@@ -0,0 +1,59 @@
+-- App passwords: per-device secrets for HTTP Basic auth (e.g. CalDAV
+-- clients), stored as SHA-256 hex digests.
+CREATE TABLE IF NOT EXISTS app_passwords (
+  id          TEXT PRIMARY KEY NOT NULL,  -- UUID as text
+  user_id     TEXT NOT NULL,
+  name        TEXT NOT NULL,
+  token_hash  TEXT UNIQUE NOT NULL,
+  created_at  INTEGER NOT NULL,           -- unix seconds
+
+  FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS app_passwords_user_id_idx ON app_passwords(user_id);
+
+-- The UIDs CalDAV clients gave todos (other todos use their id).
+CREATE TABLE IF NOT EXISTS caldav_objects (
+  todo_id     TEXT PRIMARY KEY NOT NULL,  -- UUID as text
+  uid         TEXT NOT NULL,
+
+  FOREIGN KEY(todo_id) REFERENCES todos(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS caldav_objects_uid_idx ON caldav_objects(uid);
+
+-- Todos that left a list (deleted or moved), for CalDAV sync. Kept for
+-- 30 days.
+CREATE TABLE IF NOT EXISTS caldav_tombstones (
+  list_id     TEXT NOT NULL,              -- UUID as text
+  uid         TEXT NOT NULL,
+  removed_at  INTEGER NOT NULL            -- unix seconds
+);
+
+CREATE INDEX IF NOT EXISTS caldav_tombstones_list_id_idx
+  ON caldav_tombstones(list_id, removed_at);
+
+CREATE TRIGGER IF NOT EXISTS todos_caldav_delete
+BEFORE DELETE ON todos
+BEGIN
+  INSERT INTO caldav_tombstones (list_id, uid, removed_at)
+  VALUES (
+    OLD.list_id,
+    COALESCE((SELECT uid FROM caldav_objects WHERE todo_id = OLD.id), OLD.id),
+    CAST(strftime('%s', 'now') AS INTEGER)
+  );
+  DELETE FROM caldav_tombstones
+  WHERE removed_at < CAST(strftime('%s', 'now') AS INTEGER) - 30 * 86400;
+END;
+
+CREATE TRIGGER IF NOT EXISTS todos_caldav_move
+AFTER UPDATE OF list_id ON todos
+WHEN OLD.list_id <> NEW.list_id
+BEGIN
+  INSERT INTO caldav_tombstones (list_id, uid, removed_at)
+  VALUES (
+    OLD.list_id,
+    COALESCE((SELECT uid FROM caldav_objects WHERE todo_id = OLD.id), OLD.id),
+    CAST(strftime('%s', 'now') AS INTEGER)
+  );
+END;
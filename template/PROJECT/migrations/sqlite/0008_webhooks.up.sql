@@ -0,0 +1,37 @@
+-- Webhooks: URLs that get signed POSTs for events. Those without a user
+-- are global (set up by an admin) and get every user's events.
+CREATE TABLE IF NOT EXISTS webhooks (
+  id          TEXT PRIMARY KEY NOT NULL,  -- UUID as text
+  user_id     TEXT,
+  url         TEXT NOT NULL,
+  events      TEXT NOT NULL,              -- comma-separated names, or *
+  secret      TEXT NOT NULL,              -- HMAC-SHA256 signing key
+  created_at  INTEGER NOT NULL,           -- unix seconds
+
+  FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS webhooks_user_id_idx ON webhooks(user_id);
+
+-- The outbox of webhook deliveries, which doubles as their log. A
+-- delivery is pending while it has a next_attempt_at.
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+  id               TEXT PRIMARY KEY NOT NULL,  -- UUID as text
+  webhook_id       TEXT NOT NULL,
+  event            TEXT NOT NULL,
+  payload          TEXT NOT NULL,              -- the JSON body
+  attempts         INTEGER NOT NULL DEFAULT 0,
+  next_attempt_at  INTEGER,                    -- unix seconds
+  last_attempt_at  INTEGER,                    -- unix seconds
+  last_status      INTEGER,                    -- HTTP status code
+  last_error       TEXT,
+  delivered_at     INTEGER,                    -- unix seconds
+  created_at       INTEGER NOT NULL,           -- unix seconds
+
+  FOREIGN KEY(webhook_id) REFERENCES webhooks(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook_id_idx
+  ON webhook_deliveries(webhook_id, created_at);
+CREATE INDEX IF NOT EXISTS webhook_deliveries_next_attempt_at_idx
+  ON webhook_deliveries(next_attempt_at);
@@ -0,0 +1,29 @@
+-- Trashed rows are deleted for good, as they would have been before.
+DROP TRIGGER IF EXISTS todos_caldav_trash;
+DROP TRIGGER IF EXISTS todos_caldav_delete;
+
+CREATE TRIGGER IF NOT EXISTS todos_caldav_delete
+BEFORE DELETE ON todos
+BEGIN
+  INSERT INTO caldav_tombstones (list_id, uid, removed_at)
+  VALUES (
+    OLD.list_id,
+    COALESCE((SELECT uid FROM caldav_objects WHERE todo_id = OLD.id), OLD.id),
+    CAST(strftime('%s', 'now') AS INTEGER)
+  );
+  DELETE FROM caldav_tombstones
+  WHERE removed_at < CAST(strftime('%s', 'now') AS INTEGER) - 30 * 86400;
+END;
+
+DELETE FROM users WHERE deleted_at IS NOT NULL;
+DELETE FROM todos WHERE list_id IN (SELECT id FROM lists WHERE deleted_at IS NOT NULL);
+DELETE FROM lists WHERE deleted_at IS NOT NULL;
+DELETE FROM todos WHERE deleted_at IS NOT NULL;
+
+DROP INDEX IF EXISTS todos_deleted_at_idx;
+DROP INDEX IF EXISTS lists_deleted_at_idx;
+DROP INDEX IF EXISTS users_deleted_at_idx;
+
+ALTER TABLE todos DROP COLUMN deleted_at;
+ALTER TABLE lists DROP COLUMN deleted_at;
+ALTER TABLE users DROP COLUMN deleted_at;
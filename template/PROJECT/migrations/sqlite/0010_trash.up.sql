@@ -0,0 +1,41 @@
+-- Trash: deleting a user, list or todo only sets its deleted_at, and a
+-- background job purges rows that have been in the trash for long enough.
+ALTER TABLE users ADD COLUMN deleted_at INTEGER;  -- unix seconds
+ALTER TABLE lists ADD COLUMN deleted_at INTEGER;
+ALTER TABLE todos ADD COLUMN deleted_at INTEGER;
+
+CREATE INDEX IF NOT EXISTS users_deleted_at_idx ON users(deleted_at);
+CREATE INDEX IF NOT EXISTS lists_deleted_at_idx ON lists(deleted_at);
+CREATE INDEX IF NOT EXISTS todos_deleted_at_idx ON todos(deleted_at);
+
+-- For CalDAV sync, a todo leaves its list when it is trashed, not when
+-- it is purged.
+DROP TRIGGER IF EXISTS todos_caldav_delete;
+
+CREATE TRIGGER IF NOT EXISTS todos_caldav_delete
+BEFORE DELETE ON todos
+WHEN OLD.deleted_at IS NULL
+BEGIN
+  INSERT INTO caldav_tombstones (list_id, uid, removed_at)
+  VALUES (
+    OLD.list_id,
+    COALESCE((SELECT uid FROM caldav_objects WHERE todo_id = OLD.id), OLD.id),
+    CAST(strftime('%s', 'now') AS INTEGER)
+  );
+  DELETE FROM caldav_tombstones
+  WHERE removed_at < CAST(strftime('%s', 'now') AS INTEGER) - 30 * 86400;
+END;
+
+CREATE TRIGGER IF NOT EXISTS todos_caldav_trash
+AFTER UPDATE OF deleted_at ON todos
+WHEN OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL
+BEGIN
+  INSERT INTO caldav_tombstones (list_id, uid, removed_at)
+  VALUES (
+    OLD.list_id,
+    COALESCE((SELECT uid FROM caldav_objects WHERE todo_id = OLD.id), OLD.id),
+    CAST(strftime('%s', 'now') AS INTEGER)
+  );
+  DELETE FROM caldav_tombstones
+  WHERE removed_at < CAST(strftime('%s', 'now') AS INTEGER) - 30 * 86400;
+END;
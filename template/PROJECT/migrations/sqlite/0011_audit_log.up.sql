@@ -0,0 +1,30 @@
+-- Audit log: who changed what, from where. Entries are only ever
+-- appended, and outlive the records (and users) they are about.
+CREATE TABLE IF NOT EXISTS audit_log (
+  id          INTEGER PRIMARY KEY AUTOINCREMENT,
+  at          INTEGER NOT NULL,           -- unix seconds
+  actor       TEXT,                       -- authenticated email
+  client_ip   TEXT,
+  request_id  TEXT,
+  entity      TEXT NOT NULL,              -- eg. todo
+  entity_id   TEXT NOT NULL,
+  action      TEXT NOT NULL,              -- create, update, delete or restore
+  before      TEXT,                       -- JSON
+  after       TEXT                        -- JSON
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_at_idx ON audit_log(at);
+CREATE INDEX IF NOT EXISTS audit_log_actor_idx ON audit_log(actor);
+CREATE INDEX IF NOT EXISTS audit_log_entity_idx ON audit_log(entity, entity_id);
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update
+BEFORE UPDATE ON audit_log
+BEGIN
+  SELECT RAISE(ABORT, 'the audit log is append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete
+BEFORE DELETE ON audit_log
+BEGIN
+  SELECT RAISE(ABORT, 'the audit log is append-only');
+END;
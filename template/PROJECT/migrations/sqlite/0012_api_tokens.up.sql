@@ -0,0 +1,16 @@
+-- Personal API tokens: named, scoped and expiring bearer tokens for
+-- scripts, stored as SHA-256 hex digests.
+CREATE TABLE IF NOT EXISTS api_tokens (
+  id            TEXT PRIMARY KEY NOT NULL,  -- UUID as text
+  user_id       TEXT NOT NULL,
+  name          TEXT NOT NULL,
+  scopes        TEXT NOT NULL,              -- comma-separated: read, write
+  token_hash    TEXT UNIQUE NOT NULL,
+  created_at    INTEGER NOT NULL,           -- unix seconds
+  expires_at    INTEGER NOT NULL,           -- unix seconds
+  last_used_at  INTEGER,                    -- unix seconds
+
+  FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS api_tokens_user_id_idx ON api_tokens(user_id);
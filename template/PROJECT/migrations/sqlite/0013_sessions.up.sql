@@ -0,0 +1,12 @@
+-- Browser sessions from signing in with the app itself (rather than
+-- through the proxy), stored as SHA-256 hex digests of their cookies.
+-- Keyed by email, like the proxy's user header.
+CREATE TABLE IF NOT EXISTS sessions (
+  id            TEXT PRIMARY KEY NOT NULL,  -- UUID as text
+  email         TEXT NOT NULL,
+  token_hash    TEXT UNIQUE NOT NULL,
+  created_at    INTEGER NOT NULL,           -- unix seconds
+  expires_at    INTEGER NOT NULL            -- unix seconds
+);
+
+CREATE INDEX IF NOT EXISTS sessions_expires_at_idx ON sessions(expires_at);
@@ -0,0 +1,3 @@
+-- Optional local passwords, for installs without single sign-on, as
+-- Argon2id PHC strings.
+ALTER TABLE users ADD COLUMN password_hash TEXT;
@@ -0,0 +1,2 @@
+-- The admin who made a change while acting as `actor` (with X-Act-As).
+ALTER TABLE audit_log ADD COLUMN real_actor TEXT;
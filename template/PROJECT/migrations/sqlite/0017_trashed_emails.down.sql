@@ -0,0 +1,27 @@
+-- Emails are unique again, so trashed users whose email has been
+-- registered since get their id prepended to theirs.
+UPDATE users SET email = id || '+' || email
+WHERE deleted_at IS NOT NULL
+  AND EXISTS (SELECT 1 FROM users a WHERE a.email = users.email AND a.deleted_at IS NULL);
+
+CREATE TABLE users_old (
+  id            TEXT PRIMARY KEY NOT NULL, -- UUID as text
+  email         TEXT UNIQUE NOT NULL,
+  display_name  TEXT NOT NULL,
+  created_at    INTEGER NOT NULL,          -- unix seconds
+  version       INTEGER NOT NULL DEFAULT 1,
+  deleted_at    INTEGER,                   -- unix seconds
+  password_hash TEXT,
+  role          TEXT NOT NULL DEFAULT 'member'
+    CHECK (role IN ('read_only', 'member', 'admin'))
+);
+
+INSERT INTO users_old
+  (id, email, display_name, created_at, version, deleted_at, password_hash, role)
+SELECT id, email, display_name, created_at, version, deleted_at, password_hash, role
+FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_old RENAME TO users;
+
+CREATE INDEX IF NOT EXISTS users_deleted_at_idx ON users(deleted_at);
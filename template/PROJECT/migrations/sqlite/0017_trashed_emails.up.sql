@@ -0,0 +1,26 @@
+-- Only users outside the trash need unique emails, so a trashed user's
+-- email can be registered again. SQLite cannot drop a column's UNIQUE
+-- constraint, so the table is rebuilt; migrations run with foreign keys
+-- off (see `db::migrate_sqlite`), so dropping it does not cascade.
+CREATE TABLE users_new (
+  id            TEXT PRIMARY KEY NOT NULL, -- UUID as text
+  email         TEXT NOT NULL,
+  display_name  TEXT NOT NULL,
+  created_at    INTEGER NOT NULL,          -- unix seconds
+  version       INTEGER NOT NULL DEFAULT 1,
+  deleted_at    INTEGER,                   -- unix seconds
+  password_hash TEXT,
+  role          TEXT NOT NULL DEFAULT 'member'
+    CHECK (role IN ('read_only', 'member', 'admin'))
+);
+
+INSERT INTO users_new
+  (id, email, display_name, created_at, version, deleted_at, password_hash, role)
+SELECT id, email, display_name, created_at, version, deleted_at, password_hash, role
+FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_new RENAME TO users;
+
+CREATE INDEX IF NOT EXISTS users_deleted_at_idx ON users(deleted_at);
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_idx ON users(email) WHERE deleted_at IS NULL;
@@ -0,0 +1,158 @@
+use anyhow::Context;
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    extract::Origin,
+    models::audit::{Action, AuditEntry, Entity},
+    prelude::*,
+    repository::AuditRepository,
+    AppState,
+};
+
+/// A change to a record, to be recorded in the audit log.
+#[derive(Debug, Clone)]
+pub struct Change {
+    entity: Entity,
+    entity_id: String,
+    action: Action,
+    before: Option<Value>,
+    after: Option<Value>,
+}
+
+impl Change {
+    pub fn created(entity: Entity, id: impl ToString, after: &impl Serialize) -> Self {
+        Self::new(entity, id, Action::Create, None, Some(to_value(after)))
+    }
+
+    /// An update, recording only the fields that changed.
+    pub fn updated(
+        entity: Entity,
+        id: impl ToString,
+        before: &impl Serialize,
+        after: &impl Serialize,
+    ) -> Self {
+        let (before, after) = diff(to_value(before), to_value(after));
+        Self::new(entity, id, Action::Update, Some(before), Some(after))
+    }
+
+    pub fn deleted(entity: Entity, id: impl ToString, before: &impl Serialize) -> Self {
+        Self::new(entity, id, Action::Delete, Some(to_value(before)), None)
+    }
+
+    pub fn restored(entity: Entity, id: impl ToString, after: &impl Serialize) -> Self {
+        Self::new(entity, id, Action::Restore, None, Some(to_value(after)))
+    }
+
+    fn new(
+        entity: Entity,
+        id: impl ToString,
+        action: Action,
+        before: Option<Value>,
+        after: Option<Value>,
+    ) -> Self {
+        Self {
+            entity,
+            entity_id: id.to_string(),
+            action,
+            before,
+            after,
+        }
+    }
+
+    fn entry(self, origin: &Origin) -> AuditEntry {
+        AuditEntry {
+            id: 0,
+            at: Utc::now(),
+            actor: origin.actor.clone(),
+            real_actor: origin.real_actor.clone(),
+            client_ip: origin.client_ip.map(|ip| ip.to_string()),
+            request_id: origin.request_id.clone(),
+            entity: self.entity.name().to_string(),
+            entity_id: self.entity_id,
+            action: self.action.name().to_string(),
+            before: self.before.map(|v| v.to_string()),
+            after: self.after.map(|v| v.to_string()),
+        }
+    }
+}
+
+fn to_value(data: &impl Serialize) -> Value {
+    serde_json::to_value(data).unwrap_or_else(|e| {
+        error!("Failed to serialize an audited record: {e}");
+        Value::Null
+    })
+}
+
+/// The fields of two versions of a record that differ, as they were
+/// before and are after. Anything but two objects is kept whole.
+fn diff(before: Value, after: Value) -> (Value, Value) {
+    let (before, mut after) = match (before, after) {
+        (Value::Object(before), Value::Object(after)) => (before, after),
+        (before, after) => return (before, after),
+    };
+    let mut old = Map::new();
+    let mut new = Map::new();
+    for (key, value) in before {
+        match after.remove(&key) {
+            Some(v) if v == value => {}
+            Some(v) => {
+                old.insert(key.clone(), value);
+                new.insert(key, v);
+            }
+            None => {
+                old.insert(key, value);
+            }
+        }
+    }
+    new.extend(after);
+    (Value::Object(old), Value::Object(new))
+}
+
+/// Append changes made by a request to the audit log. Failures are
+/// logged, as the changes themselves have already been made.
+pub async fn record(state: &AppState, origin: &Origin, changes: impl IntoIterator<Item = Change>) {
+    if let Err(e) = write(state.audit.as_ref(), origin, changes).await {
+        error!("{e:#}");
+    }
+}
+
+/// Append changes to the audit log.
+pub async fn write(
+    audit: &dyn AuditRepository,
+    origin: &Origin,
+    changes: impl IntoIterator<Item = Change>,
+) -> anyhow::Result<()> {
+    let entries: Vec<AuditEntry> = changes.into_iter().map(|c| c.entry(origin)).collect();
+    if entries.is_empty() {
+        return Ok(());
+    }
+    audit.insert(&entries).await.with_context(|| {
+        format!(
+            "Failed to record {} change(s) in the audit log",
+            entries.len()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn diffs_changed_fields() {
+        let before = json!({"title": "mow", "notes": null, "version": 1});
+        let after = json!({"title": "mow", "notes": "edges", "version": 2, "due_at": 3});
+        assert_eq!(
+            diff(before, after),
+            (
+                json!({"notes": null, "version": 1}),
+                json!({"notes": "edges", "version": 2, "due_at": 3})
+            )
+        );
+        assert_eq!(diff(json!(1), json!([2])), (json!(1), json!([2])));
+    }
+}
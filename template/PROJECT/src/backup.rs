@@ -0,0 +1,253 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::Utc;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    SqlitePool,
+};
+
+use crate::prelude::*;
+
+const BACKUP_PREFIX: &str = "backup-";
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Config for the scheduled in-server backup job.
+#[derive(Clone, Debug)]
+pub struct BackupConfig {
+    pub dir: PathBuf,
+    pub interval: Duration,
+    pub retention: usize,
+    pub compress: bool,
+}
+
+/// Write a consistent snapshot of the live database to `dest`.
+///
+/// Uses `VACUUM INTO`, which is safe while other connections (and the
+/// WAL) are in use. The snapshot is written to a temporary file next
+/// to `dest` and only moved into place once complete.
+pub async fn backup(db: &SqlitePool, dest: &Path, compress: bool) -> anyhow::Result<()> {
+    let snapshot = blocking({
+        let dest = dest.to_path_buf();
+        move || {
+            if dest.exists() {
+                anyhow::bail!("backup destination already exists: {}", dest.display());
+            }
+            temp_file_beside(&dest)
+        }
+    })
+    .await?;
+    vacuum_into(db, snapshot.path()).await?;
+
+    let dest = dest.to_path_buf();
+    blocking(move || {
+        if compress {
+            let compressed = temp_file_beside(&dest)?;
+            let mut encoder =
+                GzEncoder::new(BufWriter::new(compressed.as_file()), Compression::default());
+            std::io::copy(&mut BufReader::new(snapshot.as_file()), &mut encoder)?;
+            encoder.finish()?.flush()?;
+            compressed.persist_noclobber(&dest)?;
+        } else {
+            snapshot.persist_noclobber(&dest)?;
+        }
+        info!("Wrote database backup to {}", dest.display());
+        Ok(())
+    })
+    .await
+}
+
+/// Replace the database at `db_url` with the backup at `src`.
+///
+/// The backup may be gzip compressed. It is integrity checked before
+/// the current database file (and its WAL) is replaced, so the server
+/// must not be running while restoring.
+pub async fn restore(src: &Path, db_url: &str) -> anyhow::Result<()> {
+    let target = SqliteConnectOptions::from_str(db_url)?
+        .get_filename()
+        .to_path_buf();
+    if target.as_os_str().is_empty() || target == Path::new(":memory:") {
+        anyhow::bail!("cannot restore into an in-memory database: {db_url}");
+    }
+
+    let decompressed = blocking({
+        let src = src.to_path_buf();
+        move || decompress(&src)
+    })
+    .await?;
+    let source_path = decompressed.as_ref().map(|t| t.path()).unwrap_or(src);
+
+    let source = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(
+            SqliteConnectOptions::new()
+                .filename(source_path)
+                .read_only(true),
+        )
+        .await?;
+    let (check,): (String,) = sqlx::query_as("PRAGMA integrity_check;")
+        .fetch_one(&source)
+        .await?;
+    if check != "ok" {
+        anyhow::bail!("backup failed integrity check: {check}");
+    }
+
+    let staged = blocking({
+        let target = target.clone();
+        move || temp_file_beside(&target)
+    })
+    .await?;
+    vacuum_into(&source, staged.path()).await?;
+    source.close().await;
+
+    blocking({
+        let target = target.clone();
+        move || {
+            for suffix in ["-wal", "-shm"] {
+                let mut sidecar = target.clone().into_os_string();
+                sidecar.push(suffix);
+                match std::fs::remove_file(&sidecar) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+            staged.persist(&target)?;
+            Ok(())
+        }
+    })
+    .await?;
+    info!(
+        "Restored database {} from {}",
+        target.display(),
+        src.display()
+    );
+    Ok(())
+}
+
+/// Run scheduled backups into `cfg.dir` forever, keeping the newest
+/// `cfg.retention` files.
+pub async fn run_schedule(db: SqlitePool, cfg: BackupConfig) {
+    let mut ticker = tokio::time::interval(cfg.interval);
+    // The first tick completes immediately; wait a full interval instead.
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        if let Err(e) = scheduled_backup(&db, &cfg).await {
+            error!("Scheduled backup failed: {e:#}");
+        }
+    }
+}
+
+async fn scheduled_backup(db: &SqlitePool, cfg: &BackupConfig) -> anyhow::Result<()> {
+    tokio::fs::create_dir_all(&cfg.dir).await?;
+    let ext = if cfg.compress { "db.gz" } else { "db" };
+    let name = format!(
+        "{BACKUP_PREFIX}{}.{ext}",
+        Utc::now().format("%Y%m%dT%H%M%SZ")
+    );
+    backup(db, &cfg.dir.join(name), cfg.compress).await?;
+    let (dir, retention) = (cfg.dir.clone(), cfg.retention);
+    blocking(move || prune(&dir, retention)).await
+}
+
+/// Decompress the backup at `src` into a scratch file, or `None` if it
+/// is not gzip compressed.
+fn decompress(src: &Path) -> anyhow::Result<Option<tempfile::NamedTempFile>> {
+    let mut magic = [0u8; 2];
+    if File::open(src)?.read(&mut magic)? != 2 || magic != GZIP_MAGIC {
+        return Ok(None);
+    }
+    let tmp = tempfile::NamedTempFile::new()?;
+    let mut decoder = GzDecoder::new(BufReader::new(File::open(src)?));
+    let mut writer = BufWriter::new(tmp.as_file());
+    std::io::copy(&mut decoder, &mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    Ok(Some(tmp))
+}
+
+/// Delete all but the newest `retention` backups in `dir`.
+fn prune(dir: &Path, retention: usize) -> anyhow::Result<()> {
+    let mut backups: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name().and_then(|n| n.to_str()).is_some_and(|n| {
+                n.starts_with(BACKUP_PREFIX) && (n.ends_with(".db") || n.ends_with(".db.gz"))
+            })
+        })
+        .collect();
+    // Timestamped names sort chronologically:
+    backups.sort();
+    let excess = backups.len().saturating_sub(retention);
+    for old in backups.into_iter().take(excess) {
+        debug!("Pruning old backup {}", old.display());
+        std::fs::remove_file(old)?;
+    }
+    Ok(())
+}
+
+async fn vacuum_into(db: &SqlitePool, dest: &Path) -> anyhow::Result<()> {
+    let dest = dest
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("non-utf8 path: {}", dest.display()))?;
+    sqlx::query("VACUUM INTO ?;").bind(dest).execute(db).await?;
+    Ok(())
+}
+
+/// Run blocking file I/O (and gzip) on tokio's blocking thread pool, so
+/// large databases do not stall the server's other tasks.
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    tokio::task::spawn_blocking(f).await?
+}
+
+/// Create an empty temporary file in the same directory as `path`, so
+/// it can be atomically renamed over it.
+fn temp_file_beside(path: &Path) -> anyhow::Result<tempfile::NamedTempFile> {
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    Ok(tempfile::Builder::new()
+        .prefix(".backup-")
+        .suffix(".tmp")
+        .tempfile_in(dir)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_keeps_newest() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "backup-20240101T000000Z.db",
+            "backup-20240102T000000Z.db.gz",
+            "backup-20240103T000000Z.db",
+            "unrelated.db",
+        ] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+
+        prune(dir.path(), 2).unwrap();
+
+        let mut left: Vec<String> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            [
+                "backup-20240102T000000Z.db.gz",
+                "backup-20240103T000000Z.db",
+                "unrelated.db"
+            ]
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use clap::{value_parser, Arg, Command};
+use clap::{builder::TypedValueParser, value_parser, Arg, Command};
 
 pub fn app() -> Command {
     Command::new("${APP}")
@@ -66,6 +66,13 @@ pub fn app() -> Command {
                         .default_value("X-Forwarded-User")
                         .help("Header to read the authenticated user email from"),
                 )
+                .arg(
+                    Arg::new("trusted_groups_header")
+                        .long("trusted-groups-header")
+                        .env("TRUSTED_GROUPS_HEADER")
+                        .value_name("HEADER")
+                        .help("Header to read the authenticated user's comma-separated groups from (e.g. X-Forwarded-Groups)"),
+                )
                 .arg(
                     Arg::new("trusted_proxy")
                         .long("trusted-proxy")
@@ -91,6 +98,636 @@ pub fn app() -> Command {
                         .help(
                             "Header to read client IP from when trusted-forwarded-for is enabled",
                         ),
+                )
+                .arg(
+                    Arg::new("oidc_issuer")
+                        .long("oidc-issuer")
+                        .env("OIDC_ISSUER")
+                        .value_name("URL")
+                        .requires_all(["oidc_client_id", "oidc_redirect_url"])
+                        .help("Sign users in with this OpenID Connect issuer (at /auth/login)"),
+                )
+                .arg(
+                    Arg::new("oidc_client_id")
+                        .long("oidc-client-id")
+                        .env("OIDC_CLIENT_ID")
+                        .value_name("ID")
+                        .help("The client id registered with the OpenID Connect issuer"),
+                )
+                .arg(
+                    Arg::new("oidc_client_secret")
+                        .long("oidc-client-secret")
+                        .env("OIDC_CLIENT_SECRET")
+                        .value_name("SECRET")
+                        .hide_env_values(true)
+                        .help("The client secret, unless the client is public"),
+                )
+                .arg(
+                    Arg::new("oidc_redirect_url")
+                        .long("oidc-redirect-url")
+                        .env("OIDC_REDIRECT_URL")
+                        .value_name("URL")
+                        .help("This app's /auth/callback URL, as registered with the issuer"),
+                )
+                .arg(
+                    Arg::new("oidc_scopes")
+                        .long("oidc-scopes")
+                        .env("OIDC_SCOPES")
+                        .value_name("SCOPES")
+                        .value_delimiter(',')
+                        .default_value("openid,email,profile")
+                        .help("The scopes to ask the issuer for"),
+                )
+                .arg(
+                    Arg::new("session_hours")
+                        .long("session-hours")
+                        .env("SESSION_HOURS")
+                        .value_name("HOURS")
+                        .default_value("168")
+                        .value_parser(value_parser!(u64).range(1..))
+                        .help("How long users stay signed in"),
+                )
+                .arg(
+                    Arg::new("insecure_cookies")
+                        .long("insecure-cookies")
+                        .env("INSECURE_COOKIES")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Send session cookies over plain HTTP too (for local testing only)"),
+                )
+                .arg(
+                    Arg::new("jwt_header")
+                        .long("jwt-header")
+                        .env("JWT_HEADER")
+                        .value_name("HEADER")
+                        .requires("jwt_jwks")
+                        .help("Verify a signed JWT assertion from this header, eg. Cf-Access-Jwt-Assertion"),
+                )
+                .arg(
+                    Arg::new("jwt_jwks")
+                        .long("jwt-jwks")
+                        .env("JWT_JWKS")
+                        .value_name("FILE|URL")
+                        .value_parser(|s: &str| s.parse::<crate::jwt::JwksSource>())
+                        .help("JWKS file or URL with the keys JWT assertions are signed with"),
+                )
+                .arg(
+                    Arg::new("jwt_issuer")
+                        .long("jwt-issuer")
+                        .env("JWT_ISSUER")
+                        .value_name("ISSUER")
+                        .help("Only accept JWT assertions with this issuer (iss)"),
+                )
+                .arg(
+                    Arg::new("jwt_audience")
+                        .long("jwt-audience")
+                        .env("JWT_AUDIENCE")
+                        .value_name("AUDIENCE")
+                        .value_delimiter(',')
+                        .action(clap::ArgAction::Append)
+                        .help("Only accept JWT assertions for this audience (aud; repeatable)"),
+                )
+                .arg(
+                    Arg::new("jwt_leeway")
+                        .long("jwt-leeway")
+                        .env("JWT_LEEWAY")
+                        .value_name("SECONDS")
+                        .default_value("60")
+                        .value_parser(value_parser!(u64))
+                        .help("Clock skew to allow when checking JWT expiry"),
+                )
+                .arg(
+                    Arg::new("jwt_email_claim")
+                        .long("jwt-email-claim")
+                        .env("JWT_EMAIL_CLAIM")
+                        .value_name("CLAIM")
+                        .default_value("email")
+                        .help("JWT claim with the user's email"),
+                )
+                .arg(
+                    Arg::new("jwt_groups_claim")
+                        .long("jwt-groups-claim")
+                        .env("JWT_GROUPS_CLAIM")
+                        .value_name("CLAIM")
+                        .default_value("groups")
+                        .help("JWT claim with the user's groups"),
+                )
+                .arg(
+                    Arg::new("jwt_name_claim")
+                        .long("jwt-name-claim")
+                        .env("JWT_NAME_CLAIM")
+                        .value_name("CLAIM")
+                        .default_value("name")
+                        .help("JWT claim with the user's name"),
+                )
+                .arg(database_url_arg())
+                .arg(
+                    Arg::new("db_max_connections")
+                        .long("db-max-connections")
+                        .env("DB_MAX_CONNECTIONS")
+                        .value_name("COUNT")
+                        .default_value("5")
+                        .value_parser(value_parser!(u32).range(1..))
+                        .help("Size of the read-write database connection pool"),
+                )
+                .arg(
+                    Arg::new("db_read_max_connections")
+                        .long("db-read-max-connections")
+                        .env("DB_READ_MAX_CONNECTIONS")
+                        .value_name("COUNT")
+                        .default_value("5")
+                        .value_parser(value_parser!(u32).range(1..))
+                        .help("Size of the read-only database connection pool"),
+                )
+                .arg(
+                    Arg::new("db_acquire_timeout")
+                        .long("db-acquire-timeout")
+                        .env("DB_ACQUIRE_TIMEOUT")
+                        .value_name("SECONDS")
+                        .default_value("30")
+                        .value_parser(value_parser!(u64))
+                        .help("Seconds to wait for a free pooled connection"),
+                )
+                .arg(
+                    Arg::new("db_busy_timeout")
+                        .long("db-busy-timeout")
+                        .env("DB_BUSY_TIMEOUT")
+                        .value_name("MILLISECONDS")
+                        .default_value("5000")
+                        .value_parser(value_parser!(u64))
+                        .help("Milliseconds to wait on a locked database (PRAGMA busy_timeout)"),
+                )
+                .arg(
+                    Arg::new("db_synchronous")
+                        .long("db-synchronous")
+                        .env("DB_SYNCHRONOUS")
+                        .value_name("MODE")
+                        .default_value("full")
+                        .value_parser(
+                            clap::builder::PossibleValuesParser::new([
+                                "off", "normal", "full", "extra",
+                            ])
+                            .map(|s| {
+                                s.parse::<sqlx::sqlite::SqliteSynchronous>().unwrap()
+                            }),
+                        )
+                        .help("PRAGMA synchronous (normal is usually safe with WAL)"),
+                )
+                .arg(
+                    Arg::new("db_cache_size")
+                        .long("db-cache-size")
+                        .env("DB_CACHE_SIZE")
+                        .value_name("SIZE")
+                        .default_value("-2000")
+                        .allow_negative_numbers(true)
+                        .value_parser(value_parser!(i64))
+                        .help("PRAGMA cache_size per connection (pages, or KiB if negative)"),
+                )
+                .arg(
+                    Arg::new("db_mmap_size")
+                        .long("db-mmap-size")
+                        .env("DB_MMAP_SIZE")
+                        .value_name("BYTES")
+                        .default_value("0")
+                        .value_parser(value_parser!(u64))
+                        .help("PRAGMA mmap_size per connection (0 disables)"),
+                )
+                .arg(
+                    Arg::new("no_migrate")
+                        .long("no-migrate")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Do not apply pending database migrations on startup"),
+                )
+                .arg(
+                    Arg::new("ephemeral")
+                        .long("ephemeral")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with_all(["no_migrate", "backup_dir"])
+                        .help("Keep all data in memory instead of DATABASE_URL (lost on shutdown)"),
+                )
+                .arg(
+                    Arg::new("backup_dir")
+                        .long("backup-dir")
+                        .env("BACKUP_DIR")
+                        .value_name("DIR")
+                        .value_parser(value_parser!(std::path::PathBuf))
+                        .help("Enable scheduled database backups into DIR (or set BACKUP_DIR)"),
+                )
+                .arg(
+                    Arg::new("backup_interval")
+                        .long("backup-interval")
+                        .env("BACKUP_INTERVAL")
+                        .value_name("SECONDS")
+                        .default_value("86400")
+                        .value_parser(value_parser!(u64).range(1..))
+                        .help("Seconds between scheduled backups"),
+                )
+                .arg(
+                    Arg::new("backup_retention")
+                        .long("backup-retention")
+                        .env("BACKUP_RETENTION")
+                        .value_name("COUNT")
+                        .default_value("7")
+                        .value_parser(value_parser!(u64).range(1..).map(|n| n as usize))
+                        .help("Number of scheduled backups to keep"),
+                )
+                .arg(
+                    Arg::new("backup_compress")
+                        .long("backup-compress")
+                        .env("BACKUP_COMPRESS")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Gzip compress scheduled backups"),
+                )
+                .arg(
+                    Arg::new("trash_retention")
+                        .long("trash-retention")
+                        .env("TRASH_RETENTION")
+                        .value_name("DAYS")
+                        .default_value("30")
+                        .value_parser(value_parser!(u64).range(1..))
+                        .help("Days deleted users, lists and todos stay in the trash"),
+                )
+                .arg(allow_local_webhooks_arg())
+                .arg(
+                    Arg::new("admin_emails")
+                        .long("admin-email")
+                        .env("ADMIN_EMAILS")
+                        .value_name("EMAIL")
+                        .value_delimiter(',')
+                        .action(clap::ArgAction::Append)
+                        .help("Make this user an admin, whatever their role (repeatable, or comma-separated ADMIN_EMAILS)"),
+                )
+                .arg(
+                    Arg::new("admin_groups")
+                        .long("admin-group")
+                        .env("ADMIN_GROUPS")
+                        .value_name("GROUP")
+                        .value_delimiter(',')
+                        .action(clap::ArgAction::Append)
+                        .help("Make members of this proxy group admins (repeatable, or comma-separated ADMIN_GROUPS)"),
+                )
+                .arg(
+                    Arg::new("member_groups")
+                        .long("member-group")
+                        .env("MEMBER_GROUPS")
+                        .value_name("GROUP")
+                        .value_delimiter(',')
+                        .action(clap::ArgAction::Append)
+                        .help("Make members of this proxy group members (repeatable, or comma-separated MEMBER_GROUPS)"),
+                )
+                .arg(
+                    Arg::new("read_only_groups")
+                        .long("read-only-group")
+                        .env("READ_ONLY_GROUPS")
+                        .value_name("GROUP")
+                        .value_delimiter(',')
+                        .action(clap::ArgAction::Append)
+                        .help("Make members of this proxy group read-only (repeatable, or comma-separated READ_ONLY_GROUPS)"),
+                ),
+        )
+        .subcommand(
+            Command::new("db")
+                .about("Manage the database and its migrations")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .arg(database_url_arg().global(true))
+                .subcommand(
+                    Command::new("create").about("Create the database file if it does not exist"),
+                )
+                .subcommand(Command::new("migrate").about("Apply all pending migrations"))
+                .subcommand(
+                    Command::new("status").about("List migrations and whether they are applied"),
+                )
+                .subcommand(
+                    Command::new("revert")
+                        .about("Revert the most recently applied migration")
+                        .arg(
+                            Arg::new("target")
+                                .long("target")
+                                .value_name("VERSION")
+                                .value_parser(value_parser!(i64))
+                                .help("Revert every applied migration newer than VERSION (0 reverts all)"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("backup")
+                        .about("Write an online snapshot of the database to a file")
+                        .arg(
+                            Arg::new("path")
+                                .required(true)
+                                .value_name("PATH")
+                                .value_parser(value_parser!(std::path::PathBuf))
+                                .help("The backup file to create"),
+                        )
+                        .arg(
+                            Arg::new("compress")
+                                .long("compress")
+                                .short('z')
+                                .action(clap::ArgAction::SetTrue)
+                                .help("Gzip compress the backup"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("restore")
+                        .about("Replace the database with a backup (stop the server first)")
+                        .arg(
+                            Arg::new("path")
+                                .required(true)
+                                .value_name("PATH")
+                                .value_parser(value_parser!(std::path::PathBuf))
+                                .help("The backup file to restore (plain or gzip compressed)"),
+                        ),
                 ),
         )
+        .subcommand(
+            Command::new("user")
+                .about("Manage users directly in the database")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .arg(database_url_arg().global(true))
+                .arg(output_arg())
+                .subcommand(
+                    Command::new("create")
+                        .about("Create a new user")
+                        .arg(Arg::new("email").required(true).value_name("EMAIL"))
+                        .arg(Arg::new("display_name").required(true).value_name("DISPLAY_NAME")),
+                )
+                .subcommand(Command::new("list").about("List all users"))
+                .subcommand(
+                    Command::new("show")
+                        .about("Show a single user")
+                        .arg(user_key_arg()),
+                )
+                .subcommand(
+                    Command::new("delete")
+                        .about("Move a user and all of their todos to the trash")
+                        .arg(user_key_arg()),
+                )
+                .subcommand(
+                    Command::new("restore")
+                        .about("Restore a user and their todos from the trash")
+                        .arg(user_key_arg()),
+                )
+                .subcommand(
+                    Command::new("set-display-name")
+                        .about("Change a user's display name")
+                        .arg(user_key_arg())
+                        .arg(Arg::new("display_name").required(true).value_name("DISPLAY_NAME")),
+                )
+                .subcommand(
+                    Command::new("set-password")
+                        .about("Set a user's password, read from standard input, and sign them out")
+                        .arg(user_key_arg()),
+                )
+                .subcommand(
+                    Command::new("reset-password")
+                        .about("Give a user a new random password, print it, and sign them out")
+                        .arg(user_key_arg()),
+                )
+                .subcommand(
+                    Command::new("clear-password")
+                        .about("Remove a user's password, and sign them out")
+                        .arg(user_key_arg()),
+                )
+                .subcommand(
+                    Command::new("promote")
+                        .about("Give a user a role (an admin's, by default)")
+                        .arg(user_key_arg())
+                        .arg(
+                            Arg::new("role")
+                                .long("role")
+                                .value_name("ROLE")
+                                .value_parser(["admin", "member", "read_only"])
+                                .default_value("admin")
+                                .help("The role to give"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("todo")
+                .about("Manage todos directly in the database")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .arg(database_url_arg().global(true))
+                .arg(output_arg())
+                .subcommand(
+                    Command::new("list")
+                        .about("List todos")
+                        .arg(
+                            Arg::new("user")
+                                .long("user")
+                                .value_name("USER")
+                                .help("Only list todos of this user (UUID or email)"),
+                        )
+                        .arg(
+                            Arg::new("completed")
+                                .long("completed")
+                                .action(clap::ArgAction::SetTrue)
+                                .conflicts_with("pending")
+                                .help("Only list completed todos"),
+                        )
+                        .arg(
+                            Arg::new("pending")
+                                .long("pending")
+                                .action(clap::ArgAction::SetTrue)
+                                .help("Only list todos that are not completed"),
+                        )
+                        .arg(
+                            Arg::new("tag")
+                                .long("tag")
+                                .value_name("NAME")
+                                .help("Only list todos with this tag"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("complete")
+                        .about("Mark a todo as completed")
+                        .arg(todo_id_arg())
+                        .arg(
+                            Arg::new("cascade")
+                                .long("cascade")
+                                .action(clap::ArgAction::SetTrue)
+                                .help("Also complete all of the todo's subtasks"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("delete")
+                        .about("Delete a todo and its subtasks")
+                        .arg(todo_id_arg()),
+                ),
+        )
+        .subcommand(
+            Command::new("webhook")
+                .about("Manage global webhooks, which get every user's events")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .arg(database_url_arg().global(true))
+                .arg(output_arg())
+                .subcommand(
+                    Command::new("create")
+                        .about("Register a global webhook and print its signing secret")
+                        .arg(Arg::new("url").required(true).value_name("URL"))
+                        .arg(allow_local_webhooks_arg())
+                        .arg(
+                            Arg::new("event")
+                                .long("event")
+                                .short('e')
+                                .value_name("EVENT")
+                                .action(clap::ArgAction::Append)
+                                .default_value("*")
+                                .help("An event to send, eg. todo.created (repeatable; default: all)"),
+                        ),
+                )
+                .subcommand(Command::new("list").about("List global webhooks"))
+                .subcommand(
+                    Command::new("delete")
+                        .about("Delete a global webhook and its deliveries")
+                        .arg(
+                            Arg::new("id")
+                                .required(true)
+                                .value_name("ID")
+                                .help("The webhook's UUID"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("audit")
+                .about("Read the audit log of changes made through the API")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .arg(database_url_arg().global(true))
+                .subcommand(
+                    Command::new("export")
+                        .about("Dump audit log entries, oldest first")
+                        .arg(dump_format_arg().default_value("json"))
+                        .arg(
+                            Arg::new("since")
+                                .long("since")
+                                .value_name("TIME")
+                                .value_parser(parse_time)
+                                .help("Only entries from TIME on (RFC 3339, or a UTC date)"),
+                        )
+                        .arg(
+                            Arg::new("until")
+                                .long("until")
+                                .value_name("TIME")
+                                .value_parser(parse_time)
+                                .help("Only entries before TIME (RFC 3339, or a UTC date)"),
+                        )
+                        .arg(
+                            Arg::new("actor")
+                                .long("actor")
+                                .value_name("EMAIL")
+                                .help("Only changes made by this email"),
+                        )
+                        .arg(
+                            Arg::new("entity")
+                                .long("entity")
+                                .value_name("ENTITY")
+                                .help("Only changes to this kind of record, eg. todo"),
+                        )
+                        .arg(
+                            Arg::new("file")
+                                .long("file")
+                                .short('f')
+                                .value_name("PATH")
+                                .value_parser(value_parser!(std::path::PathBuf))
+                                .help("Write the entries to PATH instead of stdout"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Dump all users and todos, preserving their ids")
+                .arg(database_url_arg())
+                .arg(dump_format_arg().default_value("json"))
+                .arg(
+                    Arg::new("file")
+                        .long("file")
+                        .short('f')
+                        .value_name("PATH")
+                        .value_parser(value_parser!(std::path::PathBuf))
+                        .help("Write the dump to PATH instead of stdout"),
+                ),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Load a dump created by `export` into the database")
+                .arg(database_url_arg())
+                .arg(dump_format_arg().help("Dump format (default: guessed from the file extension)"))
+                .arg(
+                    Arg::new("dry_run")
+                        .long("dry-run")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Validate every record without writing anything"),
+                )
+                .arg(
+                    Arg::new("path")
+                        .required(true)
+                        .value_name("PATH")
+                        .value_parser(value_parser!(std::path::PathBuf))
+                        .help("The dump file to import (- for stdin)"),
+                ),
+        )
+}
+
+fn dump_format_arg() -> Arg {
+    Arg::new("format")
+        .long("format")
+        .value_name("FORMAT")
+        .value_parser(
+            clap::builder::PossibleValuesParser::new(["json", "ndjson"])
+                .map(|s| s.parse::<crate::transfer::Format>().unwrap()),
+        )
+        .help("Dump format")
+}
+
+/// Parse an RFC 3339 timestamp, or a date meaning its midnight in UTC.
+fn parse_time(s: &str) -> Result<chrono::DateTime<chrono::Utc>, String> {
+    if let Ok(date) = s.parse::<chrono::NaiveDate>() {
+        return Ok(date.and_time(chrono::NaiveTime::MIN).and_utc());
+    }
+    s.parse()
+        .map_err(|e| format!("expected an RFC 3339 time or a date: {e}"))
+}
+
+fn output_arg() -> Arg {
+    Arg::new("output")
+        .long("output")
+        .short('o')
+        .global(true)
+        .value_name("FORMAT")
+        .value_parser(["table", "json"])
+        .default_value("table")
+        .help("Output format")
+}
+
+fn user_key_arg() -> Arg {
+    Arg::new("user")
+        .required(true)
+        .value_name("USER")
+        .help("The user's UUID or email address")
+}
+
+fn todo_id_arg() -> Arg {
+    Arg::new("id")
+        .required(true)
+        .value_name("ID")
+        .help("The todo's UUID")
+}
+
+fn allow_local_webhooks_arg() -> Arg {
+    Arg::new("allow_local_webhooks")
+        .long("allow-local-webhooks")
+        .env("ALLOW_LOCAL_WEBHOOKS")
+        .action(clap::ArgAction::SetTrue)
+        .help("Allow webhooks on loopback and private network addresses")
+}
+
+fn database_url_arg() -> Arg {
+    Arg::new("database_url")
+        .long("database-url")
+        .value_name("URL")
+        .env("DATABASE_URL")
+        .default_value(crate::db::DEFAULT_DATABASE_URL)
+        .help("Database URL: sqlite:... or postgres://... (or set DATABASE_URL)")
 }
@@ -0,0 +1,146 @@
+use std::future::Future;
+use std::io::Write;
+
+use serde::Serialize;
+
+pub mod audit;
+pub mod db;
+pub mod todo;
+pub mod transfer;
+pub mod user;
+pub mod webhook;
+
+/// Exit code for a command that succeeded.
+pub const EXIT_OK: i32 = 0;
+/// Exit code for a command that failed.
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit code for a command whose target record does not exist.
+pub const EXIT_NOT_FOUND: i32 = 3;
+
+/// Error returned by commands when the requested record does not exist.
+#[derive(Debug)]
+pub struct NotFound(pub String);
+
+impl std::fmt::Display for NotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "not found: {}", self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// Run a command future on a fresh Tokio runtime and map its result to
+/// an exit code, reporting any error on `err`.
+pub fn block_on<W, F>(err: &mut W, fut: F) -> i32
+where
+    W: Write,
+    F: Future<Output = anyhow::Result<()>>,
+{
+    let rt = match tokio::runtime::Runtime::new() {
+        Ok(rt) => rt,
+        Err(e) => {
+            let _ = writeln!(err, "Failed to start Tokio runtime: {e}");
+            return EXIT_FAILURE;
+        }
+    };
+
+    match rt.block_on(fut) {
+        Ok(()) => EXIT_OK,
+        Err(e) if e.is::<NotFound>() => {
+            let _ = writeln!(err, "{e}");
+            EXIT_NOT_FOUND
+        }
+        Err(e) => {
+            let _ = writeln!(err, "Error: {e:#}");
+            EXIT_FAILURE
+        }
+    }
+}
+
+/// Output format selected with `--output`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        match matches.get_one::<String>("output").map(|s| s.as_str()) {
+            Some("json") => OutputFormat::Json,
+            _ => OutputFormat::Table,
+        }
+    }
+}
+
+/// Records that can be printed as a row of a table.
+pub trait TableRow {
+    fn headers() -> &'static [&'static str];
+    fn cells(&self) -> Vec<String>;
+}
+
+/// Print records in the requested format: an aligned table, or a JSON array.
+pub fn print_records<W: Write, T: TableRow + Serialize>(
+    out: &mut W,
+    format: OutputFormat,
+    records: &[T],
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, records)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Table => {
+            let rows: Vec<Vec<String>> = records.iter().map(|r| r.cells()).collect();
+            let mut widths: Vec<usize> = T::headers().iter().map(|h| h.len()).collect();
+            for row in &rows {
+                for (width, cell) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(cell.chars().count());
+                }
+            }
+            let headers: Vec<String> = T::headers().iter().map(|h| h.to_string()).collect();
+            for row in std::iter::once(&headers).chain(&rows) {
+                let line: Vec<String> = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, width)| format!("{cell:<width$}"))
+                    .collect();
+                writeln!(out, "{}", line.join("  ").trim_end())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print a single record: a one-row table, or a JSON object.
+pub fn print_record<W: Write, T: TableRow + Serialize>(
+    out: &mut W,
+    format: OutputFormat,
+    record: &T,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, record)?;
+            writeln!(out)?;
+            Ok(())
+        }
+        OutputFormat::Table => print_records(out, format, std::slice::from_ref(record)),
+    }
+}
+
+/// Run the CLI with `--database-url` appended, capturing (code, stdout, stderr).
+#[cfg(test)]
+pub fn test_cli(db_url: &str, args: &[&str]) -> (i32, String, String) {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let argv = ["app"]
+        .into_iter()
+        .chain(args.iter().copied())
+        .chain(["--database-url", db_url]);
+    let code = crate::run_cli(argv, &mut out, &mut err);
+    (
+        code,
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+    )
+}
@@ -0,0 +1,138 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+
+use super::block_on;
+use crate::{
+    db::{self, Database},
+    repository::AuditFilter,
+    transfer::{Encoder, Format},
+};
+
+/// Entry point for the `audit` subcommand.
+pub fn run<W1: Write, W2: Write>(
+    sub_matches: &clap::ArgMatches,
+    out: &mut W1,
+    err: &mut W2,
+) -> i32 {
+    let db_url = sub_matches.get_one::<String>("database_url").unwrap();
+    let Some(("export", m)) = sub_matches.subcommand() else {
+        unreachable!("clap requires an audit subcommand");
+    };
+    let format = *m.get_one::<Format>("format").unwrap();
+    let file = m.get_one::<PathBuf>("file");
+    let filter = AuditFilter {
+        actor: m.get_one::<String>("actor").cloned(),
+        entity: m.get_one::<String>("entity").cloned(),
+        since: m.get_one::<DateTime<Utc>>("since").copied(),
+        until: m.get_one::<DateTime<Utc>>("until").copied(),
+        ..Default::default()
+    };
+
+    block_on(err, async {
+        let db = db::connect(db_url).await?;
+        match file {
+            Some(path) => {
+                let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+                export(&db, &filter, format, &mut writer).await?;
+                writer.flush()?;
+            }
+            None => export(&db, &filter, format, out).await?,
+        }
+        Ok(())
+    })
+}
+
+/// Write the entries matching `filter`, oldest first.
+async fn export<W: Write>(
+    db: &Database,
+    filter: &AuditFilter,
+    format: Format,
+    out: &mut W,
+) -> anyhow::Result<()> {
+    let mut encoder = Encoder::new(format);
+    for entry in db.repos.audit.list(filter).await?.iter().rev() {
+        out.write_all(&encoder.record(entry)?)?;
+    }
+    out.write_all(&encoder.finish())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use crate::{
+        commands::{test_cli as cli, EXIT_OK},
+        models::audit::AuditEntry,
+    };
+
+    fn entry(actor: &str, entity: &str, days_ago: i64) -> AuditEntry {
+        AuditEntry {
+            id: 0,
+            at: Utc::now() - Duration::days(days_ago),
+            actor: Some(actor.to_string()),
+            real_actor: None,
+            client_ip: Some("127.0.0.1".to_string()),
+            request_id: None,
+            entity: entity.to_string(),
+            entity_id: "1".to_string(),
+            action: "create".to_string(),
+            before: None,
+            after: Some(r#"{"title":"mow"}"#.to_string()),
+        }
+    }
+
+    #[test]
+    fn audit_export_filters_oldest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_url = format!("sqlite://{}", dir.path().join("test.db").display());
+        assert_eq!(cli(&db_url, &["db", "migrate"]).0, EXIT_OK);
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let db = crate::db::connect(&db_url).await.unwrap();
+            let entries = [
+                entry("a@example.com", "todo", 3),
+                entry("b@example.com", "list", 2),
+                entry("a@example.com", "list", 1),
+            ];
+            db.repos.audit.insert(&entries).await.unwrap();
+        });
+
+        let (code, out, _) = cli(&db_url, &["audit", "export"]);
+        assert_eq!(code, EXIT_OK);
+        let entries: serde_json::Value = serde_json::from_str(&out).unwrap();
+        let ids: Vec<i64> = entries
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["id"].as_i64().unwrap())
+            .collect();
+        assert_eq!(ids, [1, 2, 3]);
+        assert_eq!(entries[0]["after"]["title"], "mow");
+
+        let since = (Utc::now() - Duration::days(2) - Duration::hours(1)).to_rfc3339();
+        let args = [
+            "audit",
+            "export",
+            "--actor",
+            "a@example.com",
+            "--since",
+            &since,
+            "--format",
+            "ndjson",
+        ];
+        let (code, out, _) = cli(&db_url, &args);
+        assert_eq!(code, EXIT_OK);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains(r#""entity":"list""#));
+
+        let (code, out, _) = cli(&db_url, &["audit", "export", "--entity", "tag"]);
+        assert_eq!(code, EXIT_OK);
+        assert_eq!(out, "[]\n");
+        let (code, _, err) = cli(&db_url, &["audit", "export", "--since", "yesterday"]);
+        assert_eq!(code, 2);
+        assert!(err.contains("RFC 3339"));
+    }
+}
@@ -0,0 +1,213 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Context;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+use super::block_on;
+use crate::{backup, db};
+
+/// Entry point for the `db` subcommand.
+pub fn run<W1: Write, W2: Write>(
+    sub_matches: &clap::ArgMatches,
+    out: &mut W1,
+    err: &mut W2,
+) -> i32 {
+    let db_url = sub_matches.get_one::<String>("database_url").unwrap();
+
+    block_on(err, async {
+        match sub_matches.subcommand() {
+            Some(("create", _)) => create(db_url, out).await,
+            Some(("migrate", _)) => migrate(db_url, out).await,
+            Some(("status", _)) => status(db_url, out).await,
+            Some(("revert", m)) => revert(db_url, m.get_one::<i64>("target").copied(), out).await,
+            Some(("backup", m)) => {
+                let path = m.get_one::<PathBuf>("path").unwrap();
+                backup(db_url, path, m.get_flag("compress"), out).await
+            }
+            Some(("restore", m)) => {
+                restore(db_url, m.get_one::<PathBuf>("path").unwrap(), out).await
+            }
+            _ => unreachable!("clap requires a db subcommand"),
+        }
+    })
+}
+
+const NOT_SQLITE: &str =
+    "backup and restore only support SQLite; use pg_dump and pg_restore for PostgreSQL";
+
+async fn create<W: Write>(db_url: &str, out: &mut W) -> anyhow::Result<()> {
+    if db::create(db_url).await? {
+        writeln!(out, "Created database {db_url}")?;
+    } else {
+        writeln!(out, "Database already exists: {db_url}")?;
+    }
+    Ok(())
+}
+
+async fn migrate<W: Write>(db_url: &str, out: &mut W) -> anyhow::Result<()> {
+    let db = db::connect(db_url).await?;
+    let pending = db
+        .status()
+        .await?
+        .into_iter()
+        .filter(|m| !m.applied)
+        .count();
+    db.migrate().await?;
+    writeln!(out, "Applied {pending} migration(s)")?;
+    Ok(())
+}
+
+async fn status<W: Write>(db_url: &str, out: &mut W) -> anyhow::Result<()> {
+    let db = db::connect(db_url).await?;
+    for m in db.status().await? {
+        let state = if m.applied { "applied" } else { "pending" };
+        let reversible = if m.reversible { "" } else { " (irreversible)" };
+        writeln!(
+            out,
+            "{:04} {:<8} {}{reversible}",
+            m.version, state, m.description
+        )?;
+    }
+    Ok(())
+}
+
+async fn revert<W: Write>(db_url: &str, target: Option<i64>, out: &mut W) -> anyhow::Result<()> {
+    let db = db::connect(db_url).await?;
+    let reverted = db.revert(target).await?;
+    if reverted.is_empty() {
+        writeln!(out, "No migrations to revert")?;
+    }
+    for version in reverted {
+        writeln!(out, "Reverted migration {version:04}")?;
+    }
+    Ok(())
+}
+
+async fn backup<W: Write>(
+    db_url: &str,
+    path: &Path,
+    compress: bool,
+    out: &mut W,
+) -> anyhow::Result<()> {
+    if db::is_postgres(db_url) {
+        anyhow::bail!(NOT_SQLITE);
+    }
+    // Not through db::connect, which would create a missing database (and
+    // back up an empty one).
+    let options = SqliteConnectOptions::from_str(db_url)?
+        .create_if_missing(false)
+        .busy_timeout(db::DbConfig::new(db_url).busy_timeout);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .with_context(|| format!("opening database {db_url}"))?;
+    let backed_up = backup::backup(&pool, path, compress).await;
+    pool.close().await;
+    backed_up?;
+    writeln!(out, "Backed up {db_url} to {}", path.display())?;
+    Ok(())
+}
+
+async fn restore<W: Write>(db_url: &str, path: &Path, out: &mut W) -> anyhow::Result<()> {
+    if db::is_postgres(db_url) {
+        anyhow::bail!(NOT_SQLITE);
+    }
+    backup::restore(path, db_url).await?;
+    writeln!(out, "Restored {db_url} from {}", path.display())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run_cli;
+
+    fn db_cli(db_url: &str, args: &[&str]) -> (i32, String) {
+        let mut out = Vec::new();
+        let mut err = Vec::new();
+        let argv = ["app", "db", "--database-url", db_url]
+            .into_iter()
+            .chain(args.iter().copied());
+        let code = run_cli(argv, &mut out, &mut err);
+        assert!(
+            err.is_empty(),
+            "expected no stderr output, got: {}",
+            String::from_utf8_lossy(&err)
+        );
+        (code, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn create_migrate_status_revert() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_url = format!("sqlite://{}", dir.path().join("test.db").display());
+
+        let (code, out) = db_cli(&db_url, &["create"]);
+        assert_eq!(code, 0);
+        assert!(out.starts_with("Created database"), "{out}");
+        let (_, out) = db_cli(&db_url, &["create"]);
+        assert!(out.starts_with("Database already exists"), "{out}");
+
+        let (_, out) = db_cli(&db_url, &["status"]);
+        assert!(out.contains("0001 pending  init"), "{out}");
+
+        let (code, _) = db_cli(&db_url, &["migrate"]);
+        assert_eq!(code, 0);
+        let (_, out) = db_cli(&db_url, &["status"]);
+        assert!(out.contains("0001 applied  init"), "{out}");
+
+        // Only the latest migration is reverted by default:
+        let (code, out) = db_cli(&db_url, &["revert"]);
+        assert_eq!(code, 0);
+        assert!(!out.contains("Reverted migration 0001"), "{out}");
+        let (_, out) = db_cli(&db_url, &["status"]);
+        assert!(out.contains("0001 applied  init"), "{out}");
+
+        let (code, out) = db_cli(&db_url, &["revert", "--target", "0"]);
+        assert_eq!(code, 0);
+        assert!(out.contains("Reverted migration 0001"), "{out}");
+        let (_, out) = db_cli(&db_url, &["status"]);
+        assert!(out.contains("0001 pending  init"), "{out}");
+    }
+
+    #[test]
+    fn backup_and_restore_compressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_url = format!("sqlite://{}", dir.path().join("test.db").display());
+        let backup_path = dir.path().join("backup.db.gz");
+        let backup_arg = backup_path.to_str().unwrap();
+
+        let (code, _) = db_cli(&db_url, &["migrate"]);
+        assert_eq!(code, 0);
+        let (code, _) = db_cli(&db_url, &["backup", "--compress", backup_arg]);
+        assert_eq!(code, 0);
+        assert!(backup_path.exists());
+
+        // Drop the schema, then bring it back from the backup:
+        let (code, _) = db_cli(&db_url, &["revert"]);
+        assert_eq!(code, 0);
+        let (code, out) = db_cli(&db_url, &["restore", backup_arg]);
+        assert_eq!(code, 0, "{out}");
+        let (_, out) = db_cli(&db_url, &["status"]);
+        assert!(out.contains("0001 applied  init"), "{out}");
+    }
+
+    #[test]
+    fn backup_requires_an_existing_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("missing.db");
+        let db_url = format!("sqlite://{}", db_path.display());
+        let backup_path = dir.path().join("backup.db");
+        let argv = ["app", "db", "--database-url", &db_url, "backup"]
+            .into_iter()
+            .chain([backup_path.to_str().unwrap()]);
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        assert_ne!(run_cli(argv, &mut out, &mut err), 0);
+        let err = String::from_utf8(err).unwrap();
+        assert!(err.contains("opening database"), "{err}");
+        assert!(!db_path.exists());
+        assert!(!backup_path.exists());
+    }
+}
@@ -0,0 +1,166 @@
+use std::io::Write;
+
+use uuid::Uuid;
+
+use super::{block_on, print_record, print_records, NotFound, OutputFormat, TableRow};
+use crate::{
+    db,
+    models::{ids::TodoId, todo::Todo, webhook::Event},
+    prelude::*,
+    recurrence,
+    repository::{TagRepository, TodoFilter, TodoRepository, WebhookRepository},
+    webhook,
+};
+
+/// Entry point for the `todo` subcommand.
+pub fn run<W1: Write, W2: Write>(
+    sub_matches: &clap::ArgMatches,
+    out: &mut W1,
+    err: &mut W2,
+) -> i32 {
+    let db_url = sub_matches.get_one::<String>("database_url").unwrap();
+    let format = OutputFormat::from_matches(sub_matches);
+
+    block_on(err, async {
+        let db = db::connect(db_url).await?;
+        let todos = db.repos.todos.clone();
+        let todos = todos.as_ref();
+        let todo = match sub_matches.subcommand() {
+            Some(("list", m)) => {
+                let user_id = match m.get_one::<String>("user") {
+                    Some(key) => Some(super::user::find(db.repos.users.as_ref(), key).await?.id),
+                    None => None,
+                };
+                let completed = if m.get_flag("completed") {
+                    Some(true)
+                } else if m.get_flag("pending") {
+                    Some(false)
+                } else {
+                    None
+                };
+                let tag = m.get_one::<String>("tag").cloned();
+                let todos = todos
+                    .list(TodoFilter {
+                        user_id,
+                        completed,
+                        tag,
+                        ..Default::default()
+                    })
+                    .await?;
+                return print_records(out, format, &todos);
+            }
+            Some(("complete", m)) => {
+                complete(
+                    todos,
+                    db.repos.tags.as_ref(),
+                    db.repos.webhooks.as_ref(),
+                    m.get_one::<String>("id").unwrap(),
+                    m.get_flag("cascade"),
+                )
+                .await?
+            }
+            Some(("delete", m)) => {
+                let todo = delete(todos, m.get_one::<String>("id").unwrap()).await?;
+                webhook::notify(
+                    db.repos.webhooks.as_ref(),
+                    todo.user_id,
+                    Event::TodoDeleted,
+                    &todo,
+                )
+                .await;
+                todo
+            }
+            _ => unreachable!("clap requires a todo subcommand"),
+        };
+        print_record(out, format, &todo)
+    })
+}
+
+impl TableRow for Todo {
+    fn headers() -> &'static [&'static str] {
+        &["ID", "USER ID", "DONE", "DUE", "TITLE"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.id.0.to_string(),
+            self.user_id.0.to_string(),
+            if self.completed { "x" } else { "" }.to_string(),
+            self.due_at.map(|d| d.to_rfc3339()).unwrap_or_default(),
+            self.title.clone(),
+        ]
+    }
+}
+
+fn parse_id(key: &str) -> anyhow::Result<TodoId> {
+    Ok(TodoId(
+        Uuid::from_str(key).map_err(|_| NotFound(format!("todo '{key}'")))?,
+    ))
+}
+
+/// Complete a todo, creating its next occurrence if it recurs.
+async fn complete(
+    todos: &dyn TodoRepository,
+    tags: &dyn TagRepository,
+    webhooks: &dyn WebhookRepository,
+    key: &str,
+    cascade: bool,
+) -> anyhow::Result<Todo> {
+    let id = parse_id(key)?;
+    let before = todos
+        .get(id)
+        .await?
+        .ok_or_else(|| NotFound(format!("todo '{key}'")))?;
+    if before.completed && !cascade {
+        return Ok(before);
+    }
+    let version = Some(before.version);
+    let todo = if cascade {
+        todos.set_subtree_completed(id, true, version).await?
+    } else {
+        todos.set_completed(id, true, version).await?
+    };
+    let todo = todo.ok_or_else(|| anyhow::anyhow!("todo '{key}' changed meanwhile; try again"))?;
+    if before.completed {
+        return Ok(todo);
+    }
+    webhook::notify(webhooks, todo.user_id, Event::TodoCompleted, &todo).await;
+    if let Some(next) = recurrence::schedule_next(todos, tags, &todo).await? {
+        webhook::notify(webhooks, next.user_id, Event::TodoCreated, &next).await;
+        info!(
+            "Created the next occurrence {} due {}",
+            next.id.0,
+            next.due_at.map(|d| d.to_rfc3339()).unwrap_or_default()
+        );
+    }
+    Ok(todo)
+}
+
+async fn delete(todos: &dyn TodoRepository, key: &str) -> anyhow::Result<Todo> {
+    let id = parse_id(key)?;
+    let todo = todos
+        .get(id)
+        .await?
+        .ok_or_else(|| NotFound(format!("todo '{key}'")))?;
+    todos.delete(id, None).await?;
+    Ok(todo)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::{test_cli as cli, EXIT_NOT_FOUND, EXIT_OK};
+
+    #[test]
+    fn todo_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_url = format!("sqlite://{}", dir.path().join("test.db").display());
+        assert_eq!(cli(&db_url, &["db", "migrate"]).0, EXIT_OK);
+
+        let (code, out, _) = cli(&db_url, &["todo", "list", "-o", "json"]);
+        assert_eq!(code, EXIT_OK);
+        assert_eq!(out.trim(), "[]");
+
+        let (code, _, _) = cli(&db_url, &["todo", "complete", "not-a-uuid"]);
+        assert_eq!(code, EXIT_NOT_FOUND);
+    }
+}
@@ -0,0 +1,212 @@
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use futures_util::TryStreamExt;
+
+use super::block_on;
+use crate::{
+    db::{self, Database},
+    models::todo::Subtask,
+    repository::TodoFilter,
+    transfer::{decode, DumpRecord, Encoder, Format, ImportReport, RowError},
+};
+
+/// Entry point for the `export` subcommand.
+pub fn export<W1: Write, W2: Write>(
+    sub_matches: &clap::ArgMatches,
+    out: &mut W1,
+    err: &mut W2,
+) -> i32 {
+    let db_url = sub_matches.get_one::<String>("database_url").unwrap();
+    let format = *sub_matches.get_one::<Format>("format").unwrap();
+    let file = sub_matches.get_one::<PathBuf>("file");
+
+    block_on(err, async {
+        let db = db::connect(db_url).await?;
+        match file {
+            Some(path) => {
+                let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+                dump(&db, format, &mut writer).await?;
+                writer.flush()?;
+            }
+            None => dump(&db, format, out).await?,
+        }
+        Ok(())
+    })
+}
+
+/// Entry point for the `import` subcommand.
+pub fn import<W1: Write, W2: Write>(
+    sub_matches: &clap::ArgMatches,
+    out: &mut W1,
+    err: &mut W2,
+) -> i32 {
+    let db_url = sub_matches.get_one::<String>("database_url").unwrap();
+    let path = sub_matches.get_one::<PathBuf>("path").unwrap();
+    let dry_run = sub_matches.get_flag("dry_run");
+    let format = match sub_matches.get_one::<Format>("format") {
+        Some(f) => *f,
+        None if path.extension().is_some_and(|ext| ext == "ndjson") => Format::Ndjson,
+        None => Format::Json,
+    };
+
+    let mut report = None;
+    let code = block_on(err, async {
+        let mut data = Vec::new();
+        if path.as_os_str() == "-" {
+            std::io::stdin().read_to_end(&mut data)?;
+        } else {
+            std::fs::File::open(path)?.read_to_end(&mut data)?;
+        }
+        let db = db::connect(db_url).await?;
+        let result = load(&db, format, &data, dry_run).await?;
+        if result.errors.is_empty() {
+            let verb = if dry_run { "Validated" } else { "Imported" };
+            writeln!(out, "{verb} {} record(s)", result.rows)?;
+        }
+        report = Some(result);
+        Ok(())
+    });
+
+    match report {
+        Some(report) if !report.errors.is_empty() => {
+            for e in &report.errors {
+                let _ = writeln!(err, "row {}: {}", e.row, e.error);
+            }
+            let _ = writeln!(
+                err,
+                "Import aborted: {} invalid row(s)",
+                report.errors.len()
+            );
+            super::EXIT_FAILURE
+        }
+        _ => code,
+    }
+}
+
+/// Write every user, then every list, todo, subtask, tag and tagging, as
+/// dump records.
+async fn dump<W: Write>(db: &Database, format: Format, out: &mut W) -> anyhow::Result<()> {
+    let mut encoder = Encoder::new(format);
+
+    let mut users = db.repos.users.list().await?;
+    users.sort_by_key(|u| u.created_at);
+    for user in users {
+        out.write_all(&encoder.record(&DumpRecord::User(user))?)?;
+    }
+
+    for list in db.repos.lists.list_all().await? {
+        out.write_all(&encoder.record(&DumpRecord::List(list))?)?;
+    }
+
+    // Parents are written after all of the todos, since a todo may have
+    // been created in the same second as its parent:
+    let mut subtasks = Vec::new();
+    let mut todos = db.repos.todos.stream(TodoFilter::default());
+    while let Some(mut todo) = todos.try_next().await? {
+        if let Some(parent_id) = todo.parent_id.take() {
+            subtasks.push(Subtask {
+                todo_id: todo.id,
+                parent_id,
+            });
+        }
+        out.write_all(&encoder.record(&DumpRecord::Todo(todo))?)?;
+    }
+    for link in subtasks {
+        out.write_all(&encoder.record(&DumpRecord::Subtask(link))?)?;
+    }
+
+    let tags = &db.repos.tags;
+    for tag in tags.list_all().await? {
+        out.write_all(&encoder.record(&DumpRecord::Tag(tag))?)?;
+    }
+    for link in tags.list_links().await? {
+        out.write_all(&encoder.record(&DumpRecord::TodoTag(link))?)?;
+    }
+
+    out.write_all(&encoder.finish())?;
+    Ok(())
+}
+
+/// Insert dump records, preserving their ids, in a single transaction.
+async fn load(
+    db: &Database,
+    format: Format,
+    data: &[u8],
+    dry_run: bool,
+) -> anyhow::Result<ImportReport> {
+    let rows = decode::<DumpRecord>(format, data).map_err(|e| anyhow::anyhow!(e))?;
+    let total = rows.len();
+
+    let mut errors = Vec::new();
+    let mut records = Vec::new();
+    // The row number of each entry in `records`:
+    let mut record_rows = Vec::new();
+    for (i, row) in rows.into_iter().enumerate() {
+        match row {
+            Ok(record) => {
+                records.push(record);
+                record_rows.push(i + 1);
+            }
+            Err(error) => errors.push(RowError { row: i + 1, error }),
+        }
+    }
+
+    let failed = db
+        .load_dump(&records, dry_run || !errors.is_empty())
+        .await?;
+    errors.extend(failed.into_iter().map(|(i, error)| RowError {
+        row: record_rows[i],
+        error,
+    }));
+    errors.sort_by_key(|e| e.row);
+
+    let commit = errors.is_empty() && !dry_run;
+    Ok(ImportReport {
+        dry_run,
+        rows: total,
+        imported: if commit { total } else { 0 },
+        errors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::{test_cli as cli, EXIT_FAILURE, EXIT_OK};
+
+    #[test]
+    fn export_then_import_preserves_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = format!("sqlite://{}", dir.path().join("source.db").display());
+        let target = format!("sqlite://{}", dir.path().join("target.db").display());
+        let dump = dir.path().join("dump.ndjson");
+        let dump_arg = dump.to_str().unwrap();
+
+        assert_eq!(cli(&source, &["db", "migrate"]).0, EXIT_OK);
+        assert_eq!(cli(&target, &["db", "migrate"]).0, EXIT_OK);
+        let (_, created, _) = cli(
+            &source,
+            &["user", "create", "a@example.com", "A", "-o", "json"],
+        );
+
+        let (code, _, _) = cli(
+            &source,
+            &["export", "--format", "ndjson", "--file", dump_arg],
+        );
+        assert_eq!(code, EXIT_OK);
+
+        let (code, out, _) = cli(&target, &["import", "--dry-run", dump_arg]);
+        assert_eq!(code, EXIT_OK);
+        // The user and their inbox list:
+        assert!(out.starts_with("Validated 2 record"), "{out}");
+        assert_eq!(cli(&target, &["import", dump_arg]).0, EXIT_OK);
+
+        let (_, imported, _) = cli(&target, &["user", "show", "a@example.com", "-o", "json"]);
+        assert_eq!(created, imported);
+
+        // Importing again conflicts on every row:
+        let (code, _, err) = cli(&target, &["import", dump_arg]);
+        assert_eq!(code, EXIT_FAILURE);
+        assert!(err.contains("row 1:"), "{err}");
+    }
+}
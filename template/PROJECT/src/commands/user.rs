@@ -0,0 +1,368 @@
+use std::io::Write;
+
+use serde_json::json;
+use uuid::Uuid;
+
+use super::{block_on, print_record, print_records, NotFound, OutputFormat, TableRow};
+use crate::{
+    audit::{self, Change},
+    db,
+    extract::Origin,
+    models::{
+        audit::Entity,
+        ids::UserId,
+        user::{PublicUser, Role, User},
+        webhook::Event,
+    },
+    password,
+    prelude::*,
+    repository::{TrashRepository, UserRepository},
+    token, webhook,
+};
+
+/// Length of the passwords `user reset-password` makes up.
+const RESET_PASSWORD_LEN: usize = 20;
+
+/// Entry point for the `user` subcommand.
+pub fn run<W1: Write, W2: Write>(
+    sub_matches: &clap::ArgMatches,
+    out: &mut W1,
+    err: &mut W2,
+) -> i32 {
+    let db_url = sub_matches.get_one::<String>("database_url").unwrap();
+    let format = OutputFormat::from_matches(sub_matches);
+
+    block_on(err, async {
+        let db = db::connect(db_url).await?;
+        let users = db.repos.users;
+        let users = users.as_ref();
+        let (user, event, change) = match sub_matches.subcommand() {
+            Some(("create", m)) => {
+                let email = m.get_one::<String>("email").unwrap();
+                let display_name = m.get_one::<String>("display_name").unwrap();
+                let user = PublicUser::from(users.insert(&User::new(email, display_name)).await?);
+                let change = Change::created(Entity::User, user.id.0, &user);
+                (user, Some(Event::UserCreated), Some(change))
+            }
+            Some(("list", _)) => {
+                let users: Vec<PublicUser> =
+                    users.list().await?.into_iter().map(Into::into).collect();
+                return print_records(out, format, &users);
+            }
+            Some(("show", m)) => (
+                find(users, m.get_one::<String>("user").unwrap())
+                    .await?
+                    .into(),
+                None,
+                None,
+            ),
+            Some(("delete", m)) => {
+                let user =
+                    PublicUser::from(delete(users, m.get_one::<String>("user").unwrap()).await?);
+                let change = Change::deleted(Entity::User, user.id.0, &user);
+                (user, Some(Event::UserDeleted), Some(change))
+            }
+            Some(("restore", m)) => {
+                let user = m.get_one::<String>("user").unwrap();
+                let user = PublicUser::from(restore(users, db.repos.trash.as_ref(), user).await?);
+                let change = Change::restored(Entity::User, user.id.0, &user);
+                (user, Some(Event::UserRestored), Some(change))
+            }
+            Some(("set-display-name", m)) => {
+                let user = m.get_one::<String>("user").unwrap();
+                let display_name = m.get_one::<String>("display_name").unwrap();
+                let (before, after) = set_display_name(users, user, display_name).await?;
+                updated(before, after)
+            }
+            Some(("set-password", m)) => {
+                let mut line = String::new();
+                std::io::stdin().read_line(&mut line)?;
+                let new_password = line.trim_end_matches(['\r', '\n']);
+                password::validate(new_password).map_err(anyhow::Error::msg)?;
+                let user = m.get_one::<String>("user").unwrap();
+                let user = set_password(users, user, Some(new_password)).await?;
+                let change = password_change(&user, true);
+                (user.into(), None, Some(change))
+            }
+            Some(("reset-password", m)) => {
+                let new_password = token::generate()[..RESET_PASSWORD_LEN].to_string();
+                let user = m.get_one::<String>("user").unwrap();
+                let user = set_password(users, user, Some(&new_password)).await?;
+                let change = password_change(&user, true);
+                audit::write(db.repos.audit.as_ref(), &Origin::cli(), [change]).await?;
+                writeln!(out, "{new_password}")?;
+                return Ok(());
+            }
+            Some(("clear-password", m)) => {
+                let user = m.get_one::<String>("user").unwrap();
+                let user = set_password(users, user, None).await?;
+                let change = password_change(&user, false);
+                (user.into(), None, Some(change))
+            }
+            Some(("promote", m)) => {
+                let user = m.get_one::<String>("user").unwrap();
+                let role = m.get_one::<String>("role").unwrap();
+                let role = role.parse().map_err(anyhow::Error::msg)?;
+                let (before, after) = set_role(users, user, role).await?;
+                updated(before, after)
+            }
+            _ => unreachable!("clap requires a user subcommand"),
+        };
+        audit::write(db.repos.audit.as_ref(), &Origin::cli(), change).await?;
+        if let Some(event) = event {
+            webhook::notify(db.repos.webhooks.as_ref(), user.id, event, &user).await;
+        }
+        print_record(out, format, &user)
+    })
+}
+
+/// What an update to a user prints, sends and audits.
+fn updated(before: User, after: User) -> (PublicUser, Option<Event>, Option<Change>) {
+    let (before, after) = (PublicUser::from(before), PublicUser::from(after));
+    let change = Change::updated(Entity::User, after.id.0, &before, &after);
+    (after, Some(Event::UserUpdated), Some(change))
+}
+
+/// Setting (or replacing) a user's password, or clearing it.
+fn password_change(user: &User, set: bool) -> Change {
+    let password = json!({"user_id": user.id});
+    if set {
+        Change::created(Entity::Password, user.id.0, &password)
+    } else {
+        Change::deleted(Entity::Password, user.id.0, &password)
+    }
+}
+
+impl TableRow for PublicUser {
+    fn headers() -> &'static [&'static str] {
+        &["ID", "EMAIL", "DISPLAY NAME", "ROLE", "CREATED"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.id.0.to_string(),
+            self.email.clone(),
+            self.display_name.clone(),
+            self.role.to_string(),
+            self.created_at.to_rfc3339(),
+        ]
+    }
+}
+
+/// Look up a user by UUID or email address.
+pub(super) async fn find(users: &dyn UserRepository, key: &str) -> anyhow::Result<User> {
+    let user = match Uuid::from_str(key) {
+        Ok(id) => users.get(UserId(id)).await?,
+        Err(_) => None,
+    };
+    let user = match user {
+        Some(user) => Some(user),
+        None => users.find_by_email(key).await?,
+    };
+    user.ok_or_else(|| NotFound(format!("user '{key}'")).into())
+}
+
+async fn delete(users: &dyn UserRepository, key: &str) -> anyhow::Result<User> {
+    let user = find(users, key).await?;
+    if !users.delete(user.id, None).await? {
+        return Err(NotFound(format!("user '{key}'")).into());
+    }
+    Ok(user)
+}
+
+/// Restore a trashed user, found by UUID or email address.
+async fn restore(
+    users: &dyn UserRepository,
+    trash: &dyn TrashRepository,
+    key: &str,
+) -> anyhow::Result<User> {
+    let user = trash
+        .users()
+        .await?
+        .into_iter()
+        .find(|u| u.id.0.to_string() == key || u.email == key)
+        .ok_or_else(|| NotFound(format!("user '{key}' in the trash")))?;
+    if users.find_by_email(&user.email).await?.is_some() {
+        anyhow::bail!(
+            "{} has been registered again since; delete that user first",
+            user.email
+        );
+    }
+    trash
+        .restore_user(user.id)
+        .await?
+        .ok_or_else(|| NotFound(format!("user '{key}' in the trash")).into())
+}
+
+/// The user before and after changing their display name.
+async fn set_display_name(
+    users: &dyn UserRepository,
+    key: &str,
+    display_name: &str,
+) -> anyhow::Result<(User, User)> {
+    let user = find(users, key).await?;
+    let updated = users
+        .set_display_name(user.id, display_name, None)
+        .await?
+        .ok_or_else(|| NotFound(format!("user '{key}'")))?;
+    Ok((user, updated))
+}
+
+/// The user before and after changing their role.
+async fn set_role(
+    users: &dyn UserRepository,
+    key: &str,
+    role: Role,
+) -> anyhow::Result<(User, User)> {
+    let user = find(users, key).await?;
+    let updated = users
+        .set_role(user.id, role)
+        .await?
+        .ok_or_else(|| NotFound(format!("user '{key}'")))?;
+    Ok((user, updated))
+}
+
+/// Set or remove a user's password, and end their sessions.
+async fn set_password(
+    users: &dyn UserRepository,
+    key: &str,
+    new_password: Option<&str>,
+) -> anyhow::Result<User> {
+    let user = find(users, key).await?;
+    let password_hash = match new_password {
+        Some(new_password) => Some(password::hash(new_password).await?),
+        None => None,
+    };
+    if !users
+        .set_password_hash(user.id, password_hash.as_deref())
+        .await?
+    {
+        return Err(NotFound(format!("user '{key}'")).into());
+    }
+    users.delete_sessions(&user.email).await?;
+    Ok(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::{test_cli as cli, EXIT_NOT_FOUND, EXIT_OK};
+    use crate::models::ids::WebhookId;
+
+    #[test]
+    fn user_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_url = format!("sqlite://{}", dir.path().join("test.db").display());
+        assert_eq!(cli(&db_url, &["db", "migrate"]).0, EXIT_OK);
+
+        let (code, out, _) = cli(
+            &db_url,
+            &["user", "create", "alice@example.com", "Alice", "-o", "json"],
+        );
+        assert_eq!(code, EXIT_OK);
+        let created: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(created["email"], "alice@example.com");
+
+        let (code, out, _) = cli(&db_url, &["user", "list"]);
+        assert_eq!(code, EXIT_OK);
+        assert!(out.starts_with("ID"), "{out}");
+        assert!(out.contains("alice@example.com"), "{out}");
+
+        let id = created["id"].as_str().unwrap();
+        let (code, out, _) = cli(
+            &db_url,
+            &[
+                "user",
+                "set-display-name",
+                id,
+                "Alice A.",
+                "--output",
+                "json",
+            ],
+        );
+        assert_eq!(code, EXIT_OK);
+        let updated: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(updated["display_name"], "Alice A.");
+        assert_eq!(updated["role"], "member");
+
+        let (code, out, _) = cli(&db_url, &["user", "promote", id, "-o", "json"]);
+        assert_eq!(code, EXIT_OK);
+        let promoted: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(promoted["role"], "admin");
+        let (code, out, _) = cli(
+            &db_url,
+            &["user", "promote", id, "--role", "read_only", "-o", "json"],
+        );
+        assert_eq!(code, EXIT_OK);
+        let demoted: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(demoted["role"], "read_only");
+
+        let (code, out, _) = cli(&db_url, &["user", "reset-password", "alice@example.com"]);
+        assert_eq!(code, EXIT_OK);
+        assert_eq!(out.trim().len(), 20, "{out}");
+        assert_eq!(
+            cli(&db_url, &["user", "clear-password", "alice@example.com"]).0,
+            EXIT_OK
+        );
+        assert_eq!(
+            cli(&db_url, &["user", "reset-password", "bob@example.com"]).0,
+            EXIT_NOT_FOUND
+        );
+
+        assert_eq!(
+            cli(&db_url, &["user", "delete", "alice@example.com"]).0,
+            EXIT_OK
+        );
+        let (code, _, err) = cli(&db_url, &["user", "show", "alice@example.com"]);
+        assert_eq!(code, EXIT_NOT_FOUND);
+        assert!(err.contains("not found"), "{err}");
+
+        let hook = [
+            "webhook",
+            "create",
+            "https://203.0.113.7/hook",
+            "-e",
+            "user.restored",
+        ];
+        let (code, out, _) = cli(&db_url, &[&hook[..], &["-o", "json"]].concat());
+        assert_eq!(code, EXIT_OK);
+        let hook: serde_json::Value = serde_json::from_str(&out).unwrap();
+        let hook_id = WebhookId(hook["id"].as_str().unwrap().parse().unwrap());
+
+        assert_eq!(cli(&db_url, &["user", "restore", id]).0, EXIT_OK);
+        assert_eq!(
+            cli(&db_url, &["user", "show", "alice@example.com"]).0,
+            EXIT_OK
+        );
+        assert_eq!(cli(&db_url, &["user", "restore", id]).0, EXIT_NOT_FOUND);
+        let deliveries = tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let db = crate::db::connect(&db_url).await.unwrap();
+            db.repos.webhooks.deliveries(hook_id, 10).await.unwrap()
+        });
+        let events: Vec<&str> = deliveries.iter().map(|d| d.event.as_str()).collect();
+        assert_eq!(events, ["user.restored"]);
+
+        let (code, out, _) = cli(&db_url, &["audit", "export", "--actor", "cli"]);
+        assert_eq!(code, EXIT_OK);
+        let entries: serde_json::Value = serde_json::from_str(&out).unwrap();
+        let changes: Vec<String> = entries
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| format!("{} {}", e["action"], e["entity"]).replace('"', ""))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                "create user",
+                "update user",
+                "update user",
+                "update user",
+                "create password",
+                "delete password",
+                "delete user",
+                "restore user",
+            ]
+        );
+        assert_eq!(entries[2]["after"]["role"], "admin");
+    }
+}
@@ -0,0 +1,151 @@
+use std::io::Write;
+
+use uuid::Uuid;
+
+use super::{block_on, print_record, print_records, NotFound, OutputFormat, TableRow};
+use crate::{
+    db,
+    models::{
+        ids::WebhookId,
+        webhook::{normalize_webhook_url, EventFilter, NewWebhook, Webhook},
+    },
+    prelude::*,
+    token, webhook,
+};
+
+/// Entry point for the `webhook` subcommand.
+pub fn run<W1: Write, W2: Write>(
+    sub_matches: &clap::ArgMatches,
+    out: &mut W1,
+    err: &mut W2,
+) -> i32 {
+    let db_url = sub_matches.get_one::<String>("database_url").unwrap();
+    let format = OutputFormat::from_matches(sub_matches);
+
+    block_on(err, async {
+        let webhooks = db::connect(db_url).await?.repos.webhooks;
+        match sub_matches.subcommand() {
+            Some(("create", m)) => {
+                let url = normalize_webhook_url(m.get_one::<String>("url").unwrap())
+                    .map_err(anyhow::Error::msg)?;
+                let events: Vec<&String> = m.get_many::<String>("event").unwrap().collect();
+                let events = EventFilter::new(&events).map_err(anyhow::Error::msg)?;
+                webhook::check_destination(&url, m.get_flag("allow_local_webhooks"))
+                    .await
+                    .map_err(anyhow::Error::msg)?;
+                let secret = token::generate();
+                let webhook = webhooks
+                    .insert(&Webhook::new(None, &url, events, &secret))
+                    .await?;
+                print_record(out, format, &NewWebhook { webhook, secret })
+            }
+            Some(("list", _)) => print_records(out, format, &webhooks.list(None).await?),
+            Some(("delete", m)) => {
+                let key = m.get_one::<String>("id").unwrap();
+                let not_found = || NotFound(format!("webhook '{key}'"));
+                let id = WebhookId(Uuid::from_str(key).map_err(|_| not_found())?);
+                let webhook = match webhooks.get(id).await? {
+                    Some(webhook) if webhook.user_id.is_none() => webhook,
+                    _ => return Err(not_found().into()),
+                };
+                webhooks.delete(id).await?;
+                print_record(out, format, &webhook)
+            }
+            _ => unreachable!("clap requires a webhook subcommand"),
+        }
+    })
+}
+
+impl TableRow for Webhook {
+    fn headers() -> &'static [&'static str] {
+        &["ID", "URL", "EVENTS", "CREATED"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.id.0.to_string(),
+            self.url.clone(),
+            self.events.to_string(),
+            self.created_at.to_rfc3339(),
+        ]
+    }
+}
+
+impl TableRow for NewWebhook {
+    fn headers() -> &'static [&'static str] {
+        &["ID", "URL", "EVENTS", "SECRET"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.webhook.id.0.to_string(),
+            self.webhook.url.clone(),
+            self.webhook.events.to_string(),
+            self.secret.clone(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::{test_cli as cli, EXIT_FAILURE, EXIT_NOT_FOUND, EXIT_OK};
+
+    #[test]
+    fn webhook_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_url = format!("sqlite://{}", dir.path().join("test.db").display());
+        assert_eq!(cli(&db_url, &["db", "migrate"]).0, EXIT_OK);
+
+        let (code, out, _) = cli(
+            &db_url,
+            &[
+                "webhook",
+                "create",
+                "https://203.0.113.7/todo",
+                "--event",
+                "todo.created",
+                "-e",
+                "todo.completed",
+                "-o",
+                "json",
+            ],
+        );
+        assert_eq!(code, EXIT_OK);
+        let webhook: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(
+            webhook["events"],
+            serde_json::json!(["todo.created", "todo.completed"])
+        );
+        assert_eq!(webhook["secret"].as_str().unwrap().len(), 64);
+        let id = webhook["id"].as_str().unwrap();
+
+        let (code, out, _) = cli(&db_url, &["webhook", "list"]);
+        assert_eq!(code, EXIT_OK);
+        assert!(out.contains(id));
+        assert!(!out.contains(webhook["secret"].as_str().unwrap()));
+
+        let (code, _, err) = cli(
+            &db_url,
+            &[
+                "webhook",
+                "create",
+                "https://203.0.113.7",
+                "-e",
+                "todo.eaten",
+            ],
+        );
+        assert_eq!(code, EXIT_FAILURE);
+        assert!(err.contains("unknown event"));
+        let (code, _, _) = cli(&db_url, &["webhook", "create", "ftp://x.example"]);
+        assert_eq!(code, EXIT_FAILURE);
+        let local = "http://127.0.0.1:8080/hook";
+        let (code, _, err) = cli(&db_url, &["webhook", "create", local]);
+        assert_eq!(code, EXIT_FAILURE);
+        assert!(err.contains("local or private"));
+        let allowed = ["webhook", "create", local, "--allow-local-webhooks"];
+        assert_eq!(cli(&db_url, &allowed).0, EXIT_OK);
+
+        assert_eq!(cli(&db_url, &["webhook", "delete", id]).0, EXIT_OK);
+        assert_eq!(cli(&db_url, &["webhook", "delete", id]).0, EXIT_NOT_FOUND);
+    }
+}
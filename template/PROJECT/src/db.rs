@@ -0,0 +1,562 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use sqlx::{
+    migrate::{Migrate, MigrateDatabase, Migrator},
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
+    Sqlite, SqlitePool,
+};
+#[cfg(feature = "postgres")]
+use sqlx::{postgres::PgPoolOptions, PgPool, Postgres};
+
+#[cfg(feature = "postgres")]
+use crate::repository::postgres::PgRepository;
+use crate::{
+    prelude::*,
+    repository::{
+        memory::MemoryRepository, sqlite::SqliteRepository, AuditRepository, CalDavRepository,
+        ListRepository, TagRepository, TodoRepository, TrashRepository, UserRepository,
+        WebhookRepository,
+    },
+    transfer::DumpRecord,
+};
+
+/// Default database used when DATABASE_URL is not set.
+pub const DEFAULT_DATABASE_URL: &str = "sqlite:data.db";
+
+/// SQLite migrations embedded from `migrations/sqlite` at compile time.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+/// PostgreSQL migrations embedded from `migrations/postgres` at compile time.
+#[cfg(feature = "postgres")]
+pub static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+/// Whether `url` selects the PostgreSQL backend rather than SQLite.
+pub fn is_postgres(url: &str) -> bool {
+    url.starts_with("postgres://") || url.starts_with("postgresql://")
+}
+
+/// Connection pool sizing and per-connection PRAGMA settings.
+#[derive(Clone, Debug)]
+pub struct DbConfig {
+    pub url: String,
+    /// Size of the read-write pool (the only pool for PostgreSQL).
+    pub max_connections: u32,
+    /// Size of the read-only pool used by read handlers.
+    pub read_max_connections: u32,
+    /// How long to wait for a free pooled connection.
+    pub acquire_timeout: Duration,
+    // The remaining settings only apply to SQLite.
+    /// How long a connection waits on a locked database (`PRAGMA busy_timeout`).
+    pub busy_timeout: Duration,
+    pub synchronous: SqliteSynchronous,
+    /// `PRAGMA cache_size`: pages if positive, KiB if negative.
+    pub cache_size: i64,
+    /// `PRAGMA mmap_size` in bytes (0 disables memory-mapped I/O).
+    pub mmap_size: u64,
+}
+
+impl DbConfig {
+    /// Config with the default settings for `url`.
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            max_connections: 5,
+            read_max_connections: 5,
+            acquire_timeout: Duration::from_secs(30),
+            busy_timeout: Duration::from_secs(5),
+            synchronous: SqliteSynchronous::Full,
+            cache_size: -2000,
+            mmap_size: 0,
+        }
+    }
+
+    fn is_in_memory(&self) -> bool {
+        self.url.contains(":memory:") || self.url.contains("mode=memory")
+    }
+
+    /// Options applied to every new connection.
+    fn connect_options(&self, read_only: bool) -> anyhow::Result<SqliteConnectOptions> {
+        let options = SqliteConnectOptions::from_str(&self.url)?
+            .foreign_keys(true)
+            .busy_timeout(self.busy_timeout)
+            .pragma("cache_size", self.cache_size.to_string())
+            .pragma("mmap_size", self.mmap_size.to_string());
+
+        Ok(if read_only {
+            options.read_only(true)
+        } else {
+            options
+                .create_if_missing(true)
+                .journal_mode(SqliteJournalMode::Wal)
+                .synchronous(self.synchronous)
+        })
+    }
+}
+
+/// The state of one embedded migration against a database.
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+    pub reversible: bool,
+}
+
+/// An open database, using whichever backend the URL selected, or an
+/// ephemeral in-memory store.
+#[derive(Clone, Debug)]
+pub struct Database {
+    backend: Backend,
+    /// The repositories over `backend`, built once when it was opened.
+    pub repos: Repositories,
+}
+
+#[derive(Clone, Debug)]
+enum Backend {
+    Sqlite {
+        db: SqlitePool,
+        /// Read-only pool for queries that never write.
+        db_read: SqlitePool,
+    },
+    #[cfg(feature = "postgres")]
+    Postgres(PgPool),
+    /// Unmigrated storage that is lost on shutdown (`serve --ephemeral`).
+    Memory(MemoryRepository),
+}
+
+/// Every repository of one database, each sharing the same backend.
+#[derive(Clone)]
+pub struct Repositories {
+    pub users: Arc<dyn UserRepository>,
+    pub todos: Arc<dyn TodoRepository>,
+    pub lists: Arc<dyn ListRepository>,
+    pub tags: Arc<dyn TagRepository>,
+    pub caldav: Arc<dyn CalDavRepository>,
+    pub trash: Arc<dyn TrashRepository>,
+    pub webhooks: Arc<dyn WebhookRepository>,
+    pub audit: Arc<dyn AuditRepository>,
+}
+
+impl Repositories {
+    fn new<R>(repo: R) -> Self
+    where
+        R: UserRepository
+            + TodoRepository
+            + ListRepository
+            + TagRepository
+            + CalDavRepository
+            + TrashRepository
+            + WebhookRepository
+            + AuditRepository
+            + 'static,
+    {
+        let repo = Arc::new(repo);
+        Repositories {
+            users: repo.clone(),
+            todos: repo.clone(),
+            lists: repo.clone(),
+            tags: repo.clone(),
+            caldav: repo.clone(),
+            trash: repo.clone(),
+            webhooks: repo.clone(),
+            audit: repo,
+        }
+    }
+}
+
+impl std::fmt::Debug for Repositories {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Repositories").finish_non_exhaustive()
+    }
+}
+
+#[cfg(not(feature = "postgres"))]
+const NO_POSTGRES: &str = "PostgreSQL support is not enabled; rebuild with `--features postgres`";
+
+/// Create the database if it does not already exist.
+///
+/// Returns `true` if a new database was created.
+pub async fn create(db_url: &str) -> anyhow::Result<bool> {
+    if is_postgres(db_url) {
+        #[cfg(feature = "postgres")]
+        {
+            if Postgres::database_exists(db_url).await? {
+                return Ok(false);
+            }
+            Postgres::create_database(db_url).await?;
+            info!("Created database {db_url}");
+            return Ok(true);
+        }
+        #[cfg(not(feature = "postgres"))]
+        anyhow::bail!(NO_POSTGRES);
+    }
+    if Sqlite::database_exists(db_url).await? {
+        return Ok(false);
+    }
+    Sqlite::create_database(db_url).await?;
+    info!("Created database {db_url}");
+    Ok(true)
+}
+
+/// Open a database with the default settings, creating a SQLite
+/// database file if needed. SQLite gets a single read-write pool.
+pub async fn connect(db_url: &str) -> anyhow::Result<Database> {
+    let cfg = DbConfig::new(db_url);
+    if is_postgres(db_url) {
+        return connect_pools(&cfg).await;
+    }
+    let db = open_pool(&cfg, false).await?;
+    Ok(Database::new(Backend::Sqlite {
+        db_read: db.clone(),
+        db,
+    }))
+}
+
+/// Open the database selected by `cfg.url`.
+///
+/// SQLite gets a read-write pool and a separate read-only pool, so that
+/// readers do not queue behind writers for a pooled connection. For
+/// in-memory databases both pools are the same pool, since each
+/// in-memory database is private to the pool that created it.
+pub async fn connect_pools(cfg: &DbConfig) -> anyhow::Result<Database> {
+    if is_postgres(&cfg.url) {
+        #[cfg(feature = "postgres")]
+        {
+            let db = PgPoolOptions::new()
+                .max_connections(cfg.max_connections)
+                .acquire_timeout(cfg.acquire_timeout)
+                .connect(&cfg.url)
+                .await?;
+            info!(
+                "Loaded PostgreSQL connection pool (max_connections={})",
+                cfg.max_connections
+            );
+            return Ok(Database::new(Backend::Postgres(db)));
+        }
+        #[cfg(not(feature = "postgres"))]
+        anyhow::bail!(NO_POSTGRES);
+    }
+    let db = open_pool(cfg, false).await?;
+    if cfg.is_in_memory() {
+        return Ok(Database::new(Backend::Sqlite {
+            db_read: db.clone(),
+            db,
+        }));
+    }
+    let db_read = open_pool(cfg, true).await?;
+    Ok(Database::new(Backend::Sqlite { db, db_read }))
+}
+
+async fn open_pool(cfg: &DbConfig, read_only: bool) -> anyhow::Result<SqlitePool> {
+    let max_connections = if read_only {
+        cfg.read_max_connections
+    } else {
+        cfg.max_connections
+    };
+    let db = SqlitePoolOptions::new()
+        .max_connections(max_connections)
+        .acquire_timeout(cfg.acquire_timeout)
+        .connect_with(cfg.connect_options(read_only)?)
+        .await?;
+    let kind = if read_only { "read-only" } else { "read-write" };
+    info!("Loaded {kind} database connection pool (max_connections={max_connections})");
+    Ok(db)
+}
+
+impl Database {
+    fn new(backend: Backend) -> Self {
+        let repos = match &backend {
+            Backend::Sqlite { db, db_read } => {
+                Repositories::new(SqliteRepository::new(db.clone(), db_read.clone()))
+            }
+            #[cfg(feature = "postgres")]
+            Backend::Postgres(db) => Repositories::new(PgRepository::new(db.clone())),
+            Backend::Memory(repo) => Repositories::new(repo.clone()),
+        };
+        Database { backend, repos }
+    }
+
+    /// Unmigrated storage in `repo`, lost when it is dropped.
+    pub fn memory(repo: MemoryRepository) -> Self {
+        Database::new(Backend::Memory(repo))
+    }
+
+    /// The SQLite read-write pool, for SQLite-only features like backups.
+    pub fn sqlite(&self) -> Option<&SqlitePool> {
+        match &self.backend {
+            Backend::Sqlite { db, .. } => Some(db),
+            #[cfg(feature = "postgres")]
+            Backend::Postgres(_) => None,
+            Backend::Memory(_) => None,
+        }
+    }
+
+    /// Insert dump records (keeping their ids) in a single transaction.
+    ///
+    /// Returns the index and error of every rejected record. Nothing is
+    /// committed if any record was rejected, or if `dry_run` is set.
+    pub async fn load_dump(
+        &self,
+        records: &[DumpRecord],
+        dry_run: bool,
+    ) -> anyhow::Result<Vec<(usize, String)>> {
+        match &self.backend {
+            Backend::Sqlite { db, db_read } => {
+                SqliteRepository::new(db.clone(), db_read.clone())
+                    .load_dump(records, dry_run)
+                    .await
+            }
+            #[cfg(feature = "postgres")]
+            Backend::Postgres(db) => {
+                PgRepository::new(db.clone())
+                    .load_dump(records, dry_run)
+                    .await
+            }
+            Backend::Memory(repo) => Ok(repo.load_dump(records, dry_run)),
+        }
+    }
+
+    /// Apply all pending migrations.
+    pub async fn migrate(&self) -> anyhow::Result<()> {
+        match &self.backend {
+            Backend::Sqlite { db, .. } => migrate_sqlite(db, None).await?,
+            #[cfg(feature = "postgres")]
+            Backend::Postgres(db) => POSTGRES_MIGRATOR.run(db).await?,
+            Backend::Memory(_) => {}
+        }
+        Ok(())
+    }
+
+    /// List every embedded migration along with whether it has been applied.
+    pub async fn status(&self) -> anyhow::Result<Vec<MigrationStatus>> {
+        let (migrator, applied) = match &self.backend {
+            Backend::Sqlite { db, .. } => (
+                &MIGRATOR,
+                applied_versions(&mut *db.acquire().await?).await?,
+            ),
+            #[cfg(feature = "postgres")]
+            Backend::Postgres(db) => (
+                &POSTGRES_MIGRATOR,
+                applied_versions(&mut *db.acquire().await?).await?,
+            ),
+            Backend::Memory(_) => return Ok(Vec::new()),
+        };
+
+        Ok(migrator
+            .iter()
+            .filter(|m| !m.migration_type.is_down_migration())
+            .map(|m| MigrationStatus {
+                version: m.version,
+                description: m.description.to_string(),
+                applied: applied.contains(&m.version),
+                reversible: migrator
+                    .iter()
+                    .any(|d| d.version == m.version && d.migration_type.is_down_migration()),
+            })
+            .collect())
+    }
+
+    /// Revert applied migrations newer than `target`.
+    ///
+    /// With no target, only the most recently applied migration is reverted.
+    /// Returns the versions that were reverted, newest first.
+    pub async fn revert(&self, target: Option<i64>) -> anyhow::Result<Vec<i64>> {
+        let applied: Vec<MigrationStatus> = self
+            .status()
+            .await?
+            .into_iter()
+            .filter(|m| m.applied)
+            .collect();
+
+        let target = match target {
+            Some(t) => t,
+            None => applied.iter().rev().nth(1).map(|m| m.version).unwrap_or(0),
+        };
+
+        let reverting: Vec<&MigrationStatus> = applied
+            .iter()
+            .rev()
+            .filter(|m| m.version > target)
+            .collect();
+        if let Some(m) = reverting.iter().find(|m| !m.reversible) {
+            anyhow::bail!(
+                "migration {} ({}) has no down migration and cannot be reverted",
+                m.version,
+                m.description
+            );
+        }
+
+        match &self.backend {
+            Backend::Sqlite { db, .. } => migrate_sqlite(db, Some(target)).await?,
+            #[cfg(feature = "postgres")]
+            Backend::Postgres(db) => POSTGRES_MIGRATOR.undo(db, target).await?,
+            Backend::Memory(_) => {}
+        }
+        Ok(reverting.into_iter().map(|m| m.version).collect())
+    }
+}
+
+/// Apply pending SQLite migrations, or undo those newer than `target`.
+///
+/// Rebuilding a table means dropping it, which would cascade to every row
+/// referencing it, so migrations run on a connection with foreign keys off
+/// and the references are checked once they are done. The connection is
+/// closed afterwards rather than returned to the pool.
+async fn migrate_sqlite(db: &SqlitePool, target: Option<i64>) -> anyhow::Result<()> {
+    let mut conn = db.acquire().await?;
+    conn.close_on_drop();
+    sqlx::query("PRAGMA foreign_keys = OFF")
+        .execute(&mut *conn)
+        .await?;
+    match target {
+        None => MIGRATOR.run(&mut *conn).await?,
+        Some(target) => MIGRATOR.undo(&mut *conn, target).await?,
+    }
+    let dangling = sqlx::query("PRAGMA foreign_key_check")
+        .fetch_all(&mut *conn)
+        .await?;
+    anyhow::ensure!(
+        dangling.is_empty(),
+        "migrations left {} rows referencing missing rows",
+        dangling.len()
+    );
+    Ok(())
+}
+
+async fn applied_versions<C: Migrate>(conn: &mut C) -> anyhow::Result<Vec<i64>> {
+    conn.ensure_migrations_table().await?;
+    Ok(conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| m.version)
+        .collect())
+}
+
+/// A migrated SQLite database in a temporary directory, which is removed
+/// when the returned `TempDir` is dropped.
+#[cfg(test)]
+pub async fn test_sqlite() -> (Database, tempfile::TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite://{}", dir.path().join("test.db").display());
+    let db = connect_pools(&DbConfig::new(&url)).await.unwrap();
+    db.migrate().await.unwrap();
+    (db, dir)
+}
+
+/// A migrated PostgreSQL database in a fresh schema, or `None` (skipping
+/// the test) unless `TEST_POSTGRES_URL` is set.
+#[cfg(all(test, feature = "postgres"))]
+pub async fn test_postgres() -> Option<Database> {
+    let url = std::env::var("TEST_POSTGRES_URL").ok()?;
+    let schema = format!("test_{}", uuid::Uuid::new_v4().simple());
+    let admin = PgPool::connect(&url).await.unwrap();
+    sqlx::query(&format!("CREATE SCHEMA {schema}"))
+        .execute(&admin)
+        .await
+        .unwrap();
+    let sep = if url.contains('?') { '&' } else { '?' };
+    let db = connect_pools(&DbConfig::new(&format!(
+        "{url}{sep}options[search_path]={schema}"
+    )))
+    .await
+    .unwrap();
+    db.migrate().await.unwrap();
+    Some(db)
+}
+
+/// Turn `check`, an `async fn(&Database)`, into a module of tests running
+/// it against [`test_sqlite`] as `check::sqlite` and, with the `postgres`
+/// feature, against [`test_postgres`] as `check::postgres`.
+#[cfg(test)]
+macro_rules! backend_test {
+    ($check:ident) => {
+        mod $check {
+            #[tokio::test]
+            async fn sqlite() {
+                let (db, _dir) = $crate::db::test_sqlite().await;
+                super::$check(&db).await;
+            }
+
+            #[cfg(feature = "postgres")]
+            #[tokio::test]
+            async fn postgres() {
+                let Some(db) = $crate::db::test_postgres().await else {
+                    return;
+                };
+                super::$check(&db).await;
+            }
+        }
+    };
+}
+#[cfg(test)]
+pub(crate) use backend_test;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pragmas_apply_to_every_connection() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = DbConfig {
+            busy_timeout: Duration::from_millis(1234),
+            synchronous: SqliteSynchronous::Normal,
+            cache_size: -4000,
+            ..DbConfig::new(&format!(
+                "sqlite://{}",
+                dir.path().join("test.db").display()
+            ))
+        };
+        let db = open_pool(&cfg, false).await.unwrap();
+        let db_read = open_pool(&cfg, true).await.unwrap();
+
+        // Hold several connections at once so each is a distinct connection:
+        let mut conns = Vec::new();
+        for _ in 0..3 {
+            conns.push(db.acquire().await.unwrap());
+        }
+        for conn in conns.iter_mut() {
+            let (busy,): (i64,) = sqlx::query_as("PRAGMA busy_timeout;")
+                .fetch_one(&mut **conn)
+                .await
+                .unwrap();
+            let (sync,): (i64,) = sqlx::query_as("PRAGMA synchronous;")
+                .fetch_one(&mut **conn)
+                .await
+                .unwrap();
+            let (cache,): (i64,) = sqlx::query_as("PRAGMA cache_size;")
+                .fetch_one(&mut **conn)
+                .await
+                .unwrap();
+            let (journal,): (String,) = sqlx::query_as("PRAGMA journal_mode;")
+                .fetch_one(&mut **conn)
+                .await
+                .unwrap();
+            assert_eq!((busy, sync, cache), (1234, 1, -4000));
+            assert_eq!(journal, "wal");
+        }
+        drop(conns);
+
+        assert!(sqlx::query("CREATE TABLE t (x INTEGER);")
+            .execute(&db_read)
+            .await
+            .is_err());
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    async fn postgres_status_and_revert() {
+        let Some(db) = test_postgres().await else {
+            return;
+        };
+        assert!(db.status().await.unwrap().iter().all(|m| m.applied));
+        assert_eq!(
+            db.revert(Some(0)).await.unwrap(),
+            vec![17, 16, 15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1]
+        );
+        assert!(db.status().await.unwrap().iter().all(|m| !m.applied));
+        db.migrate().await.unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::{broadcast, watch};
+
+use crate::{
+    models::{ids::UserId, webhook::Event},
+    prelude::*,
+    webhook, AppState,
+};
+
+/// How many recent changes are kept for clients resuming a stream.
+pub const LOG_SIZE: usize = 1000;
+/// How many changes a subscriber may fall behind before it misses some.
+const CHANNEL_SIZE: usize = 256;
+
+/// A change to a user's data, as told to their live clients.
+#[derive(Clone, Debug)]
+pub struct Change {
+    /// Increases by one with every change (to anyone's data) since the
+    /// server started.
+    pub id: u64,
+    pub user_id: UserId,
+    pub event: Event,
+    /// The todo or list as it is after the change (or was, if deleted).
+    pub data: Value,
+    pub at: DateTime<Utc>,
+}
+
+/// The in-process bus live clients get changes from, which keeps the
+/// latest [`LOG_SIZE`] changes so that clients can resume where they
+/// left off.
+#[derive(Clone)]
+pub struct EventBus {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    sender: broadcast::Sender<Change>,
+    log: Mutex<Log>,
+    closed: watch::Sender<bool>,
+}
+
+struct Log {
+    next_id: u64,
+    changes: VecDeque<Change>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                sender: broadcast::channel(CHANNEL_SIZE).0,
+                log: Mutex::new(Log {
+                    next_id: 1,
+                    changes: VecDeque::new(),
+                }),
+                closed: watch::channel(false).0,
+            }),
+        }
+    }
+}
+
+impl EventBus {
+    /// Record a change and send it to every subscriber.
+    pub fn publish(&self, user_id: UserId, event: Event, data: Value) -> Change {
+        // Sending under the lock keeps the log and the channel in the same
+        // order, so subscribe() can tell where one ends and the other starts.
+        let mut log = self.inner.log.lock().unwrap();
+        let change = Change {
+            id: log.next_id,
+            user_id,
+            event,
+            data,
+            at: Utc::now(),
+        };
+        log.next_id += 1;
+        log.changes.push_back(change.clone());
+        if log.changes.len() > LOG_SIZE {
+            log.changes.pop_front();
+        }
+        // Fails only when nobody is subscribed.
+        let _ = self.inner.sender.send(change.clone());
+        change
+    }
+
+    /// Subscribe to a user's changes, starting with those after
+    /// `last_id`, if given.
+    ///
+    /// A `last_id` that is no longer in the log (or from before the
+    /// server restarted) cannot be resumed from, so the subscription
+    /// starts with [`Received::Missed`] instead.
+    pub fn subscribe(&self, user_id: UserId, last_id: Option<u64>) -> Subscription {
+        let log = self.inner.log.lock().unwrap();
+        let receiver = self.inner.sender.subscribe();
+        let mut backlog = VecDeque::new();
+        if let Some(last_id) = last_id {
+            let first_id = log.changes.front().map_or(log.next_id, |c| c.id);
+            if last_id >= log.next_id || last_id + 1 < first_id {
+                backlog.push_back(Received::Missed);
+            } else {
+                backlog.extend(
+                    log.changes
+                        .iter()
+                        .filter(|c| c.id > last_id && c.user_id == user_id)
+                        .cloned()
+                        .map(Received::Change),
+                );
+            }
+        }
+        Subscription {
+            user_id,
+            backlog,
+            receiver,
+            closed: self.inner.closed.subscribe(),
+        }
+    }
+
+    /// End every subscription, e.g. when the server shuts down.
+    pub fn close(&self) {
+        self.inner.closed.send_replace(true);
+    }
+
+    /// Wait until every subscription has been dropped, e.g. for live
+    /// connections to say goodbye after [`close`](Self::close).
+    pub async fn drained(&self) {
+        self.inner.closed.closed().await;
+    }
+}
+
+/// What a subscription gets next.
+#[derive(Clone, Debug)]
+pub enum Received {
+    Change(Change),
+    /// Some changes were missed, so the client should reload its data.
+    Missed,
+}
+
+/// A user's changes as they happen, after any that were resumed from
+/// the log.
+pub struct Subscription {
+    user_id: UserId,
+    backlog: VecDeque<Received>,
+    receiver: broadcast::Receiver<Change>,
+    closed: watch::Receiver<bool>,
+}
+
+impl Subscription {
+    /// The next change, or `None` once the bus is closed.
+    pub async fn next(&mut self) -> Option<Received> {
+        if let Some(received) = self.backlog.pop_front() {
+            return Some(received);
+        }
+        loop {
+            let received = tokio::select! {
+                biased;
+                _ = self.closed.wait_for(|closed| *closed) => return None,
+                received = self.receiver.recv() => received,
+            };
+            match received {
+                Ok(change) if change.user_id == self.user_id => {
+                    return Some(Received::Change(change))
+                }
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(_)) => return Some(Received::Missed),
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// Tell a user's live clients and webhooks about a change to their data.
+pub async fn publish(state: &AppState, user_id: UserId, event: Event, data: &impl Serialize) {
+    let data = match serde_json::to_value(data) {
+        Ok(data) => data,
+        Err(e) => {
+            error!("Failed to serialize the data of {event}: {e}");
+            return;
+        }
+    };
+    state.events.publish(user_id, event, data.clone());
+    webhook::notify(state.webhooks.as_ref(), user_id, event, &data).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn next(subscription: &mut Subscription) -> Option<Received> {
+        futures_util::FutureExt::now_or_never(subscription.next()).flatten()
+    }
+
+    #[tokio::test]
+    async fn resumes_from_the_log() {
+        let bus = EventBus::default();
+        let (a, b) = (UserId(Uuid::new_v4()), UserId(Uuid::new_v4()));
+        let first = bus.publish(a, Event::TodoCreated, json!(1));
+        bus.publish(b, Event::TodoCreated, json!(2));
+        bus.publish(a, Event::TodoUpdated, json!(3));
+
+        let mut live = bus.subscribe(a, None);
+        let mut resumed = bus.subscribe(a, Some(first.id));
+        let mut future = bus.subscribe(a, Some(100));
+        bus.publish(a, Event::TodoDeleted, json!(4));
+
+        assert!(matches!(next(&mut live), Some(Received::Change(c)) if c.data == json!(4)));
+        assert!(next(&mut live).is_none());
+        let data: Vec<Value> = std::iter::from_fn(|| next(&mut resumed))
+            .map(|r| match r {
+                Received::Change(c) => c.data,
+                Received::Missed => panic!("resumable"),
+            })
+            .collect();
+        assert_eq!(data, vec![json!(3), json!(4)]);
+        assert!(matches!(next(&mut future), Some(Received::Missed)));
+
+        for i in 0..LOG_SIZE {
+            bus.publish(b, Event::TodoCreated, json!(i));
+        }
+        let mut stale = bus.subscribe(a, Some(first.id));
+        assert!(matches!(next(&mut stale), Some(Received::Missed)));
+
+        bus.close();
+        assert!(live.next().await.is_none());
+    }
+}
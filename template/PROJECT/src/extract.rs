@@ -0,0 +1,209 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+    http::{Extensions, Method, StatusCode},
+};
+use tower_http::request_id::RequestId;
+
+use crate::{
+    errors::internal_error,
+    middleware::{AuthenticatedGroups, AuthenticatedUser, ClientIp, RealUser},
+    models::user::{Role, User},
+    AppState,
+};
+
+/// The registered user matching the request's [`AuthenticatedUser`].
+///
+/// Rejects with 401 if the request is unauthenticated, and 403 if the
+/// authenticated email has no user record.
+#[derive(Clone, Debug)]
+pub struct CurrentUser(pub User);
+
+impl FromRequestParts<AppState> for CurrentUser {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let email = match parts.extensions.get::<AuthenticatedUser>() {
+            Some(AuthenticatedUser(email)) => email.clone(),
+            None => return Err((StatusCode::UNAUTHORIZED, "Unauthenticated".to_string())),
+        };
+
+        let user = state
+            .users
+            .find_by_email(&email)
+            .await
+            .map_err(internal_error)?;
+
+        match user {
+            Some(user) => Ok(CurrentUser(user)),
+            None => Err((
+                StatusCode::FORBIDDEN,
+                format!("No user is registered for {email}"),
+            )),
+        }
+    }
+}
+
+/// Which proxy groups grant which [`Role`] (`ADMIN_GROUPS`,
+/// `MEMBER_GROUPS` and `READ_ONLY_GROUPS`).
+#[derive(Clone, Debug, Default)]
+pub struct RoleGroups {
+    pub admin: Vec<String>,
+    pub member: Vec<String>,
+    pub read_only: Vec<String>,
+}
+
+impl RoleGroups {
+    /// The highest role any of `groups` is mapped to, if any is.
+    pub fn role(&self, groups: &[String]) -> Option<Role> {
+        [
+            (Role::Admin, &self.admin),
+            (Role::Member, &self.member),
+            (Role::ReadOnly, &self.read_only),
+        ]
+        .into_iter()
+        .find(|(_, names)| names.iter().any(|name| groups.contains(name)))
+        .map(|(role, _)| role)
+    }
+}
+
+/// The role of the user with this email on a request (given its
+/// extensions): admin if the email is one of `ADMIN_EMAILS`, else the
+/// role the proxy's groups are mapped to (if any are), else `stored`.
+pub fn effective_role(
+    state: &AppState,
+    extensions: &Extensions,
+    email: &str,
+    stored: Role,
+) -> Role {
+    if state.admins.iter().any(|admin| admin == email) {
+        return Role::Admin;
+    }
+    extensions
+        .get::<AuthenticatedGroups>()
+        .and_then(|AuthenticatedGroups(groups)| state.role_groups.role(groups))
+        .unwrap_or(stored)
+}
+
+/// Whether a request with this method only looks: `GET`, `HEAD` and
+/// `OPTIONS`, and CalDAV's `PROPFIND` and `REPORT`.
+fn is_read(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+        || matches!(method.as_str(), "PROPFIND" | "REPORT")
+}
+
+/// The caller's [`CurrentUser`] with their [`effective_role`].
+///
+/// Rejects like [`CurrentUser`], and with 403 if a read-only user's
+/// request would change anything.
+#[derive(Clone, Debug)]
+pub struct Authorize {
+    pub user: User,
+    pub role: Role,
+}
+
+impl Authorize {
+    /// Authorize this request for `user`, however they were found.
+    pub fn new(parts: &Parts, state: &AppState, user: User) -> Result<Self, (StatusCode, String)> {
+        let role = effective_role(state, &parts.extensions, &user.email, user.role);
+        if role == Role::ReadOnly && !is_read(&parts.method) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Read-only users cannot change anything".to_string(),
+            ));
+        }
+        Ok(Authorize { user, role })
+    }
+}
+
+impl FromRequestParts<AppState> for Authorize {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let CurrentUser(user) = CurrentUser::from_request_parts(parts, state).await?;
+        Authorize::new(parts, state, user)
+    }
+}
+
+/// Requires the caller to be [`Authorize`]d as an admin. Rejects with
+/// 403 otherwise.
+#[derive(Clone, Copy, Debug)]
+pub struct Admin;
+
+impl FromRequestParts<AppState> for Admin {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Authorize { role, .. } = Authorize::from_request_parts(parts, state).await?;
+        if role == Role::Admin {
+            Ok(Admin)
+        } else {
+            Err((StatusCode::FORBIDDEN, "Admins only".to_string()))
+        }
+    }
+}
+
+/// Who made a request and from where, as recorded in the audit log: the
+/// [`AuthenticatedUser`] (and the [`RealUser`] acting as them, if any),
+/// the [`ClientIp`] (or else the peer's address) and the request's
+/// `X-Request-Id`.
+#[derive(Clone, Debug, Default)]
+pub struct Origin {
+    pub actor: Option<String>,
+    pub real_actor: Option<String>,
+    pub client_ip: Option<IpAddr>,
+    pub request_id: Option<String>,
+}
+
+impl Origin {
+    /// Changes made with the command line tools, recorded as made by
+    /// `cli`.
+    pub fn cli() -> Self {
+        Self {
+            actor: Some("cli".to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Origin {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let client_ip = match parts.extensions.get::<ClientIp>() {
+            Some(ClientIp(ip)) => Some(*ip),
+            None => ConnectInfo::<SocketAddr>::from_request_parts(parts, state)
+                .await
+                .ok()
+                .map(|ConnectInfo(peer)| peer.ip()),
+        };
+        Ok(Origin {
+            actor: parts
+                .extensions
+                .get::<AuthenticatedUser>()
+                .map(|AuthenticatedUser(email)| email.clone()),
+            real_actor: parts
+                .extensions
+                .get::<RealUser>()
+                .map(|RealUser(email)| email.clone()),
+            client_ip,
+            request_id: parts
+                .extensions
+                .get::<RequestId>()
+                .and_then(|id| id.header_value().to_str().ok())
+                .map(str::to_string),
+        })
+    }
+}
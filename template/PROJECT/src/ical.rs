@@ -0,0 +1,394 @@
+use std::fmt::{self, Write};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+
+use crate::models::todo::{ImportTodo, Todo};
+
+/// Lines are folded to at most this many octets (RFC 5545 section 3.1).
+const MAX_LINE_OCTETS: usize = 75;
+
+/// A content line of an iCalendar object: `NAME;PARAM=VALUE:value`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Property {
+    /// Upper case.
+    pub name: String,
+    /// Upper case names, and values without quotes.
+    pub params: Vec<(String, String)>,
+    /// The raw value, still escaped if it is text.
+    pub value: String,
+}
+
+impl Property {
+    /// A property with a value that is already in iCalendar form.
+    pub fn new(name: &str, value: impl Into<String>) -> Self {
+        Property {
+            name: name.to_string(),
+            params: Vec::new(),
+            value: value.into(),
+        }
+    }
+
+    /// A property with a text value, which gets escaped.
+    pub fn text(name: &str, value: &str) -> Self {
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            match c {
+                '\\' | ';' | ',' => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                '\n' => escaped.push_str("\\n"),
+                '\r' => {}
+                c => escaped.push(c),
+            }
+        }
+        Property::new(name, escaped)
+    }
+
+    /// A property with a UTC date-time value.
+    pub fn datetime(name: &str, at: DateTime<Utc>) -> Self {
+        Property::new(name, at.format("%Y%m%dT%H%M%SZ").to_string())
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The value as unescaped text.
+    pub fn text_value(&self) -> String {
+        let mut text = String::with_capacity(self.value.len());
+        let mut chars = self.value.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                text.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n' | 'N') => text.push('\n'),
+                Some(c) => text.push(c),
+                None => text.push('\\'),
+            }
+        }
+        text
+    }
+
+    /// The value as a date-time, or a date (at midnight). Floating times
+    /// and times in a `TZID` are taken to be UTC.
+    pub fn datetime_value(&self) -> Result<DateTime<Utc>, String> {
+        let value = self.value.trim();
+        let invalid = || format!("invalid {} '{value}'", self.name);
+        let is_date = self
+            .param("VALUE")
+            .is_some_and(|v| v.eq_ignore_ascii_case("DATE"));
+        if is_date || value.len() == 8 {
+            let date = NaiveDate::parse_from_str(value, "%Y%m%d").map_err(|_| invalid())?;
+            return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+        }
+        let at = NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S")
+            .map_err(|_| invalid())?;
+        Ok(at.and_utc())
+    }
+}
+
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut line = self.name.clone();
+        for (name, value) in &self.params {
+            if value.contains([':', ';', ',']) {
+                write!(line, ";{name}=\"{value}\"")?;
+            } else {
+                write!(line, ";{name}={value}")?;
+            }
+        }
+        write!(line, ":{}", self.value)?;
+
+        let mut octets = 0;
+        for c in line.chars() {
+            if octets + c.len_utf8() > MAX_LINE_OCTETS {
+                f.write_str("\r\n ")?;
+                octets = 1;
+            }
+            f.write_char(c)?;
+            octets += c.len_utf8();
+        }
+        f.write_str("\r\n")
+    }
+}
+
+/// A component such as `VCALENDAR` or `VTODO`, with its properties and
+/// the components nested in it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Component {
+    /// Upper case.
+    pub name: String,
+    pub properties: Vec<Property>,
+    pub components: Vec<Component>,
+}
+
+impl Component {
+    pub fn new(name: &str) -> Self {
+        Component {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn push(&mut self, property: Property) {
+        self.properties.push(property);
+    }
+
+    /// The first property with this (upper case) name.
+    pub fn get(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|p| p.name == name)
+    }
+}
+
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BEGIN:{}\r\n", self.name)?;
+        for property in &self.properties {
+            write!(f, "{property}")?;
+        }
+        for component in &self.components {
+            write!(f, "{component}")?;
+        }
+        write!(f, "END:{}\r\n", self.name)
+    }
+}
+
+/// A `VCALENDAR` holding these components.
+pub fn calendar(components: Vec<Component>) -> Component {
+    let mut calendar = Component::new("VCALENDAR");
+    calendar.push(Property::new("VERSION", "2.0"));
+    calendar.push(Property::new("PRODID", "-//${APP}//EN"));
+    calendar.push(Property::new("CALSCALE", "GREGORIAN"));
+    calendar.components = components;
+    calendar
+}
+
+/// Parse an iCalendar stream into its top-level components (normally
+/// one `VCALENDAR`).
+pub fn parse(text: &str) -> Result<Vec<Component>, String> {
+    // Unfold continuation lines, remembering where each line started:
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (i, line) in text.split('\n').enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some((_, last))) => last.push_str(rest),
+            _ if line.trim().is_empty() => {}
+            _ => lines.push((i + 1, line.to_string())),
+        }
+    }
+
+    let mut open: Vec<Component> = Vec::new();
+    let mut closed = Vec::new();
+    for (n, line) in lines {
+        let property = parse_line(&line).map_err(|e| format!("line {n}: {e}"))?;
+        match property.name.as_str() {
+            "BEGIN" => open.push(Component::new(&property.value.to_ascii_uppercase())),
+            "END" => {
+                let component = open
+                    .pop()
+                    .filter(|c| c.name.eq_ignore_ascii_case(&property.value))
+                    .ok_or_else(|| format!("line {n}: unexpected END:{}", property.value))?;
+                match open.last_mut() {
+                    Some(parent) => parent.components.push(component),
+                    None => closed.push(component),
+                }
+            }
+            name => open
+                .last_mut()
+                .ok_or_else(|| format!("line {n}: {name} outside of any component"))?
+                .push(property),
+        }
+    }
+    match open.last() {
+        Some(component) => Err(format!("missing END:{}", component.name)),
+        None => Ok(closed),
+    }
+}
+
+/// Split at `sep` outside of double quotes.
+fn split_unquoted(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c == sep && !quoted {
+            parts.push(&s[start..i]);
+            start = i + 1;
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn parse_line(line: &str) -> Result<Property, String> {
+    let mut quoted = false;
+    let colon = line
+        .char_indices()
+        .find(|&(_, c)| {
+            if c == '"' {
+                quoted = !quoted;
+            }
+            c == ':' && !quoted
+        })
+        .map(|(i, _)| i)
+        .ok_or("expected NAME:value")?;
+    let mut head = split_unquoted(&line[..colon], ';').into_iter();
+    let name = head.next().unwrap_or_default().trim().to_ascii_uppercase();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(format!("invalid property name '{name}'"));
+    }
+    let params = head
+        .map(|param| match param.split_once('=') {
+            Some((k, v)) => Ok((k.trim().to_ascii_uppercase(), v.trim().replace('"', ""))),
+            None => Err(format!("invalid parameter '{param}'")),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Property {
+        name,
+        params,
+        value: line[colon + 1..].to_string(),
+    })
+}
+
+/// A todo as a `VTODO` with this UID (normally the todo's id).
+pub fn vtodo(todo: &Todo, uid: &str) -> Component {
+    let mut vtodo = Component::new("VTODO");
+    vtodo.push(Property::text("UID", uid));
+    vtodo.push(Property::datetime("DTSTAMP", todo.updated_at));
+    vtodo.push(Property::datetime("CREATED", todo.created_at));
+    vtodo.push(Property::datetime("LAST-MODIFIED", todo.updated_at));
+    vtodo.push(Property::text("SUMMARY", &todo.title));
+    if let Some(notes) = &todo.notes {
+        vtodo.push(Property::text("DESCRIPTION", notes));
+    }
+    if let Some(due_at) = todo.due_at {
+        vtodo.push(Property::datetime("DUE", due_at));
+    }
+    if let Some(rrule) = &todo.rrule {
+        vtodo.push(Property::new("RRULE", rrule));
+    }
+    if let Some(parent_id) = todo.parent_id {
+        vtodo.push(Property::new("RELATED-TO", parent_id.0.to_string()));
+    }
+    if todo.completed {
+        vtodo.push(Property::new("STATUS", "COMPLETED"));
+        vtodo.push(Property::datetime("COMPLETED", todo.updated_at));
+    } else {
+        vtodo.push(Property::new("STATUS", "NEEDS-ACTION"));
+    }
+    vtodo
+}
+
+/// A `VEVENT` at a todo's due date, or `None` if it has none. Only
+/// pending todos that repeat from their due date carry on repeating as
+/// events; the others are single events.
+pub fn vevent(todo: &Todo) -> Option<Component> {
+    let due_at = todo.due_at?;
+    let mut vevent = Component::new("VEVENT");
+    vevent.push(Property::new("UID", format!("{}-due", todo.id.0)));
+    vevent.push(Property::datetime("DTSTAMP", todo.updated_at));
+    vevent.push(Property::datetime("DTSTART", due_at));
+    vevent.push(Property::text("SUMMARY", &todo.title));
+    if let Some(notes) = &todo.notes {
+        vevent.push(Property::text("DESCRIPTION", notes));
+    }
+    let repeats = !todo.completed && !todo.repeat_from_completion;
+    if let Some(rrule) = todo.rrule.as_ref().filter(|_| repeats) {
+        vevent.push(Property::new("RRULE", rrule));
+    }
+    vevent.push(Property::new("TRANSP", "TRANSPARENT"));
+    Some(vevent)
+}
+
+/// The `VTODO` components of an iCalendar stream as import rows, each
+/// either a todo or why it is invalid.
+pub fn import_todos(text: &str) -> Result<Vec<Result<ImportTodo, String>>, String> {
+    let calendars: Vec<Component> = parse(text)?
+        .into_iter()
+        .filter(|c| c.name == "VCALENDAR")
+        .collect();
+    if calendars.is_empty() {
+        return Err("no VCALENDAR found".to_string());
+    }
+    Ok(calendars
+        .iter()
+        .flat_map(|c| &c.components)
+        .filter(|c| c.name == "VTODO")
+        .map(import_todo)
+        .collect())
+}
+
+/// A `VTODO` as an import row.
+pub fn import_todo(vtodo: &Component) -> Result<ImportTodo, String> {
+    let completed = vtodo.get("COMPLETED").is_some()
+        || vtodo
+            .get("STATUS")
+            .is_some_and(|s| s.value.eq_ignore_ascii_case("COMPLETED"));
+    Ok(ImportTodo {
+        title: vtodo
+            .get("SUMMARY")
+            .map(Property::text_value)
+            .unwrap_or_default(),
+        notes: vtodo
+            .get("DESCRIPTION")
+            .map(Property::text_value)
+            .filter(|notes| !notes.is_empty()),
+        completed,
+        due_at: vtodo.get("DUE").map(Property::datetime_value).transpose()?,
+        rrule: vtodo.get("RRULE").map(|p| p.value.clone()),
+        repeat_from_completion: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_lines_round_trip() {
+        let mut vtodo = Component::new("VTODO");
+        let summary = "Buy milk, eggs; and \\ a very long list of other things: ".repeat(3);
+        vtodo.push(Property::text("SUMMARY", &summary));
+        vtodo.push(Property::text("DESCRIPTION", "two\nlines"));
+        let text = calendar(vec![vtodo]).to_string();
+        assert!(text.lines().all(|l| l.len() <= MAX_LINE_OCTETS + 1));
+        assert!(text.contains("DESCRIPTION:two\\nlines\r\n"));
+
+        let parsed = parse(&text).unwrap();
+        let vtodo = &parsed[0].components[0];
+        assert_eq!(vtodo.get("SUMMARY").unwrap().text_value(), summary);
+        assert_eq!(vtodo.get("DESCRIPTION").unwrap().text_value(), "two\nlines");
+    }
+
+    #[test]
+    fn parses_params_and_dates() {
+        let text = "BEGIN:VCALENDAR\nBEGIN:VTODO\nSUMMARY;LANGUAGE=en:Pay rent\n\
+                    DUE;VALUE=DATE:20300201\nDTSTART;TZID=\"Europe/Paris\":20300101T090000\n\
+                    END:VTODO\nEND:VCALENDAR\n";
+        let parsed = parse(text).unwrap();
+        let vtodo = &parsed[0].components[0];
+        let summary = vtodo.get("SUMMARY").unwrap();
+        assert_eq!(summary.param("language"), Some("en"));
+        let due = vtodo.get("DUE").unwrap().datetime_value().unwrap();
+        assert_eq!(due.to_rfc3339(), "2030-02-01T00:00:00+00:00");
+        let start = vtodo.get("DTSTART").unwrap();
+        assert_eq!(start.param("TZID"), Some("Europe/Paris"));
+        assert_eq!(
+            start.datetime_value().unwrap().to_rfc3339(),
+            "2030-01-01T09:00:00+00:00"
+        );
+
+        assert!(parse("BEGIN:VCALENDAR\nEND:VTODO\n").is_err());
+        assert!(parse("BEGIN:VCALENDAR\n").is_err());
+        assert!(parse("SUMMARY:orphan\n").is_err());
+        assert!(import_todos("BEGIN:VTODO\nEND:VTODO\n").is_err());
+    }
+}
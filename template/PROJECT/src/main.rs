@@ -1,23 +1,60 @@
 use axum::http::HeaderName;
 use clap_complete::shells::Shell;
-use sqlx::SqlitePool;
+use sqlx::sqlite::SqliteSynchronous;
 use std::env;
 use std::io::Write;
 use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 
+mod audit;
+mod backup;
 mod cli;
+mod commands;
+mod db;
 mod errors;
+mod events;
+mod extract;
+mod ical;
+mod jwt;
 mod middleware;
 mod models;
+mod oidc;
+mod password;
 mod prelude;
+mod recurrence;
+mod repository;
 mod routes;
 mod server;
+mod session;
+mod token;
+mod transfer;
+mod trash;
+mod webhook;
 
 use prelude::*;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub db: SqlitePool,
+    pub users: Arc<dyn repository::UserRepository>,
+    pub lists: Arc<dyn repository::ListRepository>,
+    pub todos: Arc<dyn repository::TodoRepository>,
+    pub tags: Arc<dyn repository::TagRepository>,
+    pub caldav: Arc<dyn repository::CalDavRepository>,
+    pub trash: Arc<dyn repository::TrashRepository>,
+    pub webhooks: Arc<dyn repository::WebhookRepository>,
+    pub audit: Arc<dyn repository::AuditRepository>,
+    pub events: events::EventBus,
+    /// The emails of the users who are admins, whatever their role.
+    pub admins: Arc<[String]>,
+    /// The roles that proxy groups are mapped to.
+    pub role_groups: Arc<extract::RoleGroups>,
+    /// Signing in with an OpenID Connect issuer, if configured.
+    pub oidc: Option<Arc<oidc::OidcClient>>,
+    pub sessions: session::SessionConfig,
+    /// Wrong passwords per email and client, to throttle password guessing.
+    pub logins: Arc<password::LoginThrottle>,
+    /// Whether webhooks may point at local and private addresses.
+    pub allow_local_webhooks: bool,
 }
 
 fn main() {
@@ -70,6 +107,13 @@ where
     match matches.subcommand() {
         Some(("completions", sub_matches)) => completions(sub_matches, out, err),
         Some(("serve", sub_matches)) => serve(sub_matches, out, err),
+        Some(("db", sub_matches)) => commands::db::run(sub_matches, out, err),
+        Some(("user", sub_matches)) => commands::user::run(sub_matches, out, err),
+        Some(("todo", sub_matches)) => commands::todo::run(sub_matches, out, err),
+        Some(("webhook", sub_matches)) => commands::webhook::run(sub_matches, out, err),
+        Some(("audit", sub_matches)) => commands::audit::run(sub_matches, out, err),
+        Some(("export", sub_matches)) => commands::transfer::export(sub_matches, out, err),
+        Some(("import", sub_matches)) => commands::transfer::import(sub_matches, out, err),
         _ => 1,
     }
 }
@@ -171,11 +215,24 @@ fn serve<W1: Write, W2: Write>(sub_matches: &clap::ArgMatches, out: &mut W1, err
         }
     };
 
+    let groups_header = match sub_matches
+        .get_one::<String>("trusted_groups_header")
+        .map(|name| HeaderName::from_bytes(name.as_bytes()).map_err(|e| (name, e)))
+        .transpose()
+    {
+        Ok(h) => h,
+        Err((name, e)) => {
+            let _ = writeln!(err, "Invalid groups header name '{name}': {e}");
+            return 1;
+        }
+    };
+
     let trusted_proxy = *sub_matches.get_one::<IpAddr>("trusted_proxy").unwrap();
 
     let auth_cfg = middleware::TrustedHeaderAuthConfig {
         enabled,
         header_name,
+        groups_header,
         trusted_proxy,
     };
 
@@ -190,7 +247,7 @@ fn serve<W1: Write, W2: Write>(sub_matches: &clap::ArgMatches, out: &mut W1, err
     let fwd_enabled = sub_matches.get_flag("trusted_forwarded_for");
 
     let fwd_header_str = sub_matches
-        .get_one::<String>("forwarded_for_header_name")
+        .get_one::<String>("trusted_forwarded_for_name")
         .map(|s| s.as_str())
         .unwrap_or("X-Forwarded-For");
 
@@ -218,6 +275,152 @@ fn serve<W1: Write, W2: Write>(sub_matches: &clap::ArgMatches, out: &mut W1, err
         );
     }
 
+    // ---- JWT assertion options ----
+    let jwt_cfg = match sub_matches.get_one::<String>("jwt_header") {
+        Some(name) => {
+            let header_name = match HeaderName::from_bytes(name.as_bytes()) {
+                Ok(h) => h,
+                Err(e) => {
+                    let _ = writeln!(err, "Invalid JWT header name '{name}': {e}");
+                    return 1;
+                }
+            };
+            let claim = |id: &str| sub_matches.get_one::<String>(id).unwrap().clone();
+            let cfg = jwt::JwtConfig {
+                header_name,
+                jwks: sub_matches
+                    .get_one::<jwt::JwksSource>("jwt_jwks")
+                    .unwrap()
+                    .clone(),
+                issuer: sub_matches.get_one::<String>("jwt_issuer").cloned(),
+                audience: sub_matches
+                    .get_many::<String>("jwt_audience")
+                    .map(|aud| aud.cloned().collect())
+                    .unwrap_or_default(),
+                leeway: std::time::Duration::from_secs(
+                    *sub_matches.get_one::<u64>("jwt_leeway").unwrap(),
+                ),
+                claims: jwt::ClaimNames {
+                    email: claim("jwt_email_claim"),
+                    groups: claim("jwt_groups_claim"),
+                    name: claim("jwt_name_claim"),
+                },
+            };
+            let _ = writeln!(
+                out,
+                "JWT assertions enabled: header='{name}', jwks='{}'",
+                cfg.jwks
+            );
+            Some(cfg)
+        }
+        None => None,
+    };
+
+    // ---- OpenID Connect options ----
+    let oidc_cfg = sub_matches
+        .get_one::<String>("oidc_issuer")
+        .map(|issuer| oidc::OidcConfig {
+            issuer: issuer.clone(),
+            client_id: sub_matches
+                .get_one::<String>("oidc_client_id")
+                .unwrap()
+                .clone(),
+            client_secret: sub_matches.get_one::<String>("oidc_client_secret").cloned(),
+            redirect_url: sub_matches
+                .get_one::<String>("oidc_redirect_url")
+                .unwrap()
+                .clone(),
+            scopes: sub_matches
+                .get_many::<String>("oidc_scopes")
+                .unwrap()
+                .cloned()
+                .collect(),
+        });
+    if let Some(cfg) = &oidc_cfg {
+        let _ = writeln!(
+            out,
+            "OpenID Connect login enabled: issuer='{}', redirect='{}'",
+            cfg.issuer, cfg.redirect_url
+        );
+    }
+    let session_cfg = session::SessionConfig {
+        lifetime: std::time::Duration::from_secs(
+            *sub_matches.get_one::<u64>("session_hours").unwrap() * 60 * 60,
+        ),
+        secure: !sub_matches.get_flag("insecure_cookies"),
+    };
+
+    let db_url = sub_matches.get_one::<String>("database_url").unwrap();
+    let db_cfg = db::DbConfig {
+        max_connections: *sub_matches.get_one::<u32>("db_max_connections").unwrap(),
+        read_max_connections: *sub_matches
+            .get_one::<u32>("db_read_max_connections")
+            .unwrap(),
+        acquire_timeout: std::time::Duration::from_secs(
+            *sub_matches.get_one::<u64>("db_acquire_timeout").unwrap(),
+        ),
+        busy_timeout: std::time::Duration::from_millis(
+            *sub_matches.get_one::<u64>("db_busy_timeout").unwrap(),
+        ),
+        synchronous: *sub_matches
+            .get_one::<SqliteSynchronous>("db_synchronous")
+            .unwrap(),
+        cache_size: *sub_matches.get_one::<i64>("db_cache_size").unwrap(),
+        mmap_size: *sub_matches.get_one::<u64>("db_mmap_size").unwrap(),
+        ..db::DbConfig::new(db_url)
+    };
+    let migrate = !sub_matches.get_flag("no_migrate");
+    let db_cfg = if sub_matches.get_flag("ephemeral") {
+        let _ = writeln!(out, "Ephemeral mode: data is kept in memory only");
+        None
+    } else {
+        Some(db_cfg)
+    };
+
+    // ---- Scheduled backup options ----
+    let backup_cfg = sub_matches
+        .get_one::<std::path::PathBuf>("backup_dir")
+        .map(|dir| backup::BackupConfig {
+            dir: dir.clone(),
+            interval: std::time::Duration::from_secs(
+                *sub_matches.get_one::<u64>("backup_interval").unwrap(),
+            ),
+            retention: *sub_matches.get_one::<usize>("backup_retention").unwrap(),
+            compress: sub_matches.get_flag("backup_compress"),
+        });
+
+    if let Some(cfg) = &backup_cfg {
+        let _ = writeln!(
+            out,
+            "Scheduled backups enabled: dir='{}', interval={}s, retention={}",
+            cfg.dir.display(),
+            cfg.interval.as_secs(),
+            cfg.retention
+        );
+    }
+
+    let values = |id: &str| -> Vec<String> {
+        sub_matches
+            .get_many::<String>(id)
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default()
+    };
+    let app_cfg = server::AppConfig {
+        trash_retention: std::time::Duration::from_secs(
+            *sub_matches.get_one::<u64>("trash_retention").unwrap() * 24 * 60 * 60,
+        ),
+        admins: values("admin_emails"),
+        role_groups: extract::RoleGroups {
+            admin: values("admin_groups"),
+            member: values("member_groups"),
+            read_only: values("read_only_groups"),
+        },
+        jwt: jwt_cfg,
+        oidc: oidc_cfg,
+        sessions: session_cfg,
+        allow_local_webhooks: sub_matches.get_flag("allow_local_webhooks"),
+    };
+
     let _ = writeln!(out, "Starting server on http://{addr}");
 
     let rt = match tokio::runtime::Runtime::new() {
@@ -228,7 +431,9 @@ fn serve<W1: Write, W2: Write>(sub_matches: &clap::ArgMatches, out: &mut W1, err
         }
     };
 
-    match rt.block_on(server::run(addr, auth_cfg, fwd_cfg)) {
+    match rt.block_on(server::run(
+        addr, db_cfg, migrate, backup_cfg, auth_cfg, fwd_cfg, app_cfg,
+    )) {
         Ok(()) => 0,
         Err(e) => {
             let _ = writeln!(err, "Server error: {e:#}");
@@ -1,19 +1,35 @@
 use axum::{
     body::Body,
     extract::{ConnectInfo, State},
-    http::{HeaderName, Request, StatusCode},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Request, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
 };
-use log::warn;
+use chrono::Utc;
+use log::{error, info, warn};
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
+use std::sync::Arc;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{
+    extract::effective_role,
+    jwt::JwtVerifier,
+    models::{ids::ApiTokenId, user::Role},
+    password::{LoginError, LoginThrottle},
+    repository::UserRepository,
+    session::{self, SESSION_COOKIE},
+    token, AppState,
+};
 
 /// Config for trusting an auth header from a forward-auth proxy (user/email).
 #[derive(Clone, Debug)]
 pub struct TrustedHeaderAuthConfig {
     pub enabled: bool,
     pub header_name: HeaderName,
+    /// The header to read the user's comma-separated groups from, if any.
+    pub groups_header: Option<HeaderName>,
     pub trusted_proxy: IpAddr,
 }
 
@@ -24,6 +40,7 @@ impl TrustedHeaderAuthConfig {
         Self {
             enabled: false,
             header_name: HeaderName::from_static("x-forwarded-user"),
+            groups_header: None,
             trusted_proxy: IpAddr::from([127, 0, 0, 1]),
         }
     }
@@ -49,21 +66,56 @@ impl TrustedForwardedForConfig {
     }
 }
 
-/// Authenticated user email extracted from a trusted header.
+/// Authenticated user email extracted from a trusted header (or an API
+/// token).
 #[derive(Clone, Debug)]
 pub struct AuthenticatedUser(#[allow(dead_code)] pub String);
 
+/// The groups a verified JWT assertion (or the trusted proxy's groups
+/// header) put the user in.
+#[derive(Clone, Debug)]
+pub struct AuthenticatedGroups(pub Vec<String>);
+
+/// The user's name from a verified JWT assertion.
+#[derive(Clone, Debug)]
+pub struct AuthenticatedName(pub String);
+
+/// The admin who authenticated a request acting as the
+/// AuthenticatedUser, with [`act_as`].
+#[derive(Clone, Debug)]
+pub struct RealUser(pub String);
+
 /// Client IP extracted from trusted forwarded-for header.
 #[derive(Clone, Debug)]
-pub struct ClientIp(#[allow(dead_code)] pub IpAddr);
+pub struct ClientIp(pub IpAddr);
+
+/// Paths (or, ending in `/`, path prefixes) that authenticate requests
+/// with a secret token or app password of their own, or sign users in,
+/// so the trusted proxy may pass requests for them on without a user
+/// header.
+const TOKEN_AUTH_PATHS: &[&str] = &[
+    "/todo.ics",
+    "/.well-known/caldav",
+    "/dav",
+    "/dav/",
+    "/auth/",
+];
+
+fn is_token_auth_path(path: &str) -> bool {
+    TOKEN_AUTH_PATHS
+        .iter()
+        .any(|p| path == *p || (p.ends_with('/') && path.starts_with(p)))
+}
 
 /// Middleware that enforces trusted-header auth for user/email.
 ///
 /// Rules:
 /// - If disabled: 403 if header present.
 /// - If enabled: only trusted proxy may send it (403 otherwise).
-/// - Header must be present and non-empty.
+/// - Header must be present and non-empty, except for [`TOKEN_AUTH_PATHS`]
+///   and requests [`api_token_auth`] already authenticated.
 /// - First comma-separated token treated as email.
+/// - The groups header, if configured, is stored as AuthenticatedGroups.
 pub async fn trusted_header_auth(
     State(cfg): State<TrustedHeaderAuthConfig>,
     ConnectInfo(peer): ConnectInfo<SocketAddr>,
@@ -96,6 +148,10 @@ pub async fn trusted_header_auth(
         return next.run(req).await;
     }
 
+    if req.extensions().get::<AuthenticatedUser>().is_some() {
+        return next.run(req).await;
+    }
+
     let email: String = {
         let raw = req
             .headers()
@@ -106,16 +162,345 @@ pub async fn trusted_header_auth(
 
         let first = match raw {
             Some(v) => v.split(',').next().unwrap().trim(),
+            None if is_token_auth_path(req.uri().path()) => return next.run(req).await,
             None => return StatusCode::UNAUTHORIZED.into_response(),
         };
 
         first.to_string()
     };
 
+    let groups = cfg
+        .groups_header
+        .as_ref()
+        .and_then(|name| req.headers().get(name))
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            v.split(',')
+                .map(str::trim)
+                .filter(|group| !group.is_empty())
+                .map(str::to_string)
+                .collect()
+        });
     req.extensions_mut().insert(AuthenticatedUser(email));
+    if let Some(groups) = groups {
+        req.extensions_mut().insert(AuthenticatedGroups(groups));
+    }
+    next.run(req).await
+}
+
+/// The API token a request was authenticated with.
+#[derive(Clone, Copy, Debug)]
+pub struct BearerToken(pub ApiTokenId);
+
+/// How stale an API token's last use may be before it is recorded again,
+/// so that busy scripts do not write on every request.
+const LAST_USED_PRECISION: chrono::Duration = chrono::Duration::minutes(1);
+
+/// Middleware that authenticates personal API tokens
+/// (`Authorization: Bearer TOKEN`) as their user.
+///
+/// Rules:
+/// - Requests without a bearer token pass through untouched.
+/// - Unknown or expired tokens, and those of trashed users: 401.
+/// - Requests outside the token's scopes: 403.
+/// - Otherwise the token's user is stored as AuthenticatedUser, and the
+///   token as BearerToken.
+pub async fn api_token_auth(
+    State(users): State<Arc<dyn UserRepository>>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, token)| token.trim());
+    // JWTs (with their dots) are left to jwt_auth.
+    let Some(token_hash) = bearer
+        .filter(|token| !token.contains('.'))
+        .map(token::digest)
+    else {
+        return next.run(req).await;
+    };
+
+    let (api_token, user) = match users.find_by_api_token(&token_hash).await {
+        Ok(Some(found)) => found,
+        Ok(None) => return bearer_error(StatusCode::UNAUTHORIZED, "invalid_token"),
+        Err(e) => {
+            error!("Failed to look up an API token: {e:#}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let now = Utc::now();
+    if api_token.expires_at <= now {
+        return bearer_error(StatusCode::UNAUTHORIZED, "invalid_token");
+    }
+    if !api_token.scopes.allow(req.method()) {
+        return bearer_error(StatusCode::FORBIDDEN, "insufficient_scope");
+    }
+    if api_token
+        .last_used_at
+        .is_none_or(|at| now - at >= LAST_USED_PRECISION)
+    {
+        let touched = users.touch_api_token(api_token.id, now).await;
+        if let Err(e) = touched {
+            error!(
+                "Failed to record the use of API token {}: {e:#}",
+                api_token.id.0
+            );
+        }
+    }
+
+    req.extensions_mut().insert(AuthenticatedUser(user.email));
+    req.extensions_mut().insert(BearerToken(api_token.id));
     next.run(req).await
 }
 
+/// Middleware that authenticates command line tools with the password
+/// of a local account (`Authorization: Basic`, with the email as the
+/// user name).
+///
+/// Rules:
+/// - Requests already authenticated, without Basic credentials, or for
+///   [`TOKEN_AUTH_PATHS`] (where they are app passwords): pass through.
+/// - Wrong credentials: 401; too many for one email from one client in a
+///   row: 429.
+/// - Otherwise the user is stored as AuthenticatedUser.
+pub async fn basic_auth(
+    State((users, logins)): State<(Arc<dyn UserRepository>, Arc<LoginThrottle>)>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    if req.extensions().get::<AuthenticatedUser>().is_some() || is_token_auth_path(req.uri().path())
+    {
+        return next.run(req).await;
+    }
+    let Some(credentials) = basic_scheme(req.headers()) else {
+        return next.run(req).await;
+    };
+    let Some((email, password)) = decode_basic(credentials) else {
+        return basic_challenge();
+    };
+
+    let client_ip = match req.extensions().get::<ClientIp>() {
+        Some(ClientIp(ip)) => *ip,
+        None => peer.ip(),
+    };
+    match logins
+        .authenticate(users.as_ref(), &email, &password, Some(client_ip))
+        .await
+    {
+        Ok(user) => {
+            req.extensions_mut().insert(AuthenticatedUser(user.email));
+            next.run(req).await
+        }
+        Err(LoginError::Invalid) => basic_challenge(),
+        Err(LoginError::Throttled(wait)) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, wait.as_secs().to_string())],
+        )
+            .into_response(),
+        Err(LoginError::Internal(e)) => {
+            error!("Failed to check a password: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// The user name and password of an `Authorization: Basic` header.
+pub fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    basic_scheme(headers).and_then(decode_basic)
+}
+
+/// The credentials of an `Authorization` header with the Basic scheme,
+/// which (like all schemes) is case-insensitive.
+fn basic_scheme(headers: &HeaderMap) -> Option<&str> {
+    let (scheme, credentials) = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("basic")
+        .then(|| credentials.trim())
+}
+
+/// The user name and password in base64 encoded Basic credentials.
+fn decode_basic(credentials: &str) -> Option<(String, String)> {
+    let decoded = String::from_utf8(STANDARD.decode(credentials).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+fn basic_challenge() -> Response {
+    let challenge = "Basic realm=\"${APP}\", charset=\"UTF-8\"";
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, challenge)],
+    )
+        .into_response()
+}
+
+fn bearer_error(status: StatusCode, error: &str) -> Response {
+    let challenge = format!("Bearer error=\"{error}\"");
+    (status, [(header::WWW_AUTHENTICATE, challenge)]).into_response()
+}
+
+/// Middleware that authenticates browsers by the session cookie they
+/// got from signing in with the app itself (`/auth/login`).
+///
+/// Rules:
+/// - Requests already authenticated, or without the cookie: pass through.
+/// - Unknown and expired sessions are ignored, like a missing cookie.
+/// - Otherwise the session's email is stored as AuthenticatedUser.
+pub async fn session_auth(
+    State(users): State<Arc<dyn UserRepository>>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    if req.extensions().get::<AuthenticatedUser>().is_some() {
+        return next.run(req).await;
+    }
+    let Some(token_hash) = session::cookie(req.headers(), SESSION_COOKIE).map(token::digest) else {
+        return next.run(req).await;
+    };
+
+    let session = match users.find_session(&token_hash).await {
+        Ok(session) => session.filter(|s| s.expires_at > Utc::now()),
+        Err(e) => {
+            error!("Failed to look up a session: {e:#}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if let Some(session) = session {
+        req.extensions_mut()
+            .insert(AuthenticatedUser(session.email));
+    }
+    next.run(req).await
+}
+
+/// Middleware that verifies a signed JWT assertion from a forward-auth
+/// proxy, when configured.
+///
+/// Rules:
+/// - If not configured, or the request was already authenticated (with
+///   an API token): pass through.
+/// - Requests without the header pass through (to trusted_header_auth).
+/// - Assertions that fail verification: 401.
+/// - Otherwise the claims are stored as AuthenticatedUser, and
+///   AuthenticatedGroups and AuthenticatedName.
+pub async fn jwt_auth(
+    State(verifier): State<Option<Arc<JwtVerifier>>>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    let Some(verifier) = verifier else {
+        return next.run(req).await;
+    };
+    if req.extensions().get::<AuthenticatedUser>().is_some() {
+        return next.run(req).await;
+    }
+    let Some(token) = req
+        .headers()
+        .get(verifier.header_name())
+        .and_then(|v| v.to_str().ok())
+    else {
+        return next.run(req).await;
+    };
+
+    let identity = match verifier.verify(token).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            warn!(
+                "JWT assertion in '{}' rejected: {e}",
+                verifier.header_name()
+            );
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    };
+    req.extensions_mut()
+        .insert(AuthenticatedUser(identity.email));
+    req.extensions_mut()
+        .insert(AuthenticatedGroups(identity.groups));
+    if let Some(name) = identity.name {
+        req.extensions_mut().insert(AuthenticatedName(name));
+    }
+    next.run(req).await
+}
+
+/// The header admins name the user to act as in.
+const ACT_AS_HEADER: &str = "x-act-as";
+/// The response headers naming who acted as whom.
+const REAL_USER_HEADER: &str = "x-real-user";
+const EFFECTIVE_USER_HEADER: &str = "x-effective-user";
+
+/// Middleware that lets admins act as another user (`X-Act-As: EMAIL`),
+/// to see exactly what they see.
+///
+/// Rules:
+/// - Requests without the header pass through.
+/// - Unauthenticated: 401; not an admin: 403; no such user: 404.
+/// - Otherwise the user acted as becomes the AuthenticatedUser (without
+///   the admin's AuthenticatedGroups), the admin is stored as RealUser,
+///   and the response names both in `X-Real-User` and
+///   `X-Effective-User`.
+pub async fn act_as(State(state): State<AppState>, mut req: Request<Body>, next: Next) -> Response {
+    let Some(target) = req
+        .headers()
+        .get(ACT_AS_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+    else {
+        return next.run(req).await;
+    };
+    let Some(AuthenticatedUser(real)) = req.extensions().get::<AuthenticatedUser>().cloned() else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    let stored = match state.users.find_by_email(&real).await {
+        Ok(user) => user.map(|user| user.role).unwrap_or_default(),
+        Err(e) => {
+            error!("Failed to look up {real}: {e:#}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if effective_role(&state, req.extensions(), &real, stored) != Role::Admin {
+        warn!("{real} may not act as {target}, not being an admin");
+        return (StatusCode::FORBIDDEN, "Only admins may act as other users").into_response();
+    }
+    match state.users.find_by_email(&target).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                format!("No user is registered for {target}"),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("Failed to look up {target}: {e:#}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    info!("{real} is acting as {target}");
+    req.extensions_mut().remove::<AuthenticatedGroups>();
+    req.extensions_mut()
+        .insert(AuthenticatedUser(target.clone()));
+    req.extensions_mut().insert(RealUser(real.clone()));
+    let mut res = next.run(req).await;
+    for (name, email) in [(REAL_USER_HEADER, real), (EFFECTIVE_USER_HEADER, target)] {
+        if let Ok(value) = HeaderValue::from_str(&email) {
+            res.headers_mut().insert(name, value);
+        }
+    }
+    res
+}
+
 /// Middleware that enforces trusted forwarded-for header for client IP.
 ///
 /// Rules:
@@ -1,3 +1,18 @@
+pub mod api_token;
+pub mod app_password;
+pub mod audit;
 pub mod ids;
+pub mod list;
+pub mod session;
+pub mod tag;
 pub mod todo;
 pub mod user;
+pub mod webhook;
+
+/// The version of a new user or todo. Every change bumps it.
+pub const FIRST_VERSION: i64 = 1;
+
+/// For records from dumps made before users and todos had versions.
+fn first_version() -> i64 {
+    FIRST_VERSION
+}
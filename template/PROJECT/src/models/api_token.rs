@@ -0,0 +1,163 @@
+use std::fmt;
+
+use axum::http::Method;
+use chrono::{DateTime, Duration, Utc};
+use serde::{ser::SerializeSeq, Deserialize, Serialize, Serializer};
+use sqlx::prelude::FromRow;
+use uuid::Uuid;
+
+use super::ids::{ApiTokenId, UserId};
+
+/// Longest allowed API token name, in characters.
+pub const MAX_API_TOKEN_NAME_LEN: usize = 100;
+/// How long API tokens last by default, and at most, in days.
+pub const DEFAULT_API_TOKEN_DAYS: i64 = 90;
+pub const MAX_API_TOKEN_DAYS: i64 = 365;
+
+/// What an API token may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// `GET`, `HEAD` and `OPTIONS` requests.
+    Read,
+    /// Requests of any method.
+    Write,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 2] = [Scope::Read, Scope::Write];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+        }
+    }
+
+    fn allows(self, method: &Method) -> bool {
+        match self {
+            Scope::Read => matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS),
+            Scope::Write => true,
+        }
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scope::ALL
+            .into_iter()
+            .find(|scope| scope.name() == s)
+            .ok_or_else(|| format!("unknown scope '{s}' (expected read or write)"))
+    }
+}
+
+/// The scopes of an API token: their names separated by commas.
+/// Serialized as a list of names.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::Type)]
+#[sqlx(transparent)]
+pub struct Scopes(String);
+
+impl Scopes {
+    /// Scopes with these names, rejecting unknown names and an empty list.
+    pub fn new<S: AsRef<str>>(names: &[S]) -> Result<Self, String> {
+        if names.is_empty() {
+            return Err("scopes must not be empty".to_string());
+        }
+        let mut scopes = Vec::new();
+        for name in names {
+            let scope: Scope = name.as_ref().trim().parse()?;
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+        let names: Vec<&str> = scopes.iter().map(|s| s.name()).collect();
+        Ok(Scopes(names.join(",")))
+    }
+
+    /// Whether a request with this method is in scope.
+    pub fn allow(&self, method: &Method) -> bool {
+        self.0
+            .split(',')
+            .filter_map(|name| name.parse::<Scope>().ok())
+            .any(|scope| scope.allows(method))
+    }
+}
+
+impl fmt::Display for Scopes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for Scopes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for name in self.0.split(',') {
+            seq.serialize_element(name)?;
+        }
+        seq.end()
+    }
+}
+
+/// A bearer token a user created for a script to call the API with
+/// (`Authorization: Bearer TOKEN`). Only its digest is stored.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ApiToken {
+    pub id: ApiTokenId,
+    pub user_id: UserId,
+    pub name: String,
+    pub scopes: Scopes,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// When the token was last used, to the minute or so.
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    /// A new API token record with a fresh id, created now and expiring
+    /// after `days`.
+    pub fn new(user_id: UserId, name: &str, scopes: Scopes, days: i64) -> Self {
+        let now = Utc::now();
+        Self {
+            id: ApiTokenId(Uuid::new_v4()),
+            user_id,
+            name: name.to_string(),
+            scopes,
+            created_at: now,
+            expires_at: now + Duration::days(days),
+            last_used_at: None,
+        }
+    }
+}
+
+/// Trim an API token name, rejecting empty and overlong names.
+pub fn normalize_api_token_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("API token name must not be empty".to_string());
+    }
+    if name.chars().count() > MAX_API_TOKEN_NAME_LEN {
+        return Err(format!(
+            "API token name must be at most {MAX_API_TOKEN_NAME_LEN} characters"
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// Public API token creation request data
+#[derive(Debug, Deserialize)]
+pub struct CreateApiToken {
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// How many days the token lasts (default 90, at most 365).
+    pub expires_in_days: Option<i64>,
+}
+
+/// A just created API token, the only time the secret is shown
+#[derive(Debug, Serialize)]
+pub struct NewApiToken {
+    #[serde(flatten)]
+    pub api_token: ApiToken,
+    pub token: String,
+}
@@ -0,0 +1,59 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use uuid::Uuid;
+
+use super::ids::{AppPasswordId, UserId};
+
+/// Longest allowed app password name, in characters.
+pub const MAX_APP_PASSWORD_NAME_LEN: usize = 100;
+
+/// A secret a user created for one app or device to sign in with HTTP
+/// Basic auth (as `email:password`). Only its digest is stored.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AppPassword {
+    pub id: AppPasswordId,
+    pub user_id: UserId,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AppPassword {
+    /// A new app password record with a fresh id, created now.
+    pub fn new(user_id: UserId, name: &str) -> Self {
+        Self {
+            id: AppPasswordId(Uuid::new_v4()),
+            user_id,
+            name: name.to_string(),
+            created_at: Utc::now(),
+        }
+    }
+}
+
+/// Trim an app password name, rejecting empty and overlong names.
+pub fn normalize_app_password_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("app password name must not be empty".to_string());
+    }
+    if name.chars().count() > MAX_APP_PASSWORD_NAME_LEN {
+        return Err(format!(
+            "app password name must be at most {MAX_APP_PASSWORD_NAME_LEN} characters"
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// Public app password creation request data
+#[derive(Debug, Deserialize)]
+pub struct CreateAppPassword {
+    pub name: String,
+}
+
+/// A just created app password, the only time the secret is shown
+#[derive(Debug, Serialize)]
+pub struct NewAppPassword {
+    #[serde(flatten)]
+    pub app_password: AppPassword,
+    pub password: String,
+}
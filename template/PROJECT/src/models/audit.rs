@@ -0,0 +1,113 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Serializer};
+use sqlx::prelude::FromRow;
+
+/// One change recorded in the append-only audit log: who made it, from
+/// where, and what it did to which record.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct AuditEntry {
+    /// Increases with every entry.
+    pub id: i64,
+    pub at: DateTime<Utc>,
+    /// The authenticated email the change was made as, if any.
+    pub actor: Option<String>,
+    /// The admin who made the change acting as `actor`, if one did.
+    pub real_actor: Option<String>,
+    pub client_ip: Option<String>,
+    /// The `X-Request-Id` of the request that made the change.
+    pub request_id: Option<String>,
+    /// What kind of record changed, eg. `todo`.
+    pub entity: String,
+    pub entity_id: String,
+    /// `create`, `update`, `delete` or `restore`.
+    pub action: String,
+    /// The changed fields as they were before, or the whole record if it
+    /// was deleted (JSON).
+    #[serde(serialize_with = "json_text")]
+    pub before: Option<String>,
+    /// The changed fields as they are after, or the whole record if it
+    /// was created or restored (JSON).
+    #[serde(serialize_with = "json_text")]
+    pub after: Option<String>,
+}
+
+/// Serialize stored JSON text as the JSON it holds.
+fn json_text<S: Serializer>(text: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    let value = text
+        .as_deref()
+        .map(serde_json::from_str::<serde_json::Value>)
+        .transpose()
+        .map_err(serde::ser::Error::custom)?;
+    value.serialize(serializer)
+}
+
+/// The kinds of records whose changes are audited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entity {
+    User,
+    List,
+    Todo,
+    Tag,
+    /// A tag on a todo, with the id `TODO_ID/TAG_ID`.
+    TodoTag,
+    AppPassword,
+    ApiToken,
+    /// A user's calendar feed token, with the user's id.
+    CalendarToken,
+    /// A user's password, with the user's id (never its hash).
+    Password,
+    Webhook,
+    Delivery,
+}
+
+impl Entity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Entity::User => "user",
+            Entity::List => "list",
+            Entity::Todo => "todo",
+            Entity::Tag => "tag",
+            Entity::TodoTag => "todo_tag",
+            Entity::AppPassword => "app_password",
+            Entity::ApiToken => "api_token",
+            Entity::CalendarToken => "calendar_token",
+            Entity::Password => "password",
+            Entity::Webhook => "webhook",
+            Entity::Delivery => "delivery",
+        }
+    }
+}
+
+impl fmt::Display for Entity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What a change did to its record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+    Restore,
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Update => "update",
+            Action::Delete => "delete",
+            Action::Restore => "restore",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
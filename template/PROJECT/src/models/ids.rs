@@ -14,22 +14,110 @@ pub struct UserId(pub Uuid);
 #[serde(transparent)]
 pub struct TodoId(pub Uuid);
 
-impl Type<Sqlite> for UserId {
-    fn type_info() -> SqliteTypeInfo {
-        <String as Type<Sqlite>>::type_info()
-    }
-}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TagId(pub Uuid);
 
-impl<'r> Decode<'r, Sqlite> for UserId {
-    fn decode(value: SqliteValueRef<'r>) -> Result<Self, BoxDynError> {
-        let s = <String as Decode<Sqlite>>::decode(value)?;
-        Ok(UserId(Uuid::from_str(&s)?))
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ListId(pub Uuid);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct AppPasswordId(pub Uuid);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ApiTokenId(pub Uuid);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SessionId(pub Uuid);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct WebhookId(pub Uuid);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DeliveryId(pub Uuid);
+
+// SQLite stores ids as UUID text.
+macro_rules! impl_sqlite_uuid {
+    ($id:ident) => {
+        impl Type<Sqlite> for $id {
+            fn type_info() -> SqliteTypeInfo {
+                <String as Type<Sqlite>>::type_info()
+            }
+        }
+
+        impl<'r> Decode<'r, Sqlite> for $id {
+            fn decode(value: SqliteValueRef<'r>) -> Result<Self, BoxDynError> {
+                let s = <String as Decode<Sqlite>>::decode(value)?;
+                Ok($id(Uuid::from_str(&s)?))
+            }
+        }
+
+        impl<'q> Encode<'q, Sqlite> for $id {
+            fn encode_by_ref(
+                &self,
+                buf: &mut Vec<SqliteArgumentValue<'q>>,
+            ) -> Result<IsNull, BoxDynError> {
+                let s = self.0.to_string();
+                <String as Encode<Sqlite>>::encode_by_ref(&s, buf)
+            }
+        }
+    };
 }
 
-impl<'q> Encode<'q, Sqlite> for UserId {
-    fn encode_by_ref(&self, buf: &mut Vec<SqliteArgumentValue<'q>>) -> Result<IsNull, BoxDynError> {
-        let s = self.0.to_string();
-        <String as Encode<Sqlite>>::encode_by_ref(&s, buf)
+impl_sqlite_uuid!(UserId);
+impl_sqlite_uuid!(TodoId);
+impl_sqlite_uuid!(TagId);
+impl_sqlite_uuid!(ListId);
+impl_sqlite_uuid!(AppPasswordId);
+impl_sqlite_uuid!(ApiTokenId);
+impl_sqlite_uuid!(SessionId);
+impl_sqlite_uuid!(WebhookId);
+impl_sqlite_uuid!(DeliveryId);
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use sqlx::postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef};
+    use sqlx::{Decode, Encode, Postgres, Type};
+    use uuid::Uuid;
+
+    use super::*;
+
+    // Postgres stores ids in native UUID columns.
+    macro_rules! impl_pg_uuid {
+        ($id:ident) => {
+            impl Type<Postgres> for $id {
+                fn type_info() -> PgTypeInfo {
+                    <Uuid as Type<Postgres>>::type_info()
+                }
+            }
+
+            impl<'r> Decode<'r, Postgres> for $id {
+                fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+                    Ok($id(<Uuid as Decode<Postgres>>::decode(value)?))
+                }
+            }
+
+            impl<'q> Encode<'q, Postgres> for $id {
+                fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+                    <Uuid as Encode<Postgres>>::encode_by_ref(&self.0, buf)
+                }
+            }
+        };
     }
+
+    impl_pg_uuid!(UserId);
+    impl_pg_uuid!(TodoId);
+    impl_pg_uuid!(TagId);
+    impl_pg_uuid!(ListId);
+    impl_pg_uuid!(AppPasswordId);
+    impl_pg_uuid!(ApiTokenId);
+    impl_pg_uuid!(SessionId);
+    impl_pg_uuid!(WebhookId);
+    impl_pg_uuid!(DeliveryId);
 }
@@ -0,0 +1,100 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use uuid::Uuid;
+
+use super::ids::{ListId, UserId};
+
+/// Longest allowed list name, in characters.
+pub const MAX_LIST_NAME_LEN: usize = 100;
+
+/// A named group of todos. Every user has one `inbox` list, which new
+/// todos go to by default and which cannot be archived or deleted.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct TodoList {
+    pub id: ListId,
+    pub user_id: UserId,
+    pub name: String,
+    /// `#rrggbb`
+    pub color: Option<String>,
+    pub archived: bool,
+    /// Sort order among the user's lists (ascending).
+    pub position: i64,
+    pub inbox: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// When the list was moved to the trash.
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl TodoList {
+    /// A new list with a fresh id, created now.
+    pub fn new(user_id: UserId, name: &str, color: Option<String>, position: i64) -> Self {
+        let now = Utc::now();
+        Self {
+            id: ListId(Uuid::new_v4()),
+            user_id,
+            name: name.to_string(),
+            color,
+            archived: false,
+            position,
+            inbox: false,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+        }
+    }
+
+    /// The inbox list created along with each user.
+    pub fn inbox(user_id: UserId) -> Self {
+        Self {
+            inbox: true,
+            ..Self::new(user_id, "Inbox", None, 0)
+        }
+    }
+}
+
+/// Public list creation request data
+#[derive(Debug, Deserialize)]
+pub struct CreateList {
+    pub name: String,
+    pub color: Option<String>,
+}
+
+/// Public list update request data (an empty `color` clears it)
+#[derive(Debug, Deserialize)]
+pub struct UpdateList {
+    pub name: Option<String>,
+    pub color: Option<String>,
+    pub archived: Option<bool>,
+    pub position: Option<i64>,
+}
+
+/// Trim a list name, rejecting empty and overlong names.
+pub fn normalize_list_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("list name must not be empty".to_string());
+    }
+    if name.chars().count() > MAX_LIST_NAME_LEN {
+        return Err(format!(
+            "list name must be at most {MAX_LIST_NAME_LEN} characters"
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// Lowercase a `#rrggbb` color, rejecting anything else. An empty color
+/// means no color.
+pub fn normalize_color(color: &str) -> Result<Option<String>, String> {
+    let color = color.trim();
+    if color.is_empty() {
+        return Ok(None);
+    }
+    let hex = color.strip_prefix('#').unwrap_or_default();
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("color must look like #rrggbb, not '{color}'"));
+    }
+    Ok(Some(color.to_ascii_lowercase()))
+}
@@ -0,0 +1,30 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::prelude::FromRow;
+use uuid::Uuid;
+
+use super::ids::SessionId;
+
+/// A browser's session from signing in with the app itself, carried in
+/// a cookie. Only the digest of the cookie's secret is stored.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Session {
+    pub id: SessionId,
+    /// Who signed in, which need not be a registered user yet.
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Session {
+    /// A new session with a fresh id, started now and lasting `lifetime`.
+    pub fn new(email: &str, lifetime: Duration) -> Self {
+        let now = Utc::now();
+        Self {
+            id: SessionId(Uuid::new_v4()),
+            email: email.to_string(),
+            created_at: now,
+            expires_at: now + lifetime,
+        }
+    }
+}
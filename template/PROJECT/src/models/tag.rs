@@ -0,0 +1,51 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+
+use super::ids::{TagId, TodoId, UserId};
+
+/// Longest allowed tag name, in characters.
+pub const MAX_TAG_NAME_LEN: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Tag {
+    pub id: TagId,
+    pub user_id: UserId,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A tag along with how many todos carry it.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct TagCount {
+    pub id: TagId,
+    pub name: String,
+    pub todos: i64,
+}
+
+/// One todo carrying one tag.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct TodoTag {
+    pub todo_id: TodoId,
+    pub tag_id: TagId,
+}
+
+/// Public request data naming a tag (to create, rename or apply it)
+#[derive(Debug, Deserialize)]
+pub struct TagName {
+    pub name: String,
+}
+
+/// Trim a tag name, rejecting empty and overlong names.
+pub fn normalize_tag_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("tag name must not be empty".to_string());
+    }
+    if name.chars().count() > MAX_TAG_NAME_LEN {
+        return Err(format!(
+            "tag name must be at most {MAX_TAG_NAME_LEN} characters"
+        ));
+    }
+    Ok(name.to_string())
+}
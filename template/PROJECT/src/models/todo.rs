@@ -1,19 +1,172 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 
-use super::ids::{TodoId, UserId};
+use super::ids::{ListId, TodoId, UserId};
+
+/// How many levels deep subtasks may be nested (a top-level todo is
+/// level 1).
+pub const MAX_TODO_DEPTH: usize = 5;
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Todo {
     pub id: TodoId,
     pub user_id: UserId,
+    pub list_id: ListId,
+    /// The todo this is a subtask of.
+    pub parent_id: Option<TodoId>,
     pub title: String,
     pub notes: Option<String>,
     pub completed: bool,
     pub due_at: Option<DateTime<Utc>>,
+    /// RFC 5545 recurrence rule: completing the todo creates the next
+    /// occurrence.
+    pub rrule: Option<String>,
+    /// Schedule the next occurrence from when this one was completed,
+    /// rather than from its due date.
+    pub repeat_from_completion: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Bumped by every change, and served as the todo's ETag.
+    #[serde(default = "super::first_version")]
+    pub version: i64,
+    /// When the todo was moved to the trash.
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// A todo's parent, as dumped separately from the todos themselves so
+/// that they can be loaded in any order.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Subtask {
+    pub todo_id: TodoId,
+    pub parent_id: TodoId,
+}
+
+/// Completed and total counts of a todo's subtasks, at every level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Progress {
+    pub completed: usize,
+    pub total: usize,
+}
+
+/// A todo with its subtasks.
+#[derive(Debug, Clone, Serialize)]
+pub struct TodoNode {
+    #[serde(flatten)]
+    pub todo: Todo,
+    pub progress: Progress,
+    pub subtasks: Vec<TodoNode>,
+}
+
+impl TodoNode {
+    /// Arrange a todo's subtree (the todo and all of its descendants)
+    /// into a tree, keeping subtasks in their given order.
+    pub fn tree(root: TodoId, subtree: Vec<Todo>) -> Option<TodoNode> {
+        let mut root_todo = None;
+        let mut children: HashMap<TodoId, Vec<Todo>> = HashMap::new();
+        for todo in subtree {
+            match todo.parent_id {
+                _ if todo.id == root => root_todo = Some(todo),
+                Some(parent_id) => children.entry(parent_id).or_default().push(todo),
+                None => {}
+            }
+        }
+        root_todo.map(|todo| Self::build(todo, &mut children))
+    }
+
+    fn build(todo: Todo, children: &mut HashMap<TodoId, Vec<Todo>>) -> TodoNode {
+        let subtasks: Vec<TodoNode> = children
+            .remove(&todo.id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|child| Self::build(child, children))
+            .collect();
+        let progress = subtasks.iter().fold(Progress::default(), |p, s| Progress {
+            completed: p.completed + s.progress.completed + usize::from(s.todo.completed),
+            total: p.total + s.progress.total + 1,
+        });
+        TodoNode {
+            todo,
+            progress,
+            subtasks,
+        }
+    }
+}
+
+/// How many levels deep `todos` go, counting those whose parent is not
+/// among them as level 1.
+pub fn tree_height(todos: &[Todo]) -> usize {
+    let parents: HashMap<TodoId, Option<TodoId>> =
+        todos.iter().map(|t| (t.id, t.parent_id)).collect();
+    todos
+        .iter()
+        .map(|t| {
+            let mut level = 1;
+            let mut parent = t.parent_id;
+            while let Some(next) = parent.and_then(|id| parents.get(&id)) {
+                level += 1;
+                parent = *next;
+            }
+            level
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Public request data to move a todo under another todo (or to the top
+/// level, with `null`)
+#[derive(Debug, Deserialize)]
+pub struct SetParent {
+    pub parent_id: Option<TodoId>,
+}
+
+/// Public request data to complete (or reopen) a todo
+#[derive(Debug, Deserialize)]
+pub struct SetCompleted {
+    pub completed: bool,
+    /// Apply to all of the todo's subtasks too.
+    #[serde(default)]
+    pub cascade: bool,
+}
+
+/// Public request data to make a todo recur (or stop recurring, with a
+/// `null` rule)
+#[derive(Debug, Deserialize)]
+pub struct SetRecurrence {
+    pub rrule: Option<String>,
+    #[serde(default)]
+    pub repeat_from_completion: bool,
+}
+
+/// A todo that was just completed, and the next occurrence created for
+/// it if it recurs
+#[derive(Debug, Serialize)]
+pub struct Completed {
+    #[serde(flatten)]
+    pub todo: Todo,
+    pub next: Option<Todo>,
+}
+
+/// Public Todo import row data (any other exported fields are ignored)
+#[derive(Debug, Deserialize)]
+pub struct ImportTodo {
+    pub title: String,
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub completed: bool,
+    pub due_at: Option<DateTime<Utc>>,
+    pub rrule: Option<String>,
+    #[serde(default)]
+    pub repeat_from_completion: bool,
+}
+
+/// Public request data to move a todo to another list
+#[derive(Debug, Deserialize)]
+pub struct MoveTodo {
+    pub list_id: ListId,
 }
 
 // #[derive(Debug, Deserialize)]
@@ -23,10 +176,22 @@ pub struct Todo {
 //     pub due_at: Option<DateTime<Utc>>,
 // }
 
-// #[derive(Debug, Deserialize)]
-// pub struct UpdateTodo {
-//     pub title: Option<String>,
-//     pub notes: Option<Option<String>>, // Some(None) means “clear notes”
-//     pub completed: Option<bool>,
-//     pub due_at: Option<Option<DateTime<Utc>>>,
-// }
+/// Public todo update request data (completion, lists, parents and
+/// recurrence have their own endpoints)
+#[derive(Debug, Deserialize)]
+pub struct UpdateTodo {
+    pub title: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    pub notes: Option<Option<String>>, // Some(None) means “clear notes”
+    #[serde(default, deserialize_with = "present")]
+    pub due_at: Option<Option<DateTime<Utc>>>,
+}
+
+/// Deserialize a field that is present, even as `null`, as `Some`.
+fn present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
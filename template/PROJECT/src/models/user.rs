@@ -1,7 +1,66 @@
+use std::fmt;
+
 use super::ids::UserId;
 use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
+use uuid::Uuid;
+
+/// What a user may do, from least to most.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum Role {
+    /// May look at their own data, but not change anything.
+    ReadOnly,
+    /// May change their own data.
+    #[default]
+    Member,
+    /// May also manage users and use the admin endpoints.
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::ReadOnly, Role::Member, Role::Admin];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::ReadOnly => "read_only",
+            Role::Member => "member",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.name() == s)
+            .ok_or_else(|| format!("unknown role '{s}' (expected read_only, member or admin)"))
+    }
+}
 
 /// Internal User object
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -10,6 +69,31 @@ pub struct User {
     pub email: String,
     pub display_name: String,
     pub created_at: i64,
+    /// Bumped by every change, and served as the user's ETag.
+    #[serde(default = "super::first_version")]
+    pub version: i64,
+    /// When the user was moved to the trash (unix seconds).
+    #[serde(default)]
+    pub deleted_at: Option<i64>,
+    /// The role stored for the user, which proxy groups and
+    /// `ADMIN_EMAILS` may override per request.
+    #[serde(default)]
+    pub role: Role,
+}
+
+impl User {
+    /// A new user with a fresh id, created now.
+    pub fn new(email: &str, display_name: &str) -> Self {
+        Self {
+            id: UserId(Uuid::new_v4()),
+            email: email.to_string(),
+            display_name: display_name.to_string(),
+            created_at: Utc::now().timestamp(),
+            version: super::FIRST_VERSION,
+            deleted_at: None,
+            role: Role::default(),
+        }
+    }
 }
 
 /// Public User registration request data
@@ -19,6 +103,27 @@ pub struct CreateUser {
     pub display_name: String,
 }
 
+/// A user created by an admin, who may give them a role.
+#[derive(Debug, Deserialize)]
+pub struct AdminCreateUser {
+    pub email: String,
+    pub display_name: String,
+    #[serde(default)]
+    pub role: Role,
+}
+
+/// Public User update request data
+#[derive(Debug, Deserialize)]
+pub struct UpdateUser {
+    pub display_name: Option<String>,
+}
+
+/// An admin's change to a user's role.
+#[derive(Debug, Deserialize)]
+pub struct UpdateRole {
+    pub role: Role,
+}
+
 /// Public User response object
 #[derive(Debug, Serialize)]
 pub struct PublicUser {
@@ -26,6 +131,8 @@ pub struct PublicUser {
     pub email: String,
     pub display_name: String,
     pub created_at: DateTime<Utc>,
+    pub version: i64,
+    pub role: Role,
 }
 
 impl From<User> for PublicUser {
@@ -40,6 +147,8 @@ impl From<User> for PublicUser {
             email: u.email,
             display_name: u.display_name,
             created_at,
+            version: u.version,
+            role: u.role,
         }
     }
 }
@@ -0,0 +1,248 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use serde::{ser::SerializeSeq, Deserialize, Serialize, Serializer};
+use serde_json::json;
+use sqlx::prelude::FromRow;
+use uuid::Uuid;
+
+use super::ids::{DeliveryId, UserId, WebhookId};
+
+/// Longest allowed webhook URL, in bytes.
+pub const MAX_WEBHOOK_URL_LEN: usize = 2000;
+
+/// Something that happened, which webhooks and live clients are told
+/// about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    UserCreated,
+    UserUpdated,
+    UserDeleted,
+    /// A deleted user brought back from the trash.
+    UserRestored,
+    TodoCreated,
+    TodoUpdated,
+    TodoCompleted,
+    TodoDeleted,
+    /// A deleted todo brought back from the trash.
+    TodoRestored,
+    ListCreated,
+    ListUpdated,
+    ListDeleted,
+    /// A deleted list brought back from the trash.
+    ListRestored,
+}
+
+impl Event {
+    pub const ALL: [Event; 13] = [
+        Event::UserCreated,
+        Event::UserUpdated,
+        Event::UserDeleted,
+        Event::UserRestored,
+        Event::TodoCreated,
+        Event::TodoUpdated,
+        Event::TodoCompleted,
+        Event::TodoDeleted,
+        Event::TodoRestored,
+        Event::ListCreated,
+        Event::ListUpdated,
+        Event::ListDeleted,
+        Event::ListRestored,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::UserCreated => "user.created",
+            Event::UserUpdated => "user.updated",
+            Event::UserDeleted => "user.deleted",
+            Event::UserRestored => "user.restored",
+            Event::TodoCreated => "todo.created",
+            Event::TodoUpdated => "todo.updated",
+            Event::TodoCompleted => "todo.completed",
+            Event::TodoDeleted => "todo.deleted",
+            Event::TodoRestored => "todo.restored",
+            Event::ListCreated => "list.created",
+            Event::ListUpdated => "list.updated",
+            Event::ListDeleted => "list.deleted",
+            Event::ListRestored => "list.restored",
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Event {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Event::ALL
+            .into_iter()
+            .find(|e| e.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Event::ALL.iter().map(|e| e.name()).collect();
+                format!(
+                    "unknown event '{s}' (expected * or one of {})",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// The events a webhook subscribes to: their names separated by commas,
+/// or `*` for every event. Serialized as a list of names.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::Type)]
+#[sqlx(transparent)]
+pub struct EventFilter(String);
+
+impl EventFilter {
+    /// A filter for these event names (or `*`), rejecting unknown names
+    /// and an empty list.
+    pub fn new<S: AsRef<str>>(names: &[S]) -> Result<Self, String> {
+        if names.is_empty() {
+            return Err("events must not be empty".to_string());
+        }
+        if names.iter().any(|name| name.as_ref().trim() == "*") {
+            return Ok(EventFilter("*".to_string()));
+        }
+        let mut events = Vec::new();
+        for name in names {
+            let event = Event::from_str(name.as_ref().trim())?;
+            if !events.contains(&event) {
+                events.push(event);
+            }
+        }
+        let names: Vec<&str> = events.iter().map(|e| e.name()).collect();
+        Ok(EventFilter(names.join(",")))
+    }
+
+    pub fn matches(&self, event: Event) -> bool {
+        self.0 == "*" || self.0.split(',').any(|name| name == event.name())
+    }
+}
+
+impl fmt::Display for EventFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for EventFilter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for name in self.0.split(',') {
+            seq.serialize_element(name)?;
+        }
+        seq.end()
+    }
+}
+
+/// A URL that gets a signed `POST` for each event it subscribes to.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Webhook {
+    pub id: WebhookId,
+    /// The user whose events it gets, or `None` for a global webhook,
+    /// which gets every user's events.
+    pub user_id: Option<UserId>,
+    pub url: String,
+    pub events: EventFilter,
+    /// The HMAC-SHA256 key payloads are signed with.
+    #[serde(skip)]
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Webhook {
+    /// A new webhook with a fresh id, created now.
+    pub fn new(user_id: Option<UserId>, url: &str, events: EventFilter, secret: &str) -> Self {
+        Self {
+            id: WebhookId(Uuid::new_v4()),
+            user_id,
+            url: url.to_string(),
+            events,
+            secret: secret.to_string(),
+            created_at: Utc::now(),
+        }
+    }
+}
+
+/// Trim a webhook URL, rejecting anything but absolute `http(s)` URLs.
+pub fn normalize_webhook_url(url: &str) -> Result<String, String> {
+    let url = url.trim();
+    if url.len() > MAX_WEBHOOK_URL_LEN {
+        return Err(format!(
+            "url must be at most {MAX_WEBHOOK_URL_LEN} characters"
+        ));
+    }
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(url.to_string()),
+        Ok(_) => Err("url must be an http or https URL".to_string()),
+        Err(e) => Err(format!("invalid url: {e}")),
+    }
+}
+
+/// One event to deliver to one webhook: an entry in the outbox, kept
+/// afterwards as a log of how its delivery went.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Delivery {
+    pub id: DeliveryId,
+    pub webhook_id: WebhookId,
+    pub event: String,
+    /// The JSON body posted to the webhook.
+    pub payload: String,
+    pub attempts: i64,
+    /// When to attempt it next, while it is pending.
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    /// The HTTP status code of the last response.
+    pub last_status: Option<i64>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Delivery {
+    /// A new pending delivery of an event, due now. Its payload carries
+    /// the delivery's id, the event's name and the event's data.
+    pub fn new(webhook_id: WebhookId, event: Event, data: &serde_json::Value) -> Self {
+        let id = DeliveryId(Uuid::new_v4());
+        let now = Utc::now();
+        let payload = json!({
+            "id": id,
+            "event": event.name(),
+            "created_at": now,
+            "data": data,
+        });
+        Self {
+            id,
+            webhook_id,
+            event: event.name().to_string(),
+            payload: payload.to_string(),
+            attempts: 0,
+            next_attempt_at: Some(now),
+            last_attempt_at: None,
+            last_status: None,
+            last_error: None,
+            delivered_at: None,
+            created_at: now,
+        }
+    }
+}
+
+/// Public webhook creation request data
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhook {
+    pub url: String,
+    pub events: Vec<String>,
+}
+
+/// A just created webhook, the only time its secret is shown
+#[derive(Debug, Serialize)]
+pub struct NewWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
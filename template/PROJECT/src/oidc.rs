@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    jwt::{ClaimNames, Identity, Jwks, JwksSource},
+    token,
+};
+
+/// How long users have to sign in at the issuer and come back.
+pub const LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// How many logins may be in progress at once. Starting a login is
+/// unauthenticated, so beyond this the oldest ones are abandoned rather
+/// than letting the map grow without bound.
+const MAX_PENDING_LOGINS: usize = 10_000;
+/// How far the issuer's clock may be off when checking ID tokens.
+const LEEWAY: Duration = Duration::from_secs(60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Config for signing users in with an OpenID Connect issuer, for
+/// deployments without a forward-auth proxy.
+#[derive(Clone, Debug)]
+pub struct OidcConfig {
+    /// The issuer's URL, under which `/.well-known/openid-configuration`
+    /// is found.
+    pub issuer: String,
+    pub client_id: String,
+    /// The secret of a confidential client. Public clients rely on PKCE
+    /// alone.
+    pub client_secret: Option<String>,
+    /// This app's `/auth/callback` URL, as registered with the issuer.
+    pub redirect_url: String,
+    pub scopes: Vec<String>,
+}
+
+/// The parts of the issuer's discovery document used here.
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+    end_session_endpoint: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// A login sent to the issuer, waiting for the user to come back.
+#[derive(Debug)]
+struct PendingLogin {
+    /// The PKCE code verifier.
+    verifier: String,
+    nonce: String,
+    return_to: String,
+    started_at: Instant,
+}
+
+/// Runs the authorization code flow (with PKCE) against the issuer.
+/// Logins in progress are only kept in memory, so a restart abandons
+/// them.
+#[derive(Debug)]
+pub struct OidcClient {
+    cfg: OidcConfig,
+    issuer: String,
+    authorization_endpoint: Url,
+    token_endpoint: String,
+    end_session_endpoint: Option<Url>,
+    jwks: Arc<Jwks>,
+    client: reqwest::Client,
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+impl OidcClient {
+    /// A client for the configured issuer, failing if its discovery
+    /// document or keys cannot be fetched.
+    pub async fn discover(cfg: OidcConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .user_agent("${APP}")
+            .build()?;
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            cfg.issuer.trim_end_matches('/')
+        );
+        let body = client
+            .get(&url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .with_context(|| format!("fetching OpenID configuration from {url}"))?
+            .bytes()
+            .await?;
+        let discovery: Discovery = serde_json::from_slice(&body)
+            .with_context(|| format!("parsing OpenID configuration from {url}"))?;
+        if discovery.issuer.trim_end_matches('/') != cfg.issuer.trim_end_matches('/') {
+            anyhow::bail!(
+                "OpenID configuration from {url} is for issuer {}",
+                discovery.issuer
+            );
+        }
+        let authorization_endpoint = Url::parse(&discovery.authorization_endpoint)
+            .context("invalid authorization_endpoint")?;
+        let end_session_endpoint = discovery
+            .end_session_endpoint
+            .as_deref()
+            .map(Url::parse)
+            .transpose()
+            .context("invalid end_session_endpoint")?;
+        let jwks = Arc::new(Jwks::new(JwksSource::Url(discovery.jwks_uri)).await?);
+        Ok(Self {
+            cfg,
+            issuer: discovery.issuer,
+            authorization_endpoint,
+            token_endpoint: discovery.token_endpoint,
+            end_session_endpoint,
+            jwks,
+            client,
+            pending: Mutex::default(),
+        })
+    }
+
+    pub fn jwks(&self) -> Arc<Jwks> {
+        self.jwks.clone()
+    }
+
+    /// Start a login that returns to `return_to` once done. Returns the
+    /// login's `state`, which the browser must bring back, and the URL
+    /// to send the browser to.
+    pub fn start(&self, return_to: &str) -> (String, String) {
+        let state = token::generate();
+        let nonce = token::generate();
+        let verifier = token::generate();
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+
+        let mut url = self.authorization_endpoint.clone();
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.cfg.client_id)
+            .append_pair("redirect_uri", &self.cfg.redirect_url)
+            .append_pair("scope", &self.cfg.scopes.join(" "))
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        let login = PendingLogin {
+            verifier,
+            nonce,
+            return_to: return_to.to_string(),
+            started_at: Instant::now(),
+        };
+        remember(
+            &mut self.pending.lock().unwrap(),
+            &state,
+            login,
+            MAX_PENDING_LOGINS,
+        );
+        (state, url.into())
+    }
+
+    /// Finish the login with this `state`, which the issuer sent back
+    /// with `code`: redeem the code for an ID token and verify it.
+    /// Returns who signed in, and where they were going.
+    pub async fn finish(&self, state: &str, code: &str) -> Result<(Identity, String), String> {
+        let login = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(state)
+            .filter(|login| login.started_at.elapsed() < LOGIN_TIMEOUT)
+            .ok_or("unknown or expired login")?;
+
+        let form = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.cfg.redirect_url),
+            ("client_id", &self.cfg.client_id),
+            ("code_verifier", &login.verifier),
+        ];
+        let mut req = self.client.post(&self.token_endpoint).form(&form);
+        if let Some(secret) = &self.cfg.client_secret {
+            req = req.basic_auth(&self.cfg.client_id, Some(secret));
+        }
+        let res = req
+            .send()
+            .await
+            .map_err(|e| format!("token request failed: {e}"))?;
+        let status = res.status();
+        let body = res
+            .bytes()
+            .await
+            .map_err(|e| format!("token request failed: {e}"))?;
+        if !status.is_success() {
+            let body = String::from_utf8_lossy(&body);
+            return Err(format!("token endpoint answered {status}: {body}"));
+        }
+        let tokens: TokenResponse =
+            serde_json::from_slice(&body).map_err(|e| format!("invalid token response: {e}"))?;
+
+        let claims = self
+            .jwks
+            .decode(
+                &tokens.id_token,
+                Some(&self.issuer),
+                std::slice::from_ref(&self.cfg.client_id),
+                LEEWAY,
+            )
+            .await?;
+        if claims.get("nonce").and_then(Value::as_str) != Some(login.nonce.as_str()) {
+            return Err("ID token nonce does not match the login".to_string());
+        }
+        if claims.get("email_verified") == Some(&Value::Bool(false)) {
+            return Err("the issuer has not verified the email".to_string());
+        }
+        let identity = ClaimNames::default().identity(&claims)?;
+        Ok((identity, login.return_to))
+    }
+
+    /// Where to send users who signed out, to sign out at the issuer too
+    /// (if it supports that).
+    pub fn logout_url(&self) -> Option<String> {
+        let mut url = self.end_session_endpoint.clone()?;
+        url.query_pairs_mut()
+            .append_pair("client_id", &self.cfg.client_id);
+        Some(url.into())
+    }
+}
+
+/// Add a pending login, forgetting those that have timed out and, if
+/// there are still `max` of them, the oldest.
+fn remember(
+    pending: &mut HashMap<String, PendingLogin>,
+    state: &str,
+    login: PendingLogin,
+    max: usize,
+) {
+    pending.retain(|_, login| login.started_at.elapsed() < LOGIN_TIMEOUT);
+    while pending.len() >= max {
+        let Some(oldest) = pending
+            .iter()
+            .min_by_key(|(_, login)| login.started_at)
+            .map(|(state, _)| state.clone())
+        else {
+            break;
+        };
+        pending.remove(&oldest);
+    }
+    pending.insert(state.to_string(), login);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_logins_are_capped() {
+        let login = |started_at| PendingLogin {
+            verifier: String::new(),
+            nonce: String::new(),
+            return_to: "/".to_string(),
+            started_at,
+        };
+        let now = Instant::now();
+        let mut pending = HashMap::new();
+        remember(&mut pending, "expired", login(now - LOGIN_TIMEOUT), 3);
+        remember(&mut pending, "a", login(now - Duration::from_secs(2)), 3);
+        remember(&mut pending, "b", login(now - Duration::from_secs(1)), 3);
+        assert_eq!(pending.len(), 2);
+        remember(&mut pending, "c", login(now), 3);
+        remember(&mut pending, "d", login(now), 3);
+        let mut left: Vec<&str> = pending.keys().map(String::as_str).collect();
+        left.sort();
+        assert_eq!(left, ["b", "c", "d"]);
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+
+use uuid::Uuid;
+
+use crate::{models::user::User, repository::UserRepository};
+
+/// Shortest allowed password, in characters.
+pub const MIN_PASSWORD_LEN: usize = 8;
+/// Longest allowed password, in bytes, so hashing stays cheap.
+const MAX_PASSWORD_LEN: usize = 1024;
+/// How many wrong passwords an email may get in a row from one client
+/// before that client is locked out of it for [`LOCKOUT`].
+const MAX_FAILURES: u32 = 5;
+const LOCKOUT: Duration = Duration::from_secs(15 * 60);
+/// How many email and client pairs the throttle keeps counts for.
+/// Signing in is unauthenticated, so beyond this the oldest counts are
+/// forgotten (those that have not locked a client out first) rather than
+/// letting the map grow without bound.
+const MAX_TRACKED: usize = 10_000;
+
+/// Reject passwords that are too short (or absurdly long).
+pub fn validate(password: &str) -> Result<(), String> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(format!(
+            "Password must be at least {MIN_PASSWORD_LEN} characters"
+        ));
+    }
+    if password.len() > MAX_PASSWORD_LEN {
+        return Err(format!("Password must be at most {MAX_PASSWORD_LEN} bytes"));
+    }
+    Ok(())
+}
+
+/// A random salt, borrowing the randomness of a v4 UUID.
+fn salt() -> SaltString {
+    SaltString::encode_b64(Uuid::new_v4().as_bytes()).expect("16 bytes make a valid salt")
+}
+
+/// The Argon2id hash of a password, as a PHC string with a random salt.
+pub async fn hash(password: &str) -> anyhow::Result<String> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || {
+        let salt = salt();
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| anyhow::anyhow!("hashing password: {e}"))
+    })
+    .await?
+}
+
+/// Whether `password` matches the hash. Without a hash, a dummy one is
+/// checked instead, so that unknown emails take as long as wrong
+/// passwords.
+async fn verify(password: &str, password_hash: Option<String>) -> anyhow::Result<bool> {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    let password = password.to_string();
+    let known = password_hash.is_some();
+    tokio::task::spawn_blocking(move || {
+        let password_hash = match password_hash {
+            Some(hash) => hash,
+            None => DUMMY
+                .get_or_init(|| {
+                    let salt = salt();
+                    Argon2::default()
+                        .hash_password(b"not a password", &salt)
+                        .unwrap()
+                        .to_string()
+                })
+                .clone(),
+        };
+        let parsed = PasswordHash::new(&password_hash)
+            .map_err(|e| anyhow::anyhow!("invalid password hash: {e}"))?;
+        let matches = Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok();
+        Ok(known && matches)
+    })
+    .await?
+}
+
+/// Why signing in with a password failed.
+#[derive(Debug)]
+pub enum LoginError {
+    /// Unknown email, no password set, or the wrong password.
+    Invalid,
+    /// Too many wrong passwords; try again after this long.
+    Throttled(Duration),
+    Internal(anyhow::Error),
+}
+
+/// A lowercased email and the address of the client signing in with it.
+type ThrottleKey = (String, Option<IpAddr>);
+
+/// Counts wrong passwords per email and client IP address, locking the
+/// client out of the email for a while after [`MAX_FAILURES`] in a row.
+/// Keying on the client too means guessing someone's password from one
+/// address does not lock them out everywhere else. Kept in memory only.
+#[derive(Debug, Default)]
+pub struct LoginThrottle {
+    /// The number of attempts in a row that have not succeeded, and when
+    /// the last one started.
+    failures: Mutex<HashMap<ThrottleKey, (u32, Instant)>>,
+}
+
+impl LoginThrottle {
+    /// Count an attempt as failed until it [succeeds](Self::succeeded),
+    /// or return how long until the client may try again if it is locked
+    /// out. Checking and counting happen under one lock, so concurrent
+    /// guesses cannot all get past the check.
+    fn attempt(&self, key: &ThrottleKey) -> Result<(), Duration> {
+        count_attempt(&mut self.failures.lock().unwrap(), key, MAX_TRACKED)
+    }
+
+    fn succeeded(&self, key: &ThrottleKey) {
+        self.failures.lock().unwrap().remove(key);
+    }
+
+    /// The user with this email, if `password` is theirs.
+    pub async fn authenticate(
+        &self,
+        users: &dyn UserRepository,
+        email: &str,
+        password: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<User, LoginError> {
+        let email = email.trim();
+        // Locked out however the email is spelled.
+        let key = (email.to_lowercase(), client_ip);
+        self.attempt(&key).map_err(LoginError::Throttled)?;
+        let user = users
+            .find_by_email(email)
+            .await
+            .map_err(LoginError::Internal)?;
+        let password_hash = match &user {
+            Some(user) => users
+                .password_hash(user.id)
+                .await
+                .map_err(LoginError::Internal)?,
+            None => None,
+        };
+        let matches = verify(password, password_hash)
+            .await
+            .map_err(LoginError::Internal)?;
+        match user {
+            Some(user) if matches => {
+                self.succeeded(&key);
+                Ok(user)
+            }
+            _ => Err(LoginError::Invalid),
+        }
+    }
+}
+
+/// Count an attempt by `key` in `failures`, which keeps at most `max`
+/// keys, unless it is locked out.
+fn count_attempt(
+    failures: &mut HashMap<ThrottleKey, (u32, Instant)>,
+    key: &ThrottleKey,
+    max: usize,
+) -> Result<(), Duration> {
+    failures.retain(|_, (_, last)| last.elapsed() < LOCKOUT);
+    while !failures.contains_key(key) && failures.len() >= max {
+        let Some(oldest) = failures
+            .iter()
+            .min_by_key(|(_, (count, last))| (*count >= MAX_FAILURES, *last))
+            .map(|(key, _)| key.clone())
+        else {
+            break;
+        };
+        failures.remove(&oldest);
+    }
+    let (count, last) = failures.entry(key.clone()).or_insert((0, Instant::now()));
+    if *count >= MAX_FAILURES {
+        return Err(LOCKOUT.saturating_sub(last.elapsed()));
+    }
+    *count += 1;
+    *last = Instant::now();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::memory::MemoryRepository;
+
+    #[tokio::test]
+    async fn throttles_guesses_per_email_and_client() {
+        let users = MemoryRepository::default();
+        let user = users
+            .insert(&User::new("a@example.com", "A"))
+            .await
+            .unwrap();
+        let hash = hash("correct horse").await.unwrap();
+        users.set_password_hash(user.id, Some(&hash)).await.unwrap();
+        let throttle = LoginThrottle::default();
+        let (guesser, owner) = (Some([10, 0, 0, 1].into()), Some([10, 0, 0, 2].into()));
+
+        // Concurrent guesses are counted before any of them is checked:
+        let guesses = (0..MAX_FAILURES + 3)
+            .map(|_| throttle.authenticate(&users, "a@example.com", "wrong", guesser));
+        let results = futures_util::future::join_all(guesses).await;
+        let throttled = results
+            .iter()
+            .filter(|r| matches!(r, Err(LoginError::Throttled(_))))
+            .count();
+        assert_eq!(throttled, 3);
+        let login = throttle.authenticate(&users, "A@example.com", "correct horse", guesser);
+        assert!(matches!(login.await, Err(LoginError::Throttled(_))));
+
+        // ...but only from the guesser's address:
+        let login = throttle.authenticate(&users, "a@example.com", "correct horse", owner);
+        assert_eq!(login.await.unwrap().id, user.id);
+    }
+
+    #[test]
+    fn throttle_counts_are_capped() {
+        let mut failures = HashMap::new();
+        let key = |email: &str| (email.to_string(), None);
+        for _ in 0..MAX_FAILURES {
+            count_attempt(&mut failures, &key("locked"), 2).unwrap();
+        }
+        count_attempt(&mut failures, &key("a"), 2).unwrap();
+        count_attempt(&mut failures, &key("b"), 2).unwrap();
+        assert_eq!(failures.len(), 2);
+        assert!(failures.contains_key(&key("b")));
+
+        // Lockouts are the last to be forgotten:
+        assert!(count_attempt(&mut failures, &key("locked"), 2).is_err());
+    }
+}
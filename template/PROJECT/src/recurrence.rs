@@ -0,0 +1,530 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use uuid::Uuid;
+
+use crate::{
+    models::{ids::TodoId, todo::Todo, FIRST_VERSION},
+    prelude::*,
+    repository::{TagRepository, TodoRepository},
+};
+
+/// How often a recurrence rule repeats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// A recurrence rule: the subset of RFC 5545 RRULEs with `FREQ` (daily,
+/// weekly, monthly or yearly), `INTERVAL`, `COUNT`, `UNTIL`, `BYMONTH`,
+/// `BYMONTHDAY` and `BYDAY` (where ordinals like `-1FR` count within the
+/// month). Rules are evaluated in UTC.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RRule {
+    pub freq: Frequency,
+    pub interval: u32,
+    /// Total number of occurrences, counting the first.
+    pub count: Option<u32>,
+    pub until: Option<DateTime<Utc>>,
+    pub by_month: Vec<u32>,
+    pub by_month_day: Vec<i32>,
+    pub by_day: Vec<(Option<i32>, Weekday)>,
+}
+
+/// Give up looking for the next occurrence after this many periods in a
+/// row without one (e.g. for a rule that asks for February 30th).
+const MAX_EMPTY_PERIODS: u32 = 2000;
+
+impl FromStr for RRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = match s.get(..6) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("RRULE:") => &s[6..],
+            _ => s,
+        };
+        let mut freq = None;
+        let mut rule = RRule {
+            freq: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_month: Vec::new(),
+            by_month_day: Vec::new(),
+            by_day: Vec::new(),
+        };
+        for part in s.split(';').filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected NAME=VALUE, not '{part}'"))?;
+            let value = value.to_ascii_uppercase();
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    freq = Some(match value.as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        other => return Err(format!("unsupported FREQ: {other}")),
+                    })
+                }
+                "INTERVAL" => rule.interval = parse_number(&value, 1, 1000, "INTERVAL")?,
+                "COUNT" => rule.count = Some(parse_number(&value, 1, 10_000, "COUNT")?),
+                "UNTIL" => rule.until = Some(parse_until(&value)?),
+                "BYMONTH" => {
+                    rule.by_month = list(&value, |v| parse_number(v, 1, 12, "BYMONTH"))?;
+                }
+                "BYMONTHDAY" => {
+                    rule.by_month_day = list(&value, |v| {
+                        let day: i32 = parse_number(v, -31, 31, "BYMONTHDAY")?;
+                        match day {
+                            0 => Err("BYMONTHDAY must not be 0".to_string()),
+                            day => Ok(day),
+                        }
+                    })?;
+                }
+                "BYDAY" => rule.by_day = list(&value, parse_weekday)?,
+                "WKST" if value == "MO" => {}
+                other => return Err(format!("unsupported RRULE part: {other}")),
+            }
+        }
+        rule.freq = freq.ok_or("RRULE must have a FREQ")?;
+        if rule.count.is_some() && rule.until.is_some() {
+            return Err("RRULE must not have both COUNT and UNTIL".to_string());
+        }
+        Ok(rule)
+    }
+}
+
+fn parse_number<T: FromStr + PartialOrd + fmt::Display>(
+    value: &str,
+    min: T,
+    max: T,
+    name: &str,
+) -> Result<T, String> {
+    match value.parse::<T>() {
+        Ok(n) if n >= min && n <= max => Ok(n),
+        _ => Err(format!("{name} must be between {min} and {max}")),
+    }
+}
+
+fn parse_until(value: &str) -> Result<DateTime<Utc>, String> {
+    let error = || format!("UNTIL must look like 20301231 or 20301231T235959Z, not '{value}'");
+    let (date, time) = match value.split_once('T') {
+        Some((date, time)) => {
+            let time = time.strip_suffix('Z').unwrap_or(time);
+            (
+                date,
+                NaiveTime::parse_from_str(time, "%H%M%S").map_err(|_| error())?,
+            )
+        }
+        // A date alone includes the whole day:
+        None => (value, NaiveTime::from_hms_opt(23, 59, 59).unwrap()),
+    };
+    let date = NaiveDate::parse_from_str(date, "%Y%m%d").map_err(|_| error())?;
+    Ok(Utc.from_utc_datetime(&date.and_time(time)))
+}
+
+fn list<T>(value: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<Vec<T>, String> {
+    value.split(',').map(parse).collect()
+}
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("MO", Weekday::Mon),
+    ("TU", Weekday::Tue),
+    ("WE", Weekday::Wed),
+    ("TH", Weekday::Thu),
+    ("FR", Weekday::Fri),
+    ("SA", Weekday::Sat),
+    ("SU", Weekday::Sun),
+];
+
+fn parse_weekday(value: &str) -> Result<(Option<i32>, Weekday), String> {
+    let split = value.len().saturating_sub(2);
+    let (ordinal, day) = value.split_at(split);
+    let weekday = WEEKDAYS
+        .iter()
+        .find(|(name, _)| *name == day)
+        .map(|(_, weekday)| *weekday)
+        .ok_or_else(|| format!("unknown BYDAY weekday: '{value}'"))?;
+    let ordinal = match ordinal {
+        "" => None,
+        n => match parse_number(n.trim_start_matches('+'), -5, 5, "BYDAY ordinal")? {
+            0 => return Err("BYDAY ordinal must not be 0".to_string()),
+            n => Some(n),
+        },
+    };
+    Ok((ordinal, weekday))
+}
+
+impl fmt::Display for RRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let freq = match self.freq {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        };
+        write!(f, "FREQ={freq}")?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={count}")?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%dT%H%M%SZ"))?;
+        }
+        let join = |items: Vec<String>| items.join(",");
+        if !self.by_month.is_empty() {
+            let months = self.by_month.iter().map(u32::to_string).collect();
+            write!(f, ";BYMONTH={}", join(months))?;
+        }
+        if !self.by_month_day.is_empty() {
+            let days = self.by_month_day.iter().map(i32::to_string).collect();
+            write!(f, ";BYMONTHDAY={}", join(days))?;
+        }
+        if !self.by_day.is_empty() {
+            let days = self
+                .by_day
+                .iter()
+                .map(|(ordinal, weekday)| {
+                    let name = WEEKDAYS.iter().find(|(_, w)| w == weekday).unwrap().0;
+                    match ordinal {
+                        Some(n) => format!("{n}{name}"),
+                        None => name.to_string(),
+                    }
+                })
+                .collect();
+            write!(f, ";BYDAY={}", join(days))?;
+        }
+        Ok(())
+    }
+}
+
+impl RRule {
+    /// The occurrences after `start`, in a series that begins at `start`
+    /// (which counts as the first occurrence towards `COUNT`).
+    pub fn after(&self, start: DateTime<Utc>) -> impl Iterator<Item = DateTime<Utc>> + '_ {
+        let remaining = self.count.map_or(usize::MAX, |c| c as usize - 1);
+        Occurrences {
+            rule: self,
+            start,
+            period: 0,
+            empty_periods: 0,
+            pending: VecDeque::new(),
+        }
+        .filter(move |at| *at > start)
+        .take_while(move |at| self.until.is_none_or(|until| *at <= until))
+        .take(remaining)
+    }
+
+    /// This rule for the series after its first occurrence.
+    fn advance(&self) -> Self {
+        RRule {
+            count: self.count.map(|c| c.saturating_sub(1)),
+            ..self.clone()
+        }
+    }
+
+    fn month_days(&self, year: i32, month: u32, start: NaiveDate) -> Vec<NaiveDate> {
+        let Some(first) = NaiveDate::from_ymd_opt(year, month, 1) else {
+            return Vec::new();
+        };
+        let days_in_month = first
+            .checked_add_months(chrono::Months::new(1))
+            .map_or(31, |next| (next - first).num_days() as i32);
+        let dates = |days: Vec<i32>| -> Vec<NaiveDate> {
+            days.into_iter()
+                .filter_map(|d| NaiveDate::from_ymd_opt(year, month, d as u32))
+                .collect()
+        };
+        if self.by_month_day.is_empty() && self.by_day.is_empty() {
+            return dates(vec![start.day() as i32]);
+        }
+        let mut days: Vec<NaiveDate> = Vec::new();
+        if !self.by_month_day.is_empty() {
+            days = dates(
+                self.by_month_day
+                    .iter()
+                    .map(|&d| if d < 0 { days_in_month + d + 1 } else { d })
+                    .filter(|&d| d >= 1 && d <= days_in_month)
+                    .collect(),
+            );
+        }
+        if !self.by_day.is_empty() {
+            let all: Vec<NaiveDate> = dates((1..=days_in_month).collect());
+            let matching: Vec<NaiveDate> = all
+                .iter()
+                .copied()
+                .filter(|date| {
+                    self.by_day.iter().any(|(ordinal, weekday)| {
+                        if date.weekday() != *weekday {
+                            return false;
+                        }
+                        let same: Vec<&NaiveDate> =
+                            all.iter().filter(|d| d.weekday() == *weekday).collect();
+                        let index = same.iter().position(|d| *d == date).unwrap() as i32;
+                        match ordinal {
+                            None => true,
+                            Some(n) if *n > 0 => index == n - 1,
+                            Some(n) => index == same.len() as i32 + n,
+                        }
+                    })
+                })
+                .collect();
+            days = if self.by_month_day.is_empty() {
+                matching
+            } else {
+                days.into_iter().filter(|d| matching.contains(d)).collect()
+            };
+        }
+        days.sort();
+        days.dedup();
+        days
+    }
+
+    fn matches(&self, date: NaiveDate) -> bool {
+        let month_ok = self.by_month.is_empty() || self.by_month.contains(&date.month());
+        let day_ok = self.by_month_day.is_empty()
+            || self
+                .month_days(date.year(), date.month(), date)
+                .contains(&date);
+        let weekday_ok = self.by_day.is_empty()
+            || self
+                .by_day
+                .iter()
+                .any(|(_, weekday)| *weekday == date.weekday());
+        month_ok && day_ok && weekday_ok
+    }
+
+    /// The dates in the `period`th period of a series beginning on `start`.
+    fn period_dates(&self, start: NaiveDate, period: u32) -> Vec<NaiveDate> {
+        let step = period.saturating_mul(self.interval);
+        match self.freq {
+            Frequency::Daily => {
+                let date = start + Duration::days(step as i64);
+                if self.matches(date) {
+                    vec![date]
+                } else {
+                    Vec::new()
+                }
+            }
+            Frequency::Weekly => {
+                let monday = start - Duration::days(start.weekday().num_days_from_monday() as i64)
+                    + Duration::weeks(step as i64);
+                let weekdays: Vec<Weekday> = if self.by_day.is_empty() {
+                    vec![start.weekday()]
+                } else {
+                    self.by_day.iter().map(|(_, weekday)| *weekday).collect()
+                };
+                let mut dates: Vec<NaiveDate> = weekdays
+                    .into_iter()
+                    .map(|w| monday + Duration::days(w.num_days_from_monday() as i64))
+                    .filter(|date| {
+                        self.by_month.is_empty() || self.by_month.contains(&date.month())
+                    })
+                    .collect();
+                dates.sort();
+                dates.dedup();
+                dates
+            }
+            Frequency::Monthly => {
+                let months = start.month0() + step;
+                let year = start.year() + (months / 12) as i32;
+                let month = months % 12 + 1;
+                if !self.by_month.is_empty() && !self.by_month.contains(&month) {
+                    return Vec::new();
+                }
+                self.month_days(year, month, start)
+            }
+            Frequency::Yearly => {
+                let year = start.year() + step as i32;
+                let months: Vec<u32> = if !self.by_month.is_empty() {
+                    self.by_month.clone()
+                } else if self.by_month_day.is_empty() && self.by_day.is_empty() {
+                    vec![start.month()]
+                } else {
+                    (1..=12).collect()
+                };
+                let mut dates: Vec<NaiveDate> = months
+                    .into_iter()
+                    .flat_map(|month| self.month_days(year, month, start))
+                    .collect();
+                dates.sort();
+                dates
+            }
+        }
+    }
+}
+
+/// Every date matching a rule, on or after the start of the series, at
+/// the start's time of day.
+struct Occurrences<'a> {
+    rule: &'a RRule,
+    start: DateTime<Utc>,
+    period: u32,
+    empty_periods: u32,
+    pending: VecDeque<DateTime<Utc>>,
+}
+
+impl Iterator for Occurrences<'_> {
+    type Item = DateTime<Utc>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            if self.empty_periods >= MAX_EMPTY_PERIODS {
+                return None;
+            }
+            let start = self.start.date_naive();
+            let time = self.start.time();
+            self.pending.extend(
+                self.rule
+                    .period_dates(start, self.period)
+                    .into_iter()
+                    .filter(|date| *date >= start)
+                    .map(|date| Utc.from_utc_datetime(&date.and_time(time))),
+            );
+            self.period += 1;
+            if self.pending.is_empty() {
+                self.empty_periods += 1;
+            } else {
+                self.empty_periods = 0;
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+/// Where the series of a recurring todo completed at `completed_at`
+/// continues from: its due date, or the completion date (at the due time
+/// of day) when it repeats from completion or has no due date.
+pub fn series_start(todo: &Todo, completed_at: DateTime<Utc>) -> DateTime<Utc> {
+    match todo.due_at {
+        Some(due_at) if !todo.repeat_from_completion => due_at,
+        Some(due_at) => Utc.from_utc_datetime(&completed_at.date_naive().and_time(due_at.time())),
+        None => completed_at,
+    }
+}
+
+/// The next occurrence of a recurring todo that was completed at
+/// `completed_at`, or `None` if it does not recur (any more). Its due
+/// date is the first occurrence after [`series_start`].
+pub fn next_occurrence(todo: &Todo, completed_at: DateTime<Utc>) -> Option<Todo> {
+    let rule = match todo.rrule.as_deref().map(RRule::from_str)? {
+        Ok(rule) => rule,
+        Err(e) => {
+            warn!("Todo {} has an invalid RRULE: {e}", todo.id.0);
+            return None;
+        }
+    };
+    let due_at = rule.after(series_start(todo, completed_at)).next()?;
+    Some(Todo {
+        id: TodoId(Uuid::new_v4()),
+        completed: false,
+        due_at: Some(due_at),
+        rrule: Some(rule.advance().to_string()),
+        created_at: completed_at,
+        updated_at: completed_at,
+        version: FIRST_VERSION,
+        ..todo.clone()
+    })
+}
+
+/// Create the next occurrence of `todo`, which was just completed, with
+/// the same tags. Returns `None` if it does not recur (any more).
+pub async fn schedule_next(
+    todos: &dyn TodoRepository,
+    tags: &dyn TagRepository,
+    todo: &Todo,
+) -> anyhow::Result<Option<Todo>> {
+    let Some(next) = next_occurrence(todo, todo.updated_at) else {
+        return Ok(None);
+    };
+    if let Some((_, e)) = todos
+        .insert_many(std::slice::from_ref(&next), false)
+        .await?
+        .pop()
+    {
+        anyhow::bail!(
+            "could not create the next occurrence of todo {}: {e}",
+            todo.id.0
+        );
+    }
+    for tag in tags.for_todo(todo.id).await? {
+        tags.add_to_todo(next.id, tag.id).await?;
+    }
+    Ok(Some(next))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn upcoming(rule: &str, start: &str, n: usize) -> Vec<String> {
+        let rule: RRule = rule.parse().unwrap();
+        rule.after(at(start))
+            .take(n)
+            .map(|d| d.format("%Y-%m-%d %a").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn parse_round_trips() {
+        let rule: RRule = "rrule:freq=monthly;interval=2;byday=-1fr,+1MO;count=3"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            rule.to_string(),
+            "FREQ=MONTHLY;INTERVAL=2;COUNT=3;BYDAY=-1FR,1MO"
+        );
+        for bad in [
+            "",
+            "INTERVAL=2",
+            "FREQ=HOURLY",
+            "FREQ=DAILY;BYDAY=XX",
+            "FREQ=DAILY;BYSETPOS=1",
+        ] {
+            assert!(bad.parse::<RRule>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn expands_rules() {
+        let start = "2030-01-31T09:00:00Z";
+        assert_eq!(
+            upcoming("FREQ=DAILY;INTERVAL=2", start, 2),
+            ["2030-02-02 Sat", "2030-02-04 Mon"]
+        );
+        assert_eq!(
+            upcoming("FREQ=WEEKLY;BYDAY=MO,FR", start, 3),
+            ["2030-02-01 Fri", "2030-02-04 Mon", "2030-02-08 Fri"]
+        );
+        // Months without a 31st are skipped:
+        assert_eq!(
+            upcoming("FREQ=MONTHLY", start, 2),
+            ["2030-03-31 Sun", "2030-05-31 Fri"]
+        );
+        assert_eq!(
+            upcoming("FREQ=MONTHLY;BYDAY=-1FR", start, 2),
+            ["2030-02-22 Fri", "2030-03-29 Fri"]
+        );
+        assert_eq!(
+            upcoming("FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=29", start, 1),
+            ["2032-02-29 Sun"]
+        );
+        // COUNT includes the start; UNTIL is inclusive:
+        assert_eq!(upcoming("FREQ=DAILY;COUNT=3", start, 9).len(), 2);
+        assert_eq!(upcoming("FREQ=DAILY;UNTIL=20300202", start, 9).len(), 2);
+        assert!(upcoming("FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=30", start, 1).is_empty());
+    }
+}
@@ -0,0 +1,323 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::{stream::BoxStream, TryStreamExt};
+
+use crate::models::{
+    api_token::ApiToken,
+    app_password::AppPassword,
+    audit::AuditEntry,
+    ids::{ApiTokenId, AppPasswordId, DeliveryId, ListId, TagId, TodoId, UserId, WebhookId},
+    list::TodoList,
+    session::Session,
+    tag::{Tag, TagCount, TodoTag},
+    todo::Todo,
+    user::{Role, User},
+    webhook::{Delivery, Webhook},
+};
+
+pub mod memory;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod sqlite;
+
+/// Storage for users. Trashed users (and their lists and todos) are
+/// left out of everything but the [`TrashRepository`].
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// Insert a new user along with their inbox list, returning the user
+    /// as stored.
+    async fn insert(&self, user: &User) -> anyhow::Result<User>;
+    async fn get(&self, id: UserId) -> anyhow::Result<Option<User>>;
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<Option<User>>;
+    /// All users, ordered by email.
+    async fn list(&self) -> anyhow::Result<Vec<User>>;
+    /// Set a user's display name if they are still at `version` (when
+    /// given). `None` if no such user, or if they have changed since.
+    async fn set_display_name(
+        &self,
+        id: UserId,
+        display_name: &str,
+        version: Option<i64>,
+    ) -> anyhow::Result<Option<User>>;
+    /// Set a user's role. `None` if no such user.
+    async fn set_role(&self, id: UserId, role: Role) -> anyhow::Result<Option<User>>;
+    /// Move a user and all of their lists and todos to the trash, if they
+    /// are still at `version` (when given). Returns `false` if no such
+    /// user, or if they have changed since.
+    async fn delete(&self, id: UserId, version: Option<i64>) -> anyhow::Result<bool>;
+    /// Set the SHA-256 hex digest of a user's calendar feed token,
+    /// replacing any previous token.
+    async fn set_calendar_token(&self, id: UserId, token_hash: &str) -> anyhow::Result<()>;
+    /// Returns `false` if the user had no calendar feed token.
+    async fn delete_calendar_token(&self, id: UserId) -> anyhow::Result<bool>;
+    /// The user whose calendar feed token has this digest.
+    async fn find_by_calendar_token(&self, token_hash: &str) -> anyhow::Result<Option<User>>;
+    /// Set, or with `None` remove, the Argon2id hash of a user's
+    /// password. Returns `false` if no such user.
+    async fn set_password_hash(
+        &self,
+        id: UserId,
+        password_hash: Option<&str>,
+    ) -> anyhow::Result<bool>;
+    /// The hash of a user's password, if they have one.
+    async fn password_hash(&self, id: UserId) -> anyhow::Result<Option<String>>;
+    /// Store an app password with the digest of its secret.
+    async fn insert_app_password(
+        &self,
+        app_password: &AppPassword,
+        token_hash: &str,
+    ) -> anyhow::Result<AppPassword>;
+    /// A user's app passwords in creation order.
+    async fn app_passwords(&self, id: UserId) -> anyhow::Result<Vec<AppPassword>>;
+    /// Returns `false` if the user had no such app password.
+    async fn delete_app_password(
+        &self,
+        id: UserId,
+        app_password_id: AppPasswordId,
+    ) -> anyhow::Result<bool>;
+    /// The user with an app password whose secret has this digest.
+    async fn find_by_app_password(&self, token_hash: &str) -> anyhow::Result<Option<User>>;
+    /// Store an API token with the digest of its secret.
+    async fn insert_api_token(
+        &self,
+        api_token: &ApiToken,
+        token_hash: &str,
+    ) -> anyhow::Result<ApiToken>;
+    /// A user's API tokens (including expired ones) in creation order.
+    async fn api_tokens(&self, id: UserId) -> anyhow::Result<Vec<ApiToken>>;
+    /// Returns `false` if the user had no such API token.
+    async fn delete_api_token(&self, id: UserId, api_token_id: ApiTokenId) -> anyhow::Result<bool>;
+    /// The API token whose secret has this digest (expired or not), and
+    /// its user.
+    async fn find_by_api_token(&self, token_hash: &str)
+        -> anyhow::Result<Option<(ApiToken, User)>>;
+    /// Record that an API token was used at `at`.
+    async fn touch_api_token(
+        &self,
+        api_token_id: ApiTokenId,
+        at: DateTime<Utc>,
+    ) -> anyhow::Result<()>;
+    /// Store a browser session with the digest of its cookie's secret.
+    async fn insert_session(&self, session: &Session, token_hash: &str) -> anyhow::Result<Session>;
+    /// The session whose cookie's secret has this digest (expired or not).
+    async fn find_session(&self, token_hash: &str) -> anyhow::Result<Option<Session>>;
+    /// Returns `false` if there was no such session.
+    async fn delete_session(&self, token_hash: &str) -> anyhow::Result<bool>;
+    /// Delete the sessions that expired before `before`, returning how
+    /// many were.
+    async fn purge_sessions(&self, before: DateTime<Utc>) -> anyhow::Result<u64>;
+    /// End all sessions for `email`, returning how many there were.
+    async fn delete_sessions(&self, email: &str) -> anyhow::Result<u64>;
+}
+
+/// Which todos to return from [`TodoRepository::stream`].
+#[derive(Clone, Debug, Default)]
+pub struct TodoFilter {
+    pub user_id: Option<UserId>,
+    pub completed: Option<bool>,
+    pub list_id: Option<ListId>,
+    /// Only todos carrying a tag with this name.
+    pub tag: Option<String>,
+}
+
+/// Storage for todos, leaving out trashed ones. Every change to a todo
+/// bumps its version.
+#[async_trait]
+pub trait TodoRepository: Send + Sync {
+    async fn get(&self, id: TodoId) -> anyhow::Result<Option<Todo>>;
+    /// Matching todos in creation order, fetched incrementally.
+    fn stream(&self, filter: TodoFilter) -> BoxStream<'static, anyhow::Result<Todo>>;
+    async fn list(&self, filter: TodoFilter) -> anyhow::Result<Vec<Todo>> {
+        self.stream(filter).try_collect().await
+    }
+    /// Complete (or reopen) a todo if it is still at `version` (when
+    /// given). `None` if no such todo, or if it has changed since.
+    async fn set_completed(
+        &self,
+        id: TodoId,
+        completed: bool,
+        version: Option<i64>,
+    ) -> anyhow::Result<Option<Todo>>;
+    /// Complete (or reopen) a todo along with all of its subtasks, if the
+    /// todo is still at `version` (when given). `None` if no such todo, or
+    /// if it has changed since, in which case nothing is changed.
+    async fn set_subtree_completed(
+        &self,
+        id: TodoId,
+        completed: bool,
+        version: Option<i64>,
+    ) -> anyhow::Result<Option<Todo>>;
+    /// Move a todo to another list.
+    async fn set_list(&self, id: TodoId, list_id: ListId) -> anyhow::Result<Option<Todo>>;
+    /// Make a todo a subtask of another todo, or a top-level todo.
+    async fn set_parent(
+        &self,
+        id: TodoId,
+        parent_id: Option<TodoId>,
+    ) -> anyhow::Result<Option<Todo>>;
+    /// Save a todo's title, notes, completion, due date and recurrence if
+    /// it is still at `todo.version`. `None` if no such todo, or if it has
+    /// changed since.
+    async fn update(&self, todo: &Todo) -> anyhow::Result<Option<Todo>>;
+    /// Set (or with `None`, clear) a todo's recurrence rule.
+    async fn set_recurrence(
+        &self,
+        id: TodoId,
+        rrule: Option<&str>,
+        repeat_from_completion: bool,
+    ) -> anyhow::Result<Option<Todo>>;
+    /// A todo and all of its subtasks at every level, in creation order.
+    /// Empty if no such todo.
+    async fn subtree(&self, id: TodoId) -> anyhow::Result<Vec<Todo>>;
+    /// Move a todo and all of its subtasks to the trash, if it is still at
+    /// `version` (when given). Returns `false` if no such todo, or if it
+    /// has changed since.
+    async fn delete(&self, id: TodoId, version: Option<i64>) -> anyhow::Result<bool>;
+    /// Insert todos (keeping their ids) in a single transaction.
+    ///
+    /// Returns the index and error of every rejected todo. Nothing is
+    /// committed if any todo was rejected, or if `dry_run` is set.
+    async fn insert_many(
+        &self,
+        todos: &[Todo],
+        dry_run: bool,
+    ) -> anyhow::Result<Vec<(usize, String)>>;
+}
+
+/// Storage for tags and the many-to-many link between tags and todos.
+#[async_trait]
+pub trait TagRepository: Send + Sync {
+    /// Create a new tag. Fails if the user already has a tag with this name.
+    async fn insert(&self, tag: &Tag) -> anyhow::Result<Tag>;
+    async fn get(&self, id: TagId) -> anyhow::Result<Option<Tag>>;
+    async fn find_by_name(&self, user_id: UserId, name: &str) -> anyhow::Result<Option<Tag>>;
+    /// Every user's tags, in creation order (for dumps).
+    async fn list_all(&self) -> anyhow::Result<Vec<Tag>>;
+    /// Every tag on every todo (for dumps).
+    async fn list_links(&self) -> anyhow::Result<Vec<TodoTag>>;
+    /// The user's tags with their todo counts, ordered by name.
+    async fn list(&self, user_id: UserId) -> anyhow::Result<Vec<TagCount>>;
+    async fn rename(&self, id: TagId, name: &str) -> anyhow::Result<Option<Tag>>;
+    /// Delete a tag, removing it from every todo. Returns `false` if no such tag.
+    async fn delete(&self, id: TagId) -> anyhow::Result<bool>;
+    /// The tags on a todo, ordered by name.
+    async fn for_todo(&self, todo_id: TodoId) -> anyhow::Result<Vec<Tag>>;
+    /// Returns `false` if the todo already had the tag.
+    async fn add_to_todo(&self, todo_id: TodoId, tag_id: TagId) -> anyhow::Result<bool>;
+    /// Returns `false` if the todo did not have the tag.
+    async fn remove_from_todo(&self, todo_id: TodoId, tag_id: TagId) -> anyhow::Result<bool>;
+}
+
+/// Storage for todo lists, leaving out trashed ones.
+#[async_trait]
+pub trait ListRepository: Send + Sync {
+    async fn insert(&self, list: &TodoList) -> anyhow::Result<TodoList>;
+    async fn get(&self, id: ListId) -> anyhow::Result<Option<TodoList>>;
+    async fn inbox(&self, user_id: UserId) -> anyhow::Result<Option<TodoList>>;
+    /// The user's lists ordered by position, optionally only archived (or
+    /// only unarchived) lists.
+    async fn list(&self, user_id: UserId, archived: Option<bool>) -> anyhow::Result<Vec<TodoList>>;
+    /// Every user's lists, in creation order (for dumps).
+    async fn list_all(&self) -> anyhow::Result<Vec<TodoList>>;
+    /// Save a list's name, color, archived flag and position.
+    async fn update(&self, list: &TodoList) -> anyhow::Result<Option<TodoList>>;
+    /// Move a list to the trash, first moving its todos to
+    /// `move_todos_to`, or trashing them (and their subtasks) along with
+    /// the list if that is `None`. Returns `false` if no such list.
+    async fn delete(&self, id: ListId, move_todos_to: Option<ListId>) -> anyhow::Result<bool>;
+}
+
+/// Trashed users, lists and todos. Restoring an item also restores
+/// whatever was trashed along with it; restored todos whose list is
+/// still in the trash go to the inbox, and a restored todo whose parent
+/// is still in the trash becomes a top-level todo.
+#[async_trait]
+pub trait TrashRepository: Send + Sync {
+    /// A user's trashed lists, most recently trashed first.
+    async fn lists(&self, user_id: UserId) -> anyhow::Result<Vec<TodoList>>;
+    /// A user's trashed todos, most recently trashed first.
+    async fn todos(&self, user_id: UserId) -> anyhow::Result<Vec<Todo>>;
+    /// Trashed users, most recently trashed first.
+    async fn users(&self) -> anyhow::Result<Vec<User>>;
+    /// Restore one of a user's trashed lists, along with the todos
+    /// trashed with it. `None` if the user has no such list in the trash.
+    async fn restore_list(&self, user_id: UserId, id: ListId) -> anyhow::Result<Option<TodoList>>;
+    /// Restore one of a user's trashed todos, along with the subtasks
+    /// trashed with it. `None` if the user has no such todo in the trash.
+    async fn restore_todo(&self, user_id: UserId, id: TodoId) -> anyhow::Result<Option<Todo>>;
+    /// Restore a trashed user along with the lists and todos trashed with
+    /// them. `None` if no such user is in the trash.
+    async fn restore_user(&self, id: UserId) -> anyhow::Result<Option<User>>;
+    /// Delete everything trashed before `before` for good, returning how
+    /// many users, lists and todos were purged.
+    async fn purge(&self, before: DateTime<Utc>) -> anyhow::Result<u64>;
+}
+
+/// CalDAV bookkeeping: the UIDs clients gave todos, and which todos left
+/// each list (deleted or moved elsewhere) for clients to sync.
+#[async_trait]
+pub trait CalDavRepository: Send + Sync {
+    /// The todos in a list that have a UID other than their id.
+    async fn uids(&self, list_id: ListId) -> anyhow::Result<Vec<(TodoId, String)>>;
+    async fn set_uid(&self, todo_id: TodoId, uid: &str) -> anyhow::Result<()>;
+    /// The todo in a list with this UID (or, for todos without one, id).
+    async fn find_by_uid(&self, list_id: ListId, uid: &str) -> anyhow::Result<Option<Todo>>;
+    /// The UIDs of the todos that left a list at or after `since`, and
+    /// when. Only the last 30 days are kept.
+    async fn removed_since(
+        &self,
+        list_id: ListId,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<(String, DateTime<Utc>)>>;
+}
+
+/// Storage for webhooks and their outbox of deliveries.
+#[async_trait]
+pub trait WebhookRepository: Send + Sync {
+    async fn insert(&self, webhook: &Webhook) -> anyhow::Result<Webhook>;
+    async fn get(&self, id: WebhookId) -> anyhow::Result<Option<Webhook>>;
+    /// A user's webhooks (or, for `None`, the global ones), oldest first.
+    async fn list(&self, user_id: Option<UserId>) -> anyhow::Result<Vec<Webhook>>;
+    /// Delete a webhook along with its deliveries.
+    async fn delete(&self, id: WebhookId) -> anyhow::Result<bool>;
+    /// The webhooks that get a user's events: their own and the global
+    /// ones.
+    async fn subscribers(&self, user_id: UserId) -> anyhow::Result<Vec<Webhook>>;
+    async fn insert_deliveries(&self, deliveries: &[Delivery]) -> anyhow::Result<()>;
+    async fn get_delivery(&self, id: DeliveryId) -> anyhow::Result<Option<Delivery>>;
+    /// A webhook's latest deliveries, newest first.
+    async fn deliveries(&self, webhook_id: WebhookId, limit: i64) -> anyhow::Result<Vec<Delivery>>;
+    /// Pending deliveries due by `now`, oldest first.
+    async fn due_deliveries(&self, now: DateTime<Utc>, limit: i64)
+        -> anyhow::Result<Vec<Delivery>>;
+    /// Store the outcome of a delivery attempt (everything but the
+    /// delivery's webhook, event and payload).
+    async fn update_delivery(&self, delivery: &Delivery) -> anyhow::Result<Option<Delivery>>;
+}
+
+/// Which entries to return from [`AuditRepository::list`].
+#[derive(Clone, Debug, Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub entity: Option<String>,
+    pub entity_id: Option<String>,
+    pub action: Option<String>,
+    pub request_id: Option<String>,
+    /// Only entries made at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only entries made before this time.
+    pub until: Option<DateTime<Utc>>,
+    /// Only entries older than this one, to page through the log.
+    pub before_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// The append-only audit log.
+#[async_trait]
+pub trait AuditRepository: Send + Sync {
+    /// Append entries to the log, in order. Their ids are assigned by
+    /// the log.
+    async fn insert(&self, entries: &[AuditEntry]) -> anyhow::Result<()>;
+    /// Matching entries, newest first.
+    async fn list(&self, filter: &AuditFilter) -> anyhow::Result<Vec<AuditEntry>>;
+}
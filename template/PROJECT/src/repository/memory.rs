@@ -0,0 +1,1667 @@
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use futures_util::{stream::BoxStream, StreamExt};
+
+use super::{
+    AuditFilter, AuditRepository, CalDavRepository, ListRepository, TagRepository, TodoFilter,
+    TodoRepository, TrashRepository, UserRepository, WebhookRepository,
+};
+use crate::{
+    models::{
+        api_token::ApiToken,
+        app_password::AppPassword,
+        audit::AuditEntry,
+        ids::{ApiTokenId, AppPasswordId, DeliveryId, ListId, TagId, TodoId, UserId, WebhookId},
+        list::TodoList,
+        session::Session,
+        tag::{Tag, TagCount, TodoTag},
+        todo::{Subtask, Todo},
+        user::{Role, User},
+        webhook::{Delivery, Webhook},
+    },
+    transfer::DumpRecord,
+};
+
+/// In-memory storage for tests and `serve --ephemeral`.
+///
+/// Enforces the same constraints as the SQL schema: unique ids, unique
+/// user emails and per-user tag names, one inbox per user, rows must
+/// refer to existing rows, and deletes cascade. Trashed rows stay in the
+/// tables, with their `deleted_at` set. Nothing is persisted.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryRepository {
+    tables: Arc<RwLock<Tables>>,
+}
+
+/// Rows in insertion (and so creation) order.
+#[derive(Clone, Debug, Default)]
+struct Tables {
+    users: Vec<User>,
+    lists: Vec<TodoList>,
+    todos: Vec<Todo>,
+    tags: Vec<Tag>,
+    todo_tags: Vec<TodoTag>,
+    /// Users and the digests of their calendar feed tokens.
+    calendar_tokens: Vec<(UserId, String)>,
+    /// Users and the hashes of their passwords.
+    passwords: Vec<(UserId, String)>,
+    /// App passwords and the digests of their secrets.
+    app_passwords: Vec<(AppPassword, String)>,
+    /// API tokens and the digests of their secrets.
+    api_tokens: Vec<(ApiToken, String)>,
+    /// Browser sessions and the digests of their cookies' secrets.
+    sessions: Vec<(Session, String)>,
+    caldav_uids: Vec<(TodoId, String)>,
+    /// The lists todos left, their UIDs, and when.
+    caldav_tombstones: Vec<(ListId, String, DateTime<Utc>)>,
+    webhooks: Vec<Webhook>,
+    webhook_deliveries: Vec<Delivery>,
+    /// Audit log entries, oldest first. Nothing removes them.
+    audit_log: Vec<AuditEntry>,
+}
+
+impl Tables {
+    fn insert_user(&mut self, user: &User) -> Result<(), String> {
+        if self.users.iter().any(|u| u.id == user.id) {
+            return Err("duplicate user id".to_string());
+        }
+        if self
+            .users
+            .iter()
+            .any(|u| u.email == user.email && u.deleted_at.is_none())
+        {
+            return Err(format!("email '{}' is already registered", user.email));
+        }
+        self.users.push(user.clone());
+        Ok(())
+    }
+
+    fn insert_todo(&mut self, todo: &Todo) -> Result<(), String> {
+        if self.todos.iter().any(|t| t.id == todo.id) {
+            return Err("duplicate todo id".to_string());
+        }
+        if !self.users.iter().any(|u| u.id == todo.user_id) {
+            return Err(format!("no user with id {}", todo.user_id.0));
+        }
+        self.check_list(todo)?;
+        self.check_parent(todo)?;
+        self.todos.push(todo.clone());
+        Ok(())
+    }
+
+    /// A todo's parent must be another todo of the same user.
+    fn check_parent(&self, todo: &Todo) -> Result<(), String> {
+        match todo.parent_id {
+            Some(parent_id)
+                if parent_id == todo.id
+                    || !self
+                        .todos
+                        .iter()
+                        .any(|t| t.id == parent_id && t.user_id == todo.user_id) =>
+            {
+                Err(format!(
+                    "no parent todo with id {} for this user",
+                    parent_id.0
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn insert_subtask(&mut self, link: &Subtask) -> Result<(), String> {
+        let Some(todo) = self.todos.iter().find(|t| t.id == link.todo_id) else {
+            return Err(format!("no todo with id {}", link.todo_id.0));
+        };
+        let todo = Todo {
+            parent_id: Some(link.parent_id),
+            ..todo.clone()
+        };
+        self.check_parent(&todo)?;
+        self.replace_todo(todo);
+        Ok(())
+    }
+
+    fn replace_todo(&mut self, todo: Todo) {
+        if let Some(t) = self.todos.iter_mut().find(|t| t.id == todo.id) {
+            *t = todo;
+        }
+    }
+
+    /// The ids of a todo and all of its subtasks, if it is not trashed.
+    fn subtree(&self, id: TodoId) -> Vec<TodoId> {
+        self.subtree_where(id, |t| t.deleted_at.is_none())
+    }
+
+    /// The ids of a todo and all of its subtasks, as far as they match
+    /// `include`.
+    fn subtree_where(&self, id: TodoId, include: impl Fn(&Todo) -> bool) -> Vec<TodoId> {
+        let mut ids: Vec<TodoId> = self
+            .todos
+            .iter()
+            .filter(|t| t.id == id && include(t))
+            .map(|t| t.id)
+            .collect();
+        let mut i = 0;
+        while i < ids.len() {
+            let parent = ids[i];
+            ids.extend(
+                self.todos
+                    .iter()
+                    .filter(|t| t.parent_id == Some(parent) && include(t) && !ids.contains(&t.id))
+                    .map(|t| t.id)
+                    .collect::<Vec<_>>(),
+            );
+            i += 1;
+        }
+        ids
+    }
+
+    /// Move a user's todos, and every subtask of them, to the trash.
+    fn trash_todos(&mut self, ids: &[TodoId], now: DateTime<Utc>) {
+        let ids: Vec<TodoId> = ids.iter().flat_map(|id| self.subtree(*id)).collect();
+        for todo in self.todos.iter_mut().filter(|t| ids.contains(&t.id)) {
+            todo.deleted_at = Some(now);
+            todo.version += 1;
+        }
+    }
+
+    /// After a restore, move a user's todos out of lists that are still
+    /// in the trash (to their inbox), and out from under parents that are.
+    fn rehome_restored(&mut self, user_id: UserId) {
+        let Tables { lists, todos, .. } = self;
+        let inbox = lists.iter().find(|l| l.user_id == user_id && l.inbox);
+        let trashed_lists: Vec<ListId> = lists
+            .iter()
+            .filter(|l| l.deleted_at.is_some())
+            .map(|l| l.id)
+            .collect();
+        let trashed_todos: Vec<TodoId> = todos
+            .iter()
+            .filter(|t| t.deleted_at.is_some())
+            .map(|t| t.id)
+            .collect();
+        for todo in todos
+            .iter_mut()
+            .filter(|t| t.user_id == user_id && t.deleted_at.is_none())
+        {
+            if let Some(inbox) = inbox.filter(|_| trashed_lists.contains(&todo.list_id)) {
+                todo.list_id = inbox.id;
+            }
+            if todo.parent_id.is_some_and(|id| trashed_todos.contains(&id)) {
+                todo.parent_id = None;
+            }
+        }
+    }
+
+    /// A todo's list must belong to the todo's user.
+    fn check_list(&self, todo: &Todo) -> Result<(), String> {
+        if !self
+            .lists
+            .iter()
+            .any(|l| l.id == todo.list_id && l.user_id == todo.user_id)
+        {
+            return Err(format!("no list with id {} for this user", todo.list_id.0));
+        }
+        Ok(())
+    }
+
+    fn insert_list(&mut self, list: &TodoList) -> Result<(), String> {
+        if self.lists.iter().any(|l| l.id == list.id) {
+            return Err("duplicate list id".to_string());
+        }
+        if !self.users.iter().any(|u| u.id == list.user_id) {
+            return Err(format!("no user with id {}", list.user_id.0));
+        }
+        if list.inbox
+            && self
+                .lists
+                .iter()
+                .any(|l| l.user_id == list.user_id && l.inbox)
+        {
+            return Err("user already has an inbox".to_string());
+        }
+        self.lists.push(list.clone());
+        Ok(())
+    }
+
+    fn insert_tag(&mut self, tag: &Tag) -> Result<(), String> {
+        if self.tags.iter().any(|t| t.id == tag.id) {
+            return Err("duplicate tag id".to_string());
+        }
+        if self
+            .tags
+            .iter()
+            .any(|t| t.user_id == tag.user_id && t.name == tag.name)
+        {
+            return Err(format!("tag '{}' already exists", tag.name));
+        }
+        if !self.users.iter().any(|u| u.id == tag.user_id) {
+            return Err(format!("no user with id {}", tag.user_id.0));
+        }
+        self.tags.push(tag.clone());
+        Ok(())
+    }
+
+    /// Returns `false` if the todo already had the tag.
+    fn insert_todo_tag(&mut self, link: &TodoTag) -> Result<bool, String> {
+        if !self.todos.iter().any(|t| t.id == link.todo_id) {
+            return Err(format!("no todo with id {}", link.todo_id.0));
+        }
+        if !self.tags.iter().any(|t| t.id == link.tag_id) {
+            return Err(format!("no tag with id {}", link.tag_id.0));
+        }
+        if self.has_tag(link.todo_id, link.tag_id) {
+            return Ok(false);
+        }
+        self.todo_tags.push(link.clone());
+        Ok(true)
+    }
+
+    /// Whether a todo exists and is not trashed.
+    fn is_live(&self, todo_id: TodoId) -> bool {
+        self.todos
+            .iter()
+            .any(|t| t.id == todo_id && t.deleted_at.is_none())
+    }
+
+    fn has_tag(&self, todo_id: TodoId, tag_id: TagId) -> bool {
+        self.todo_tags
+            .iter()
+            .any(|l| l.todo_id == todo_id && l.tag_id == tag_id)
+    }
+
+    /// Which list each todo is in, to pass to [`Tables::bury_removed`]
+    /// after changes that may delete or move todos.
+    fn placements(&self) -> Vec<(TodoId, ListId)> {
+        self.todos
+            .iter()
+            .filter(|t| t.deleted_at.is_none())
+            .map(|t| (t.id, t.list_id))
+            .collect()
+    }
+
+    /// Record a CalDAV tombstone for each of the `before` todos that has
+    /// since been trashed, deleted or moved to another list (as the SQL
+    /// triggers do), and forget the UIDs of deleted todos.
+    fn bury_removed(&mut self, before: Vec<(TodoId, ListId)>) {
+        let Tables {
+            todos,
+            caldav_uids,
+            caldav_tombstones,
+            ..
+        } = self;
+        let now = Utc::now();
+        for (id, list_id) in before {
+            if todos
+                .iter()
+                .any(|t| t.id == id && t.list_id == list_id && t.deleted_at.is_none())
+            {
+                continue;
+            }
+            let uid = caldav_uids
+                .iter()
+                .find(|(todo_id, _)| *todo_id == id)
+                .map_or_else(|| id.0.to_string(), |(_, uid)| uid.clone());
+            caldav_tombstones.push((list_id, uid, now));
+        }
+        caldav_tombstones.retain(|(_, _, at)| *at >= now - Duration::days(30));
+        caldav_uids.retain(|(id, _)| todos.iter().any(|t| t.id == *id));
+    }
+
+    /// Drop todos in lists, subtasks of todos, links to todos and tags,
+    /// and deliveries to webhooks, that no longer exist.
+    fn cascade(&mut self) {
+        let Tables {
+            lists,
+            todos,
+            tags,
+            todo_tags,
+            webhooks,
+            webhook_deliveries,
+            ..
+        } = self;
+        webhook_deliveries.retain(|d| webhooks.iter().any(|w| w.id == d.webhook_id));
+        todos.retain(|t| lists.iter().any(|l| l.id == t.list_id));
+        loop {
+            let before = todos.len();
+            let ids: Vec<TodoId> = todos.iter().map(|t| t.id).collect();
+            todos.retain(|t| t.parent_id.is_none_or(|id| ids.contains(&id)));
+            if todos.len() == before {
+                break;
+            }
+        }
+        todo_tags.retain(|l| {
+            todos.iter().any(|t| t.id == l.todo_id) && tags.iter().any(|t| t.id == l.tag_id)
+        });
+    }
+}
+
+impl MemoryRepository {
+    /// Apply `insert` to a copy of the tables, keeping the copy only if
+    /// every insert succeeded and this is not a dry run.
+    fn transaction<T>(
+        &self,
+        rows: &[T],
+        dry_run: bool,
+        insert: impl Fn(&mut Tables, &T) -> Result<(), String>,
+    ) -> Vec<(usize, String)> {
+        let mut tables = self.tables.write().unwrap();
+        let mut copy = tables.clone();
+        let errors: Vec<(usize, String)> = rows
+            .iter()
+            .enumerate()
+            .filter_map(|(i, row)| insert(&mut copy, row).err().map(|e| (i, e)))
+            .collect();
+        if errors.is_empty() && !dry_run {
+            *tables = copy;
+        }
+        errors
+    }
+
+    /// Insert dump records (keeping their ids) all at once.
+    ///
+    /// Returns the index and error of every rejected record. Nothing is
+    /// stored if any record was rejected, or if `dry_run` is set.
+    pub fn load_dump(&self, records: &[DumpRecord], dry_run: bool) -> Vec<(usize, String)> {
+        self.transaction(records, dry_run, |tables, record| match record {
+            DumpRecord::User(user) => tables.insert_user(user),
+            DumpRecord::List(list) => tables.insert_list(list),
+            DumpRecord::Todo(todo) => tables.insert_todo(todo),
+            DumpRecord::Subtask(link) => tables.insert_subtask(link),
+            DumpRecord::Tag(tag) => tables.insert_tag(tag),
+            DumpRecord::TodoTag(link) => tables.insert_todo_tag(link).map(|_| ()),
+        })
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryRepository {
+    async fn insert(&self, user: &User) -> anyhow::Result<User> {
+        let errors = self.transaction(&[user], false, |tables, user| {
+            tables.insert_user(user)?;
+            tables.insert_list(&TodoList::inbox(user.id))
+        });
+        if let Some((_, e)) = errors.into_iter().next() {
+            anyhow::bail!(e);
+        }
+        Ok(user.clone())
+    }
+
+    async fn get(&self, id: UserId) -> anyhow::Result<Option<User>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .users
+            .iter()
+            .find(|u| u.id == id && u.deleted_at.is_none())
+            .cloned())
+    }
+
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<Option<User>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .users
+            .iter()
+            .find(|u| u.email == email && u.deleted_at.is_none())
+            .cloned())
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<User>> {
+        let mut users: Vec<User> = self
+            .tables
+            .read()
+            .unwrap()
+            .users
+            .iter()
+            .filter(|u| u.deleted_at.is_none())
+            .cloned()
+            .collect();
+        users.sort_by(|a, b| a.email.cmp(&b.email));
+        Ok(users)
+    }
+
+    async fn set_display_name(
+        &self,
+        id: UserId,
+        display_name: &str,
+        version: Option<i64>,
+    ) -> anyhow::Result<Option<User>> {
+        let mut tables = self.tables.write().unwrap();
+        let user = tables.users.iter_mut().find(|u| {
+            u.id == id && u.deleted_at.is_none() && version.is_none_or(|v| u.version == v)
+        });
+        Ok(user.map(|u| {
+            u.display_name = display_name.to_string();
+            u.version += 1;
+            u.clone()
+        }))
+    }
+
+    async fn set_role(&self, id: UserId, role: Role) -> anyhow::Result<Option<User>> {
+        let mut tables = self.tables.write().unwrap();
+        let user = tables
+            .users
+            .iter_mut()
+            .find(|u| u.id == id && u.deleted_at.is_none());
+        Ok(user.map(|u| {
+            u.role = role;
+            u.version += 1;
+            u.clone()
+        }))
+    }
+
+    async fn delete(&self, id: UserId, version: Option<i64>) -> anyhow::Result<bool> {
+        let mut tables = self.tables.write().unwrap();
+        let now = Utc::now();
+        let Some(user) = tables.users.iter_mut().find(|u| {
+            u.id == id && u.deleted_at.is_none() && version.is_none_or(|v| u.version == v)
+        }) else {
+            return Ok(false);
+        };
+        user.deleted_at = Some(now.timestamp());
+        user.version += 1;
+        let placements = tables.placements();
+        for todo in tables
+            .todos
+            .iter_mut()
+            .filter(|t| t.user_id == id && t.deleted_at.is_none())
+        {
+            todo.deleted_at = Some(now);
+            todo.version += 1;
+        }
+        for list in tables
+            .lists
+            .iter_mut()
+            .filter(|l| l.user_id == id && l.deleted_at.is_none())
+        {
+            list.deleted_at = Some(now);
+        }
+        tables.bury_removed(placements);
+        Ok(true)
+    }
+
+    async fn set_calendar_token(&self, id: UserId, token_hash: &str) -> anyhow::Result<()> {
+        let mut tables = self.tables.write().unwrap();
+        if !tables.users.iter().any(|u| u.id == id) {
+            anyhow::bail!("no user with id {}", id.0);
+        }
+        tables.calendar_tokens.retain(|(user_id, _)| *user_id != id);
+        tables.calendar_tokens.push((id, token_hash.to_string()));
+        Ok(())
+    }
+
+    async fn delete_calendar_token(&self, id: UserId) -> anyhow::Result<bool> {
+        let mut tables = self.tables.write().unwrap();
+        let before = tables.calendar_tokens.len();
+        tables.calendar_tokens.retain(|(user_id, _)| *user_id != id);
+        Ok(tables.calendar_tokens.len() < before)
+    }
+
+    async fn find_by_calendar_token(&self, token_hash: &str) -> anyhow::Result<Option<User>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .calendar_tokens
+            .iter()
+            .find(|(_, hash)| hash == token_hash)
+            .and_then(|(id, _)| {
+                tables
+                    .users
+                    .iter()
+                    .find(|u| u.id == *id && u.deleted_at.is_none())
+            })
+            .cloned())
+    }
+
+    async fn set_password_hash(
+        &self,
+        id: UserId,
+        password_hash: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        let mut tables = self.tables.write().unwrap();
+        if !tables
+            .users
+            .iter()
+            .any(|u| u.id == id && u.deleted_at.is_none())
+        {
+            return Ok(false);
+        }
+        tables.passwords.retain(|(user_id, _)| *user_id != id);
+        if let Some(hash) = password_hash {
+            tables.passwords.push((id, hash.to_string()));
+        }
+        Ok(true)
+    }
+
+    async fn password_hash(&self, id: UserId) -> anyhow::Result<Option<String>> {
+        let tables = self.tables.read().unwrap();
+        let live = tables
+            .users
+            .iter()
+            .any(|u| u.id == id && u.deleted_at.is_none());
+        Ok(tables
+            .passwords
+            .iter()
+            .find(|(user_id, _)| live && *user_id == id)
+            .map(|(_, hash)| hash.clone()))
+    }
+    async fn insert_app_password(
+        &self,
+        app_password: &AppPassword,
+        token_hash: &str,
+    ) -> anyhow::Result<AppPassword> {
+        let mut tables = self.tables.write().unwrap();
+        if !tables.users.iter().any(|u| u.id == app_password.user_id) {
+            anyhow::bail!("no user with id {}", app_password.user_id.0);
+        }
+        if tables
+            .app_passwords
+            .iter()
+            .any(|(p, hash)| p.id == app_password.id || hash == token_hash)
+        {
+            anyhow::bail!("duplicate app password");
+        }
+        tables
+            .app_passwords
+            .push((app_password.clone(), token_hash.to_string()));
+        Ok(app_password.clone())
+    }
+
+    async fn app_passwords(&self, id: UserId) -> anyhow::Result<Vec<AppPassword>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .app_passwords
+            .iter()
+            .filter(|(p, _)| p.user_id == id)
+            .map(|(p, _)| p.clone())
+            .collect())
+    }
+
+    async fn delete_app_password(
+        &self,
+        id: UserId,
+        app_password_id: AppPasswordId,
+    ) -> anyhow::Result<bool> {
+        let mut tables = self.tables.write().unwrap();
+        let before = tables.app_passwords.len();
+        tables
+            .app_passwords
+            .retain(|(p, _)| p.id != app_password_id || p.user_id != id);
+        Ok(tables.app_passwords.len() < before)
+    }
+
+    async fn find_by_app_password(&self, token_hash: &str) -> anyhow::Result<Option<User>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .app_passwords
+            .iter()
+            .find(|(_, hash)| hash == token_hash)
+            .and_then(|(p, _)| {
+                tables
+                    .users
+                    .iter()
+                    .find(|u| u.id == p.user_id && u.deleted_at.is_none())
+            })
+            .cloned())
+    }
+
+    async fn insert_api_token(
+        &self,
+        api_token: &ApiToken,
+        token_hash: &str,
+    ) -> anyhow::Result<ApiToken> {
+        let mut tables = self.tables.write().unwrap();
+        if !tables.users.iter().any(|u| u.id == api_token.user_id) {
+            anyhow::bail!("no user with id {}", api_token.user_id.0);
+        }
+        if tables
+            .api_tokens
+            .iter()
+            .any(|(t, hash)| t.id == api_token.id || hash == token_hash)
+        {
+            anyhow::bail!("duplicate API token");
+        }
+        tables
+            .api_tokens
+            .push((api_token.clone(), token_hash.to_string()));
+        Ok(api_token.clone())
+    }
+
+    async fn api_tokens(&self, id: UserId) -> anyhow::Result<Vec<ApiToken>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .api_tokens
+            .iter()
+            .filter(|(t, _)| t.user_id == id)
+            .map(|(t, _)| t.clone())
+            .collect())
+    }
+
+    async fn delete_api_token(&self, id: UserId, api_token_id: ApiTokenId) -> anyhow::Result<bool> {
+        let mut tables = self.tables.write().unwrap();
+        let before = tables.api_tokens.len();
+        tables
+            .api_tokens
+            .retain(|(t, _)| t.id != api_token_id || t.user_id != id);
+        Ok(tables.api_tokens.len() < before)
+    }
+
+    async fn find_by_api_token(
+        &self,
+        token_hash: &str,
+    ) -> anyhow::Result<Option<(ApiToken, User)>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .api_tokens
+            .iter()
+            .find(|(_, hash)| hash == token_hash)
+            .and_then(|(t, _)| {
+                let user = tables
+                    .users
+                    .iter()
+                    .find(|u| u.id == t.user_id && u.deleted_at.is_none())?;
+                Some((t.clone(), user.clone()))
+            }))
+    }
+
+    async fn touch_api_token(
+        &self,
+        api_token_id: ApiTokenId,
+        at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let mut tables = self.tables.write().unwrap();
+        if let Some((t, _)) = tables
+            .api_tokens
+            .iter_mut()
+            .find(|(t, _)| t.id == api_token_id)
+        {
+            t.last_used_at = Some(at);
+        }
+        Ok(())
+    }
+
+    async fn insert_session(&self, session: &Session, token_hash: &str) -> anyhow::Result<Session> {
+        let mut tables = self.tables.write().unwrap();
+        if tables
+            .sessions
+            .iter()
+            .any(|(s, hash)| s.id == session.id || hash == token_hash)
+        {
+            anyhow::bail!("duplicate session");
+        }
+        tables
+            .sessions
+            .push((session.clone(), token_hash.to_string()));
+        Ok(session.clone())
+    }
+
+    async fn find_session(&self, token_hash: &str) -> anyhow::Result<Option<Session>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .sessions
+            .iter()
+            .find(|(_, hash)| hash == token_hash)
+            .map(|(s, _)| s.clone()))
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> anyhow::Result<bool> {
+        let mut tables = self.tables.write().unwrap();
+        let before = tables.sessions.len();
+        tables.sessions.retain(|(_, hash)| hash != token_hash);
+        Ok(tables.sessions.len() < before)
+    }
+
+    async fn purge_sessions(&self, before: DateTime<Utc>) -> anyhow::Result<u64> {
+        let mut tables = self.tables.write().unwrap();
+        let count = tables.sessions.len();
+        tables.sessions.retain(|(s, _)| s.expires_at >= before);
+        Ok((count - tables.sessions.len()) as u64)
+    }
+
+    async fn delete_sessions(&self, email: &str) -> anyhow::Result<u64> {
+        let mut tables = self.tables.write().unwrap();
+        let count = tables.sessions.len();
+        tables.sessions.retain(|(s, _)| s.email != email);
+        Ok((count - tables.sessions.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl TodoRepository for MemoryRepository {
+    async fn get(&self, id: TodoId) -> anyhow::Result<Option<Todo>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .todos
+            .iter()
+            .find(|t| t.id == id && t.deleted_at.is_none())
+            .cloned())
+    }
+
+    fn stream(&self, filter: TodoFilter) -> BoxStream<'static, anyhow::Result<Todo>> {
+        let tables = self.tables.read().unwrap();
+        let tagged = |todo: &Todo, name: &str| {
+            tables
+                .tags
+                .iter()
+                .any(|t| t.name == name && tables.has_tag(todo.id, t.id))
+        };
+        let todos: Vec<anyhow::Result<Todo>> = tables
+            .todos
+            .iter()
+            .filter(|t| t.deleted_at.is_none())
+            .filter(|t| filter.user_id.is_none_or(|id| t.user_id == id))
+            .filter(|t| filter.completed.is_none_or(|c| t.completed == c))
+            .filter(|t| filter.list_id.is_none_or(|id| t.list_id == id))
+            .filter(|t| filter.tag.as_deref().is_none_or(|name| tagged(t, name)))
+            .cloned()
+            .map(Ok)
+            .collect();
+        futures_util::stream::iter(todos).boxed()
+    }
+
+    async fn set_completed(
+        &self,
+        id: TodoId,
+        completed: bool,
+        version: Option<i64>,
+    ) -> anyhow::Result<Option<Todo>> {
+        let mut tables = self.tables.write().unwrap();
+        let todo = tables.todos.iter_mut().find(|t| {
+            t.id == id && t.deleted_at.is_none() && version.is_none_or(|v| t.version == v)
+        });
+        Ok(todo.map(|t| {
+            t.completed = completed;
+            t.updated_at = Utc::now();
+            t.version += 1;
+            t.clone()
+        }))
+    }
+
+    async fn set_subtree_completed(
+        &self,
+        id: TodoId,
+        completed: bool,
+        version: Option<i64>,
+    ) -> anyhow::Result<Option<Todo>> {
+        let mut tables = self.tables.write().unwrap();
+        if !tables
+            .todos
+            .iter()
+            .any(|t| t.id == id && t.deleted_at.is_none() && version.is_none_or(|v| t.version == v))
+        {
+            return Ok(None);
+        }
+        let ids = tables.subtree(id);
+        let now = Utc::now();
+        for todo in tables.todos.iter_mut().filter(|t| ids.contains(&t.id)) {
+            todo.completed = completed;
+            todo.updated_at = now;
+            todo.version += 1;
+        }
+        Ok(tables
+            .todos
+            .iter()
+            .find(|t| t.id == id && t.deleted_at.is_none())
+            .cloned())
+    }
+
+    async fn set_list(&self, id: TodoId, list_id: ListId) -> anyhow::Result<Option<Todo>> {
+        let mut tables = self.tables.write().unwrap();
+        let Some(todo) = tables
+            .todos
+            .iter()
+            .find(|t| t.id == id && t.deleted_at.is_none())
+        else {
+            return Ok(None);
+        };
+        let moved = Todo {
+            list_id,
+            updated_at: Utc::now(),
+            version: todo.version + 1,
+            ..todo.clone()
+        };
+        tables.check_list(&moved).map_err(anyhow::Error::msg)?;
+        let placements = tables.placements();
+        tables.replace_todo(moved.clone());
+        tables.bury_removed(placements);
+        Ok(Some(moved))
+    }
+
+    async fn set_parent(
+        &self,
+        id: TodoId,
+        parent_id: Option<TodoId>,
+    ) -> anyhow::Result<Option<Todo>> {
+        let mut tables = self.tables.write().unwrap();
+        let Some(todo) = tables
+            .todos
+            .iter()
+            .find(|t| t.id == id && t.deleted_at.is_none())
+        else {
+            return Ok(None);
+        };
+        let moved = Todo {
+            parent_id,
+            updated_at: Utc::now(),
+            version: todo.version + 1,
+            ..todo.clone()
+        };
+        tables.check_parent(&moved).map_err(anyhow::Error::msg)?;
+        tables.replace_todo(moved.clone());
+        Ok(Some(moved))
+    }
+
+    async fn update(&self, todo: &Todo) -> anyhow::Result<Option<Todo>> {
+        let mut tables = self.tables.write().unwrap();
+        let current = tables
+            .todos
+            .iter_mut()
+            .find(|t| t.id == todo.id && t.version == todo.version && t.deleted_at.is_none());
+        Ok(current.map(|t| {
+            t.title = todo.title.clone();
+            t.notes = todo.notes.clone();
+            t.completed = todo.completed;
+            t.due_at = todo.due_at;
+            t.rrule = todo.rrule.clone();
+            t.repeat_from_completion = todo.repeat_from_completion;
+            t.updated_at = Utc::now();
+            t.version += 1;
+            t.clone()
+        }))
+    }
+
+    async fn set_recurrence(
+        &self,
+        id: TodoId,
+        rrule: Option<&str>,
+        repeat_from_completion: bool,
+    ) -> anyhow::Result<Option<Todo>> {
+        let mut tables = self.tables.write().unwrap();
+        let todo = tables
+            .todos
+            .iter_mut()
+            .find(|t| t.id == id && t.deleted_at.is_none());
+        Ok(todo.map(|t| {
+            t.rrule = rrule.map(str::to_string);
+            t.repeat_from_completion = repeat_from_completion;
+            t.updated_at = Utc::now();
+            t.version += 1;
+            t.clone()
+        }))
+    }
+
+    async fn subtree(&self, id: TodoId) -> anyhow::Result<Vec<Todo>> {
+        let tables = self.tables.read().unwrap();
+        let ids = tables.subtree(id);
+        Ok(tables
+            .todos
+            .iter()
+            .filter(|t| ids.contains(&t.id))
+            .cloned()
+            .collect())
+    }
+
+    async fn delete(&self, id: TodoId, version: Option<i64>) -> anyhow::Result<bool> {
+        let mut tables = self.tables.write().unwrap();
+        if !tables
+            .todos
+            .iter()
+            .any(|t| t.id == id && t.deleted_at.is_none() && version.is_none_or(|v| t.version == v))
+        {
+            return Ok(false);
+        }
+        let placements = tables.placements();
+        tables.trash_todos(&[id], Utc::now());
+        tables.bury_removed(placements);
+        Ok(true)
+    }
+
+    async fn insert_many(
+        &self,
+        todos: &[Todo],
+        dry_run: bool,
+    ) -> anyhow::Result<Vec<(usize, String)>> {
+        Ok(self.transaction(todos, dry_run, Tables::insert_todo))
+    }
+}
+
+#[async_trait]
+impl TagRepository for MemoryRepository {
+    async fn insert(&self, tag: &Tag) -> anyhow::Result<Tag> {
+        self.tables
+            .write()
+            .unwrap()
+            .insert_tag(tag)
+            .map_err(anyhow::Error::msg)?;
+        Ok(tag.clone())
+    }
+
+    async fn get(&self, id: TagId) -> anyhow::Result<Option<Tag>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables.tags.iter().find(|t| t.id == id).cloned())
+    }
+
+    async fn find_by_name(&self, user_id: UserId, name: &str) -> anyhow::Result<Option<Tag>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .tags
+            .iter()
+            .find(|t| t.user_id == user_id && t.name == name)
+            .cloned())
+    }
+
+    async fn list_all(&self) -> anyhow::Result<Vec<Tag>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .tags
+            .iter()
+            .filter(|t| {
+                tables
+                    .users
+                    .iter()
+                    .any(|u| u.id == t.user_id && u.deleted_at.is_none())
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn list_links(&self) -> anyhow::Result<Vec<TodoTag>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .todo_tags
+            .iter()
+            .filter(|l| tables.is_live(l.todo_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn list(&self, user_id: UserId) -> anyhow::Result<Vec<TagCount>> {
+        let tables = self.tables.read().unwrap();
+        let mut tags: Vec<TagCount> = tables
+            .tags
+            .iter()
+            .filter(|t| t.user_id == user_id)
+            .map(|t| TagCount {
+                id: t.id,
+                name: t.name.clone(),
+                todos: tables
+                    .todo_tags
+                    .iter()
+                    .filter(|l| l.tag_id == t.id && tables.is_live(l.todo_id))
+                    .count() as i64,
+            })
+            .collect();
+        tags.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(tags)
+    }
+
+    async fn rename(&self, id: TagId, name: &str) -> anyhow::Result<Option<Tag>> {
+        let mut tables = self.tables.write().unwrap();
+        let Some(user_id) = tables.tags.iter().find(|t| t.id == id).map(|t| t.user_id) else {
+            return Ok(None);
+        };
+        if tables
+            .tags
+            .iter()
+            .any(|t| t.id != id && t.user_id == user_id && t.name == name)
+        {
+            anyhow::bail!("tag '{name}' already exists");
+        }
+        Ok(tables.tags.iter_mut().find(|t| t.id == id).map(|t| {
+            t.name = name.to_string();
+            t.clone()
+        }))
+    }
+
+    async fn delete(&self, id: TagId) -> anyhow::Result<bool> {
+        let mut tables = self.tables.write().unwrap();
+        let before = tables.tags.len();
+        tables.tags.retain(|t| t.id != id);
+        tables.cascade();
+        Ok(tables.tags.len() < before)
+    }
+
+    async fn for_todo(&self, todo_id: TodoId) -> anyhow::Result<Vec<Tag>> {
+        let tables = self.tables.read().unwrap();
+        let mut tags: Vec<Tag> = tables
+            .tags
+            .iter()
+            .filter(|t| tables.has_tag(todo_id, t.id))
+            .cloned()
+            .collect();
+        tags.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(tags)
+    }
+
+    async fn add_to_todo(&self, todo_id: TodoId, tag_id: TagId) -> anyhow::Result<bool> {
+        self.tables
+            .write()
+            .unwrap()
+            .insert_todo_tag(&TodoTag { todo_id, tag_id })
+            .map_err(anyhow::Error::msg)
+    }
+
+    async fn remove_from_todo(&self, todo_id: TodoId, tag_id: TagId) -> anyhow::Result<bool> {
+        let mut tables = self.tables.write().unwrap();
+        let before = tables.todo_tags.len();
+        tables
+            .todo_tags
+            .retain(|l| l.todo_id != todo_id || l.tag_id != tag_id);
+        Ok(tables.todo_tags.len() < before)
+    }
+}
+
+#[async_trait]
+impl ListRepository for MemoryRepository {
+    async fn insert(&self, list: &TodoList) -> anyhow::Result<TodoList> {
+        self.tables
+            .write()
+            .unwrap()
+            .insert_list(list)
+            .map_err(anyhow::Error::msg)?;
+        Ok(list.clone())
+    }
+
+    async fn get(&self, id: ListId) -> anyhow::Result<Option<TodoList>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .lists
+            .iter()
+            .find(|l| l.id == id && l.deleted_at.is_none())
+            .cloned())
+    }
+
+    async fn inbox(&self, user_id: UserId) -> anyhow::Result<Option<TodoList>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .lists
+            .iter()
+            .find(|l| l.user_id == user_id && l.inbox && l.deleted_at.is_none())
+            .cloned())
+    }
+
+    async fn list(&self, user_id: UserId, archived: Option<bool>) -> anyhow::Result<Vec<TodoList>> {
+        let tables = self.tables.read().unwrap();
+        let mut lists: Vec<TodoList> = tables
+            .lists
+            .iter()
+            .filter(|l| l.user_id == user_id && l.deleted_at.is_none())
+            .filter(|l| archived.is_none_or(|a| l.archived == a))
+            .cloned()
+            .collect();
+        // Stable, so equal positions stay in creation order:
+        lists.sort_by_key(|l| l.position);
+        Ok(lists)
+    }
+
+    async fn list_all(&self) -> anyhow::Result<Vec<TodoList>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .lists
+            .iter()
+            .filter(|l| l.deleted_at.is_none())
+            .cloned()
+            .collect())
+    }
+
+    async fn update(&self, list: &TodoList) -> anyhow::Result<Option<TodoList>> {
+        let mut tables = self.tables.write().unwrap();
+        let current = tables
+            .lists
+            .iter_mut()
+            .find(|l| l.id == list.id && l.deleted_at.is_none());
+        Ok(current.map(|l| {
+            l.name = list.name.clone();
+            l.color = list.color.clone();
+            l.archived = list.archived;
+            l.position = list.position;
+            l.updated_at = Utc::now();
+            l.clone()
+        }))
+    }
+
+    async fn delete(&self, id: ListId, move_todos_to: Option<ListId>) -> anyhow::Result<bool> {
+        let mut tables = self.tables.write().unwrap();
+        let now = Utc::now();
+        let placements = tables.placements();
+        let todos: Vec<TodoId> = tables
+            .todos
+            .iter()
+            .filter(|t| t.list_id == id && t.deleted_at.is_none())
+            .map(|t| t.id)
+            .collect();
+        match move_todos_to {
+            Some(to) => {
+                for todo in tables.todos.iter_mut().filter(|t| todos.contains(&t.id)) {
+                    todo.list_id = to;
+                    todo.updated_at = now;
+                    todo.version += 1;
+                }
+            }
+            None => tables.trash_todos(&todos, now),
+        }
+        let list = tables
+            .lists
+            .iter_mut()
+            .find(|l| l.id == id && l.deleted_at.is_none());
+        let found = list.map(|l| l.deleted_at = Some(now)).is_some();
+        tables.bury_removed(placements);
+        Ok(found)
+    }
+}
+
+#[async_trait]
+impl TrashRepository for MemoryRepository {
+    async fn lists(&self, user_id: UserId) -> anyhow::Result<Vec<TodoList>> {
+        let tables = self.tables.read().unwrap();
+        let mut lists: Vec<TodoList> = tables
+            .lists
+            .iter()
+            .filter(|l| l.user_id == user_id && l.deleted_at.is_some())
+            .cloned()
+            .collect();
+        // Stable, so lists trashed together stay in creation order:
+        lists.sort_by_key(|l| std::cmp::Reverse(l.deleted_at));
+        Ok(lists)
+    }
+
+    async fn todos(&self, user_id: UserId) -> anyhow::Result<Vec<Todo>> {
+        let tables = self.tables.read().unwrap();
+        let mut todos: Vec<Todo> = tables
+            .todos
+            .iter()
+            .filter(|t| t.user_id == user_id && t.deleted_at.is_some())
+            .cloned()
+            .collect();
+        todos.sort_by_key(|t| std::cmp::Reverse(t.deleted_at));
+        Ok(todos)
+    }
+
+    async fn users(&self) -> anyhow::Result<Vec<User>> {
+        let tables = self.tables.read().unwrap();
+        let mut users: Vec<User> = tables
+            .users
+            .iter()
+            .filter(|u| u.deleted_at.is_some())
+            .cloned()
+            .collect();
+        users.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at).then(a.email.cmp(&b.email)));
+        Ok(users)
+    }
+
+    async fn restore_list(&self, user_id: UserId, id: ListId) -> anyhow::Result<Option<TodoList>> {
+        let mut tables = self.tables.write().unwrap();
+        let now = Utc::now();
+        let Some(list) = tables
+            .lists
+            .iter_mut()
+            .find(|l| l.id == id && l.user_id == user_id && l.deleted_at.is_some())
+        else {
+            return Ok(None);
+        };
+        let deleted_at = list.deleted_at.take();
+        list.updated_at = now;
+        let list = list.clone();
+        for todo in tables
+            .todos
+            .iter_mut()
+            .filter(|t| t.list_id == id && t.deleted_at == deleted_at)
+        {
+            todo.deleted_at = None;
+            todo.updated_at = now;
+            todo.version += 1;
+        }
+        tables.rehome_restored(user_id);
+        Ok(Some(list))
+    }
+
+    async fn restore_todo(&self, user_id: UserId, id: TodoId) -> anyhow::Result<Option<Todo>> {
+        let mut tables = self.tables.write().unwrap();
+        let now = Utc::now();
+        let Some(deleted_at) = tables
+            .todos
+            .iter()
+            .find(|t| t.id == id && t.user_id == user_id)
+            .and_then(|t| t.deleted_at)
+        else {
+            return Ok(None);
+        };
+        let ids = tables.subtree_where(id, |t| t.deleted_at == Some(deleted_at));
+        for todo in tables.todos.iter_mut().filter(|t| ids.contains(&t.id)) {
+            todo.deleted_at = None;
+            todo.updated_at = now;
+            todo.version += 1;
+        }
+        tables.rehome_restored(user_id);
+        Ok(tables.todos.iter().find(|t| t.id == id).cloned())
+    }
+
+    async fn restore_user(&self, id: UserId) -> anyhow::Result<Option<User>> {
+        let mut tables = self.tables.write().unwrap();
+        let now = Utc::now();
+        let Some(user) = tables
+            .users
+            .iter_mut()
+            .find(|u| u.id == id && u.deleted_at.is_some())
+        else {
+            return Ok(None);
+        };
+        let deleted_at = user.deleted_at.take();
+        user.version += 1;
+        let user = user.clone();
+        let trashed_with_user =
+            |at: Option<DateTime<Utc>>| at.map(|at| at.timestamp()) == deleted_at;
+        for list in tables
+            .lists
+            .iter_mut()
+            .filter(|l| l.user_id == id && trashed_with_user(l.deleted_at))
+        {
+            list.deleted_at = None;
+            list.updated_at = now;
+        }
+        for todo in tables
+            .todos
+            .iter_mut()
+            .filter(|t| t.user_id == id && trashed_with_user(t.deleted_at))
+        {
+            todo.deleted_at = None;
+            todo.updated_at = now;
+            todo.version += 1;
+        }
+        tables.rehome_restored(id);
+        Ok(Some(user))
+    }
+
+    async fn purge(&self, before: DateTime<Utc>) -> anyhow::Result<u64> {
+        let mut tables = self.tables.write().unwrap();
+        let expired = |at: Option<DateTime<Utc>>| at.is_some_and(|at| at < before);
+        let counts = (tables.users.len(), tables.lists.len(), tables.todos.len());
+        let users: Vec<UserId> = tables
+            .users
+            .iter()
+            .filter(|u| u.deleted_at.is_some_and(|at| at < before.timestamp()))
+            .map(|u| u.id)
+            .collect();
+        tables.users.retain(|u| !users.contains(&u.id));
+        tables
+            .lists
+            .retain(|l| !expired(l.deleted_at) && !users.contains(&l.user_id));
+        tables
+            .todos
+            .retain(|t| !expired(t.deleted_at) && !users.contains(&t.user_id));
+        tables.tags.retain(|t| !users.contains(&t.user_id));
+        tables.calendar_tokens.retain(|(id, _)| !users.contains(id));
+        tables.passwords.retain(|(id, _)| !users.contains(id));
+        tables
+            .app_passwords
+            .retain(|(p, _)| !users.contains(&p.user_id));
+        tables
+            .api_tokens
+            .retain(|(t, _)| !users.contains(&t.user_id));
+        tables
+            .webhooks
+            .retain(|w| w.user_id.is_none_or(|id| !users.contains(&id)));
+        tables.cascade();
+        tables.bury_removed(Vec::new());
+        let purged = (counts.0 - tables.users.len())
+            + (counts.1 - tables.lists.len())
+            + (counts.2 - tables.todos.len());
+        Ok(purged as u64)
+    }
+}
+
+#[async_trait]
+impl CalDavRepository for MemoryRepository {
+    async fn uids(&self, list_id: ListId) -> anyhow::Result<Vec<(TodoId, String)>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .caldav_uids
+            .iter()
+            .filter(|(id, _)| {
+                tables
+                    .todos
+                    .iter()
+                    .any(|t| t.id == *id && t.list_id == list_id && t.deleted_at.is_none())
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn set_uid(&self, todo_id: TodoId, uid: &str) -> anyhow::Result<()> {
+        let mut tables = self.tables.write().unwrap();
+        if !tables.todos.iter().any(|t| t.id == todo_id) {
+            anyhow::bail!("no todo with id {}", todo_id.0);
+        }
+        tables.caldav_uids.retain(|(id, _)| *id != todo_id);
+        tables.caldav_uids.push((todo_id, uid.to_string()));
+        Ok(())
+    }
+
+    async fn find_by_uid(&self, list_id: ListId, uid: &str) -> anyhow::Result<Option<Todo>> {
+        let tables = self.tables.read().unwrap();
+        let uid_of = |todo: &Todo| {
+            tables
+                .caldav_uids
+                .iter()
+                .find(|(id, _)| *id == todo.id)
+                .map_or_else(|| todo.id.0.to_string(), |(_, uid)| uid.clone())
+        };
+        Ok(tables
+            .todos
+            .iter()
+            .find(|t| t.list_id == list_id && t.deleted_at.is_none() && uid_of(t) == uid)
+            .cloned())
+    }
+
+    async fn removed_since(
+        &self,
+        list_id: ListId,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<(String, DateTime<Utc>)>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .caldav_tombstones
+            .iter()
+            .filter(|(id, _, at)| *id == list_id && *at >= since)
+            .map(|(_, uid, at)| (uid.clone(), *at))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl AuditRepository for MemoryRepository {
+    async fn insert(&self, entries: &[AuditEntry]) -> anyhow::Result<()> {
+        let mut tables = self.tables.write().unwrap();
+        for entry in entries {
+            let id = tables.audit_log.last().map_or(1, |e| e.id + 1);
+            tables.audit_log.push(AuditEntry {
+                id,
+                ..entry.clone()
+            });
+        }
+        Ok(())
+    }
+
+    async fn list(&self, filter: &AuditFilter) -> anyhow::Result<Vec<AuditEntry>> {
+        let tables = self.tables.read().unwrap();
+        let entries = tables
+            .audit_log
+            .iter()
+            .rev()
+            .filter(|e| filter.actor.is_none() || e.actor == filter.actor)
+            .filter(|e| filter.entity.as_ref().is_none_or(|x| *x == e.entity))
+            .filter(|e| filter.entity_id.as_ref().is_none_or(|x| *x == e.entity_id))
+            .filter(|e| filter.action.as_ref().is_none_or(|x| *x == e.action))
+            .filter(|e| filter.request_id.is_none() || e.request_id == filter.request_id)
+            .filter(|e| filter.since.is_none_or(|t| e.at >= t))
+            .filter(|e| filter.until.is_none_or(|t| e.at < t))
+            .filter(|e| filter.before_id.is_none_or(|id| e.id < id))
+            .take(filter.limit.map_or(usize::MAX, |n| n.max(0) as usize))
+            .cloned()
+            .collect();
+        Ok(entries)
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for MemoryRepository {
+    async fn insert(&self, webhook: &Webhook) -> anyhow::Result<Webhook> {
+        let mut tables = self.tables.write().unwrap();
+        if tables.webhooks.iter().any(|w| w.id == webhook.id) {
+            anyhow::bail!("duplicate webhook id");
+        }
+        if let Some(user_id) = webhook
+            .user_id
+            .filter(|id| !tables.users.iter().any(|u| u.id == *id))
+        {
+            anyhow::bail!("no user with id {}", user_id.0);
+        }
+        tables.webhooks.push(webhook.clone());
+        Ok(webhook.clone())
+    }
+
+    async fn get(&self, id: WebhookId) -> anyhow::Result<Option<Webhook>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables.webhooks.iter().find(|w| w.id == id).cloned())
+    }
+
+    async fn list(&self, user_id: Option<UserId>) -> anyhow::Result<Vec<Webhook>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .webhooks
+            .iter()
+            .filter(|w| w.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete(&self, id: WebhookId) -> anyhow::Result<bool> {
+        let mut tables = self.tables.write().unwrap();
+        let before = tables.webhooks.len();
+        tables.webhooks.retain(|w| w.id != id);
+        tables.cascade();
+        Ok(tables.webhooks.len() < before)
+    }
+
+    async fn subscribers(&self, user_id: UserId) -> anyhow::Result<Vec<Webhook>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .webhooks
+            .iter()
+            .filter(|w| w.user_id.is_none_or(|id| id == user_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_deliveries(&self, deliveries: &[Delivery]) -> anyhow::Result<()> {
+        let mut tables = self.tables.write().unwrap();
+        for delivery in deliveries {
+            if tables
+                .webhook_deliveries
+                .iter()
+                .any(|d| d.id == delivery.id)
+            {
+                anyhow::bail!("duplicate delivery id");
+            }
+            if !tables.webhooks.iter().any(|w| w.id == delivery.webhook_id) {
+                anyhow::bail!("no webhook with id {}", delivery.webhook_id.0);
+            }
+        }
+        tables.webhook_deliveries.extend_from_slice(deliveries);
+        Ok(())
+    }
+
+    async fn get_delivery(&self, id: DeliveryId) -> anyhow::Result<Option<Delivery>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .webhook_deliveries
+            .iter()
+            .find(|d| d.id == id)
+            .cloned())
+    }
+
+    async fn deliveries(&self, webhook_id: WebhookId, limit: i64) -> anyhow::Result<Vec<Delivery>> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .webhook_deliveries
+            .iter()
+            .rev()
+            .filter(|d| d.webhook_id == webhook_id)
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn due_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        limit: i64,
+    ) -> anyhow::Result<Vec<Delivery>> {
+        let tables = self.tables.read().unwrap();
+        let mut due: Vec<Delivery> = tables
+            .webhook_deliveries
+            .iter()
+            .filter(|d| d.next_attempt_at.is_some_and(|at| at <= now))
+            .cloned()
+            .collect();
+        due.sort_by_key(|d| d.next_attempt_at);
+        due.truncate(limit.max(0) as usize);
+        Ok(due)
+    }
+
+    async fn update_delivery(&self, delivery: &Delivery) -> anyhow::Result<Option<Delivery>> {
+        let mut tables = self.tables.write().unwrap();
+        Ok(tables
+            .webhook_deliveries
+            .iter_mut()
+            .find(|d| d.id == delivery.id)
+            .map(|d| {
+                d.attempts = delivery.attempts;
+                d.next_attempt_at = delivery.next_attempt_at;
+                d.last_attempt_at = delivery.last_attempt_at;
+                d.last_status = delivery.last_status;
+                d.last_error = delivery.last_error.clone();
+                d.delivered_at = delivery.delivered_at;
+                d.clone()
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::models::FIRST_VERSION;
+
+    fn todo(list: &TodoList, title: &str) -> Todo {
+        let now = Utc::now();
+        Todo {
+            id: TodoId(Uuid::new_v4()),
+            user_id: list.user_id,
+            list_id: list.id,
+            parent_id: None,
+            title: title.to_string(),
+            notes: None,
+            completed: false,
+            due_at: None,
+            rrule: None,
+            repeat_from_completion: false,
+            created_at: now,
+            updated_at: now,
+            version: FIRST_VERSION,
+            deleted_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn enforces_schema_constraints() {
+        let repo = MemoryRepository::default();
+        let (users, todos, tags, lists): (
+            &dyn UserRepository,
+            &dyn TodoRepository,
+            &dyn TagRepository,
+            &dyn ListRepository,
+        ) = (&repo, &repo, &repo, &repo);
+        let alice = users
+            .insert(&User::new("a@example.com", "A"))
+            .await
+            .unwrap();
+        assert!(users
+            .insert(&User::new("a@example.com", "B"))
+            .await
+            .is_err());
+
+        let inbox = lists.inbox(alice.id).await.unwrap().unwrap();
+        assert!(lists.insert(&TodoList::inbox(alice.id)).await.is_err());
+
+        // A todo for an unknown user rejects the whole batch:
+        let stranger = TodoList::inbox(UserId(Uuid::new_v4()));
+        let batch = [todo(&inbox, "first"), todo(&stranger, "second")];
+        let errors = todos.insert_many(&batch, false).await.unwrap();
+        assert_eq!(errors.iter().map(|e| e.0).collect::<Vec<_>>(), vec![1]);
+        assert!(todos.list(TodoFilter::default()).await.unwrap().is_empty());
+
+        let errors = todos.insert_many(&batch[..1], false).await.unwrap();
+        assert!(errors.is_empty());
+        let done = todos
+            .set_completed(batch[0].id, true, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(done.completed);
+        let pending = TodoFilter {
+            completed: Some(false),
+            ..Default::default()
+        };
+        assert!(todos.list(pending).await.unwrap().is_empty());
+
+        let home = Tag {
+            id: TagId(Uuid::new_v4()),
+            user_id: alice.id,
+            name: "home".to_string(),
+            created_at: Utc::now(),
+        };
+        tags.insert(&home).await.unwrap();
+        let duplicate = Tag {
+            id: TagId(Uuid::new_v4()),
+            ..home.clone()
+        };
+        assert!(tags.insert(&duplicate).await.is_err());
+        assert!(tags.add_to_todo(batch[0].id, home.id).await.unwrap());
+        assert!(!tags.add_to_todo(batch[0].id, home.id).await.unwrap());
+        let tagged = TodoFilter {
+            tag: Some("home".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(todos.list(tagged).await.unwrap().len(), 1);
+
+        // Deleting a list moves its todos, or deletes them along with it:
+        let work = TodoList::new(alice.id, "Work", None, 1);
+        lists.insert(&work).await.unwrap();
+        let moved = todos.set_list(batch[0].id, work.id).await.unwrap().unwrap();
+        assert_eq!(moved.list_id, work.id);
+        assert!(todos.set_list(batch[0].id, stranger.id).await.is_err());
+        assert!(lists.delete(work.id, Some(inbox.id)).await.unwrap());
+        let in_inbox = TodoFilter {
+            list_id: Some(inbox.id),
+            ..Default::default()
+        };
+        assert_eq!(todos.list(in_inbox).await.unwrap().len(), 1);
+
+        // Deleting a todo trashes its subtasks, and restoring it brings
+        // them back:
+        let step = Todo {
+            parent_id: Some(batch[0].id),
+            ..todo(&inbox, "step")
+        };
+        todos
+            .insert_many(std::slice::from_ref(&step), false)
+            .await
+            .unwrap();
+        assert!(todos.set_parent(step.id, Some(step.id)).await.is_err());
+        assert_eq!(todos.subtree(batch[0].id).await.unwrap().len(), 2);
+        todos
+            .set_subtree_completed(batch[0].id, false, None)
+            .await
+            .unwrap();
+        assert!(!todos.get(step.id).await.unwrap().unwrap().completed);
+        assert!(todos.delete(batch[0].id, None).await.unwrap());
+        assert!(todos.get(step.id).await.unwrap().is_none());
+        let trash: &dyn TrashRepository = &repo;
+        assert_eq!(trash.todos(alice.id).await.unwrap().len(), 2);
+        let restored = trash.restore_todo(alice.id, batch[0].id).await.unwrap();
+        assert!(restored.is_some_and(|t| t.deleted_at.is_none()));
+        assert!(todos.get(step.id).await.unwrap().is_some());
+
+        // Deleting the user trashes their todos, and purging the trash
+        // cascades to their tags:
+        assert!(users.delete(alice.id, None).await.unwrap());
+        assert!(todos.get(batch[0].id).await.unwrap().is_none());
+        assert_eq!(trash.users().await.unwrap().len(), 1);
+        let purged = trash.purge(Utc::now() + Duration::seconds(1)).await;
+        assert_eq!(purged.unwrap(), 5);
+        assert!(tags.get(home.id).await.unwrap().is_none());
+        assert!(tags.list_links().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,1513 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::{stream::BoxStream, StreamExt};
+use sqlx::{PgPool, Postgres, Transaction};
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::{
+    AuditFilter, AuditRepository, CalDavRepository, ListRepository, TagRepository, TodoFilter,
+    TodoRepository, TrashRepository, UserRepository, WebhookRepository,
+};
+use crate::{
+    models::{
+        api_token::ApiToken,
+        app_password::AppPassword,
+        audit::AuditEntry,
+        ids::{ApiTokenId, AppPasswordId, DeliveryId, ListId, TagId, TodoId, UserId, WebhookId},
+        list::TodoList,
+        session::Session,
+        tag::{Tag, TagCount, TodoTag},
+        todo::{Subtask, Todo},
+        user::{Role, User},
+        webhook::{Delivery, Webhook},
+    },
+    transfer::DumpRecord,
+};
+
+// Queries are checked at runtime here, since the compile-time checked
+// macros are verified against the SQLite DATABASE_URL.
+
+/// `users` columns as read into [`User`] (which keeps unix seconds).
+const USER_COLUMNS: &str =
+    "id, email, display_name, EXTRACT(EPOCH FROM created_at)::BIGINT AS created_at, version,
+     EXTRACT(EPOCH FROM deleted_at)::BIGINT AS deleted_at, role";
+const TODO_COLUMNS: &str =
+    "id, user_id, list_id, parent_id, title, notes, completed, due_at, rrule, repeat_from_completion,
+     created_at, updated_at, version, deleted_at";
+/// A todo (`$1`) and its subtasks at every level.
+const SUBTREE: &str = "WITH RECURSIVE subtree(id) AS (
+       SELECT $1::uuid
+       UNION
+       SELECT todos.id FROM todos JOIN subtree ON todos.parent_id = subtree.id
+     )";
+const LIST_COLUMNS: &str =
+    "id, user_id, name, color, archived, position, inbox, created_at, updated_at, deleted_at";
+const TAG_COLUMNS: &str = "id, user_id, name, created_at";
+const APP_PASSWORD_COLUMNS: &str = "id, user_id, name, created_at";
+const API_TOKEN_COLUMNS: &str = "id, user_id, name, scopes, created_at, expires_at, last_used_at";
+const SESSION_COLUMNS: &str = "id, email, created_at, expires_at";
+const WEBHOOK_COLUMNS: &str = "id, user_id, url, events, secret, created_at";
+const DELIVERY_COLUMNS: &str =
+    "id, webhook_id, event, payload, attempts, next_attempt_at, last_attempt_at, last_status,
+     last_error, delivered_at, created_at";
+
+/// PostgreSQL storage.
+#[derive(Clone, Debug)]
+pub struct PgRepository {
+    db: PgPool,
+}
+
+impl PgRepository {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+
+    /// Insert dump records (keeping their ids) in a single transaction.
+    ///
+    /// Returns the index and error of every rejected record. Nothing is
+    /// committed if any record was rejected, or if `dry_run` is set.
+    pub async fn load_dump(
+        &self,
+        records: &[DumpRecord],
+        dry_run: bool,
+    ) -> anyhow::Result<Vec<(usize, String)>> {
+        let mut tx = self.db.begin().await?;
+        let mut errors = Vec::new();
+        for (i, record) in records.iter().enumerate() {
+            let result = match record {
+                DumpRecord::User(user) => insert_user(&mut tx, user).await.map(|_| ()),
+                DumpRecord::List(list) => insert_list(&mut tx, list).await.map(|_| ()),
+                DumpRecord::Todo(todo) => insert_todo(&mut tx, todo).await,
+                DumpRecord::Subtask(link) => insert_subtask(&mut tx, link).await,
+                DumpRecord::Tag(tag) => insert_tag(&mut tx, tag).await.map(|_| ()),
+                DumpRecord::TodoTag(link) => insert_todo_tag(&mut tx, link).await.map(|_| ()),
+            };
+            if let Err(e) = result {
+                errors.push((i, e.to_string()));
+            }
+        }
+        finish(tx, errors, dry_run).await
+    }
+}
+
+// Unlike SQLite, a failed statement aborts the whole Postgres
+// transaction, so each insert runs inside its own savepoint.
+
+async fn insert_user(tx: &mut Transaction<'_, Postgres>, user: &User) -> sqlx::Result<User> {
+    let mut savepoint = sqlx::Acquire::begin(&mut **tx).await?;
+    let user = sqlx::query_as::<_, User>(&format!(
+        "INSERT INTO users (id, email, display_name, created_at, version, role)
+         VALUES ($1, $2, $3, to_timestamp($4), $5, $6)
+         RETURNING {USER_COLUMNS}"
+    ))
+    .bind(user.id)
+    .bind(&user.email)
+    .bind(&user.display_name)
+    .bind(user.created_at as f64)
+    .bind(user.version)
+    .bind(user.role)
+    .fetch_one(&mut *savepoint)
+    .await?;
+    savepoint.commit().await?;
+    Ok(user)
+}
+
+async fn insert_todo(tx: &mut Transaction<'_, Postgres>, todo: &Todo) -> sqlx::Result<()> {
+    let mut savepoint = sqlx::Acquire::begin(&mut **tx).await?;
+    sqlx::query(
+        "INSERT INTO todos (
+           id, user_id, list_id, parent_id, title, notes, completed, due_at, rrule,
+           repeat_from_completion, created_at, updated_at, version
+         )
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
+    )
+    .bind(todo.id)
+    .bind(todo.user_id)
+    .bind(todo.list_id)
+    .bind(todo.parent_id)
+    .bind(&todo.title)
+    .bind(&todo.notes)
+    .bind(todo.completed)
+    .bind(todo.due_at)
+    .bind(&todo.rrule)
+    .bind(todo.repeat_from_completion)
+    .bind(todo.created_at)
+    .bind(todo.updated_at)
+    .bind(todo.version)
+    .execute(&mut *savepoint)
+    .await?;
+    savepoint.commit().await?;
+    Ok(())
+}
+
+async fn insert_subtask(tx: &mut Transaction<'_, Postgres>, link: &Subtask) -> sqlx::Result<()> {
+    let mut savepoint = sqlx::Acquire::begin(&mut **tx).await?;
+    let result = sqlx::query("UPDATE todos SET parent_id = $1 WHERE id = $2")
+        .bind(link.parent_id)
+        .bind(link.todo_id)
+        .execute(&mut *savepoint)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+    savepoint.commit().await?;
+    Ok(())
+}
+
+async fn insert_list(
+    tx: &mut Transaction<'_, Postgres>,
+    list: &TodoList,
+) -> sqlx::Result<TodoList> {
+    let mut savepoint = sqlx::Acquire::begin(&mut **tx).await?;
+    let list = sqlx::query_as::<_, TodoList>(&format!(
+        "INSERT INTO lists (
+           id, user_id, name, color, archived, position, inbox, created_at, updated_at
+         )
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+         RETURNING {LIST_COLUMNS}"
+    ))
+    .bind(list.id)
+    .bind(list.user_id)
+    .bind(&list.name)
+    .bind(&list.color)
+    .bind(list.archived)
+    .bind(list.position)
+    .bind(list.inbox)
+    .bind(list.created_at)
+    .bind(list.updated_at)
+    .fetch_one(&mut *savepoint)
+    .await?;
+    savepoint.commit().await?;
+    Ok(list)
+}
+
+async fn insert_tag(tx: &mut Transaction<'_, Postgres>, tag: &Tag) -> sqlx::Result<Tag> {
+    let mut savepoint = sqlx::Acquire::begin(&mut **tx).await?;
+    let tag = sqlx::query_as::<_, Tag>(&format!(
+        "INSERT INTO tags (id, user_id, name, created_at)
+         VALUES ($1, $2, $3, $4)
+         RETURNING {TAG_COLUMNS}"
+    ))
+    .bind(tag.id)
+    .bind(tag.user_id)
+    .bind(&tag.name)
+    .bind(tag.created_at)
+    .fetch_one(&mut *savepoint)
+    .await?;
+    savepoint.commit().await?;
+    Ok(tag)
+}
+
+/// Returns `false` if the todo already had the tag.
+async fn insert_todo_tag(tx: &mut Transaction<'_, Postgres>, link: &TodoTag) -> sqlx::Result<bool> {
+    let mut savepoint = sqlx::Acquire::begin(&mut **tx).await?;
+    let result = sqlx::query(
+        "INSERT INTO todo_tags (todo_id, tag_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+    )
+    .bind(link.todo_id)
+    .bind(link.tag_id)
+    .execute(&mut *savepoint)
+    .await?;
+    savepoint.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// After a restore, move a user's todos out of lists that are still in
+/// the trash (to their inbox), and out from under parents that are.
+async fn rehome_restored(tx: &mut Transaction<'_, Postgres>, user_id: UserId) -> sqlx::Result<()> {
+    sqlx::query(
+        "UPDATE todos SET list_id = (SELECT id FROM lists WHERE user_id = $1 AND inbox)
+         WHERE user_id = $1 AND deleted_at IS NULL
+           AND list_id IN (SELECT id FROM lists WHERE deleted_at IS NOT NULL)",
+    )
+    .bind(user_id)
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query(
+        "UPDATE todos SET parent_id = NULL
+         WHERE user_id = $1 AND deleted_at IS NULL
+           AND parent_id IN (SELECT id FROM todos WHERE deleted_at IS NOT NULL)",
+    )
+    .bind(user_id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Commit only when nothing failed and this is not a dry run.
+async fn finish(
+    tx: Transaction<'_, Postgres>,
+    errors: Vec<(usize, String)>,
+    dry_run: bool,
+) -> anyhow::Result<Vec<(usize, String)>> {
+    if errors.is_empty() && !dry_run {
+        tx.commit().await?;
+    } else {
+        tx.rollback().await?;
+    }
+    Ok(errors)
+}
+
+#[async_trait]
+impl UserRepository for PgRepository {
+    async fn insert(&self, user: &User) -> anyhow::Result<User> {
+        let mut tx = self.db.begin().await?;
+        let user = insert_user(&mut tx, user).await?;
+        insert_list(&mut tx, &TodoList::inbox(user.id)).await?;
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    async fn get(&self, id: UserId) -> anyhow::Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(&format!(
+            "SELECT {USER_COLUMNS} FROM users WHERE id = $1 AND deleted_at IS NULL"
+        ))
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(user)
+    }
+
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(&format!(
+            "SELECT {USER_COLUMNS} FROM users WHERE email = $1 AND deleted_at IS NULL"
+        ))
+        .bind(email)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(user)
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<User>> {
+        let users = sqlx::query_as::<_, User>(&format!(
+            "SELECT {USER_COLUMNS} FROM users WHERE deleted_at IS NULL ORDER BY email"
+        ))
+        .fetch_all(&self.db)
+        .await?;
+        Ok(users)
+    }
+
+    async fn set_display_name(
+        &self,
+        id: UserId,
+        display_name: &str,
+        version: Option<i64>,
+    ) -> anyhow::Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(&format!(
+            "UPDATE users SET display_name = $1, version = version + 1
+             WHERE id = $2 AND ($3::bigint IS NULL OR version = $3) AND deleted_at IS NULL
+             RETURNING {USER_COLUMNS}"
+        ))
+        .bind(display_name)
+        .bind(id)
+        .bind(version)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(user)
+    }
+
+    async fn set_role(&self, id: UserId, role: Role) -> anyhow::Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(&format!(
+            "UPDATE users SET role = $1, version = version + 1
+             WHERE id = $2 AND deleted_at IS NULL
+             RETURNING {USER_COLUMNS}"
+        ))
+        .bind(role)
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(user)
+    }
+
+    async fn delete(&self, id: UserId, version: Option<i64>) -> anyhow::Result<bool> {
+        // now() is the same throughout the transaction, which is how a
+        // restore tells what was trashed along with the user.
+        let mut tx = self.db.begin().await?;
+        let result = sqlx::query(
+            "UPDATE users SET deleted_at = now(), version = version + 1
+             WHERE id = $1 AND ($2::bigint IS NULL OR version = $2) AND deleted_at IS NULL",
+        )
+        .bind(id)
+        .bind(version)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query(
+            "UPDATE todos SET deleted_at = now(), version = version + 1
+             WHERE user_id = $1 AND deleted_at IS NULL",
+        )
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "UPDATE lists SET deleted_at = now() WHERE user_id = $1 AND deleted_at IS NULL",
+        )
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn set_calendar_token(&self, id: UserId, token_hash: &str) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO calendar_tokens (user_id, token_hash, created_at)
+             VALUES ($1, $2, now())
+             ON CONFLICT (user_id) DO UPDATE
+             SET token_hash = excluded.token_hash, created_at = excluded.created_at",
+        )
+        .bind(id)
+        .bind(token_hash)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_calendar_token(&self, id: UserId) -> anyhow::Result<bool> {
+        let result = sqlx::query("DELETE FROM calendar_tokens WHERE user_id = $1")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_by_calendar_token(&self, token_hash: &str) -> anyhow::Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(&format!(
+            "SELECT {USER_COLUMNS} FROM users
+             WHERE id = (SELECT user_id FROM calendar_tokens WHERE token_hash = $1)
+               AND deleted_at IS NULL"
+        ))
+        .bind(token_hash)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(user)
+    }
+
+    async fn set_password_hash(
+        &self,
+        id: UserId,
+        password_hash: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        let result =
+            sqlx::query("UPDATE users SET password_hash = $1 WHERE id = $2 AND deleted_at IS NULL")
+                .bind(password_hash)
+                .bind(id)
+                .execute(&self.db)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn password_hash(&self, id: UserId) -> anyhow::Result<Option<String>> {
+        let password_hash: Option<Option<String>> = sqlx::query_scalar(
+            "SELECT password_hash FROM users WHERE id = $1 AND deleted_at IS NULL",
+        )
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(password_hash.flatten())
+    }
+    async fn insert_app_password(
+        &self,
+        app_password: &AppPassword,
+        token_hash: &str,
+    ) -> anyhow::Result<AppPassword> {
+        let app_password = sqlx::query_as::<_, AppPassword>(&format!(
+            "INSERT INTO app_passwords (id, user_id, name, token_hash, created_at)
+             VALUES ($1, $2, $3, $4, $5)
+             RETURNING {APP_PASSWORD_COLUMNS}"
+        ))
+        .bind(app_password.id)
+        .bind(app_password.user_id)
+        .bind(&app_password.name)
+        .bind(token_hash)
+        .bind(app_password.created_at)
+        .fetch_one(&self.db)
+        .await?;
+        Ok(app_password)
+    }
+
+    async fn app_passwords(&self, id: UserId) -> anyhow::Result<Vec<AppPassword>> {
+        let app_passwords = sqlx::query_as::<_, AppPassword>(&format!(
+            "SELECT {APP_PASSWORD_COLUMNS} FROM app_passwords
+             WHERE user_id = $1
+             ORDER BY created_at, id"
+        ))
+        .bind(id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(app_passwords)
+    }
+
+    async fn delete_app_password(
+        &self,
+        id: UserId,
+        app_password_id: AppPasswordId,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query("DELETE FROM app_passwords WHERE id = $1 AND user_id = $2")
+            .bind(app_password_id)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_by_app_password(&self, token_hash: &str) -> anyhow::Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(&format!(
+            "SELECT {USER_COLUMNS} FROM users
+             WHERE id = (SELECT user_id FROM app_passwords WHERE token_hash = $1)
+               AND deleted_at IS NULL"
+        ))
+        .bind(token_hash)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(user)
+    }
+
+    async fn insert_api_token(
+        &self,
+        api_token: &ApiToken,
+        token_hash: &str,
+    ) -> anyhow::Result<ApiToken> {
+        let api_token = sqlx::query_as::<_, ApiToken>(&format!(
+            "INSERT INTO api_tokens (id, user_id, name, scopes, token_hash, created_at, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             RETURNING {API_TOKEN_COLUMNS}"
+        ))
+        .bind(api_token.id)
+        .bind(api_token.user_id)
+        .bind(&api_token.name)
+        .bind(&api_token.scopes)
+        .bind(token_hash)
+        .bind(api_token.created_at)
+        .bind(api_token.expires_at)
+        .fetch_one(&self.db)
+        .await?;
+        Ok(api_token)
+    }
+
+    async fn api_tokens(&self, id: UserId) -> anyhow::Result<Vec<ApiToken>> {
+        let api_tokens = sqlx::query_as::<_, ApiToken>(&format!(
+            "SELECT {API_TOKEN_COLUMNS} FROM api_tokens
+             WHERE user_id = $1
+             ORDER BY created_at, id"
+        ))
+        .bind(id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(api_tokens)
+    }
+
+    async fn delete_api_token(&self, id: UserId, api_token_id: ApiTokenId) -> anyhow::Result<bool> {
+        let result = sqlx::query("DELETE FROM api_tokens WHERE id = $1 AND user_id = $2")
+            .bind(api_token_id)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_by_api_token(
+        &self,
+        token_hash: &str,
+    ) -> anyhow::Result<Option<(ApiToken, User)>> {
+        let api_token = sqlx::query_as::<_, ApiToken>(&format!(
+            "SELECT {API_TOKEN_COLUMNS} FROM api_tokens WHERE token_hash = $1"
+        ))
+        .bind(token_hash)
+        .fetch_optional(&self.db)
+        .await?;
+        let Some(api_token) = api_token else {
+            return Ok(None);
+        };
+        let user = UserRepository::get(self, api_token.user_id).await?;
+        Ok(user.map(|user| (api_token, user)))
+    }
+
+    async fn touch_api_token(
+        &self,
+        api_token_id: ApiTokenId,
+        at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        sqlx::query("UPDATE api_tokens SET last_used_at = $1 WHERE id = $2")
+            .bind(at)
+            .bind(api_token_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_session(&self, session: &Session, token_hash: &str) -> anyhow::Result<Session> {
+        let session = sqlx::query_as::<_, Session>(&format!(
+            "INSERT INTO sessions (id, email, token_hash, created_at, expires_at)
+             VALUES ($1, $2, $3, $4, $5)
+             RETURNING {SESSION_COLUMNS}"
+        ))
+        .bind(session.id)
+        .bind(&session.email)
+        .bind(token_hash)
+        .bind(session.created_at)
+        .bind(session.expires_at)
+        .fetch_one(&self.db)
+        .await?;
+        Ok(session)
+    }
+
+    async fn find_session(&self, token_hash: &str) -> anyhow::Result<Option<Session>> {
+        let session = sqlx::query_as::<_, Session>(&format!(
+            "SELECT {SESSION_COLUMNS} FROM sessions WHERE token_hash = $1"
+        ))
+        .bind(token_hash)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(session)
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query("DELETE FROM sessions WHERE token_hash = $1")
+            .bind(token_hash)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn purge_sessions(&self, before: DateTime<Utc>) -> anyhow::Result<u64> {
+        let result = sqlx::query("DELETE FROM sessions WHERE expires_at < $1")
+            .bind(before)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_sessions(&self, email: &str) -> anyhow::Result<u64> {
+        let result = sqlx::query("DELETE FROM sessions WHERE email = $1")
+            .bind(email)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl TodoRepository for PgRepository {
+    async fn get(&self, id: TodoId) -> anyhow::Result<Option<Todo>> {
+        let todo = sqlx::query_as::<_, Todo>(&format!(
+            "SELECT {TODO_COLUMNS} FROM todos WHERE id = $1 AND deleted_at IS NULL"
+        ))
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(todo)
+    }
+
+    fn stream(&self, filter: TodoFilter) -> BoxStream<'static, anyhow::Result<Todo>> {
+        let db = self.db.clone();
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+
+        tokio::spawn(async move {
+            let sql = format!(
+                "SELECT {TODO_COLUMNS} FROM todos
+                 WHERE deleted_at IS NULL
+                   AND ($1::uuid IS NULL OR user_id = $1)
+                   AND ($2::boolean IS NULL OR completed = $2)
+                   AND ($3::text IS NULL OR id IN (
+                     SELECT todo_tags.todo_id FROM todo_tags
+                     JOIN tags ON tags.id = todo_tags.tag_id
+                     WHERE tags.name = $3
+                   ))
+                   AND ($4::uuid IS NULL OR list_id = $4)
+                 ORDER BY created_at"
+            );
+            let mut rows = sqlx::query_as::<_, Todo>(&sql)
+                .bind(filter.user_id)
+                .bind(filter.completed)
+                .bind(filter.tag)
+                .bind(filter.list_id)
+                .fetch(&db);
+
+            while let Some(row) = rows.next().await {
+                if tx.send(row.map_err(Into::into)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        ReceiverStream::new(rx).boxed()
+    }
+
+    async fn set_completed(
+        &self,
+        id: TodoId,
+        completed: bool,
+        version: Option<i64>,
+    ) -> anyhow::Result<Option<Todo>> {
+        let todo = sqlx::query_as::<_, Todo>(&format!(
+            "UPDATE todos SET completed = $1, updated_at = now(), version = version + 1
+             WHERE id = $2 AND ($3::bigint IS NULL OR version = $3) AND deleted_at IS NULL
+             RETURNING {TODO_COLUMNS}"
+        ))
+        .bind(completed)
+        .bind(id)
+        .bind(version)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(todo)
+    }
+
+    async fn set_subtree_completed(
+        &self,
+        id: TodoId,
+        completed: bool,
+        version: Option<i64>,
+    ) -> anyhow::Result<Option<Todo>> {
+        let mut tx = self.db.begin().await?;
+        let root = sqlx::query(
+            "UPDATE todos SET completed = $1, updated_at = now(), version = version + 1
+             WHERE id = $2 AND ($3::bigint IS NULL OR version = $3) AND deleted_at IS NULL",
+        )
+        .bind(completed)
+        .bind(id)
+        .bind(version)
+        .execute(&mut *tx)
+        .await?;
+        if root.rows_affected() == 0 {
+            return Ok(None);
+        }
+        sqlx::query(&format!(
+            "{SUBTREE}
+             UPDATE todos SET completed = $2, updated_at = now(), version = version + 1
+             WHERE id IN (SELECT id FROM subtree) AND id <> $1 AND deleted_at IS NULL"
+        ))
+        .bind(id)
+        .bind(completed)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        TodoRepository::get(self, id).await
+    }
+
+    async fn set_list(&self, id: TodoId, list_id: ListId) -> anyhow::Result<Option<Todo>> {
+        let todo = sqlx::query_as::<_, Todo>(&format!(
+            "UPDATE todos SET list_id = $1, updated_at = now(), version = version + 1
+             WHERE id = $2 AND deleted_at IS NULL
+             RETURNING {TODO_COLUMNS}"
+        ))
+        .bind(list_id)
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(todo)
+    }
+
+    async fn set_parent(
+        &self,
+        id: TodoId,
+        parent_id: Option<TodoId>,
+    ) -> anyhow::Result<Option<Todo>> {
+        let todo = sqlx::query_as::<_, Todo>(&format!(
+            "UPDATE todos SET parent_id = $1, updated_at = now(), version = version + 1
+             WHERE id = $2 AND deleted_at IS NULL
+             RETURNING {TODO_COLUMNS}"
+        ))
+        .bind(parent_id)
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(todo)
+    }
+
+    async fn update(&self, todo: &Todo) -> anyhow::Result<Option<Todo>> {
+        let todo = sqlx::query_as::<_, Todo>(&format!(
+            "UPDATE todos
+             SET title = $1, notes = $2, completed = $3, due_at = $4, rrule = $5,
+                 repeat_from_completion = $6, updated_at = now(), version = version + 1
+             WHERE id = $7 AND version = $8 AND deleted_at IS NULL
+             RETURNING {TODO_COLUMNS}"
+        ))
+        .bind(&todo.title)
+        .bind(&todo.notes)
+        .bind(todo.completed)
+        .bind(todo.due_at)
+        .bind(&todo.rrule)
+        .bind(todo.repeat_from_completion)
+        .bind(todo.id)
+        .bind(todo.version)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(todo)
+    }
+
+    async fn set_recurrence(
+        &self,
+        id: TodoId,
+        rrule: Option<&str>,
+        repeat_from_completion: bool,
+    ) -> anyhow::Result<Option<Todo>> {
+        let todo = sqlx::query_as::<_, Todo>(&format!(
+            "UPDATE todos
+             SET rrule = $1, repeat_from_completion = $2, updated_at = now(),
+                 version = version + 1
+             WHERE id = $3 AND deleted_at IS NULL
+             RETURNING {TODO_COLUMNS}"
+        ))
+        .bind(rrule)
+        .bind(repeat_from_completion)
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(todo)
+    }
+
+    async fn subtree(&self, id: TodoId) -> anyhow::Result<Vec<Todo>> {
+        let todos = sqlx::query_as::<_, Todo>(&format!(
+            "{SUBTREE}
+             SELECT {TODO_COLUMNS} FROM todos
+             WHERE id IN (SELECT id FROM subtree) AND deleted_at IS NULL
+             ORDER BY created_at"
+        ))
+        .bind(id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(todos)
+    }
+
+    async fn delete(&self, id: TodoId, version: Option<i64>) -> anyhow::Result<bool> {
+        let result = sqlx::query(&format!(
+            "{SUBTREE}
+             UPDATE todos SET deleted_at = now(), version = version + 1
+             WHERE id IN (SELECT id FROM subtree) AND deleted_at IS NULL
+               AND EXISTS (
+                 SELECT 1 FROM todos
+                 WHERE id = $1 AND ($2::bigint IS NULL OR version = $2) AND deleted_at IS NULL
+               )"
+        ))
+        .bind(id)
+        .bind(version)
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn insert_many(
+        &self,
+        todos: &[Todo],
+        dry_run: bool,
+    ) -> anyhow::Result<Vec<(usize, String)>> {
+        let mut tx = self.db.begin().await?;
+        let mut errors = Vec::new();
+        for (i, todo) in todos.iter().enumerate() {
+            if let Err(e) = insert_todo(&mut tx, todo).await {
+                errors.push((i, e.to_string()));
+            }
+        }
+        finish(tx, errors, dry_run).await
+    }
+}
+
+#[async_trait]
+impl TagRepository for PgRepository {
+    async fn insert(&self, tag: &Tag) -> anyhow::Result<Tag> {
+        let mut tx = self.db.begin().await?;
+        let tag = insert_tag(&mut tx, tag).await?;
+        tx.commit().await?;
+        Ok(tag)
+    }
+
+    async fn get(&self, id: TagId) -> anyhow::Result<Option<Tag>> {
+        let tag =
+            sqlx::query_as::<_, Tag>(&format!("SELECT {TAG_COLUMNS} FROM tags WHERE id = $1"))
+                .bind(id)
+                .fetch_optional(&self.db)
+                .await?;
+        Ok(tag)
+    }
+
+    async fn find_by_name(&self, user_id: UserId, name: &str) -> anyhow::Result<Option<Tag>> {
+        let tag = sqlx::query_as::<_, Tag>(&format!(
+            "SELECT {TAG_COLUMNS} FROM tags WHERE user_id = $1 AND name = $2"
+        ))
+        .bind(user_id)
+        .bind(name)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(tag)
+    }
+
+    async fn list_all(&self) -> anyhow::Result<Vec<Tag>> {
+        let tags = sqlx::query_as::<_, Tag>(&format!(
+            "SELECT {TAG_COLUMNS} FROM tags
+             WHERE user_id IN (SELECT id FROM users WHERE deleted_at IS NULL)
+             ORDER BY created_at"
+        ))
+        .fetch_all(&self.db)
+        .await?;
+        Ok(tags)
+    }
+
+    async fn list_links(&self) -> anyhow::Result<Vec<TodoTag>> {
+        let links = sqlx::query_as::<_, TodoTag>(
+            "SELECT todo_id, tag_id FROM todo_tags
+             WHERE todo_id IN (SELECT id FROM todos WHERE deleted_at IS NULL)",
+        )
+        .fetch_all(&self.db)
+        .await?;
+        Ok(links)
+    }
+
+    async fn list(&self, user_id: UserId) -> anyhow::Result<Vec<TagCount>> {
+        let tags = sqlx::query_as::<_, TagCount>(
+            "SELECT tags.id, tags.name, COUNT(todo_tags.todo_id) AS todos
+             FROM tags
+             LEFT JOIN todo_tags ON todo_tags.tag_id = tags.id
+               AND todo_tags.todo_id IN (SELECT id FROM todos WHERE deleted_at IS NULL)
+             WHERE tags.user_id = $1
+             GROUP BY tags.id
+             ORDER BY tags.name",
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(tags)
+    }
+
+    async fn rename(&self, id: TagId, name: &str) -> anyhow::Result<Option<Tag>> {
+        let tag = sqlx::query_as::<_, Tag>(&format!(
+            "UPDATE tags SET name = $1 WHERE id = $2 RETURNING {TAG_COLUMNS}"
+        ))
+        .bind(name)
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(tag)
+    }
+
+    async fn delete(&self, id: TagId) -> anyhow::Result<bool> {
+        let result = sqlx::query("DELETE FROM tags WHERE id = $1")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn for_todo(&self, todo_id: TodoId) -> anyhow::Result<Vec<Tag>> {
+        let tags = sqlx::query_as::<_, Tag>(
+            "SELECT tags.id, tags.user_id, tags.name, tags.created_at
+             FROM tags
+             JOIN todo_tags ON todo_tags.tag_id = tags.id
+             WHERE todo_tags.todo_id = $1
+             ORDER BY tags.name",
+        )
+        .bind(todo_id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(tags)
+    }
+
+    async fn add_to_todo(&self, todo_id: TodoId, tag_id: TagId) -> anyhow::Result<bool> {
+        let mut tx = self.db.begin().await?;
+        let added = insert_todo_tag(&mut tx, &TodoTag { todo_id, tag_id }).await?;
+        tx.commit().await?;
+        Ok(added)
+    }
+
+    async fn remove_from_todo(&self, todo_id: TodoId, tag_id: TagId) -> anyhow::Result<bool> {
+        let result = sqlx::query("DELETE FROM todo_tags WHERE todo_id = $1 AND tag_id = $2")
+            .bind(todo_id)
+            .bind(tag_id)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl ListRepository for PgRepository {
+    async fn insert(&self, list: &TodoList) -> anyhow::Result<TodoList> {
+        let mut tx = self.db.begin().await?;
+        let list = insert_list(&mut tx, list).await?;
+        tx.commit().await?;
+        Ok(list)
+    }
+
+    async fn get(&self, id: ListId) -> anyhow::Result<Option<TodoList>> {
+        let list = sqlx::query_as::<_, TodoList>(&format!(
+            "SELECT {LIST_COLUMNS} FROM lists WHERE id = $1 AND deleted_at IS NULL"
+        ))
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(list)
+    }
+
+    async fn inbox(&self, user_id: UserId) -> anyhow::Result<Option<TodoList>> {
+        let list = sqlx::query_as::<_, TodoList>(&format!(
+            "SELECT {LIST_COLUMNS} FROM lists WHERE user_id = $1 AND inbox AND deleted_at IS NULL"
+        ))
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(list)
+    }
+
+    async fn list(&self, user_id: UserId, archived: Option<bool>) -> anyhow::Result<Vec<TodoList>> {
+        let lists = sqlx::query_as::<_, TodoList>(&format!(
+            "SELECT {LIST_COLUMNS} FROM lists
+             WHERE user_id = $1 AND ($2::boolean IS NULL OR archived = $2) AND deleted_at IS NULL
+             ORDER BY position, created_at"
+        ))
+        .bind(user_id)
+        .bind(archived)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(lists)
+    }
+
+    async fn list_all(&self) -> anyhow::Result<Vec<TodoList>> {
+        let lists = sqlx::query_as::<_, TodoList>(&format!(
+            "SELECT {LIST_COLUMNS} FROM lists WHERE deleted_at IS NULL ORDER BY created_at"
+        ))
+        .fetch_all(&self.db)
+        .await?;
+        Ok(lists)
+    }
+
+    async fn update(&self, list: &TodoList) -> anyhow::Result<Option<TodoList>> {
+        let list = sqlx::query_as::<_, TodoList>(&format!(
+            "UPDATE lists
+             SET name = $1, color = $2, archived = $3, position = $4, updated_at = now()
+             WHERE id = $5 AND deleted_at IS NULL
+             RETURNING {LIST_COLUMNS}"
+        ))
+        .bind(&list.name)
+        .bind(&list.color)
+        .bind(list.archived)
+        .bind(list.position)
+        .bind(list.id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(list)
+    }
+
+    async fn delete(&self, id: ListId, move_todos_to: Option<ListId>) -> anyhow::Result<bool> {
+        let mut tx = self.db.begin().await?;
+        match move_todos_to {
+            Some(to) => {
+                sqlx::query(
+                    "UPDATE todos SET list_id = $1, updated_at = now(), version = version + 1
+                     WHERE list_id = $2 AND deleted_at IS NULL",
+                )
+                .bind(to)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                // Subtasks in other lists go too, as with deleting a todo.
+                sqlx::query(
+                    "WITH RECURSIVE subtree(id) AS (
+                       SELECT id FROM todos WHERE list_id = $1 AND deleted_at IS NULL
+                       UNION
+                       SELECT todos.id FROM todos JOIN subtree ON todos.parent_id = subtree.id
+                     )
+                     UPDATE todos SET deleted_at = now(), version = version + 1
+                     WHERE id IN (SELECT id FROM subtree) AND deleted_at IS NULL",
+                )
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        let result =
+            sqlx::query("UPDATE lists SET deleted_at = now() WHERE id = $1 AND deleted_at IS NULL")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl TrashRepository for PgRepository {
+    async fn lists(&self, user_id: UserId) -> anyhow::Result<Vec<TodoList>> {
+        let lists = sqlx::query_as::<_, TodoList>(&format!(
+            "SELECT {LIST_COLUMNS} FROM lists
+             WHERE user_id = $1 AND deleted_at IS NOT NULL
+             ORDER BY deleted_at DESC, created_at"
+        ))
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(lists)
+    }
+
+    async fn todos(&self, user_id: UserId) -> anyhow::Result<Vec<Todo>> {
+        let todos = sqlx::query_as::<_, Todo>(&format!(
+            "SELECT {TODO_COLUMNS} FROM todos
+             WHERE user_id = $1 AND deleted_at IS NOT NULL
+             ORDER BY deleted_at DESC, created_at"
+        ))
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(todos)
+    }
+
+    async fn users(&self) -> anyhow::Result<Vec<User>> {
+        let users = sqlx::query_as::<_, User>(&format!(
+            "SELECT {USER_COLUMNS} FROM users
+             WHERE deleted_at IS NOT NULL
+             ORDER BY deleted_at DESC, email"
+        ))
+        .fetch_all(&self.db)
+        .await?;
+        Ok(users)
+    }
+
+    async fn restore_list(&self, user_id: UserId, id: ListId) -> anyhow::Result<Option<TodoList>> {
+        let mut tx = self.db.begin().await?;
+        let Some(deleted_at) = sqlx::query_scalar::<_, DateTime<Utc>>(
+            "SELECT deleted_at FROM lists
+             WHERE id = $1 AND user_id = $2 AND deleted_at IS NOT NULL
+             FOR UPDATE",
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        sqlx::query(
+            "UPDATE todos SET deleted_at = NULL, updated_at = now(), version = version + 1
+             WHERE list_id = $1 AND deleted_at = $2",
+        )
+        .bind(id)
+        .bind(deleted_at)
+        .execute(&mut *tx)
+        .await?;
+        let list = sqlx::query_as::<_, TodoList>(&format!(
+            "UPDATE lists SET deleted_at = NULL, updated_at = now()
+             WHERE id = $1
+             RETURNING {LIST_COLUMNS}"
+        ))
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        rehome_restored(&mut tx, user_id).await?;
+        tx.commit().await?;
+        Ok(Some(list))
+    }
+
+    async fn restore_todo(&self, user_id: UserId, id: TodoId) -> anyhow::Result<Option<Todo>> {
+        let mut tx = self.db.begin().await?;
+        let Some(deleted_at) = sqlx::query_scalar::<_, DateTime<Utc>>(
+            "SELECT deleted_at FROM todos
+             WHERE id = $1 AND user_id = $2 AND deleted_at IS NOT NULL
+             FOR UPDATE",
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        sqlx::query(
+            "WITH RECURSIVE subtree(id) AS (
+               SELECT $1::uuid
+               UNION
+               SELECT todos.id FROM todos JOIN subtree ON todos.parent_id = subtree.id
+               WHERE todos.deleted_at = $2
+             )
+             UPDATE todos SET deleted_at = NULL, updated_at = now(), version = version + 1
+             WHERE id IN (SELECT id FROM subtree) AND deleted_at = $2",
+        )
+        .bind(id)
+        .bind(deleted_at)
+        .execute(&mut *tx)
+        .await?;
+        rehome_restored(&mut tx, user_id).await?;
+        let todo =
+            sqlx::query_as::<_, Todo>(&format!("SELECT {TODO_COLUMNS} FROM todos WHERE id = $1"))
+                .bind(id)
+                .fetch_one(&mut *tx)
+                .await?;
+        tx.commit().await?;
+        Ok(Some(todo))
+    }
+
+    async fn restore_user(&self, id: UserId) -> anyhow::Result<Option<User>> {
+        let mut tx = self.db.begin().await?;
+        let Some(deleted_at) = sqlx::query_scalar::<_, DateTime<Utc>>(
+            "SELECT deleted_at FROM users WHERE id = $1 AND deleted_at IS NOT NULL FOR UPDATE",
+        )
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        sqlx::query(
+            "UPDATE lists SET deleted_at = NULL, updated_at = now()
+             WHERE user_id = $1 AND deleted_at = $2",
+        )
+        .bind(id)
+        .bind(deleted_at)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "UPDATE todos SET deleted_at = NULL, updated_at = now(), version = version + 1
+             WHERE user_id = $1 AND deleted_at = $2",
+        )
+        .bind(id)
+        .bind(deleted_at)
+        .execute(&mut *tx)
+        .await?;
+        let user = sqlx::query_as::<_, User>(&format!(
+            "UPDATE users SET deleted_at = NULL, version = version + 1
+             WHERE id = $1
+             RETURNING {USER_COLUMNS}"
+        ))
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        rehome_restored(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(Some(user))
+    }
+
+    async fn purge(&self, before: DateTime<Utc>) -> anyhow::Result<u64> {
+        let mut tx = self.db.begin().await?;
+        let mut purged = 0;
+        // Subtasks, and the todos in lists, go too (ON DELETE CASCADE).
+        for table in ["todos", "lists", "users"] {
+            purged += sqlx::query(&format!("DELETE FROM {table} WHERE deleted_at < $1"))
+                .bind(before)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+        tx.commit().await?;
+        Ok(purged)
+    }
+}
+
+#[async_trait]
+impl CalDavRepository for PgRepository {
+    async fn uids(&self, list_id: ListId) -> anyhow::Result<Vec<(TodoId, String)>> {
+        let uids = sqlx::query_as::<_, (TodoId, String)>(
+            "SELECT o.todo_id, o.uid
+             FROM caldav_objects o
+             JOIN todos t ON t.id = o.todo_id
+             WHERE t.list_id = $1 AND t.deleted_at IS NULL",
+        )
+        .bind(list_id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(uids)
+    }
+
+    async fn set_uid(&self, todo_id: TodoId, uid: &str) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO caldav_objects (todo_id, uid) VALUES ($1, $2)
+             ON CONFLICT (todo_id) DO UPDATE SET uid = excluded.uid",
+        )
+        .bind(todo_id)
+        .bind(uid)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn find_by_uid(&self, list_id: ListId, uid: &str) -> anyhow::Result<Option<Todo>> {
+        let todo = sqlx::query_as::<_, Todo>(&format!(
+            "SELECT {TODO_COLUMNS} FROM todos
+             WHERE list_id = $1 AND deleted_at IS NULL AND (
+               id IN (SELECT todo_id FROM caldav_objects WHERE uid = $2)
+               OR (id::text = $2 AND id NOT IN (SELECT todo_id FROM caldav_objects))
+             )"
+        ))
+        .bind(list_id)
+        .bind(uid)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(todo)
+    }
+
+    async fn removed_since(
+        &self,
+        list_id: ListId,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<(String, DateTime<Utc>)>> {
+        let removed = sqlx::query_as::<_, (String, DateTime<Utc>)>(
+            "SELECT uid, removed_at FROM caldav_tombstones
+             WHERE list_id = $1 AND removed_at >= $2
+             ORDER BY removed_at",
+        )
+        .bind(list_id)
+        .bind(since)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(removed)
+    }
+}
+
+#[async_trait]
+impl AuditRepository for PgRepository {
+    async fn insert(&self, entries: &[AuditEntry]) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+        for entry in entries {
+            sqlx::query(
+                "INSERT INTO audit_log
+                   (at, actor, real_actor, client_ip, request_id, entity, entity_id, action,
+                    before, after)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+            )
+            .bind(entry.at)
+            .bind(&entry.actor)
+            .bind(&entry.real_actor)
+            .bind(&entry.client_ip)
+            .bind(&entry.request_id)
+            .bind(&entry.entity)
+            .bind(&entry.entity_id)
+            .bind(&entry.action)
+            .bind(&entry.before)
+            .bind(&entry.after)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn list(&self, filter: &AuditFilter) -> anyhow::Result<Vec<AuditEntry>> {
+        let entries = sqlx::query_as::<_, AuditEntry>(
+            "SELECT id, at, actor, real_actor, client_ip, request_id, entity, entity_id, action,
+                    before, after
+             FROM audit_log
+             WHERE ($1::text IS NULL OR actor = $1)
+               AND ($2::text IS NULL OR entity = $2)
+               AND ($3::text IS NULL OR entity_id = $3)
+               AND ($4::text IS NULL OR action = $4)
+               AND ($5::text IS NULL OR request_id = $5)
+               AND ($6::timestamptz IS NULL OR at >= $6)
+               AND ($7::timestamptz IS NULL OR at < $7)
+               AND ($8::bigint IS NULL OR id < $8)
+             ORDER BY id DESC
+             LIMIT $9",
+        )
+        .bind(&filter.actor)
+        .bind(&filter.entity)
+        .bind(&filter.entity_id)
+        .bind(&filter.action)
+        .bind(&filter.request_id)
+        .bind(filter.since)
+        .bind(filter.until)
+        .bind(filter.before_id)
+        .bind(filter.limit)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(entries)
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for PgRepository {
+    async fn insert(&self, webhook: &Webhook) -> anyhow::Result<Webhook> {
+        let webhook = sqlx::query_as::<_, Webhook>(&format!(
+            "INSERT INTO webhooks (id, user_id, url, events, secret, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             RETURNING {WEBHOOK_COLUMNS}"
+        ))
+        .bind(webhook.id)
+        .bind(webhook.user_id)
+        .bind(&webhook.url)
+        .bind(&webhook.events)
+        .bind(&webhook.secret)
+        .bind(webhook.created_at)
+        .fetch_one(&self.db)
+        .await?;
+        Ok(webhook)
+    }
+
+    async fn get(&self, id: WebhookId) -> anyhow::Result<Option<Webhook>> {
+        let webhook = sqlx::query_as::<_, Webhook>(&format!(
+            "SELECT {WEBHOOK_COLUMNS} FROM webhooks WHERE id = $1"
+        ))
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(webhook)
+    }
+
+    async fn list(&self, user_id: Option<UserId>) -> anyhow::Result<Vec<Webhook>> {
+        let webhooks = sqlx::query_as::<_, Webhook>(&format!(
+            "SELECT {WEBHOOK_COLUMNS} FROM webhooks
+             WHERE user_id IS NOT DISTINCT FROM $1
+             ORDER BY created_at, id"
+        ))
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(webhooks)
+    }
+
+    async fn delete(&self, id: WebhookId) -> anyhow::Result<bool> {
+        let result = sqlx::query("DELETE FROM webhooks WHERE id = $1")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn subscribers(&self, user_id: UserId) -> anyhow::Result<Vec<Webhook>> {
+        let webhooks = sqlx::query_as::<_, Webhook>(&format!(
+            "SELECT {WEBHOOK_COLUMNS} FROM webhooks
+             WHERE user_id = $1 OR user_id IS NULL
+             ORDER BY created_at, id"
+        ))
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(webhooks)
+    }
+
+    async fn insert_deliveries(&self, deliveries: &[Delivery]) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+        for delivery in deliveries {
+            sqlx::query(
+                "INSERT INTO webhook_deliveries
+                   (id, webhook_id, event, payload, next_attempt_at, created_at)
+                 VALUES ($1, $2, $3, $4, $5, $6)",
+            )
+            .bind(delivery.id)
+            .bind(delivery.webhook_id)
+            .bind(&delivery.event)
+            .bind(&delivery.payload)
+            .bind(delivery.next_attempt_at)
+            .bind(delivery.created_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_delivery(&self, id: DeliveryId) -> anyhow::Result<Option<Delivery>> {
+        let delivery = sqlx::query_as::<_, Delivery>(&format!(
+            "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries WHERE id = $1"
+        ))
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(delivery)
+    }
+
+    async fn deliveries(&self, webhook_id: WebhookId, limit: i64) -> anyhow::Result<Vec<Delivery>> {
+        let deliveries = sqlx::query_as::<_, Delivery>(&format!(
+            "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries
+             WHERE webhook_id = $1
+             ORDER BY created_at DESC, id DESC
+             LIMIT $2"
+        ))
+        .bind(webhook_id)
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(deliveries)
+    }
+
+    async fn due_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        limit: i64,
+    ) -> anyhow::Result<Vec<Delivery>> {
+        let deliveries = sqlx::query_as::<_, Delivery>(&format!(
+            "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries
+             WHERE next_attempt_at <= $1
+             ORDER BY next_attempt_at, created_at, id
+             LIMIT $2"
+        ))
+        .bind(now)
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(deliveries)
+    }
+
+    async fn update_delivery(&self, delivery: &Delivery) -> anyhow::Result<Option<Delivery>> {
+        let delivery = sqlx::query_as::<_, Delivery>(&format!(
+            "UPDATE webhook_deliveries
+             SET attempts = $2, next_attempt_at = $3, last_attempt_at = $4, last_status = $5,
+                 last_error = $6, delivered_at = $7
+             WHERE id = $1
+             RETURNING {DELIVERY_COLUMNS}"
+        ))
+        .bind(delivery.id)
+        .bind(delivery.attempts)
+        .bind(delivery.next_attempt_at)
+        .bind(delivery.last_attempt_at)
+        .bind(delivery.last_status)
+        .bind(&delivery.last_error)
+        .bind(delivery.delivered_at)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(delivery)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::db::test_postgres;
+
+    #[tokio::test]
+    async fn load_dump_keeps_ids_and_timestamps() {
+        let Some(db) = test_postgres().await else {
+            return;
+        };
+        let user = User::new("a@example.com", "A");
+        let inbox = TodoList::inbox(user.id);
+        let at = Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap();
+        let todo = Todo {
+            id: TodoId(Uuid::new_v4()),
+            user_id: user.id,
+            list_id: inbox.id,
+            parent_id: None,
+            title: "first".to_string(),
+            notes: None,
+            completed: false,
+            due_at: Some(at),
+            rrule: None,
+            repeat_from_completion: false,
+            created_at: at,
+            updated_at: at,
+            version: 3,
+            deleted_at: None,
+        };
+        let records = [
+            DumpRecord::User(user.clone()),
+            DumpRecord::List(inbox),
+            DumpRecord::Todo(todo.clone()),
+        ];
+
+        assert!(db.load_dump(&records, false).await.unwrap().is_empty());
+        let stored = db.repos.users.get(user.id).await.unwrap().unwrap();
+        assert_eq!(stored.created_at, user.created_at);
+        let stored = db.repos.todos.get(todo.id).await.unwrap().unwrap();
+        assert_eq!((stored.due_at, stored.version), (Some(at), 3));
+
+        // Loading again conflicts on every record, and later records
+        // are still checked after the first failure:
+        let errors = db.load_dump(&records, false).await.unwrap();
+        assert_eq!(
+            errors.iter().map(|e| e.0).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+    }
+}
@@ -0,0 +1,2204 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::{stream::BoxStream, StreamExt};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::{
+    AuditFilter, AuditRepository, CalDavRepository, ListRepository, TagRepository, TodoFilter,
+    TodoRepository, TrashRepository, UserRepository, WebhookRepository,
+};
+use crate::{
+    models::{
+        api_token::{ApiToken, Scopes},
+        app_password::AppPassword,
+        audit::AuditEntry,
+        ids::{
+            ApiTokenId, AppPasswordId, DeliveryId, ListId, SessionId, TagId, TodoId, UserId,
+            WebhookId,
+        },
+        list::TodoList,
+        session::Session,
+        tag::{Tag, TagCount, TodoTag},
+        todo::{Subtask, Todo},
+        user::{Role, User},
+        webhook::{Delivery, EventFilter, Webhook},
+    },
+    transfer::DumpRecord,
+};
+
+/// SQLite storage. Reads that can run on a read-only connection use
+/// the separate `db_read` pool.
+#[derive(Clone, Debug)]
+pub struct SqliteRepository {
+    db: SqlitePool,
+    db_read: SqlitePool,
+}
+
+impl SqliteRepository {
+    pub fn new(db: SqlitePool, db_read: SqlitePool) -> Self {
+        Self { db, db_read }
+    }
+
+    /// Insert dump records (keeping their ids) in a single transaction.
+    ///
+    /// Returns the index and error of every rejected record. Nothing is
+    /// committed if any record was rejected, or if `dry_run` is set.
+    pub async fn load_dump(
+        &self,
+        records: &[DumpRecord],
+        dry_run: bool,
+    ) -> anyhow::Result<Vec<(usize, String)>> {
+        let mut tx = self.db.begin().await?;
+        let mut errors = Vec::new();
+        for (i, record) in records.iter().enumerate() {
+            let result = match record {
+                DumpRecord::User(user) => insert_user(&mut tx, user).await.map(|_| ()),
+                DumpRecord::List(list) => insert_list(&mut tx, list).await.map(|_| ()),
+                DumpRecord::Todo(todo) => insert_todo(&mut tx, todo).await,
+                DumpRecord::Subtask(link) => insert_subtask(&mut tx, link).await,
+                DumpRecord::Tag(tag) => insert_tag(&mut tx, tag).await.map(|_| ()),
+                DumpRecord::TodoTag(link) => insert_todo_tag(&mut tx, link).await.map(|_| ()),
+            };
+            if let Err(e) = result {
+                errors.push((i, e.to_string()));
+            }
+        }
+        finish(tx, errors, dry_run).await
+    }
+}
+
+async fn insert_user(tx: &mut Transaction<'_, Sqlite>, user: &User) -> sqlx::Result<User> {
+    sqlx::query_as!(
+        User,
+        r#"
+        INSERT INTO users (id, email, display_name, created_at, version, role)
+        VALUES (?, ?, ?, ?, ?, ?)
+        RETURNING
+          id             as "id: UserId",
+          email,
+          display_name,
+          created_at,
+          version,
+          deleted_at,
+          role           as "role: Role"
+        "#,
+        user.id,
+        user.email,
+        user.display_name,
+        user.created_at,
+        user.version,
+        user.role
+    )
+    .fetch_one(&mut **tx)
+    .await
+}
+
+async fn insert_todo(tx: &mut Transaction<'_, Sqlite>, todo: &Todo) -> sqlx::Result<()> {
+    let due_at = todo.due_at.map(|d| d.timestamp());
+    let created_at = todo.created_at.timestamp();
+    let updated_at = todo.updated_at.timestamp();
+    sqlx::query!(
+        r#"
+        INSERT INTO todos (
+          id, user_id, list_id, parent_id, title, notes, completed, due_at, rrule,
+          repeat_from_completion, created_at, updated_at, version
+        )
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+        todo.id,
+        todo.user_id,
+        todo.list_id,
+        todo.parent_id,
+        todo.title,
+        todo.notes,
+        todo.completed,
+        due_at,
+        todo.rrule,
+        todo.repeat_from_completion,
+        created_at,
+        updated_at,
+        todo.version
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+async fn insert_subtask(tx: &mut Transaction<'_, Sqlite>, link: &Subtask) -> sqlx::Result<()> {
+    let result = sqlx::query!(
+        "UPDATE todos SET parent_id = ? WHERE id = ?",
+        link.parent_id,
+        link.todo_id
+    )
+    .execute(&mut **tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+    Ok(())
+}
+
+async fn insert_list(tx: &mut Transaction<'_, Sqlite>, list: &TodoList) -> sqlx::Result<TodoList> {
+    let created_at = list.created_at.timestamp();
+    let updated_at = list.updated_at.timestamp();
+    sqlx::query_as!(
+        TodoList,
+        r#"
+        INSERT INTO lists (
+          id, user_id, name, color, archived, position, inbox, created_at, updated_at
+        )
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        RETURNING
+          id          as "id: ListId",
+          user_id     as "user_id: UserId",
+          name,
+          color,
+          archived    as "archived: bool",
+          position,
+          inbox       as "inbox: bool",
+          created_at  as "created_at: DateTime<Utc>",
+          updated_at  as "updated_at: DateTime<Utc>",
+          deleted_at  as "deleted_at: DateTime<Utc>"
+        "#,
+        list.id,
+        list.user_id,
+        list.name,
+        list.color,
+        list.archived,
+        list.position,
+        list.inbox,
+        created_at,
+        updated_at
+    )
+    .fetch_one(&mut **tx)
+    .await
+}
+
+async fn insert_tag(tx: &mut Transaction<'_, Sqlite>, tag: &Tag) -> sqlx::Result<Tag> {
+    let created_at = tag.created_at.timestamp();
+    sqlx::query_as!(
+        Tag,
+        r#"
+        INSERT INTO tags (id, user_id, name, created_at)
+        VALUES (?, ?, ?, ?)
+        RETURNING
+          id          as "id: TagId",
+          user_id     as "user_id: UserId",
+          name,
+          created_at  as "created_at: DateTime<Utc>"
+        "#,
+        tag.id,
+        tag.user_id,
+        tag.name,
+        created_at
+    )
+    .fetch_one(&mut **tx)
+    .await
+}
+
+/// Returns `false` if the todo already had the tag.
+async fn insert_todo_tag(tx: &mut Transaction<'_, Sqlite>, link: &TodoTag) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        "INSERT INTO todo_tags (todo_id, tag_id) VALUES (?, ?) ON CONFLICT DO NOTHING",
+        link.todo_id,
+        link.tag_id
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// After a restore, move a user's todos out of lists that are still in
+/// the trash (to their inbox), and out from under parents that are.
+async fn rehome_restored(tx: &mut Transaction<'_, Sqlite>, user_id: UserId) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE todos SET list_id = (SELECT id FROM lists WHERE user_id = ?1 AND inbox)
+        WHERE user_id = ?1 AND deleted_at IS NULL
+          AND list_id IN (SELECT id FROM lists WHERE deleted_at IS NOT NULL)
+        "#,
+        user_id
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE todos SET parent_id = NULL
+        WHERE user_id = ?1 AND deleted_at IS NULL
+          AND parent_id IN (SELECT id FROM todos WHERE deleted_at IS NOT NULL)
+        "#,
+        user_id
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Commit only when nothing failed and this is not a dry run.
+async fn finish(
+    tx: Transaction<'_, Sqlite>,
+    errors: Vec<(usize, String)>,
+    dry_run: bool,
+) -> anyhow::Result<Vec<(usize, String)>> {
+    if errors.is_empty() && !dry_run {
+        tx.commit().await?;
+    } else {
+        tx.rollback().await?;
+    }
+    Ok(errors)
+}
+
+#[async_trait]
+impl UserRepository for SqliteRepository {
+    async fn insert(&self, user: &User) -> anyhow::Result<User> {
+        let mut tx = self.db.begin().await?;
+        let user = insert_user(&mut tx, user).await?;
+        insert_list(&mut tx, &TodoList::inbox(user.id)).await?;
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    async fn get(&self, id: UserId) -> anyhow::Result<Option<User>> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            SELECT
+              id             as "id: UserId",
+              email,
+              display_name,
+              created_at,
+              version,
+              deleted_at,
+              role           as "role: Role"
+            FROM users
+            WHERE id = ? AND deleted_at IS NULL
+            "#,
+            id
+        )
+        .fetch_optional(&self.db_read)
+        .await?;
+        Ok(user)
+    }
+
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<Option<User>> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            SELECT
+              id             as "id: UserId",
+              email,
+              display_name,
+              created_at,
+              version,
+              deleted_at,
+              role           as "role: Role"
+            FROM users
+            WHERE email = ? AND deleted_at IS NULL
+            "#,
+            email
+        )
+        .fetch_optional(&self.db_read)
+        .await?;
+        Ok(user)
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<User>> {
+        let users = sqlx::query_as!(
+            User,
+            r#"
+            SELECT
+              id             as "id: UserId",
+              email,
+              display_name,
+              created_at,
+              version,
+              deleted_at,
+              role           as "role: Role"
+            FROM users
+            WHERE deleted_at IS NULL
+            ORDER BY email
+            "#
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(users)
+    }
+
+    async fn set_display_name(
+        &self,
+        id: UserId,
+        display_name: &str,
+        version: Option<i64>,
+    ) -> anyhow::Result<Option<User>> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            UPDATE users SET display_name = ?1, version = version + 1
+            WHERE id = ?2 AND (?3 IS NULL OR version = ?3) AND deleted_at IS NULL
+            RETURNING
+              id             as "id: UserId",
+              email,
+              display_name,
+              created_at,
+              version,
+              deleted_at,
+              role           as "role: Role"
+            "#,
+            display_name,
+            id,
+            version
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(user)
+    }
+
+    async fn set_role(&self, id: UserId, role: Role) -> anyhow::Result<Option<User>> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            UPDATE users SET role = ?1, version = version + 1
+            WHERE id = ?2 AND deleted_at IS NULL
+            RETURNING
+              id             as "id: UserId",
+              email,
+              display_name,
+              created_at,
+              version,
+              deleted_at,
+              role           as "role: Role"
+            "#,
+            role,
+            id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(user)
+    }
+
+    async fn delete(&self, id: UserId, version: Option<i64>) -> anyhow::Result<bool> {
+        let deleted_at = Utc::now().timestamp();
+        let mut tx = self.db.begin().await?;
+        let result = sqlx::query!(
+            r#"
+            UPDATE users SET deleted_at = ?3, version = version + 1
+            WHERE id = ?1 AND (?2 IS NULL OR version = ?2) AND deleted_at IS NULL
+            "#,
+            id,
+            version,
+            deleted_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query!(
+            r#"
+            UPDATE todos SET deleted_at = ?2, version = version + 1
+            WHERE user_id = ?1 AND deleted_at IS NULL
+            "#,
+            id,
+            deleted_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "UPDATE lists SET deleted_at = ?2 WHERE user_id = ?1 AND deleted_at IS NULL",
+            id,
+            deleted_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn set_calendar_token(&self, id: UserId, token_hash: &str) -> anyhow::Result<()> {
+        let now = Utc::now().timestamp();
+        sqlx::query!(
+            r#"
+            INSERT INTO calendar_tokens (user_id, token_hash, created_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT(user_id) DO UPDATE
+            SET token_hash = excluded.token_hash, created_at = excluded.created_at
+            "#,
+            id,
+            token_hash,
+            now
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_calendar_token(&self, id: UserId) -> anyhow::Result<bool> {
+        let result = sqlx::query!("DELETE FROM calendar_tokens WHERE user_id = ?", id)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_by_calendar_token(&self, token_hash: &str) -> anyhow::Result<Option<User>> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            SELECT
+              u.id           as "id: UserId",
+              u.email,
+              u.display_name,
+              u.created_at,
+              u.version,
+              u.deleted_at,
+              u.role         as "role: Role"
+            FROM users u
+            JOIN calendar_tokens c ON c.user_id = u.id
+            WHERE c.token_hash = ? AND u.deleted_at IS NULL
+            "#,
+            token_hash
+        )
+        .fetch_optional(&self.db_read)
+        .await?;
+        Ok(user)
+    }
+
+    async fn set_password_hash(
+        &self,
+        id: UserId,
+        password_hash: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "UPDATE users SET password_hash = ? WHERE id = ? AND deleted_at IS NULL",
+            password_hash,
+            id
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn password_hash(&self, id: UserId) -> anyhow::Result<Option<String>> {
+        let password_hash = sqlx::query_scalar!(
+            "SELECT password_hash FROM users WHERE id = ? AND deleted_at IS NULL",
+            id
+        )
+        .fetch_optional(&self.db_read)
+        .await?;
+        Ok(password_hash.flatten())
+    }
+    async fn insert_app_password(
+        &self,
+        app_password: &AppPassword,
+        token_hash: &str,
+    ) -> anyhow::Result<AppPassword> {
+        let created_at = app_password.created_at.timestamp();
+        let app_password = sqlx::query_as!(
+            AppPassword,
+            r#"
+            INSERT INTO app_passwords (id, user_id, name, token_hash, created_at)
+            VALUES (?, ?, ?, ?, ?)
+            RETURNING
+              id          as "id: AppPasswordId",
+              user_id     as "user_id: UserId",
+              name,
+              created_at  as "created_at: DateTime<Utc>"
+            "#,
+            app_password.id,
+            app_password.user_id,
+            app_password.name,
+            token_hash,
+            created_at
+        )
+        .fetch_one(&self.db)
+        .await?;
+        Ok(app_password)
+    }
+
+    async fn app_passwords(&self, id: UserId) -> anyhow::Result<Vec<AppPassword>> {
+        let app_passwords = sqlx::query_as!(
+            AppPassword,
+            r#"
+            SELECT
+              id          as "id: AppPasswordId",
+              user_id     as "user_id: UserId",
+              name,
+              created_at  as "created_at: DateTime<Utc>"
+            FROM app_passwords
+            WHERE user_id = ?
+            ORDER BY created_at, rowid
+            "#,
+            id
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(app_passwords)
+    }
+
+    async fn delete_app_password(
+        &self,
+        id: UserId,
+        app_password_id: AppPasswordId,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM app_passwords WHERE id = ? AND user_id = ?",
+            app_password_id,
+            id
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_by_app_password(&self, token_hash: &str) -> anyhow::Result<Option<User>> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            SELECT
+              u.id           as "id: UserId",
+              u.email,
+              u.display_name,
+              u.created_at,
+              u.version,
+              u.deleted_at,
+              u.role         as "role: Role"
+            FROM users u
+            JOIN app_passwords a ON a.user_id = u.id
+            WHERE a.token_hash = ? AND u.deleted_at IS NULL
+            "#,
+            token_hash
+        )
+        .fetch_optional(&self.db_read)
+        .await?;
+        Ok(user)
+    }
+
+    async fn insert_api_token(
+        &self,
+        api_token: &ApiToken,
+        token_hash: &str,
+    ) -> anyhow::Result<ApiToken> {
+        let created_at = api_token.created_at.timestamp();
+        let expires_at = api_token.expires_at.timestamp();
+        let api_token = sqlx::query_as!(
+            ApiToken,
+            r#"
+            INSERT INTO api_tokens (id, user_id, name, scopes, token_hash, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            RETURNING
+              id            as "id: ApiTokenId",
+              user_id       as "user_id: UserId",
+              name,
+              scopes        as "scopes: Scopes",
+              created_at    as "created_at: DateTime<Utc>",
+              expires_at    as "expires_at: DateTime<Utc>",
+              last_used_at  as "last_used_at: DateTime<Utc>"
+            "#,
+            api_token.id,
+            api_token.user_id,
+            api_token.name,
+            api_token.scopes,
+            token_hash,
+            created_at,
+            expires_at
+        )
+        .fetch_one(&self.db)
+        .await?;
+        Ok(api_token)
+    }
+
+    async fn api_tokens(&self, id: UserId) -> anyhow::Result<Vec<ApiToken>> {
+        let api_tokens = sqlx::query_as!(
+            ApiToken,
+            r#"
+            SELECT
+              id            as "id: ApiTokenId",
+              user_id       as "user_id: UserId",
+              name,
+              scopes        as "scopes: Scopes",
+              created_at    as "created_at: DateTime<Utc>",
+              expires_at    as "expires_at: DateTime<Utc>",
+              last_used_at  as "last_used_at: DateTime<Utc>"
+            FROM api_tokens
+            WHERE user_id = ?
+            ORDER BY created_at, rowid
+            "#,
+            id
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(api_tokens)
+    }
+
+    async fn delete_api_token(&self, id: UserId, api_token_id: ApiTokenId) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM api_tokens WHERE id = ? AND user_id = ?",
+            api_token_id,
+            id
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_by_api_token(
+        &self,
+        token_hash: &str,
+    ) -> anyhow::Result<Option<(ApiToken, User)>> {
+        let api_token = sqlx::query_as!(
+            ApiToken,
+            r#"
+            SELECT
+              id            as "id: ApiTokenId",
+              user_id       as "user_id: UserId",
+              name,
+              scopes        as "scopes: Scopes",
+              created_at    as "created_at: DateTime<Utc>",
+              expires_at    as "expires_at: DateTime<Utc>",
+              last_used_at  as "last_used_at: DateTime<Utc>"
+            FROM api_tokens
+            WHERE token_hash = ?
+            "#,
+            token_hash
+        )
+        .fetch_optional(&self.db_read)
+        .await?;
+        let Some(api_token) = api_token else {
+            return Ok(None);
+        };
+        let user = UserRepository::get(self, api_token.user_id).await?;
+        Ok(user.map(|user| (api_token, user)))
+    }
+
+    async fn touch_api_token(
+        &self,
+        api_token_id: ApiTokenId,
+        at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let at = at.timestamp();
+        sqlx::query!(
+            "UPDATE api_tokens SET last_used_at = ? WHERE id = ?",
+            at,
+            api_token_id
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn insert_session(&self, session: &Session, token_hash: &str) -> anyhow::Result<Session> {
+        let created_at = session.created_at.timestamp();
+        let expires_at = session.expires_at.timestamp();
+        let session = sqlx::query_as!(
+            Session,
+            r#"
+            INSERT INTO sessions (id, email, token_hash, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?)
+            RETURNING
+              id            as "id: SessionId",
+              email,
+              created_at    as "created_at: DateTime<Utc>",
+              expires_at    as "expires_at: DateTime<Utc>"
+            "#,
+            session.id,
+            session.email,
+            token_hash,
+            created_at,
+            expires_at
+        )
+        .fetch_one(&self.db)
+        .await?;
+        Ok(session)
+    }
+
+    async fn find_session(&self, token_hash: &str) -> anyhow::Result<Option<Session>> {
+        let session = sqlx::query_as!(
+            Session,
+            r#"
+            SELECT
+              id            as "id: SessionId",
+              email,
+              created_at    as "created_at: DateTime<Utc>",
+              expires_at    as "expires_at: DateTime<Utc>"
+            FROM sessions
+            WHERE token_hash = ?
+            "#,
+            token_hash
+        )
+        .fetch_optional(&self.db_read)
+        .await?;
+        Ok(session)
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query!("DELETE FROM sessions WHERE token_hash = ?", token_hash)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn purge_sessions(&self, before: DateTime<Utc>) -> anyhow::Result<u64> {
+        let before = before.timestamp();
+        let result = sqlx::query!("DELETE FROM sessions WHERE expires_at < ?", before)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_sessions(&self, email: &str) -> anyhow::Result<u64> {
+        let result = sqlx::query!("DELETE FROM sessions WHERE email = ?", email)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl TodoRepository for SqliteRepository {
+    async fn get(&self, id: TodoId) -> anyhow::Result<Option<Todo>> {
+        let todo = sqlx::query_as!(
+            Todo,
+            r#"
+            SELECT
+              id          as "id: TodoId",
+              user_id     as "user_id: UserId",
+              list_id     as "list_id!: ListId",
+              parent_id   as "parent_id: TodoId",
+              title,
+              notes,
+              completed   as "completed: bool",
+              due_at      as "due_at: DateTime<Utc>",
+              rrule,
+              repeat_from_completion as "repeat_from_completion: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              version,
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            FROM todos
+            WHERE id = ? AND deleted_at IS NULL
+            "#,
+            id
+        )
+        .fetch_optional(&self.db_read)
+        .await?;
+        Ok(todo)
+    }
+
+    fn stream(&self, filter: TodoFilter) -> BoxStream<'static, anyhow::Result<Todo>> {
+        let db = self.db_read.clone();
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+
+        tokio::spawn(async move {
+            let mut rows = sqlx::query_as!(
+                Todo,
+                r#"
+                SELECT
+                  id          as "id: TodoId",
+                  user_id     as "user_id: UserId",
+                  list_id     as "list_id!: ListId",
+                  parent_id   as "parent_id: TodoId",
+                  title,
+                  notes,
+                  completed   as "completed: bool",
+                  due_at      as "due_at: DateTime<Utc>",
+                  rrule,
+                  repeat_from_completion as "repeat_from_completion: bool",
+                  created_at  as "created_at: DateTime<Utc>",
+                  updated_at  as "updated_at: DateTime<Utc>",
+                  version,
+                  deleted_at  as "deleted_at: DateTime<Utc>"
+                FROM todos
+                WHERE deleted_at IS NULL
+                  AND (?1 IS NULL OR user_id = ?1)
+                  AND (?2 IS NULL OR completed = ?2)
+                  AND (?3 IS NULL OR id IN (
+                    SELECT todo_tags.todo_id FROM todo_tags
+                    JOIN tags ON tags.id = todo_tags.tag_id
+                    WHERE tags.name = ?3
+                  ))
+                  AND (?4 IS NULL OR list_id = ?4)
+                ORDER BY created_at
+                "#,
+                filter.user_id,
+                filter.completed,
+                filter.tag,
+                filter.list_id
+            )
+            .fetch(&db);
+
+            while let Some(row) = rows.next().await {
+                if tx.send(row.map_err(Into::into)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        ReceiverStream::new(rx).boxed()
+    }
+
+    async fn set_completed(
+        &self,
+        id: TodoId,
+        completed: bool,
+        version: Option<i64>,
+    ) -> anyhow::Result<Option<Todo>> {
+        let updated_at = Utc::now().timestamp();
+        let todo = sqlx::query_as!(
+            Todo,
+            r#"
+            UPDATE todos SET completed = ?1, updated_at = ?2, version = version + 1
+            WHERE id = ?3 AND (?4 IS NULL OR version = ?4) AND deleted_at IS NULL
+            RETURNING
+              id          as "id: TodoId",
+              user_id     as "user_id: UserId",
+              list_id     as "list_id!: ListId",
+              parent_id   as "parent_id: TodoId",
+              title,
+              notes,
+              completed   as "completed: bool",
+              due_at      as "due_at: DateTime<Utc>",
+              rrule,
+              repeat_from_completion as "repeat_from_completion: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              version,
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            "#,
+            completed,
+            updated_at,
+            id,
+            version
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(todo)
+    }
+
+    async fn set_subtree_completed(
+        &self,
+        id: TodoId,
+        completed: bool,
+        version: Option<i64>,
+    ) -> anyhow::Result<Option<Todo>> {
+        let updated_at = Utc::now().timestamp();
+        let mut tx = self.db.begin().await?;
+        let root = sqlx::query!(
+            r#"
+            UPDATE todos SET completed = ?1, updated_at = ?2, version = version + 1
+            WHERE id = ?3 AND (?4 IS NULL OR version = ?4) AND deleted_at IS NULL
+            "#,
+            completed,
+            updated_at,
+            id,
+            version
+        )
+        .execute(&mut *tx)
+        .await?;
+        if root.rows_affected() == 0 {
+            return Ok(None);
+        }
+        sqlx::query!(
+            r#"
+            UPDATE todos SET completed = ?1, updated_at = ?2, version = version + 1
+            WHERE id IN (
+              WITH RECURSIVE subtree(id) AS (
+                SELECT id FROM todos WHERE parent_id = ?3
+                UNION
+                SELECT todos.id FROM todos JOIN subtree ON todos.parent_id = subtree.id
+              )
+              SELECT id FROM subtree
+            ) AND deleted_at IS NULL
+            "#,
+            completed,
+            updated_at,
+            id
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        TodoRepository::get(self, id).await
+    }
+
+    async fn set_list(&self, id: TodoId, list_id: ListId) -> anyhow::Result<Option<Todo>> {
+        let updated_at = Utc::now().timestamp();
+        let todo = sqlx::query_as!(
+            Todo,
+            r#"
+            UPDATE todos SET list_id = ?, updated_at = ?, version = version + 1
+            WHERE id = ? AND deleted_at IS NULL
+            RETURNING
+              id          as "id: TodoId",
+              user_id     as "user_id: UserId",
+              list_id     as "list_id!: ListId",
+              parent_id   as "parent_id: TodoId",
+              title,
+              notes,
+              completed   as "completed: bool",
+              due_at      as "due_at: DateTime<Utc>",
+              rrule,
+              repeat_from_completion as "repeat_from_completion: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              version,
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            "#,
+            list_id,
+            updated_at,
+            id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(todo)
+    }
+
+    async fn set_parent(
+        &self,
+        id: TodoId,
+        parent_id: Option<TodoId>,
+    ) -> anyhow::Result<Option<Todo>> {
+        let updated_at = Utc::now().timestamp();
+        let todo = sqlx::query_as!(
+            Todo,
+            r#"
+            UPDATE todos SET parent_id = ?, updated_at = ?, version = version + 1
+            WHERE id = ? AND deleted_at IS NULL
+            RETURNING
+              id          as "id: TodoId",
+              user_id     as "user_id: UserId",
+              list_id     as "list_id!: ListId",
+              parent_id   as "parent_id: TodoId",
+              title,
+              notes,
+              completed   as "completed: bool",
+              due_at      as "due_at: DateTime<Utc>",
+              rrule,
+              repeat_from_completion as "repeat_from_completion: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              version,
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            "#,
+            parent_id,
+            updated_at,
+            id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(todo)
+    }
+
+    async fn update(&self, todo: &Todo) -> anyhow::Result<Option<Todo>> {
+        let due_at = todo.due_at.map(|d| d.timestamp());
+        let updated_at = Utc::now().timestamp();
+        let todo = sqlx::query_as!(
+            Todo,
+            r#"
+            UPDATE todos
+            SET title = ?, notes = ?, completed = ?, due_at = ?, rrule = ?,
+                repeat_from_completion = ?, updated_at = ?, version = version + 1
+            WHERE id = ? AND version = ? AND deleted_at IS NULL
+            RETURNING
+              id          as "id: TodoId",
+              user_id     as "user_id: UserId",
+              list_id     as "list_id!: ListId",
+              parent_id   as "parent_id: TodoId",
+              title,
+              notes,
+              completed   as "completed: bool",
+              due_at      as "due_at: DateTime<Utc>",
+              rrule,
+              repeat_from_completion as "repeat_from_completion: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              version,
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            "#,
+            todo.title,
+            todo.notes,
+            todo.completed,
+            due_at,
+            todo.rrule,
+            todo.repeat_from_completion,
+            updated_at,
+            todo.id,
+            todo.version
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(todo)
+    }
+
+    async fn set_recurrence(
+        &self,
+        id: TodoId,
+        rrule: Option<&str>,
+        repeat_from_completion: bool,
+    ) -> anyhow::Result<Option<Todo>> {
+        let updated_at = Utc::now().timestamp();
+        let todo = sqlx::query_as!(
+            Todo,
+            r#"
+            UPDATE todos SET rrule = ?, repeat_from_completion = ?, updated_at = ?, version = version + 1
+            WHERE id = ? AND deleted_at IS NULL
+            RETURNING
+              id          as "id: TodoId",
+              user_id     as "user_id: UserId",
+              list_id     as "list_id!: ListId",
+              parent_id   as "parent_id: TodoId",
+              title,
+              notes,
+              completed   as "completed: bool",
+              due_at      as "due_at: DateTime<Utc>",
+              rrule,
+              repeat_from_completion as "repeat_from_completion: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              version,
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            "#,
+            rrule,
+            repeat_from_completion,
+            updated_at,
+            id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(todo)
+    }
+
+    async fn subtree(&self, id: TodoId) -> anyhow::Result<Vec<Todo>> {
+        let todos = sqlx::query_as!(
+            Todo,
+            r#"
+            WITH RECURSIVE subtree(id) AS (
+              SELECT ?
+              UNION
+              SELECT todos.id FROM todos JOIN subtree ON todos.parent_id = subtree.id
+            )
+            SELECT
+              id          as "id: TodoId",
+              user_id     as "user_id: UserId",
+              list_id     as "list_id!: ListId",
+              parent_id   as "parent_id: TodoId",
+              title,
+              notes,
+              completed   as "completed: bool",
+              due_at      as "due_at: DateTime<Utc>",
+              rrule,
+              repeat_from_completion as "repeat_from_completion: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              version,
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            FROM todos
+            WHERE id IN (SELECT id FROM subtree) AND deleted_at IS NULL
+            ORDER BY created_at
+            "#,
+            id
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(todos)
+    }
+
+    async fn delete(&self, id: TodoId, version: Option<i64>) -> anyhow::Result<bool> {
+        let deleted_at = Utc::now().timestamp();
+        let result = sqlx::query!(
+            r#"
+            UPDATE todos SET deleted_at = ?3, version = version + 1
+            WHERE deleted_at IS NULL AND id IN (
+              WITH RECURSIVE subtree(id) AS (
+                SELECT id FROM todos
+                WHERE id = ?1 AND (?2 IS NULL OR version = ?2) AND deleted_at IS NULL
+                UNION
+                SELECT todos.id FROM todos JOIN subtree ON todos.parent_id = subtree.id
+              )
+              SELECT id FROM subtree
+            )
+            "#,
+            id,
+            version,
+            deleted_at
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn insert_many(
+        &self,
+        todos: &[Todo],
+        dry_run: bool,
+    ) -> anyhow::Result<Vec<(usize, String)>> {
+        let mut tx = self.db.begin().await?;
+        let mut errors = Vec::new();
+        for (i, todo) in todos.iter().enumerate() {
+            if let Err(e) = insert_todo(&mut tx, todo).await {
+                errors.push((i, e.to_string()));
+            }
+        }
+        finish(tx, errors, dry_run).await
+    }
+}
+
+#[async_trait]
+impl TagRepository for SqliteRepository {
+    async fn insert(&self, tag: &Tag) -> anyhow::Result<Tag> {
+        let mut tx = self.db.begin().await?;
+        let tag = insert_tag(&mut tx, tag).await?;
+        tx.commit().await?;
+        Ok(tag)
+    }
+
+    async fn get(&self, id: TagId) -> anyhow::Result<Option<Tag>> {
+        let tag = sqlx::query_as!(
+            Tag,
+            r#"
+            SELECT
+              id          as "id: TagId",
+              user_id     as "user_id: UserId",
+              name,
+              created_at  as "created_at: DateTime<Utc>"
+            FROM tags
+            WHERE id = ?
+            "#,
+            id
+        )
+        .fetch_optional(&self.db_read)
+        .await?;
+        Ok(tag)
+    }
+
+    async fn find_by_name(&self, user_id: UserId, name: &str) -> anyhow::Result<Option<Tag>> {
+        let tag = sqlx::query_as!(
+            Tag,
+            r#"
+            SELECT
+              id          as "id: TagId",
+              user_id     as "user_id: UserId",
+              name,
+              created_at  as "created_at: DateTime<Utc>"
+            FROM tags
+            WHERE user_id = ? AND name = ?
+            "#,
+            user_id,
+            name
+        )
+        .fetch_optional(&self.db_read)
+        .await?;
+        Ok(tag)
+    }
+
+    async fn list_all(&self) -> anyhow::Result<Vec<Tag>> {
+        let tags = sqlx::query_as!(
+            Tag,
+            r#"
+            SELECT
+              id          as "id: TagId",
+              user_id     as "user_id: UserId",
+              name,
+              created_at  as "created_at: DateTime<Utc>"
+            FROM tags
+            WHERE user_id IN (SELECT id FROM users WHERE deleted_at IS NULL)
+            ORDER BY created_at
+            "#
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(tags)
+    }
+
+    async fn list_links(&self) -> anyhow::Result<Vec<TodoTag>> {
+        let links = sqlx::query_as!(
+            TodoTag,
+            r#"
+            SELECT
+              todo_id  as "todo_id: TodoId",
+              tag_id   as "tag_id: TagId"
+            FROM todo_tags
+            WHERE todo_id IN (SELECT id FROM todos WHERE deleted_at IS NULL)
+            "#
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(links)
+    }
+
+    async fn list(&self, user_id: UserId) -> anyhow::Result<Vec<TagCount>> {
+        let tags = sqlx::query_as!(
+            TagCount,
+            r#"
+            SELECT
+              tags.id                 as "id: TagId",
+              tags.name,
+              COUNT(todo_tags.todo_id) as "todos!: i64"
+            FROM tags
+            LEFT JOIN todo_tags ON todo_tags.tag_id = tags.id
+              AND todo_tags.todo_id IN (SELECT id FROM todos WHERE deleted_at IS NULL)
+            WHERE tags.user_id = ?
+            GROUP BY tags.id
+            ORDER BY tags.name
+            "#,
+            user_id
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(tags)
+    }
+
+    async fn rename(&self, id: TagId, name: &str) -> anyhow::Result<Option<Tag>> {
+        let tag = sqlx::query_as!(
+            Tag,
+            r#"
+            UPDATE tags SET name = ?
+            WHERE id = ?
+            RETURNING
+              id          as "id: TagId",
+              user_id     as "user_id: UserId",
+              name,
+              created_at  as "created_at: DateTime<Utc>"
+            "#,
+            name,
+            id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(tag)
+    }
+
+    async fn delete(&self, id: TagId) -> anyhow::Result<bool> {
+        let result = sqlx::query!("DELETE FROM tags WHERE id = ?", id)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn for_todo(&self, todo_id: TodoId) -> anyhow::Result<Vec<Tag>> {
+        let tags = sqlx::query_as!(
+            Tag,
+            r#"
+            SELECT
+              tags.id          as "id: TagId",
+              tags.user_id     as "user_id: UserId",
+              tags.name,
+              tags.created_at  as "created_at: DateTime<Utc>"
+            FROM tags
+            JOIN todo_tags ON todo_tags.tag_id = tags.id
+            WHERE todo_tags.todo_id = ?
+            ORDER BY tags.name
+            "#,
+            todo_id
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(tags)
+    }
+
+    async fn add_to_todo(&self, todo_id: TodoId, tag_id: TagId) -> anyhow::Result<bool> {
+        let mut tx = self.db.begin().await?;
+        let added = insert_todo_tag(&mut tx, &TodoTag { todo_id, tag_id }).await?;
+        tx.commit().await?;
+        Ok(added)
+    }
+
+    async fn remove_from_todo(&self, todo_id: TodoId, tag_id: TagId) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM todo_tags WHERE todo_id = ? AND tag_id = ?",
+            todo_id,
+            tag_id
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl ListRepository for SqliteRepository {
+    async fn insert(&self, list: &TodoList) -> anyhow::Result<TodoList> {
+        let mut tx = self.db.begin().await?;
+        let list = insert_list(&mut tx, list).await?;
+        tx.commit().await?;
+        Ok(list)
+    }
+
+    async fn get(&self, id: ListId) -> anyhow::Result<Option<TodoList>> {
+        let list = sqlx::query_as!(
+            TodoList,
+            r#"
+            SELECT
+              id          as "id: ListId",
+              user_id     as "user_id: UserId",
+              name,
+              color,
+              archived    as "archived: bool",
+              position,
+              inbox       as "inbox: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            FROM lists
+            WHERE id = ? AND deleted_at IS NULL
+            "#,
+            id
+        )
+        .fetch_optional(&self.db_read)
+        .await?;
+        Ok(list)
+    }
+
+    async fn inbox(&self, user_id: UserId) -> anyhow::Result<Option<TodoList>> {
+        let list = sqlx::query_as!(
+            TodoList,
+            r#"
+            SELECT
+              id          as "id: ListId",
+              user_id     as "user_id: UserId",
+              name,
+              color,
+              archived    as "archived: bool",
+              position,
+              inbox       as "inbox: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            FROM lists
+            WHERE user_id = ? AND inbox AND deleted_at IS NULL
+            "#,
+            user_id
+        )
+        .fetch_optional(&self.db_read)
+        .await?;
+        Ok(list)
+    }
+
+    async fn list(&self, user_id: UserId, archived: Option<bool>) -> anyhow::Result<Vec<TodoList>> {
+        let lists = sqlx::query_as!(
+            TodoList,
+            r#"
+            SELECT
+              id          as "id: ListId",
+              user_id     as "user_id: UserId",
+              name,
+              color,
+              archived    as "archived: bool",
+              position,
+              inbox       as "inbox: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            FROM lists
+            WHERE user_id = ?1 AND (?2 IS NULL OR archived = ?2) AND deleted_at IS NULL
+            ORDER BY position, created_at
+            "#,
+            user_id,
+            archived
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(lists)
+    }
+
+    async fn list_all(&self) -> anyhow::Result<Vec<TodoList>> {
+        let lists = sqlx::query_as!(
+            TodoList,
+            r#"
+            SELECT
+              id          as "id: ListId",
+              user_id     as "user_id: UserId",
+              name,
+              color,
+              archived    as "archived: bool",
+              position,
+              inbox       as "inbox: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            FROM lists
+            WHERE deleted_at IS NULL
+            ORDER BY created_at
+            "#
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(lists)
+    }
+
+    async fn update(&self, list: &TodoList) -> anyhow::Result<Option<TodoList>> {
+        let updated_at = Utc::now().timestamp();
+        let list = sqlx::query_as!(
+            TodoList,
+            r#"
+            UPDATE lists SET name = ?, color = ?, archived = ?, position = ?, updated_at = ?
+            WHERE id = ? AND deleted_at IS NULL
+            RETURNING
+              id          as "id: ListId",
+              user_id     as "user_id: UserId",
+              name,
+              color,
+              archived    as "archived: bool",
+              position,
+              inbox       as "inbox: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            "#,
+            list.name,
+            list.color,
+            list.archived,
+            list.position,
+            updated_at,
+            list.id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(list)
+    }
+
+    async fn delete(&self, id: ListId, move_todos_to: Option<ListId>) -> anyhow::Result<bool> {
+        let now = Utc::now().timestamp();
+        let mut tx = self.db.begin().await?;
+        match move_todos_to {
+            Some(to) => {
+                sqlx::query!(
+                    r#"
+                    UPDATE todos SET list_id = ?, updated_at = ?, version = version + 1
+                    WHERE list_id = ? AND deleted_at IS NULL
+                    "#,
+                    to,
+                    now,
+                    id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                sqlx::query!(
+                    r#"
+                    UPDATE todos SET deleted_at = ?2, version = version + 1
+                    WHERE deleted_at IS NULL AND id IN (
+                      WITH RECURSIVE subtree(id) AS (
+                        SELECT id FROM todos WHERE list_id = ?1 AND deleted_at IS NULL
+                        UNION
+                        SELECT todos.id FROM todos JOIN subtree ON todos.parent_id = subtree.id
+                      )
+                      SELECT id FROM subtree
+                    )
+                    "#,
+                    id,
+                    now
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        let result = sqlx::query!(
+            "UPDATE lists SET deleted_at = ? WHERE id = ? AND deleted_at IS NULL",
+            now,
+            id
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl TrashRepository for SqliteRepository {
+    async fn lists(&self, user_id: UserId) -> anyhow::Result<Vec<TodoList>> {
+        let lists = sqlx::query_as!(
+            TodoList,
+            r#"
+            SELECT
+              id          as "id: ListId",
+              user_id     as "user_id: UserId",
+              name,
+              color,
+              archived    as "archived: bool",
+              position,
+              inbox       as "inbox: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            FROM lists
+            WHERE user_id = ? AND deleted_at IS NOT NULL
+            ORDER BY deleted_at DESC, created_at
+            "#,
+            user_id
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(lists)
+    }
+
+    async fn todos(&self, user_id: UserId) -> anyhow::Result<Vec<Todo>> {
+        let todos = sqlx::query_as!(
+            Todo,
+            r#"
+            SELECT
+              id          as "id: TodoId",
+              user_id     as "user_id: UserId",
+              list_id     as "list_id!: ListId",
+              parent_id   as "parent_id: TodoId",
+              title,
+              notes,
+              completed   as "completed: bool",
+              due_at      as "due_at: DateTime<Utc>",
+              rrule,
+              repeat_from_completion as "repeat_from_completion: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              version,
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            FROM todos
+            WHERE user_id = ? AND deleted_at IS NOT NULL
+            ORDER BY deleted_at DESC, created_at
+            "#,
+            user_id
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(todos)
+    }
+
+    async fn users(&self) -> anyhow::Result<Vec<User>> {
+        let users = sqlx::query_as!(
+            User,
+            r#"
+            SELECT
+              id             as "id: UserId",
+              email,
+              display_name,
+              created_at,
+              version,
+              deleted_at,
+              role           as "role: Role"
+            FROM users
+            WHERE deleted_at IS NOT NULL
+            ORDER BY deleted_at DESC, email
+            "#
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(users)
+    }
+
+    async fn restore_list(&self, user_id: UserId, id: ListId) -> anyhow::Result<Option<TodoList>> {
+        let updated_at = Utc::now().timestamp();
+        let mut tx = self.db.begin().await?;
+        let Some(deleted_at) = sqlx::query_scalar!(
+            r#"
+            SELECT deleted_at as "deleted_at!"
+            FROM lists
+            WHERE id = ? AND user_id = ? AND deleted_at IS NOT NULL
+            "#,
+            id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        sqlx::query!(
+            r#"
+            UPDATE todos SET deleted_at = NULL, updated_at = ?3, version = version + 1
+            WHERE list_id = ?1 AND deleted_at = ?2
+            "#,
+            id,
+            deleted_at,
+            updated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        let list = sqlx::query_as!(
+            TodoList,
+            r#"
+            UPDATE lists SET deleted_at = NULL, updated_at = ?
+            WHERE id = ?
+            RETURNING
+              id          as "id: ListId",
+              user_id     as "user_id: UserId",
+              name,
+              color,
+              archived    as "archived: bool",
+              position,
+              inbox       as "inbox: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            "#,
+            updated_at,
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        rehome_restored(&mut tx, user_id).await?;
+        tx.commit().await?;
+        Ok(Some(list))
+    }
+
+    async fn restore_todo(&self, user_id: UserId, id: TodoId) -> anyhow::Result<Option<Todo>> {
+        let updated_at = Utc::now().timestamp();
+        let mut tx = self.db.begin().await?;
+        let Some(deleted_at) = sqlx::query_scalar!(
+            r#"
+            SELECT deleted_at as "deleted_at!"
+            FROM todos
+            WHERE id = ? AND user_id = ? AND deleted_at IS NOT NULL
+            "#,
+            id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        sqlx::query!(
+            r#"
+            UPDATE todos SET deleted_at = NULL, updated_at = ?3, version = version + 1
+            WHERE deleted_at = ?2 AND id IN (
+              WITH RECURSIVE subtree(id) AS (
+                SELECT ?1
+                UNION
+                SELECT todos.id FROM todos JOIN subtree ON todos.parent_id = subtree.id
+                WHERE todos.deleted_at = ?2
+              )
+              SELECT id FROM subtree
+            )
+            "#,
+            id,
+            deleted_at,
+            updated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        rehome_restored(&mut tx, user_id).await?;
+        let todo = sqlx::query_as!(
+            Todo,
+            r#"
+            SELECT
+              id          as "id: TodoId",
+              user_id     as "user_id: UserId",
+              list_id     as "list_id!: ListId",
+              parent_id   as "parent_id: TodoId",
+              title,
+              notes,
+              completed   as "completed: bool",
+              due_at      as "due_at: DateTime<Utc>",
+              rrule,
+              repeat_from_completion as "repeat_from_completion: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              version,
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            FROM todos
+            WHERE id = ?
+            "#,
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(Some(todo))
+    }
+
+    async fn restore_user(&self, id: UserId) -> anyhow::Result<Option<User>> {
+        let updated_at = Utc::now().timestamp();
+        let mut tx = self.db.begin().await?;
+        let Some(deleted_at) = sqlx::query_scalar!(
+            r#"
+            SELECT deleted_at as "deleted_at!"
+            FROM users
+            WHERE id = ? AND deleted_at IS NOT NULL
+            "#,
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        sqlx::query!(
+            r#"
+            UPDATE lists SET deleted_at = NULL, updated_at = ?3
+            WHERE user_id = ?1 AND deleted_at = ?2
+            "#,
+            id,
+            deleted_at,
+            updated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"
+            UPDATE todos SET deleted_at = NULL, updated_at = ?3, version = version + 1
+            WHERE user_id = ?1 AND deleted_at = ?2
+            "#,
+            id,
+            deleted_at,
+            updated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            UPDATE users SET deleted_at = NULL, version = version + 1
+            WHERE id = ?
+            RETURNING
+              id             as "id: UserId",
+              email,
+              display_name,
+              created_at,
+              version,
+              deleted_at,
+              role           as "role: Role"
+            "#,
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        rehome_restored(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(Some(user))
+    }
+
+    async fn purge(&self, before: DateTime<Utc>) -> anyhow::Result<u64> {
+        let before = before.timestamp();
+        let mut tx = self.db.begin().await?;
+        // Subtasks are trashed with (or before) their parents, and todos
+        // with (or before) their lists, so each delete only leaves rows
+        // that the next one can go without.
+        let todos = sqlx::query!("DELETE FROM todos WHERE deleted_at < ?", before)
+            .execute(&mut *tx)
+            .await?;
+        let lists = sqlx::query!("DELETE FROM lists WHERE deleted_at < ?", before)
+            .execute(&mut *tx)
+            .await?;
+        let users = sqlx::query!("DELETE FROM users WHERE deleted_at < ?", before)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(todos.rows_affected() + lists.rows_affected() + users.rows_affected())
+    }
+}
+
+#[async_trait]
+impl CalDavRepository for SqliteRepository {
+    async fn uids(&self, list_id: ListId) -> anyhow::Result<Vec<(TodoId, String)>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT o.todo_id as "todo_id: TodoId", o.uid
+            FROM caldav_objects o
+            JOIN todos t ON t.id = o.todo_id
+            WHERE t.list_id = ? AND t.deleted_at IS NULL
+            "#,
+            list_id
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(rows.into_iter().map(|r| (r.todo_id, r.uid)).collect())
+    }
+
+    async fn set_uid(&self, todo_id: TodoId, uid: &str) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO caldav_objects (todo_id, uid) VALUES (?, ?)
+            ON CONFLICT(todo_id) DO UPDATE SET uid = excluded.uid
+            "#,
+            todo_id,
+            uid
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn find_by_uid(&self, list_id: ListId, uid: &str) -> anyhow::Result<Option<Todo>> {
+        let todo = sqlx::query_as!(
+            Todo,
+            r#"
+            SELECT
+              id          as "id: TodoId",
+              user_id     as "user_id: UserId",
+              list_id     as "list_id!: ListId",
+              parent_id   as "parent_id: TodoId",
+              title,
+              notes,
+              completed   as "completed: bool",
+              due_at      as "due_at: DateTime<Utc>",
+              rrule,
+              repeat_from_completion as "repeat_from_completion: bool",
+              created_at  as "created_at: DateTime<Utc>",
+              updated_at  as "updated_at: DateTime<Utc>",
+              version,
+              deleted_at  as "deleted_at: DateTime<Utc>"
+            FROM todos
+            WHERE list_id = ?1 AND deleted_at IS NULL AND (
+              id IN (SELECT todo_id FROM caldav_objects WHERE uid = ?2)
+              OR (id = ?2 AND id NOT IN (SELECT todo_id FROM caldav_objects))
+            )
+            "#,
+            list_id,
+            uid
+        )
+        .fetch_optional(&self.db_read)
+        .await?;
+        Ok(todo)
+    }
+
+    async fn removed_since(
+        &self,
+        list_id: ListId,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<(String, DateTime<Utc>)>> {
+        let since = since.timestamp();
+        let rows = sqlx::query!(
+            r#"
+            SELECT uid, removed_at as "removed_at: DateTime<Utc>"
+            FROM caldav_tombstones
+            WHERE list_id = ? AND removed_at >= ?
+            ORDER BY removed_at
+            "#,
+            list_id,
+            since
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(rows.into_iter().map(|r| (r.uid, r.removed_at)).collect())
+    }
+}
+
+#[async_trait]
+impl AuditRepository for SqliteRepository {
+    async fn insert(&self, entries: &[AuditEntry]) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+        for entry in entries {
+            let at = entry.at.timestamp();
+            sqlx::query!(
+                r#"
+                INSERT INTO audit_log
+                  (at, actor, real_actor, client_ip, request_id, entity, entity_id, action,
+                   before, after)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+                at,
+                entry.actor,
+                entry.real_actor,
+                entry.client_ip,
+                entry.request_id,
+                entry.entity,
+                entry.entity_id,
+                entry.action,
+                entry.before,
+                entry.after
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn list(&self, filter: &AuditFilter) -> anyhow::Result<Vec<AuditEntry>> {
+        let since = filter.since.map(|t| t.timestamp());
+        let until = filter.until.map(|t| t.timestamp());
+        let limit = filter.limit.unwrap_or(-1);
+        let entries = sqlx::query_as!(
+            AuditEntry,
+            r#"
+            SELECT
+              id          as "id!",
+              at          as "at: DateTime<Utc>",
+              actor,
+              real_actor,
+              client_ip,
+              request_id,
+              entity,
+              entity_id,
+              action,
+              before,
+              after
+            FROM audit_log
+            WHERE (?1 IS NULL OR actor = ?1)
+              AND (?2 IS NULL OR entity = ?2)
+              AND (?3 IS NULL OR entity_id = ?3)
+              AND (?4 IS NULL OR action = ?4)
+              AND (?5 IS NULL OR request_id = ?5)
+              AND (?6 IS NULL OR at >= ?6)
+              AND (?7 IS NULL OR at < ?7)
+              AND (?8 IS NULL OR id < ?8)
+            ORDER BY id DESC
+            LIMIT ?9
+            "#,
+            filter.actor,
+            filter.entity,
+            filter.entity_id,
+            filter.action,
+            filter.request_id,
+            since,
+            until,
+            filter.before_id,
+            limit
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(entries)
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for SqliteRepository {
+    async fn insert(&self, webhook: &Webhook) -> anyhow::Result<Webhook> {
+        let created_at = webhook.created_at.timestamp();
+        let webhook = sqlx::query_as!(
+            Webhook,
+            r#"
+            INSERT INTO webhooks (id, user_id, url, events, secret, created_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            RETURNING
+              id          as "id: WebhookId",
+              user_id     as "user_id: UserId",
+              url,
+              events      as "events: EventFilter",
+              secret,
+              created_at  as "created_at: DateTime<Utc>"
+            "#,
+            webhook.id,
+            webhook.user_id,
+            webhook.url,
+            webhook.events,
+            webhook.secret,
+            created_at
+        )
+        .fetch_one(&self.db)
+        .await?;
+        Ok(webhook)
+    }
+
+    async fn get(&self, id: WebhookId) -> anyhow::Result<Option<Webhook>> {
+        let webhook = sqlx::query_as!(
+            Webhook,
+            r#"
+            SELECT
+              id          as "id: WebhookId",
+              user_id     as "user_id: UserId",
+              url,
+              events      as "events: EventFilter",
+              secret,
+              created_at  as "created_at: DateTime<Utc>"
+            FROM webhooks
+            WHERE id = ?
+            "#,
+            id
+        )
+        .fetch_optional(&self.db_read)
+        .await?;
+        Ok(webhook)
+    }
+
+    async fn list(&self, user_id: Option<UserId>) -> anyhow::Result<Vec<Webhook>> {
+        let webhooks = sqlx::query_as!(
+            Webhook,
+            r#"
+            SELECT
+              id          as "id: WebhookId",
+              user_id     as "user_id: UserId",
+              url,
+              events      as "events: EventFilter",
+              secret,
+              created_at  as "created_at: DateTime<Utc>"
+            FROM webhooks
+            WHERE user_id IS ?
+            ORDER BY created_at, id
+            "#,
+            user_id
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(webhooks)
+    }
+
+    async fn delete(&self, id: WebhookId) -> anyhow::Result<bool> {
+        let result = sqlx::query!("DELETE FROM webhooks WHERE id = ?", id)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn subscribers(&self, user_id: UserId) -> anyhow::Result<Vec<Webhook>> {
+        let webhooks = sqlx::query_as!(
+            Webhook,
+            r#"
+            SELECT
+              id          as "id: WebhookId",
+              user_id     as "user_id: UserId",
+              url,
+              events      as "events: EventFilter",
+              secret,
+              created_at  as "created_at: DateTime<Utc>"
+            FROM webhooks
+            WHERE user_id = ? OR user_id IS NULL
+            ORDER BY created_at, id
+            "#,
+            user_id
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(webhooks)
+    }
+
+    async fn insert_deliveries(&self, deliveries: &[Delivery]) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+        for delivery in deliveries {
+            let next_attempt_at = delivery.next_attempt_at.map(|at| at.timestamp());
+            let created_at = delivery.created_at.timestamp();
+            sqlx::query!(
+                r#"
+                INSERT INTO webhook_deliveries
+                  (id, webhook_id, event, payload, next_attempt_at, created_at)
+                VALUES (?, ?, ?, ?, ?, ?)
+                "#,
+                delivery.id,
+                delivery.webhook_id,
+                delivery.event,
+                delivery.payload,
+                next_attempt_at,
+                created_at
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_delivery(&self, id: DeliveryId) -> anyhow::Result<Option<Delivery>> {
+        let delivery = sqlx::query_as!(
+            Delivery,
+            r#"
+            SELECT
+              id               as "id: DeliveryId",
+              webhook_id       as "webhook_id: WebhookId",
+              event,
+              payload,
+              attempts,
+              next_attempt_at  as "next_attempt_at: DateTime<Utc>",
+              last_attempt_at  as "last_attempt_at: DateTime<Utc>",
+              last_status,
+              last_error,
+              delivered_at     as "delivered_at: DateTime<Utc>",
+              created_at       as "created_at: DateTime<Utc>"
+            FROM webhook_deliveries
+            WHERE id = ?
+            "#,
+            id
+        )
+        .fetch_optional(&self.db_read)
+        .await?;
+        Ok(delivery)
+    }
+
+    async fn deliveries(&self, webhook_id: WebhookId, limit: i64) -> anyhow::Result<Vec<Delivery>> {
+        let deliveries = sqlx::query_as!(
+            Delivery,
+            r#"
+            SELECT
+              id               as "id: DeliveryId",
+              webhook_id       as "webhook_id: WebhookId",
+              event,
+              payload,
+              attempts,
+              next_attempt_at  as "next_attempt_at: DateTime<Utc>",
+              last_attempt_at  as "last_attempt_at: DateTime<Utc>",
+              last_status,
+              last_error,
+              delivered_at     as "delivered_at: DateTime<Utc>",
+              created_at       as "created_at: DateTime<Utc>"
+            FROM webhook_deliveries
+            WHERE webhook_id = ?
+            ORDER BY created_at DESC, rowid DESC
+            LIMIT ?
+            "#,
+            webhook_id,
+            limit
+        )
+        .fetch_all(&self.db_read)
+        .await?;
+        Ok(deliveries)
+    }
+
+    async fn due_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        limit: i64,
+    ) -> anyhow::Result<Vec<Delivery>> {
+        let now = now.timestamp();
+        let deliveries = sqlx::query_as!(
+            Delivery,
+            r#"
+            SELECT
+              id               as "id: DeliveryId",
+              webhook_id       as "webhook_id: WebhookId",
+              event,
+              payload,
+              attempts,
+              next_attempt_at  as "next_attempt_at: DateTime<Utc>",
+              last_attempt_at  as "last_attempt_at: DateTime<Utc>",
+              last_status,
+              last_error,
+              delivered_at     as "delivered_at: DateTime<Utc>",
+              created_at       as "created_at: DateTime<Utc>"
+            FROM webhook_deliveries
+            WHERE next_attempt_at <= ?
+            ORDER BY next_attempt_at, created_at, rowid
+            LIMIT ?
+            "#,
+            now,
+            limit
+        )
+        .fetch_all(&self.db)
+        .await?;
+        Ok(deliveries)
+    }
+
+    async fn update_delivery(&self, delivery: &Delivery) -> anyhow::Result<Option<Delivery>> {
+        let next_attempt_at = delivery.next_attempt_at.map(|at| at.timestamp());
+        let last_attempt_at = delivery.last_attempt_at.map(|at| at.timestamp());
+        let delivered_at = delivery.delivered_at.map(|at| at.timestamp());
+        let delivery = sqlx::query_as!(
+            Delivery,
+            r#"
+            UPDATE webhook_deliveries
+            SET attempts = ?, next_attempt_at = ?, last_attempt_at = ?, last_status = ?,
+                last_error = ?, delivered_at = ?
+            WHERE id = ?
+            RETURNING
+              id               as "id: DeliveryId",
+              webhook_id       as "webhook_id: WebhookId",
+              event,
+              payload,
+              attempts,
+              next_attempt_at  as "next_attempt_at: DateTime<Utc>",
+              last_attempt_at  as "last_attempt_at: DateTime<Utc>",
+              last_status,
+              last_error,
+              delivered_at     as "delivered_at: DateTime<Utc>",
+              created_at       as "created_at: DateTime<Utc>"
+            "#,
+            delivery.attempts,
+            next_attempt_at,
+            last_attempt_at,
+            delivery.last_status,
+            delivery.last_error,
+            delivered_at,
+            delivery.id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(delivery)
+    }
+}
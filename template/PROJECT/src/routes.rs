@@ -1,42 +1,119 @@
-use axum::{http::StatusCode, middleware, routing::get, Router};
-use tower_http::trace::TraceLayer;
+use axum::{
+    http::StatusCode,
+    middleware,
+    routing::{any, get},
+    Router,
+};
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+
+use std::sync::Arc;
 
 use crate::{
+    jwt::JwtVerifier,
     middleware::{
-        trusted_forwarded_for, trusted_header_auth, TrustedForwardedForConfig,
-        TrustedHeaderAuthConfig,
+        act_as, api_token_auth, basic_auth, jwt_auth, session_auth, trusted_forwarded_for,
+        trusted_header_auth, TrustedForwardedForConfig, TrustedHeaderAuthConfig,
     },
     AppState,
 };
 
+pub mod admin;
+pub mod api_token;
+pub mod app_password;
+pub mod auth;
+pub mod caldav;
+pub mod calendar;
+mod conditional;
+pub mod events;
+pub mod export;
 pub mod hello;
+pub mod import;
+pub mod list;
+pub mod tag;
+pub mod todo;
+pub mod trash;
 pub mod user;
+pub mod webhook;
 pub mod whoami;
+pub mod ws;
 
 /// Build your Axum router. Keep this as a separate function so it’s testable.
 pub fn router(
     user_cfg: TrustedHeaderAuthConfig,
     fwd_cfg: TrustedForwardedForConfig,
+    jwt: Option<Arc<JwtVerifier>>,
+    state: &AppState,
 ) -> Router<AppState> {
+    let users = state.users.clone();
+    let mut secret_headers = Vec::new();
+    if user_cfg.enabled {
+        secret_headers.push(user_cfg.header_name.clone());
+        secret_headers.extend(user_cfg.groups_header.clone());
+    }
+    secret_headers.extend(jwt.as_ref().map(|jwt| jwt.header_name().clone()));
     let app = Router::<AppState>::new()
         .route("/", get(root))
         .route("/healthz", get(healthz))
         .nest("/hello", hello::router())
-        .nest("/whoami", whoami::router())
+        .nest(
+            "/whoami",
+            whoami::router(whoami::SecretHeaders(secret_headers.into())),
+        )
+        .nest("/auth", auth::router())
         .nest("/user", user::router())
+        .nest("/list", list::router())
+        .nest("/todo", todo::router())
+        .nest("/trash", trash::router())
+        .route("/todo.ics", get(calendar::feed))
+        .nest("/calendar", calendar::router())
+        .nest("/app-password", app_password::router())
+        .nest("/api-token", api_token::router())
+        .route("/.well-known/caldav", any(caldav::well_known))
+        .route("/dav", any(caldav::dav))
+        .route("/dav/", any(caldav::dav))
+        .route("/dav/{*path}", any(caldav::dav))
+        .route("/events", get(events::stream))
+        .route("/ws", get(ws::connect))
+        .nest("/webhook", webhook::router())
+        .nest("/tag", tag::router())
+        .nest("/export", export::router())
+        .nest("/import", import::router())
+        .nest("/admin", admin::router())
         .fallback(fallback_404)
         .layer(TraceLayer::new_for_http());
 
-    // Always install both middlewares; they self-disable and
-    // reject spoofing when disabled.
-    app.layer(middleware::from_fn_with_state(
-        fwd_cfg,
-        trusted_forwarded_for,
-    ))
-    .layer(middleware::from_fn_with_state(
-        user_cfg,
-        trusted_header_auth,
-    ))
+    // Admins acting as another user take their identity once the
+    // request is authenticated.
+    app.layer(middleware::from_fn_with_state(state.clone(), act_as))
+        // Always install both trusted header middlewares; they
+        // self-disable and reject spoofing when disabled.
+        .layer(middleware::from_fn_with_state(
+            user_cfg,
+            trusted_header_auth,
+        ))
+        // API tokens, passwords, JWT assertions and session cookies are
+        // checked first, so that requests carrying one need no user header
+        // from the proxy.
+        .layer(middleware::from_fn_with_state(users.clone(), session_auth))
+        .layer(middleware::from_fn_with_state(jwt, jwt_auth))
+        .layer(middleware::from_fn_with_state(
+            (users.clone(), state.logins.clone()),
+            basic_auth,
+        ))
+        .layer(middleware::from_fn_with_state(users, api_token_auth))
+        // The client's address is known before authenticating, so that
+        // wrong passwords are throttled per client.
+        .layer(middleware::from_fn_with_state(
+            fwd_cfg,
+            trusted_forwarded_for,
+        ))
+        // Every request gets an X-Request-Id (unless the proxy gave it one),
+        // which the audit log records and the response echoes.
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
 }
 
 async fn root() -> &'static str {
@@ -50,3 +127,158 @@ async fn healthz() -> &'static str {
 async fn fallback_404() -> (StatusCode, &'static str) {
     (StatusCode::NOT_FOUND, "Not Found")
 }
+
+/// Build the app against a fresh migrated database, trusting the
+/// `X-Forwarded-User` header from 127.0.0.1 (the mocked peer address).
+#[cfg(test)]
+pub async fn test_app() -> (Router, tempfile::TempDir) {
+    let (db, dir) = crate::db::test_sqlite().await;
+    (test_app_with(&db), dir)
+}
+
+/// Build the app like [`test_app`], against an already migrated database.
+#[cfg(test)]
+pub fn test_app_with(db: &crate::db::Database) -> Router {
+    test_app_with_state(test_state(db))
+}
+
+/// The user header [`call`] sends, as the proxy would.
+#[cfg(test)]
+pub const TEST_USER: (&str, &str) = ("x-forwarded-user", "a@example.com");
+
+/// Send a request with these headers to the app, returning the response's
+/// status, headers and body.
+#[cfg(test)]
+pub async fn send(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> (StatusCode, axum::http::HeaderMap, String) {
+    use tower::ServiceExt;
+
+    let mut req = axum::http::Request::builder().method(method).uri(uri);
+    for (name, value) in headers {
+        req = req.header(*name, *value);
+    }
+    let res = app
+        .clone()
+        .oneshot(req.body(axum::body::Body::from(body.to_string())).unwrap())
+        .await
+        .unwrap();
+    let status = res.status();
+    let headers = res.headers().clone();
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, headers, String::from_utf8(body.to_vec()).unwrap())
+}
+
+/// Send `body` as JSON with these headers, returning the response's status,
+/// headers and JSON body (`null` if it has none).
+#[cfg(test)]
+pub async fn call_with(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    headers: &[(&str, &str)],
+    body: serde_json::Value,
+) -> (StatusCode, axum::http::HeaderMap, serde_json::Value) {
+    let headers = [headers, &[("content-type", "application/json")]].concat();
+    let (status, headers, body) = send(app, method, uri, &headers, &body.to_string()).await;
+    (
+        status,
+        headers,
+        serde_json::from_str(&body).unwrap_or_default(),
+    )
+}
+
+/// Send `body` as JSON as `user`, returning the response's status and JSON
+/// body.
+#[cfg(test)]
+pub async fn call_as(
+    app: &Router,
+    user: &str,
+    method: &str,
+    uri: &str,
+    body: serde_json::Value,
+) -> (StatusCode, serde_json::Value) {
+    let (status, _, body) = call_with(app, method, uri, &[(TEST_USER.0, user)], body).await;
+    (status, body)
+}
+
+/// Send `body` as JSON as `a@example.com`, returning the response's status
+/// and JSON body.
+#[cfg(test)]
+pub async fn call(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    body: serde_json::Value,
+) -> (StatusCode, serde_json::Value) {
+    call_as(app, TEST_USER.1, method, uri, body).await
+}
+
+/// The state [`test_app_with`] gives the app.
+#[cfg(test)]
+pub fn test_state(db: &crate::db::Database) -> AppState {
+    let crate::db::Repositories {
+        users,
+        lists,
+        todos,
+        tags,
+        caldav,
+        trash,
+        webhooks,
+        audit,
+    } = db.repos.clone();
+    AppState {
+        users,
+        lists,
+        todos,
+        tags,
+        caldav,
+        trash,
+        webhooks,
+        audit,
+        events: Default::default(),
+        admins: ["admin@example.com".to_string()].into(),
+        role_groups: Arc::new(crate::extract::RoleGroups {
+            admin: vec!["admins".to_string()],
+            member: vec!["staff".to_string()],
+            read_only: vec!["viewers".to_string()],
+        }),
+        oidc: None,
+        sessions: Default::default(),
+        logins: Default::default(),
+        allow_local_webhooks: false,
+    }
+}
+
+/// Build the app like [`test_app_with`], with the given state.
+#[cfg(test)]
+pub fn test_app_with_state(state: AppState) -> Router {
+    build_test_app(state, None)
+}
+
+/// Build the app like [`test_app_with`], also verifying JWT assertions.
+#[cfg(test)]
+pub fn test_app_with_jwt(db: &crate::db::Database, jwt: Arc<JwtVerifier>) -> Router {
+    build_test_app(test_state(db), Some(jwt))
+}
+
+#[cfg(test)]
+fn build_test_app(state: AppState, jwt: Option<Arc<JwtVerifier>>) -> Router {
+    use axum::extract::connect_info::MockConnectInfo;
+    use std::net::SocketAddr;
+
+    let user_cfg = TrustedHeaderAuthConfig {
+        enabled: true,
+        groups_header: Some(axum::http::HeaderName::from_static("x-forwarded-groups")),
+        ..TrustedHeaderAuthConfig::disabled()
+    };
+    router(user_cfg, TrustedForwardedForConfig::disabled(), jwt, &state)
+        .with_state(state)
+        .layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))))
+}
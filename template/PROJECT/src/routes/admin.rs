@@ -0,0 +1,356 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{get, patch},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::{
+    audit::{self, Change},
+    errors::{internal_error, not_found_error},
+    events,
+    extract::{Admin, Origin},
+    models::{
+        audit::{AuditEntry, Entity},
+        ids::UserId,
+        user::{AdminCreateUser, PublicUser, UpdateRole, User},
+        webhook::Event,
+    },
+    repository::AuditFilter,
+    AppState,
+};
+
+/// How many audit log entries a page has by default, and at most.
+const DEFAULT_AUDIT_LIMIT: i64 = 100;
+const MAX_AUDIT_LIMIT: i64 = 1000;
+
+pub fn router() -> Router<AppState> {
+    // this router is responsible for everything under `/admin`
+    Router::<AppState>::new()
+        .route("/audit", get(list_audit))
+        .route("/users", get(list_users).post(create_user))
+        .route("/users/{user_id}", patch(update_user).delete(delete_user))
+}
+
+#[derive(Debug, Deserialize)]
+struct AuditParams {
+    actor: Option<String>,
+    entity: Option<String>,
+    entity_id: Option<String>,
+    action: Option<String>,
+    request_id: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    before_id: Option<i64>,
+    limit: Option<i64>,
+}
+
+/// The audit log, newest first, filtered by any of the parameters. Pages
+/// go back with `before_id` set to the last entry's id.
+async fn list_audit(
+    State(state): State<AppState>,
+    _: Admin,
+    Query(params): Query<AuditParams>,
+) -> Result<Json<Vec<AuditEntry>>, (StatusCode, String)> {
+    let limit = params.limit.unwrap_or(DEFAULT_AUDIT_LIMIT);
+    if !(1..=MAX_AUDIT_LIMIT).contains(&limit) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {MAX_AUDIT_LIMIT}"),
+        ));
+    }
+    let entries = state
+        .audit
+        .list(&AuditFilter {
+            actor: params.actor,
+            entity: params.entity,
+            entity_id: params.entity_id,
+            action: params.action,
+            request_id: params.request_id,
+            since: params.since,
+            until: params.until,
+            before_id: params.before_id,
+            limit: Some(limit),
+        })
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(entries))
+}
+
+/// Every user, ordered by email.
+async fn list_users(
+    State(state): State<AppState>,
+    _: Admin,
+) -> Result<Json<Vec<PublicUser>>, (StatusCode, String)> {
+    let users = state.users.list().await.map_err(internal_error)?;
+    Ok(Json(users.into_iter().map(Into::into).collect()))
+}
+
+/// Register a user with any email, and role.
+async fn create_user(
+    State(state): State<AppState>,
+    _: Admin,
+    origin: Origin,
+    Json(payload): Json<AdminCreateUser>,
+) -> Result<(StatusCode, Json<PublicUser>), (StatusCode, String)> {
+    super::user::check_email_free(&state, &payload.email).await?;
+    let user = User {
+        role: payload.role,
+        ..User::new(&payload.email, &payload.display_name)
+    };
+    let user = state.users.insert(&user).await.map_err(internal_error)?;
+    let user = PublicUser::from(user);
+    audit::record(
+        &state,
+        &origin,
+        [Change::created(Entity::User, user.id.0, &user)],
+    )
+    .await;
+    events::publish(&state, user.id, Event::UserCreated, &user).await;
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+/// Change a user's role.
+async fn update_user(
+    State(state): State<AppState>,
+    _: Admin,
+    origin: Origin,
+    Path(id): Path<UserId>,
+    Json(payload): Json<UpdateRole>,
+) -> Result<Json<PublicUser>, (StatusCode, String)> {
+    let before = state
+        .users
+        .get(id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| not_found_error(format!("No user {}", id.0)))?;
+    let user = state
+        .users
+        .set_role(id, payload.role)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| not_found_error(format!("No user {}", id.0)))?;
+    let (before, user) = (PublicUser::from(before), PublicUser::from(user));
+    let change = Change::updated(Entity::User, id.0, &before, &user);
+    audit::record(&state, &origin, [change]).await;
+    events::publish(&state, user.id, Event::UserUpdated, &user).await;
+    Ok(Json(user))
+}
+
+/// Move a user with all of their data to the trash.
+async fn delete_user(
+    State(state): State<AppState>,
+    _: Admin,
+    origin: Origin,
+    Path(id): Path<UserId>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let user = state
+        .users
+        .get(id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| not_found_error(format!("No user {}", id.0)))?;
+    if !state.users.delete(id, None).await.map_err(internal_error)? {
+        return Err(not_found_error(format!("No user {}", id.0)));
+    }
+    let user = PublicUser::from(user);
+    audit::record(
+        &state,
+        &origin,
+        [Change::deleted(Entity::User, id.0, &user)],
+    )
+    .await;
+    events::publish(&state, user.id, Event::UserDeleted, &user).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::{json, Value};
+
+    use crate::{
+        db::Database,
+        routes::{call_as, call_with, send, test_app_with},
+    };
+
+    crate::db::backend_test!(audit_log_records_changes);
+
+    async fn audit_log_records_changes(db: &Database) {
+        let app = &test_app_with(db);
+        let a = "a@example.com";
+        let admin = "admin@example.com";
+        for email in [a, admin] {
+            let user = json!({"email": email, "display_name": "A"});
+            let (status, _) = call_as(app, email, "POST", "/user", user).await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+        let (_, list) = call_as(app, a, "POST", "/list", json!({"name": "Work"})).await;
+        let uri = format!("/list/{}", list["id"].as_str().unwrap());
+        let traced = [("x-forwarded-user", a), ("x-request-id", "req-1")];
+        let rename = json!({"name": "Home"});
+        let (_, headers, _) = call_with(app, "PATCH", &uri, &traced, rename).await;
+        assert_eq!(headers["x-request-id"], "req-1");
+        call_as(app, a, "DELETE", &uri, Value::Null).await;
+
+        let (status, _) = call_as(app, a, "GET", "/admin/audit", Value::Null).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let uri = format!(
+            "/admin/audit?entity=list&entity_id={}",
+            list["id"].as_str().unwrap()
+        );
+        let (status, entries) = call_as(app, admin, "GET", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let actions: Vec<&str> = entries
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["action"].as_str().unwrap())
+            .collect();
+        assert_eq!(actions, ["delete", "update", "create"]);
+        let update = &entries[1];
+        assert_eq!(update["actor"], a);
+        assert_eq!(update["client_ip"], "127.0.0.1");
+        assert_eq!(update["request_id"], "req-1");
+        // Updates record only the fields that changed:
+        assert_eq!(update["before"]["name"], "Work");
+        assert_eq!(update["after"]["name"], "Home");
+        assert!(update["before"].get("color").is_none());
+        assert_eq!(entries[0]["before"]["name"], "Home");
+        assert_eq!(entries[0]["after"], Value::Null);
+        assert_eq!(entries[2]["after"]["name"], "Work");
+
+        let uri = format!("/admin/audit?actor={admin}&entity=user&limit=1");
+        let (_, entries) = call_as(app, admin, "GET", &uri, Value::Null).await;
+        assert_eq!(entries.as_array().unwrap().len(), 1);
+        assert_eq!(entries[0]["after"]["email"], admin);
+        let uri = format!("/admin/audit?before_id={}", entries[0]["id"]);
+        let (_, older) = call_as(app, admin, "GET", &uri, Value::Null).await;
+        assert_eq!(older[0]["actor"], a);
+        assert_eq!(older[0]["entity"], "user");
+        let (status, _) = call_as(app, admin, "GET", "/admin/audit?limit=0", Value::Null).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    crate::db::backend_test!(roles_limit_what_users_may_do);
+
+    async fn roles_limit_what_users_may_do(db: &Database) {
+        let app = &test_app_with(db);
+        let (a, b, admin) = ("a@example.com", "b@example.com", "admin@example.com");
+        let user = |email: &str| json!({"email": email, "display_name": "A"});
+        for email in [a, admin] {
+            let (status, created) = call_as(app, email, "POST", "/user", user(email)).await;
+            assert_eq!(status, StatusCode::CREATED);
+            assert_eq!(created["role"], "member");
+        }
+
+        // Members may only register themselves, and not manage users:
+        let (status, _) = call_as(app, a, "POST", "/user", user(b)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = call_as(app, a, "GET", "/admin/users", Value::Null).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Admins (from ADMIN_EMAILS here) may:
+        let new_user = json!({"email": b, "display_name": "B", "role": "read_only"});
+        let (status, created) = call_as(app, admin, "POST", "/admin/users", new_user).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created["role"], "read_only");
+        let (status, users) = call_as(app, admin, "GET", "/admin/users", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(users.as_array().unwrap().len(), 3);
+
+        // Read-only users may look, but not change anything:
+        let (status, _) = call_as(app, b, "GET", "/list", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = call_as(app, b, "POST", "/list", json!({"name": "Work"})).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let uri = format!("/admin/users/{}", created["id"].as_str().unwrap());
+        let (status, updated) = call_as(app, admin, "PATCH", &uri, json!({"role": "member"})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(updated["role"], "member");
+        let (status, _) = call_as(app, b, "POST", "/list", json!({"name": "Work"})).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        // The proxy's groups override the stored role:
+        let in_groups = |groups| [("x-forwarded-user", a), ("x-forwarded-groups", groups)];
+        let admins = in_groups("staff,admins");
+        let (status, ..) = call_with(app, "GET", "/admin/users", &admins, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let list = json!({"name": "Home"});
+        let (status, ..) = call_with(app, "POST", "/list", &in_groups("viewers"), list).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let list = json!({"name": "Errands"});
+        let (status, ..) = call_with(app, "POST", "/list", &in_groups("others"), list).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, _) = call_as(app, admin, "DELETE", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = call_as(app, admin, "DELETE", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, users) = call_as(app, admin, "GET", "/admin/users", Value::Null).await;
+        assert_eq!(users.as_array().unwrap().len(), 2);
+        let uri = format!(
+            "/admin/audit?entity=user&entity_id={}",
+            created["id"].as_str().unwrap()
+        );
+        let (_, entries) = call_as(app, admin, "GET", &uri, Value::Null).await;
+        let actions: Vec<&str> = entries
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["action"].as_str().unwrap())
+            .collect();
+        assert_eq!(actions, ["delete", "update", "create"]);
+    }
+
+    crate::db::backend_test!(admins_act_as_other_users);
+
+    async fn admins_act_as_other_users(db: &Database) {
+        let app = &test_app_with(db);
+        let (a, admin) = ("a@example.com", "admin@example.com");
+        for email in [a, admin] {
+            let user = json!({"email": email, "display_name": "A"});
+            call_as(app, email, "POST", "/user", user).await;
+        }
+        call_as(app, a, "POST", "/list", json!({"name": "Work"})).await;
+
+        let as_a = [("x-forwarded-user", admin), ("x-act-as", a)];
+        let (status, headers, lists) = call_with(app, "GET", "/list", &as_a, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["x-real-user"], admin);
+        assert_eq!(headers["x-effective-user"], a);
+        assert!(lists
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|l| l["name"] == "Work"));
+
+        let (_, _, whoami) = send(app, "GET", "/whoami", &as_a, "").await;
+        assert!(whoami.contains("user=a@example.com\nreal_user=admin@example.com\n"));
+
+        // Changes are made as the user, and audited with both:
+        let home = json!({"name": "Home"});
+        let (status, ..) = call_with(app, "POST", "/list", &as_a, home).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let uri = format!("/admin/audit?actor={a}&entity=list&limit=1");
+        let (_, entries) = call_as(app, admin, "GET", &uri, Value::Null).await;
+        assert_eq!(entries[0]["after"]["name"], "Home");
+        assert_eq!(entries[0]["real_actor"], admin);
+
+        // Acting as a member means having their role, not the admin's
+        // (nor the admin's groups):
+        let in_admins = [as_a[0], as_a[1], ("x-forwarded-groups", "admins")];
+        let (status, ..) = send(app, "GET", "/admin/users", &in_admins, "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Only admins may act as anyone, and only as registered users:
+        let as_admin = [("x-forwarded-user", a), ("x-act-as", admin)];
+        let (status, ..) = send(app, "GET", "/list", &as_admin, "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let as_b = [("x-forwarded-user", admin), ("x-act-as", "b@example.com")];
+        let (status, ..) = send(app, "GET", "/list", &as_b, "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
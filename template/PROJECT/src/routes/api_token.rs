@@ -0,0 +1,212 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{delete, get},
+    Extension, Json, Router,
+};
+
+use crate::{
+    audit::{self, Change},
+    errors::{internal_error, not_found_error},
+    extract::{Authorize, Origin},
+    middleware::BearerToken,
+    models::{
+        api_token::{
+            normalize_api_token_name, ApiToken, CreateApiToken, NewApiToken, Scopes,
+            DEFAULT_API_TOKEN_DAYS, MAX_API_TOKEN_DAYS,
+        },
+        audit::Entity,
+        ids::ApiTokenId,
+    },
+    token, AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    // this router is responsible for everything under `/api-token`
+    Router::<AppState>::new()
+        .route("/", get(list_api_tokens).post(create_api_token))
+        .route("/{api_token_id}", delete(delete_api_token))
+}
+
+/// The caller's API tokens (without their secrets), expired ones too.
+async fn list_api_tokens(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+) -> Result<Json<Vec<ApiToken>>, (StatusCode, String)> {
+    let api_tokens = state
+        .users
+        .api_tokens(user.id)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(api_tokens))
+}
+
+/// Create an API token for the caller. The secret is only returned this
+/// once. Tokens cannot be used to create more tokens.
+async fn create_api_token(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    bearer: Option<Extension<BearerToken>>,
+    origin: Origin,
+    Json(payload): Json<CreateApiToken>,
+) -> Result<(StatusCode, Json<NewApiToken>), (StatusCode, String)> {
+    if bearer.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "API tokens cannot create API tokens".to_string(),
+        ));
+    }
+    let bad_request = |e| (StatusCode::BAD_REQUEST, e);
+    let name = normalize_api_token_name(&payload.name).map_err(bad_request)?;
+    let scopes = Scopes::new(&payload.scopes).map_err(bad_request)?;
+    let days = payload.expires_in_days.unwrap_or(DEFAULT_API_TOKEN_DAYS);
+    if !(1..=MAX_API_TOKEN_DAYS).contains(&days) {
+        return Err(bad_request(format!(
+            "expires_in_days must be between 1 and {MAX_API_TOKEN_DAYS}"
+        )));
+    }
+    let secret = token::generate();
+    let api_token = state
+        .users
+        .insert_api_token(
+            &ApiToken::new(user.id, &name, scopes, days),
+            &token::digest(&secret),
+        )
+        .await
+        .map_err(internal_error)?;
+    let change = Change::created(Entity::ApiToken, api_token.id.0, &api_token);
+    audit::record(&state, &origin, [change]).await;
+    Ok((
+        StatusCode::CREATED,
+        Json(NewApiToken {
+            api_token,
+            token: secret,
+        }),
+    ))
+}
+
+/// Revoke one of the caller's API tokens.
+async fn delete_api_token(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<ApiTokenId>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let not_found = || not_found_error(format!("No API token {}", id.0));
+    let api_token = state
+        .users
+        .api_tokens(user.id)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .find(|t| t.id == id)
+        .ok_or_else(not_found)?;
+    if state
+        .users
+        .delete_api_token(user.id, id)
+        .await
+        .map_err(internal_error)?
+    {
+        let change = Change::deleted(Entity::ApiToken, id.0, &api_token);
+        audit::record(&state, &origin, [change]).await;
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(not_found())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::{json, Value};
+
+    use crate::{
+        db::Database,
+        models::api_token::{ApiToken, Scopes},
+        routes::{call, call_with, test_app_with_state, test_state},
+        token,
+    };
+
+    /// Call the app with a bearer token, and no user header.
+    async fn call_with_token(
+        app: &axum::Router,
+        token: &str,
+        method: &str,
+        uri: &str,
+        body: Value,
+    ) -> (StatusCode, Value) {
+        let bearer = format!("Bearer {token}");
+        let headers = [("authorization", bearer.as_str())];
+        let (status, _, body) = call_with(app, method, uri, &headers, body).await;
+        (status, body)
+    }
+
+    crate::db::backend_test!(api_tokens_authenticate_within_scope);
+
+    async fn api_tokens_authenticate_within_scope(db: &Database) {
+        let state = test_state(db);
+        let app = test_app_with_state(state.clone());
+        let user = json!({"email": "a@example.com", "display_name": "A"});
+        let (status, user) = call(&app, "POST", "/user", user).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let token = json!({"name": "CI", "scopes": ["read"], "expires_in_days": 7});
+        let (status, read) = call(&app, "POST", "/api-token", token).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(read["scopes"], json!(["read"]));
+        let read_token = read["token"].as_str().unwrap();
+        let token = json!({"name": "Deploy", "scopes": ["read", "write"]});
+        let (_, write) = call(&app, "POST", "/api-token", token).await;
+        let write_token = write["token"].as_str().unwrap();
+
+        // Tokens stand in for the proxy's user header, within their scopes:
+        let (status, lists) = call_with_token(&app, read_token, "GET", "/list", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(lists[0]["name"], "Inbox");
+        let work = json!({"name": "Work"});
+        let (status, _) = call_with_token(&app, read_token, "POST", "/list", work.clone()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = call_with_token(&app, write_token, "POST", "/list", work).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = call_with_token(&app, "nope", "GET", "/list", Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let token = json!({"name": "More", "scopes": ["write"]});
+        let (status, _) = call_with_token(&app, write_token, "POST", "/api-token", token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (_, tokens) = call(&app, "GET", "/api-token", Value::Null).await;
+        assert_eq!(tokens.as_array().unwrap().len(), 2);
+        assert_eq!(tokens[0]["name"], "CI");
+        assert!(tokens[0]["last_used_at"].is_string());
+        assert!(tokens[0].get("token").is_none());
+
+        for token in [
+            json!({"name": " ", "scopes": ["read"]}),
+            json!({"name": "CI", "scopes": []}),
+            json!({"name": "CI", "scopes": ["admin"]}),
+            json!({"name": "CI", "scopes": ["read"], "expires_in_days": 0}),
+        ] {
+            let (status, _) = call(&app, "POST", "/api-token", token).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+
+        // Expired and revoked tokens no longer work:
+        let user_id = serde_json::from_value(user["id"].clone()).unwrap();
+        let scopes = Scopes::new(&["read"]).unwrap();
+        let expired = ApiToken::new(user_id, "Old", scopes, -1);
+        state
+            .users
+            .insert_api_token(&expired, &token::digest("expired"))
+            .await
+            .unwrap();
+        let (status, _) = call_with_token(&app, "expired", "GET", "/list", Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let uri = format!("/api-token/{}", read["id"].as_str().unwrap());
+        let (status, _) = call(&app, "DELETE", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = call(&app, "DELETE", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call_with_token(&app, read_token, "GET", "/list", Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
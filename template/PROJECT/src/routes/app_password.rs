@@ -0,0 +1,96 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{delete, get},
+    Json, Router,
+};
+
+use crate::{
+    audit::{self, Change},
+    errors::{internal_error, not_found_error},
+    extract::{Authorize, Origin},
+    models::{
+        app_password::{
+            normalize_app_password_name, AppPassword, CreateAppPassword, NewAppPassword,
+        },
+        audit::Entity,
+        ids::AppPasswordId,
+    },
+    token, AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    // this router is responsible for everything under `/app-password`
+    Router::<AppState>::new()
+        .route("/", get(list_app_passwords).post(create_app_password))
+        .route("/{app_password_id}", delete(delete_app_password))
+}
+
+/// The caller's app passwords (without their secrets).
+async fn list_app_passwords(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+) -> Result<Json<Vec<AppPassword>>, (StatusCode, String)> {
+    let app_passwords = state
+        .users
+        .app_passwords(user.id)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(app_passwords))
+}
+
+/// Create an app password for the caller. The secret is only returned
+/// this once.
+async fn create_app_password(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Json(payload): Json<CreateAppPassword>,
+) -> Result<(StatusCode, Json<NewAppPassword>), (StatusCode, String)> {
+    let name =
+        normalize_app_password_name(&payload.name).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let password = token::generate();
+    let app_password = state
+        .users
+        .insert_app_password(&AppPassword::new(user.id, &name), &token::digest(&password))
+        .await
+        .map_err(internal_error)?;
+    let change = Change::created(Entity::AppPassword, app_password.id.0, &app_password);
+    audit::record(&state, &origin, [change]).await;
+    Ok((
+        StatusCode::CREATED,
+        Json(NewAppPassword {
+            app_password,
+            password,
+        }),
+    ))
+}
+
+async fn delete_app_password(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<AppPasswordId>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let not_found = || not_found_error(format!("No app password {}", id.0));
+    let app_password = state
+        .users
+        .app_passwords(user.id)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .find(|p| p.id == id)
+        .ok_or_else(not_found)?;
+    if state
+        .users
+        .delete_app_password(user.id, id)
+        .await
+        .map_err(internal_error)?
+    {
+        let change = Change::deleted(Entity::AppPassword, id.0, &app_password);
+        audit::record(&state, &origin, [change]).await;
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(not_found())
+    }
+}
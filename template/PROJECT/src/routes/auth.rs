@@ -0,0 +1,511 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+
+use crate::{
+    errors::{internal_error, not_found_error},
+    extract::Origin,
+    oidc::{OidcClient, LOGIN_TIMEOUT},
+    password::LoginError,
+    prelude::*,
+    session::{self, SESSION_COOKIE},
+    token, AppState,
+};
+
+/// The cookie tying a login in progress to the browser that started it.
+const STATE_COOKIE: &str = "oidc_state";
+
+pub fn router() -> Router<AppState> {
+    // this router is responsible for everything under `/auth`
+    Router::<AppState>::new()
+        .route("/login", get(login).post(password_login))
+        .route("/callback", get(callback))
+        .route("/logout", post(logout))
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginParams {
+    /// Where to go once signed in: a path on this site.
+    return_to: Option<String>,
+}
+
+/// Signing in with the password of a local account.
+#[derive(Debug, Deserialize)]
+struct PasswordLogin {
+    email: String,
+    password: String,
+}
+
+/// What the issuer sends the browser back with.
+#[derive(Debug, Deserialize)]
+struct CallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+fn oidc_client(state: &AppState) -> Result<Arc<OidcClient>, (StatusCode, String)> {
+    state
+        .oidc
+        .clone()
+        .ok_or_else(|| not_found_error("Signing in with OpenID Connect is not configured"))
+}
+
+/// Only paths on this site, so that logins cannot send users elsewhere.
+fn local_path(return_to: Option<&str>) -> &str {
+    match return_to {
+        Some(path) if path.starts_with('/') && !path.starts_with("//") && !path.contains('\\') => {
+            path
+        }
+        _ => "/",
+    }
+}
+
+/// Send the browser to the issuer to sign in.
+async fn login(
+    State(state): State<AppState>,
+    Query(params): Query<LoginParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let oidc = oidc_client(&state)?;
+    let (login_state, url) = oidc.start(local_path(params.return_to.as_deref()));
+    let cookie = state
+        .sessions
+        .cookie(STATE_COOKIE, &login_state, "/auth", LOGIN_TIMEOUT);
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to(&url)).into_response())
+}
+
+/// Sign in with the password of a local account, starting a session.
+async fn password_login(
+    State(state): State<AppState>,
+    origin: Origin,
+    Json(payload): Json<PasswordLogin>,
+) -> Result<Response, (StatusCode, String)> {
+    let user = state
+        .logins
+        .authenticate(
+            state.users.as_ref(),
+            &payload.email,
+            &payload.password,
+            origin.client_ip,
+        )
+        .await
+        .map_err(|e| match e {
+            LoginError::Invalid => (
+                StatusCode::UNAUTHORIZED,
+                "Wrong email or password".to_string(),
+            ),
+            LoginError::Throttled(wait) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!(
+                    "Too many wrong passwords; try again in {} minutes",
+                    wait.as_secs().div_ceil(60)
+                ),
+            ),
+            LoginError::Internal(e) => internal_error(e),
+        })?;
+    let cookie = state
+        .sessions
+        .start(state.users.as_ref(), &user.email)
+        .await
+        .map_err(internal_error)?;
+    info!("{} signed in with a password", user.email);
+    Ok(([(header::SET_COOKIE, cookie)], StatusCode::NO_CONTENT).into_response())
+}
+
+/// Where the issuer sends the browser back to: start a session for the
+/// user it signed in, and go on to where they were going.
+async fn callback(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<CallbackParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let oidc = oidc_client(&state)?;
+    if let Some(error) = params.error {
+        let description = params.error_description.unwrap_or_default();
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            format!("Signing in failed: {error} {description}"),
+        ));
+    }
+    let (Some(code), Some(login_state)) = (params.code, params.state) else {
+        return Err((StatusCode::BAD_REQUEST, "Missing code or state".to_string()));
+    };
+    if session::cookie(&headers, STATE_COOKIE) != Some(login_state.as_str()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Signing in was started in another browser".to_string(),
+        ));
+    }
+
+    let (identity, return_to) = oidc.finish(&login_state, &code).await.map_err(|e| {
+        warn!("OpenID Connect login failed: {e}");
+        (StatusCode::UNAUTHORIZED, "Signing in failed".to_string())
+    })?;
+    let cookie = state
+        .sessions
+        .start(state.users.as_ref(), &identity.email)
+        .await
+        .map_err(internal_error)?;
+    info!("{} signed in with OpenID Connect", identity.email);
+    let clear = state.sessions.clear_cookie(STATE_COOKIE, "/auth");
+    Ok((
+        AppendHeaders([(header::SET_COOKIE, cookie), (header::SET_COOKIE, clear)]),
+        Redirect::to(&return_to),
+    )
+        .into_response())
+}
+
+/// Sign out: end the browser's session, and if the issuer supports it,
+/// the user's session there too. Only a `POST` does this, so other sites
+/// cannot sign users out with a link.
+async fn logout(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    if let Some(secret) = session::cookie(&headers, SESSION_COOKIE) {
+        state
+            .users
+            .delete_session(&token::digest(secret))
+            .await
+            .map_err(internal_error)?;
+    }
+    let clear = state.sessions.clear_cookie(SESSION_COOKIE, "/");
+    let to = state
+        .oidc
+        .as_ref()
+        .and_then(|oidc| oidc.logout_url())
+        .unwrap_or_else(|| "/".to_string());
+    Ok(([(header::SET_COOKIE, clear)], Redirect::to(&to)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        extract::{Query, State},
+        http::{header, HeaderMap, StatusCode},
+        response::{IntoResponse, Redirect},
+        routing::{get, post},
+        Form, Json, Router,
+    };
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use chrono::Utc;
+    use serde_json::{json, Value};
+    use sha2::{Digest, Sha256};
+
+    use crate::{
+        db::Database,
+        jwt::tests::{jwks, sign},
+        models::{session::Session, user::User},
+        oidc::{OidcClient, OidcConfig},
+        password,
+        routes::{send, test_app_with_state, test_state},
+        token,
+    };
+
+    /// A local OpenID Connect issuer that signs in whoever asks, as
+    /// `a@example.com`.
+    #[derive(Clone, Default)]
+    struct Issuer {
+        url: String,
+        /// The PKCE challenges and nonces of the codes it handed out.
+        codes: Arc<Mutex<HashMap<String, (String, String)>>>,
+    }
+
+    async fn authorize(
+        State(issuer): State<Issuer>,
+        Query(params): Query<HashMap<String, String>>,
+    ) -> impl IntoResponse {
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["client_id"], "todo-app");
+        assert_eq!(params["scope"], "openid email profile");
+        assert_eq!(params["code_challenge_method"], "S256");
+        let code = token::generate();
+        issuer.codes.lock().unwrap().insert(
+            code.clone(),
+            (params["code_challenge"].clone(), params["nonce"].clone()),
+        );
+        let state = &params["state"];
+        Redirect::to(&format!(
+            "{}?code={code}&state={state}",
+            params["redirect_uri"]
+        ))
+    }
+
+    async fn exchange(
+        State(issuer): State<Issuer>,
+        headers: HeaderMap,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> impl IntoResponse {
+        let basic = format!("Basic {}", base64_std("todo-app:s3cret"));
+        if headers.get(header::AUTHORIZATION).unwrap() != basic.as_str() {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"error": "invalid_client"})),
+            );
+        }
+        let Some((challenge, nonce)) = issuer.codes.lock().unwrap().remove(&form["code"]) else {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "invalid_grant"})),
+            );
+        };
+        let verified = URL_SAFE_NO_PAD.encode(Sha256::digest(form["code_verifier"].as_bytes()));
+        if verified != challenge || form["grant_type"] != "authorization_code" {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "invalid_grant"})),
+            );
+        }
+        let claims = json!({
+            "iss": issuer.url,
+            "aud": "todo-app",
+            "sub": "alice",
+            "exp": Utc::now().timestamp() + 300,
+            "nonce": nonce,
+            "email": "a@example.com",
+            "email_verified": true,
+            "name": "Alice",
+        });
+        let id_token = sign(&claims, None);
+        (
+            StatusCode::OK,
+            Json(json!({"id_token": id_token, "token_type": "Bearer"})),
+        )
+    }
+
+    fn base64_std(s: &str) -> String {
+        use base64::engine::general_purpose::STANDARD;
+        STANDARD.encode(s)
+    }
+
+    /// Serve an [`Issuer`] on a free local port.
+    async fn spawn_issuer() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let issuer = Issuer {
+            url: url.clone(),
+            ..Default::default()
+        };
+        let discovery = json!({
+            "issuer": url,
+            "authorization_endpoint": format!("{url}/authorize"),
+            "token_endpoint": format!("{url}/token"),
+            "jwks_uri": format!("{url}/jwks.json"),
+            "end_session_endpoint": format!("{url}/logout"),
+        });
+        let app = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(move || async move { Json(discovery) }),
+            )
+            .route("/jwks.json", get(|| async { Json(jwks()) }))
+            .route("/authorize", get(authorize))
+            .route("/token", post(exchange))
+            .with_state(issuer);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    /// Call the app with these cookies, returning the status, the
+    /// `Location` and `Set-Cookie` headers, and the body.
+    async fn browse(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        cookies: &str,
+        body: Value,
+    ) -> (StatusCode, String, Vec<String>, String) {
+        let headers = [("cookie", cookies), ("content-type", "application/json")];
+        let (status, headers, body) = send(app, method, uri, &headers, &body.to_string()).await;
+        let location = headers
+            .get(header::LOCATION)
+            .map(|v| v.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        let set_cookies = headers
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .map(|v| v.to_str().unwrap().to_string())
+            .collect();
+        (status, location, set_cookies, body)
+    }
+
+    /// The `name=value` part of the `Set-Cookie` for `name`.
+    fn cookie(set_cookies: &[String], name: &str) -> String {
+        set_cookies
+            .iter()
+            .find(|c| c.starts_with(&format!("{name}=")))
+            .map(|c| c.split(';').next().unwrap().to_string())
+            .unwrap()
+    }
+
+    /// Sign in through the issuer like a browser would, returning the
+    /// callback's response.
+    async fn sign_in(app: &Router, return_to: &str) -> (StatusCode, String, Vec<String>, String) {
+        let uri = format!("/auth/login?return_to={return_to}");
+        let (status, location, set_cookies, _) = browse(app, "GET", &uri, "", Value::Null).await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        let state_cookie = cookie(&set_cookies, "oidc_state");
+        assert!(set_cookies[0].contains("Path=/auth; Max-Age=600; HttpOnly; SameSite=Lax"));
+
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let res = client.get(&location).send().await.unwrap();
+        let callback = res.headers()[header::LOCATION].to_str().unwrap();
+        let callback = callback
+            .strip_prefix("https://todo.example.com")
+            .unwrap()
+            .to_string();
+        browse(app, "GET", &callback, &state_cookie, Value::Null).await
+    }
+
+    crate::db::backend_test!(oidc_login_starts_a_session);
+
+    async fn oidc_login_starts_a_session(db: &Database) {
+        let url = spawn_issuer().await;
+        let oidc = OidcClient::discover(OidcConfig {
+            issuer: url.clone(),
+            client_id: "todo-app".to_string(),
+            client_secret: Some("s3cret".to_string()),
+            redirect_url: "https://todo.example.com/auth/callback".to_string(),
+            scopes: vec!["openid".into(), "email".into(), "profile".into()],
+        })
+        .await
+        .unwrap();
+        let mut state = test_state(db);
+        state.oidc = Some(Arc::new(oidc));
+        let app = test_app_with_state(state.clone());
+
+        let (status, location, set_cookies, _) = sign_in(&app, "/list").await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        assert_eq!(location, "/list");
+        assert!(set_cookies[0].ends_with("; HttpOnly; SameSite=Lax; Secure"));
+        let session = cookie(&set_cookies, "session");
+
+        // The session stands in for the proxy's user header:
+        let (_, _, _, whoami) = browse(&app, "GET", "/whoami", &session, Value::Null).await;
+        assert!(whoami.contains("user=a@example.com\n"));
+        assert!(whoami.contains("cookie: <redacted>\n"), "{whoami}");
+        let user = json!({"email": "a@example.com", "display_name": "A"});
+        let (status, ..) = browse(&app, "POST", "/user", &session, user).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, ..) = browse(&app, "GET", "/list", &session, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Logins only return to paths on this site:
+        let (_, location, ..) = sign_in(&app, "//evil.example.com").await;
+        assert_eq!(location, "/");
+        // A login must come back to the browser that started it, once:
+        let (_, location, set_cookies, _) =
+            browse(&app, "GET", "/auth/login", "", Value::Null).await;
+        let state_cookie = cookie(&set_cookies, "oidc_state");
+        let res = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .get(&location)
+            .send()
+            .await
+            .unwrap();
+        let callback = res.headers()[header::LOCATION].to_str().unwrap();
+        let callback = callback.strip_prefix("https://todo.example.com").unwrap();
+        let (status, ..) = browse(&app, "GET", callback, "oidc_state=other", Value::Null).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, ..) = browse(&app, "GET", callback, &state_cookie, Value::Null).await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        let (status, ..) = browse(&app, "GET", callback, &state_cookie, Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let denied = "/auth/callback?error=access_denied";
+        let (status, ..) = browse(&app, "GET", denied, &state_cookie, Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Signing out takes a POST, so a link or image elsewhere cannot do
+        // it, and ends the session, at the issuer too:
+        let (status, ..) = browse(&app, "GET", "/auth/logout", &session, Value::Null).await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        let (status, location, set_cookies, _) =
+            browse(&app, "POST", "/auth/logout", &session, Value::Null).await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        assert_eq!(location, format!("{url}/logout?client_id=todo-app"));
+        assert!(set_cookies[0].starts_with("session=; Path=/; Max-Age=0;"));
+        let (status, ..) = browse(&app, "GET", "/list", &session, Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Expired sessions are ignored, and purged:
+        let expired = Session::new("a@example.com", chrono::Duration::seconds(-1));
+        state
+            .users
+            .insert_session(&expired, &token::digest("expired"))
+            .await
+            .unwrap();
+        let (status, ..) = browse(&app, "GET", "/list", "session=expired", Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(state.users.purge_sessions(Utc::now()).await.unwrap() >= 1);
+    }
+
+    crate::db::backend_test!(password_login_and_basic_auth);
+
+    async fn password_login_and_basic_auth(db: &Database) {
+        let state = test_state(db);
+        let app = test_app_with_state(state.clone());
+        let user = User::new("a@example.com", "A");
+        let user = state.users.insert(&user).await.unwrap();
+        let hash = password::hash("correct horse").await.unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(state
+            .users
+            .set_password_hash(user.id, Some(&hash))
+            .await
+            .unwrap());
+
+        let login = |password: &str| json!({"email": "a@example.com", "password": password});
+        let (status, ..) = browse(&app, "POST", "/auth/login", "", login("wrong")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _, set_cookies, _) =
+            browse(&app, "POST", "/auth/login", "", login("correct horse")).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let session = cookie(&set_cookies, "session");
+        let (_, _, _, whoami) = browse(&app, "GET", "/whoami", &session, Value::Null).await;
+        assert!(whoami.contains("user=a@example.com\n"));
+
+        // Command line tools can send the password with every request:
+        let right = format!("Basic {}", base64_std("a@example.com:correct horse"));
+        let right = [("authorization", right.as_str())];
+        let wrong = format!("Basic {}", base64_std("a@example.com:wrong"));
+        let wrong = [("authorization", wrong.as_str())];
+        let (status, ..) = send(&app, "GET", "/list", &right, "").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, headers, _) = send(&app, "GET", "/list", &wrong, "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(headers.contains_key(header::WWW_AUTHENTICATE));
+
+        // Five wrong passwords in a row lock the client out of the email:
+        for _ in 0..4 {
+            let (status, ..) = browse(&app, "POST", "/auth/login", "", login("wrong")).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+        let (status, ..) = browse(&app, "POST", "/auth/login", "", login("correct horse")).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        let (status, headers, _) = send(&app, "GET", "/list", &right, "").await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(headers.contains_key(header::RETRY_AFTER));
+
+        // Without a password, there is nothing to sign in with:
+        state.users.set_password_hash(user.id, None).await.unwrap();
+        let fresh = test_app_with_state(test_state(db));
+        let (status, ..) = browse(&fresh, "POST", "/auth/login", "", login("correct horse")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
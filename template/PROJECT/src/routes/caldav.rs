@@ -0,0 +1,1022 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
+    str::FromStr,
+};
+
+use axum::{
+    body::Bytes,
+    extract::{FromRequestParts, State},
+    http::{header, request::Parts, HeaderMap, HeaderName, Method, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Change},
+    errors::{internal_error, not_found_error},
+    events,
+    extract::{Authorize, Origin},
+    ical,
+    middleware::{basic_credentials, AuthenticatedUser},
+    models::{
+        audit::Entity,
+        ids::{ListId, TodoId},
+        list::TodoList,
+        todo::{ImportTodo, Todo},
+        user::User,
+        webhook::Event,
+        FIRST_VERSION,
+    },
+    recurrence::RRule,
+    repository::TodoFilter,
+    token, AppState,
+};
+
+mod xml;
+
+use xml::{Multistatus, PropName, PropRequest, Report, APPLE, CALDAV, CALENDARSERVER, DAV};
+
+const ROOT: &str = "/dav/";
+const PRINCIPAL: &str = "/dav/principal/";
+const HOME: &str = "/dav/calendars/";
+
+const SYNC_TOKEN_PREFIX: &str = "data:,";
+/// How long removed todos are remembered for sync reports (the database
+/// forgets them after this), and so how long sync tokens stay valid.
+const SYNC_WINDOW: Duration = Duration::days(30);
+
+/// Properties returned for `allprop` requests, where a resource has them.
+const ALL_PROPS: &[(&str, &str)] = &[
+    (DAV, "resourcetype"),
+    (DAV, "displayname"),
+    (DAV, "current-user-principal"),
+    (DAV, "principal-URL"),
+    (DAV, "owner"),
+    (CALDAV, "calendar-home-set"),
+    (CALDAV, "calendar-user-address-set"),
+    (CALDAV, "supported-calendar-component-set"),
+    (DAV, "supported-report-set"),
+    (DAV, "current-user-privilege-set"),
+    (DAV, "sync-token"),
+    (CALENDARSERVER, "getctag"),
+    (APPLE, "calendar-color"),
+    (APPLE, "calendar-order"),
+    (DAV, "getetag"),
+    (DAV, "getcontenttype"),
+];
+
+/// The user a CalDAV request is for: the [`Authorize`]d user if the
+/// trusted proxy named one, or else from HTTP Basic auth with their email
+/// and one of their app passwords, since most CalDAV clients can do
+/// nothing else. Read-only users may only look, either way.
+pub(super) struct DavUser(User);
+
+impl FromRequestParts<AppState> for DavUser {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Response> {
+        if parts.extensions.get::<AuthenticatedUser>().is_some() {
+            return match Authorize::from_request_parts(parts, state).await {
+                Ok(Authorize { user, .. }) => Ok(DavUser(user)),
+                Err(rejection) => Err(rejection.into_response()),
+            };
+        }
+
+        let user = match basic_credentials(&parts.headers) {
+            Some((email, password)) => state
+                .users
+                .find_by_app_password(&token::digest(&password))
+                .await
+                .map_err(|e| internal_error(e).into_response())?
+                .filter(|user| user.email.eq_ignore_ascii_case(&email)),
+            None => None,
+        };
+        let Some(user) = user else {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Basic realm=\"${APP}\"")],
+                "Unauthenticated",
+            )
+                .into_response());
+        };
+        match Authorize::new(parts, state, user) {
+            Ok(Authorize { user, .. }) => Ok(DavUser(user)),
+            Err(rejection) => Err(rejection.into_response()),
+        }
+    }
+}
+
+/// What a path under `/dav` names.
+#[derive(Debug, PartialEq, Eq)]
+enum Target {
+    Root,
+    Principal,
+    /// The collection of the user's calendars.
+    Home,
+    /// A list, as a calendar of `VTODO`s.
+    Calendar(ListId),
+    /// A todo in a list, by UID.
+    Object(ListId, String),
+}
+
+impl Target {
+    fn parse(path: &str) -> Option<Self> {
+        let rest = path.strip_prefix("/dav")?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        let segments: Vec<&str> = rest.split('/').filter(|s| !s.is_empty()).collect();
+        let list_id = |s: &str| Uuid::parse_str(s).ok().map(ListId);
+        Some(match segments[..] {
+            [] => Target::Root,
+            ["principal"] => Target::Principal,
+            ["calendars"] => Target::Home,
+            ["calendars", list] => Target::Calendar(list_id(list)?),
+            ["calendars", list, name] => {
+                Target::Object(list_id(list)?, percent_decode(name.strip_suffix(".ics")?)?)
+            }
+            _ => return None,
+        })
+    }
+}
+
+/// Percent-encode a UID for use as a path segment.
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~@".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            let _ = write!(encoded, "%{b:02X}");
+        }
+    }
+    encoded
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn calendar_path(list_id: ListId) -> String {
+    format!("{HOME}{}/", list_id.0)
+}
+
+fn object_path(list_id: ListId, uid: &str) -> String {
+    format!("{}{}.ics", calendar_path(list_id), percent_encode(uid))
+}
+
+/// A todo as a calendar object: a calendar with just its `VTODO`.
+struct Object {
+    uid: String,
+    todo: Todo,
+    ics: String,
+    etag: String,
+}
+
+impl Object {
+    fn new(todo: Todo, uid: String) -> Self {
+        let ics = ical::calendar(vec![ical::vtodo(&todo, &uid)]).to_string();
+        let etag = format!("\"{:x}\"", Sha256::digest(ics.as_bytes()));
+        Object {
+            uid,
+            todo,
+            ics,
+            etag,
+        }
+    }
+}
+
+/// A resource to describe in a multistatus response.
+enum Resource<'a> {
+    Root,
+    Principal,
+    Home,
+    Calendar {
+        list: &'a TodoList,
+        sync_token: &'a str,
+    },
+    Object(&'a Object),
+}
+
+impl Resource<'_> {
+    fn path(&self) -> String {
+        match self {
+            Resource::Root => ROOT.to_string(),
+            Resource::Principal => PRINCIPAL.to_string(),
+            Resource::Home => HOME.to_string(),
+            Resource::Calendar { list, .. } => calendar_path(list.id),
+            Resource::Object(object) => object_path(object.todo.list_id, &object.uid),
+        }
+    }
+
+    /// A property's value as XML, or `None` if the resource does not have
+    /// it.
+    fn prop(&self, user: &User, name: &PropName) -> Option<String> {
+        use Resource::*;
+
+        let privileges = |names: &[&str]| -> String {
+            names
+                .iter()
+                .map(|p| format!("<D:privilege><D:{p}/></D:privilege>"))
+                .collect()
+        };
+        let value = match (name.ns.as_str(), name.local.as_str(), self) {
+            (DAV, "resourcetype", Root | Home) => "<D:collection/>".to_string(),
+            (DAV, "resourcetype", Principal) => "<D:principal/>".to_string(),
+            (DAV, "resourcetype", Calendar { .. }) => "<D:collection/><C:calendar/>".to_string(),
+            (DAV, "resourcetype", Object(_)) => String::new(),
+            (DAV, "displayname", Principal) => xml::escape(&user.display_name),
+            (DAV, "displayname", Calendar { list, .. }) => xml::escape(&list.name),
+            (DAV, "current-user-principal", _) => xml::href(PRINCIPAL),
+            (DAV, "principal-URL", Principal) | (DAV, "owner", Calendar { .. }) => {
+                xml::href(PRINCIPAL)
+            }
+            (CALDAV, "calendar-home-set", Principal) => xml::href(HOME),
+            (CALDAV, "calendar-user-address-set", Principal) => {
+                xml::href(&format!("mailto:{}", user.email))
+            }
+            (CALDAV, "supported-calendar-component-set", Calendar { .. }) => {
+                "<C:comp name=\"VTODO\"/>".to_string()
+            }
+            (DAV, "supported-report-set", Calendar { .. }) => [
+                "C:calendar-query",
+                "C:calendar-multiget",
+                "D:sync-collection",
+            ]
+            .iter()
+            .map(|r| {
+                format!("<D:supported-report><D:report><{r}/></D:report></D:supported-report>")
+            })
+            .collect(),
+            (DAV, "current-user-privilege-set", Calendar { .. }) => {
+                privileges(&["read", "write", "write-content", "bind", "unbind"])
+            }
+            (DAV, "current-user-privilege-set", Object(_)) => {
+                privileges(&["read", "write", "write-content"])
+            }
+            (DAV, "sync-token", Calendar { sync_token, .. })
+            | (CALENDARSERVER, "getctag", Calendar { sync_token, .. }) => xml::escape(sync_token),
+            (APPLE, "calendar-color", Calendar { list, .. }) => xml::escape(list.color.as_deref()?),
+            (APPLE, "calendar-order", Calendar { list, .. }) => list.position.to_string(),
+            (DAV, "getetag", Object(object)) => xml::escape(&object.etag),
+            (DAV, "getcontenttype", Object(_)) => {
+                "text/calendar; charset=utf-8; component=VTODO".to_string()
+            }
+            (CALDAV, "calendar-data", Object(object)) => xml::escape(&object.ics),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+/// Add the requested properties of a resource to a multistatus response.
+fn respond(ms: &mut Multistatus, user: &User, resource: &Resource, props: &PropRequest) {
+    let mut found = Vec::new();
+    let mut missing = Vec::new();
+    match props {
+        PropRequest::All => {
+            for (ns, local) in ALL_PROPS {
+                let name = PropName {
+                    ns: ns.to_string(),
+                    local: local.to_string(),
+                };
+                if let Some(value) = resource.prop(user, &name) {
+                    found.push((name, value));
+                }
+            }
+        }
+        PropRequest::Props(names) => {
+            for name in names {
+                match resource.prop(user, name) {
+                    Some(value) => found.push((name.clone(), value)),
+                    None => missing.push(name.clone()),
+                }
+            }
+        }
+    }
+    ms.response(&resource.path(), &found, &missing);
+}
+
+fn multistatus(body: String) -> Response {
+    (
+        StatusCode::MULTI_STATUS,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        body,
+    )
+        .into_response()
+}
+
+/// A `DAV:error` response for a failed precondition.
+fn dav_error(status: StatusCode, ns: &str, precondition: &str) -> Response {
+    (
+        status,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        xml::error(ns, precondition),
+    )
+        .into_response()
+}
+
+fn bad_request(e: String) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, e)
+}
+
+fn text(body: &Bytes) -> Result<&str, (StatusCode, String)> {
+    std::str::from_utf8(body).map_err(|e| bad_request(e.to_string()))
+}
+
+/// A list's todos as calendar objects, under their UIDs.
+async fn objects(state: &AppState, list_id: ListId) -> Result<Vec<Object>, (StatusCode, String)> {
+    let uids: HashMap<TodoId, String> = state
+        .caldav
+        .uids(list_id)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .collect();
+    let todos = state
+        .todos
+        .list(TodoFilter {
+            list_id: Some(list_id),
+            ..Default::default()
+        })
+        .await
+        .map_err(internal_error)?;
+    Ok(todos
+        .into_iter()
+        .map(|todo| {
+            let uid = uids
+                .get(&todo.id)
+                .cloned()
+                .unwrap_or_else(|| todo.id.0.to_string());
+            Object::new(todo, uid)
+        })
+        .collect())
+}
+
+/// A list's sync token (and CTag): the time that a `sync-collection`
+/// report reports changes to the list from. This is its latest change,
+/// but at least the start of yesterday, so that tokens of quiet lists
+/// stay valid while changing at most once a day. Changes made in that
+/// same second are reported again, which is harmless, rather than risk
+/// missing any.
+async fn sync_token(
+    state: &AppState,
+    list_id: ListId,
+    objects: &[Object],
+) -> Result<String, (StatusCode, String)> {
+    let removed = state
+        .caldav
+        .removed_since(list_id, DateTime::UNIX_EPOCH)
+        .await
+        .map_err(internal_error)?;
+    const DAY: i64 = 24 * 60 * 60;
+    let yesterday = (Utc::now().timestamp() / DAY - 1) * DAY;
+    let latest = objects
+        .iter()
+        .map(|o| o.todo.updated_at)
+        .chain(removed.into_iter().map(|(_, at)| at))
+        .map(|at| at.timestamp())
+        .fold(yesterday, i64::max);
+    Ok(format!("{SYNC_TOKEN_PREFIX}{latest}"))
+}
+
+/// The time a sync token stands for, if it is one of ours and recent
+/// enough for removals since then to still be known.
+fn parse_sync_token(token: &str) -> Option<DateTime<Utc>> {
+    let seconds = token.strip_prefix(SYNC_TOKEN_PREFIX)?.parse().ok()?;
+    DateTime::from_timestamp(seconds, 0).filter(|at| *at > Utc::now() - SYNC_WINDOW)
+}
+
+/// Check `If-Match` and `If-None-Match` against the ETag of an object
+/// (`None` if there is no such object yet).
+fn check_preconditions(
+    headers: &HeaderMap,
+    etag: Option<&str>,
+) -> Result<(), (StatusCode, String)> {
+    let get = |name| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+    };
+    let matches = |value: &str| {
+        value
+            .split(',')
+            .map(str::trim)
+            .any(|tag| (tag == "*" && etag.is_some()) || Some(tag) == etag)
+    };
+    if get(header::IF_MATCH).is_some_and(|v| !matches(v))
+        || get(header::IF_NONE_MATCH).is_some_and(matches)
+    {
+        Err(changed_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// For objects that fail a precondition, or change while being written.
+fn changed_error() -> (StatusCode, String) {
+    (
+        StatusCode::PRECONDITION_FAILED,
+        "The calendar object has changed".to_string(),
+    )
+}
+
+/// Serve a CalDAV request for anything under `/dav`. Each of the caller's
+/// lists is a calendar of `VTODO`s, holding one object per todo.
+pub(super) async fn dav(
+    State(state): State<AppState>,
+    DavUser(user): DavUser,
+    mut origin: Origin,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    // Clients signing in with an app password have no authenticated
+    // email, so changes are recorded as made by the user themselves.
+    origin.actor.get_or_insert_with(|| user.email.clone());
+    let result = match Target::parse(uri.path()) {
+        None => Err(not_found_error("Not Found")),
+        Some(target) => match (method.as_str(), target) {
+            ("OPTIONS", _) => Ok(options()),
+            ("PROPFIND", target) => propfind(&state, &user, target, &headers, &body).await,
+            ("REPORT", Target::Calendar(list_id)) => report(&state, &user, list_id, &body).await,
+            ("GET" | "HEAD", Target::Object(list_id, uid)) => {
+                get_object(&state, &user, list_id, &uid).await
+            }
+            ("PUT", Target::Object(list_id, uid)) => {
+                put_object(&state, &origin, &user, list_id, &uid, &headers, &body).await
+            }
+            ("DELETE", Target::Object(list_id, uid)) => {
+                delete_object(&state, &origin, &user, list_id, &uid, &headers).await
+            }
+            _ => Err((
+                StatusCode::METHOD_NOT_ALLOWED,
+                format!("{method} is not supported here"),
+            )),
+        },
+    };
+    result.unwrap_or_else(IntoResponse::into_response)
+}
+
+/// Where CalDAV clients look for the server (RFC 6764).
+pub(super) async fn well_known() -> Redirect {
+    Redirect::permanent(ROOT)
+}
+
+fn options() -> Response {
+    (
+        [
+            (HeaderName::from_static("dav"), "1, 3, calendar-access"),
+            (
+                header::ALLOW,
+                "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, REPORT",
+            ),
+        ],
+        "",
+    )
+        .into_response()
+}
+
+async fn propfind(
+    state: &AppState,
+    user: &User,
+    target: Target,
+    headers: &HeaderMap,
+    body: &Bytes,
+) -> Result<Response, (StatusCode, String)> {
+    let props = xml::parse_propfind(text(body)?).map_err(bad_request)?;
+    // Depth "infinity" (the default) is answered like depth 1:
+    let children = headers.get("depth").is_none_or(|depth| depth != "0");
+    let mut ms = Multistatus::default();
+
+    match target {
+        Target::Root => {
+            respond(&mut ms, user, &Resource::Root, &props);
+            if children {
+                respond(&mut ms, user, &Resource::Principal, &props);
+                respond(&mut ms, user, &Resource::Home, &props);
+            }
+        }
+        Target::Principal => respond(&mut ms, user, &Resource::Principal, &props),
+        Target::Home => {
+            respond(&mut ms, user, &Resource::Home, &props);
+            if children {
+                let lists = state
+                    .lists
+                    .list(user.id, Some(false))
+                    .await
+                    .map_err(internal_error)?;
+                for list in &lists {
+                    let objects = objects(state, list.id).await?;
+                    let sync_token = sync_token(state, list.id, &objects).await?;
+                    let calendar = Resource::Calendar {
+                        list,
+                        sync_token: &sync_token,
+                    };
+                    respond(&mut ms, user, &calendar, &props);
+                }
+            }
+        }
+        Target::Calendar(list_id) => {
+            let list = super::list::owned_list(state, user.id, list_id).await?;
+            let objects = objects(state, list.id).await?;
+            let sync_token = sync_token(state, list.id, &objects).await?;
+            let calendar = Resource::Calendar {
+                list: &list,
+                sync_token: &sync_token,
+            };
+            respond(&mut ms, user, &calendar, &props);
+            if children {
+                for object in &objects {
+                    respond(&mut ms, user, &Resource::Object(object), &props);
+                }
+            }
+        }
+        Target::Object(list_id, uid) => {
+            let object = find_object(state, user, list_id, &uid)
+                .await?
+                .ok_or_else(|| not_found_error("No such calendar object"))?;
+            respond(&mut ms, user, &Resource::Object(&object), &props);
+        }
+    }
+    Ok(multistatus(ms.finish(None)))
+}
+
+async fn report(
+    state: &AppState,
+    user: &User,
+    list_id: ListId,
+    body: &Bytes,
+) -> Result<Response, (StatusCode, String)> {
+    let list = super::list::owned_list(state, user.id, list_id).await?;
+    let report = xml::parse_report(text(body)?).map_err(bad_request)?;
+    let objects = objects(state, list.id).await?;
+    let mut ms = Multistatus::default();
+
+    match report {
+        Report::CalendarQuery {
+            props,
+            todos,
+            pending_only,
+        } => {
+            let matching = objects
+                .iter()
+                .filter(|o| todos && !(pending_only && o.todo.completed));
+            for object in matching {
+                respond(&mut ms, user, &Resource::Object(object), &props);
+            }
+            Ok(multistatus(ms.finish(None)))
+        }
+        Report::CalendarMultiget { props, hrefs } => {
+            for href in hrefs {
+                // Hrefs may be full URLs rather than paths:
+                let target = href
+                    .parse::<Uri>()
+                    .ok()
+                    .and_then(|uri| Target::parse(uri.path()));
+                let object = match target {
+                    Some(Target::Object(id, uid)) if id == list.id => {
+                        objects.iter().find(|o| o.uid == uid)
+                    }
+                    _ => None,
+                };
+                match object {
+                    Some(object) => respond(&mut ms, user, &Resource::Object(object), &props),
+                    None => ms.status(&href, StatusCode::NOT_FOUND),
+                }
+            }
+            Ok(multistatus(ms.finish(None)))
+        }
+        Report::SyncCollection { props, sync_token } => {
+            let since = if sync_token.is_empty() {
+                None
+            } else {
+                match parse_sync_token(&sync_token) {
+                    Some(since) => Some(since),
+                    None => return Ok(dav_error(StatusCode::FORBIDDEN, DAV, "valid-sync-token")),
+                }
+            };
+            let new_token = self::sync_token(state, list.id, &objects).await?;
+            let changed = objects
+                .iter()
+                .filter(|o| since.is_none_or(|since| o.todo.updated_at >= since));
+            for object in changed {
+                respond(&mut ms, user, &Resource::Object(object), &props);
+            }
+            if let Some(since) = since {
+                // A todo that left and came back is reported as changed:
+                let removed: BTreeSet<String> = state
+                    .caldav
+                    .removed_since(list.id, since)
+                    .await
+                    .map_err(internal_error)?
+                    .into_iter()
+                    .map(|(uid, _)| uid)
+                    .filter(|uid| !objects.iter().any(|o| o.uid == *uid))
+                    .collect();
+                for uid in removed {
+                    ms.status(&object_path(list.id, &uid), StatusCode::NOT_FOUND);
+                }
+            }
+            Ok(multistatus(ms.finish(Some(&new_token))))
+        }
+    }
+}
+
+/// The object with this UID in one of the user's calendars.
+async fn find_object(
+    state: &AppState,
+    user: &User,
+    list_id: ListId,
+    uid: &str,
+) -> Result<Option<Object>, (StatusCode, String)> {
+    let list = super::list::owned_list(state, user.id, list_id).await?;
+    let todo = state
+        .caldav
+        .find_by_uid(list.id, uid)
+        .await
+        .map_err(internal_error)?;
+    Ok(todo.map(|todo| Object::new(todo, uid.to_string())))
+}
+
+async fn get_object(
+    state: &AppState,
+    user: &User,
+    list_id: ListId,
+    uid: &str,
+) -> Result<Response, (StatusCode, String)> {
+    let object = find_object(state, user, list_id, uid)
+        .await?
+        .ok_or_else(|| not_found_error("No such calendar object"))?;
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                "text/calendar; charset=utf-8".to_string(),
+            ),
+            (header::ETAG, object.etag),
+        ],
+        object.ics,
+    )
+        .into_response())
+}
+
+/// The todo of a calendar object: a calendar with a single `VTODO` with
+/// this UID (besides any overridden occurrences, which are ignored).
+fn parse_object(text: &str, uid: &str) -> Result<ImportTodo, String> {
+    let components = ical::parse(text)?;
+    let vtodo = components
+        .iter()
+        .filter(|c| c.name == "VCALENDAR")
+        .flat_map(|c| &c.components)
+        .find(|c| c.name == "VTODO" && c.get("RECURRENCE-ID").is_none())
+        .ok_or("no VTODO found")?;
+    if vtodo.get("UID").map(ical::Property::text_value).as_deref() != Some(uid) {
+        return Err(format!("the VTODO's UID must be {uid}"));
+    }
+    let mut todo = ical::import_todo(vtodo)?;
+    if todo.title.trim().is_empty() {
+        return Err("title must not be empty".to_string());
+    }
+    todo.rrule = todo
+        .rrule
+        .as_deref()
+        .filter(|rule| !rule.trim().is_empty())
+        .map(|rule| RRule::from_str(rule).map(|rule| rule.to_string()))
+        .transpose()?;
+    Ok(todo)
+}
+
+/// Create or replace a todo from a calendar object. Completing a
+/// recurring todo this way does not schedule its next occurrence, as
+/// CalDAV clients do that themselves.
+async fn put_object(
+    state: &AppState,
+    origin: &Origin,
+    user: &User,
+    list_id: ListId,
+    uid: &str,
+    headers: &HeaderMap,
+    body: &Bytes,
+) -> Result<Response, (StatusCode, String)> {
+    let list = super::list::owned_list(state, user.id, list_id).await?;
+    let fields = parse_object(text(body)?, uid).map_err(bad_request)?;
+    let existing = state
+        .caldav
+        .find_by_uid(list.id, uid)
+        .await
+        .map_err(internal_error)?;
+    let etag = existing
+        .as_ref()
+        .map(|todo| Object::new(todo.clone(), uid.to_string()).etag);
+    check_preconditions(headers, etag.as_deref())?;
+
+    let (status, event, change, todo) = match existing {
+        Some(before) => {
+            let event = if fields.completed && !before.completed {
+                Event::TodoCompleted
+            } else {
+                Event::TodoUpdated
+            };
+            let todo = Todo {
+                title: fields.title,
+                notes: fields.notes,
+                completed: fields.completed,
+                due_at: fields.due_at,
+                rrule: fields.rrule,
+                ..before.clone()
+            };
+            let todo = state
+                .todos
+                .update(&todo)
+                .await
+                .map_err(internal_error)?
+                .ok_or_else(changed_error)?;
+            let change = Change::updated(Entity::Todo, todo.id.0, &before, &todo);
+            (StatusCode::NO_CONTENT, event, change, todo)
+        }
+        None => {
+            let now = Utc::now();
+            let todo = Todo {
+                id: TodoId(Uuid::new_v4()),
+                user_id: user.id,
+                list_id: list.id,
+                parent_id: None,
+                title: fields.title,
+                notes: fields.notes,
+                completed: fields.completed,
+                due_at: fields.due_at,
+                rrule: fields.rrule,
+                repeat_from_completion: false,
+                created_at: now,
+                updated_at: now,
+                version: FIRST_VERSION,
+                deleted_at: None,
+            };
+            let failed = state
+                .todos
+                .insert_many(std::slice::from_ref(&todo), false)
+                .await
+                .map_err(internal_error)?;
+            if let Some((_, error)) = failed.into_iter().next() {
+                return Err(bad_request(error));
+            }
+            if uid != todo.id.0.to_string() {
+                state
+                    .caldav
+                    .set_uid(todo.id, uid)
+                    .await
+                    .map_err(internal_error)?;
+            }
+            let change = Change::created(Entity::Todo, todo.id.0, &todo);
+            (StatusCode::CREATED, Event::TodoCreated, change, todo)
+        }
+    };
+    audit::record(state, origin, [change]).await;
+    events::publish(state, user.id, event, &todo).await;
+    let object = Object::new(todo, uid.to_string());
+    Ok((status, [(header::ETAG, object.etag)]).into_response())
+}
+
+/// Trash a todo (and its subtasks) by its calendar object.
+async fn delete_object(
+    state: &AppState,
+    origin: &Origin,
+    user: &User,
+    list_id: ListId,
+    uid: &str,
+    headers: &HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let object = find_object(state, user, list_id, uid)
+        .await?
+        .ok_or_else(|| not_found_error("No such calendar object"))?;
+    check_preconditions(headers, Some(&object.etag))?;
+    let deleted = state
+        .todos
+        .delete(object.todo.id, Some(object.todo.version))
+        .await
+        .map_err(internal_error)?;
+    if !deleted {
+        return Err(changed_error());
+    }
+    let change = Change::deleted(Entity::Todo, object.todo.id.0, &object.todo);
+    audit::record(state, origin, [change]).await;
+    events::publish(state, user.id, Event::TodoDeleted, &object.todo).await;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, StatusCode};
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use serde_json::{json, Value};
+
+    use crate::{
+        db::Database,
+        routes::{call, send, test_app_with, TEST_USER},
+    };
+
+    fn vtodo(uid: &str, extra: &str) -> String {
+        format!(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Example//EN\r\n\
+             BEGIN:VTODO\r\nUID:{uid}\r\nSUMMARY:Call Bob\r\n{extra}END:VTODO\r\n\
+             END:VCALENDAR\r\n"
+        )
+    }
+
+    fn sync_token(xml: &str) -> String {
+        let start = xml.find("<D:sync-token>").unwrap() + "<D:sync-token>".len();
+        let end = xml.find("</D:sync-token>").unwrap();
+        xml[start..end].to_string()
+    }
+
+    crate::db::backend_test!(caldav_serves_lists_as_calendars);
+
+    async fn caldav_serves_lists_as_calendars(db: &Database) {
+        let app = &test_app_with(db);
+        let user = json!({"email": "a@example.com", "display_name": "A"});
+        call(app, "POST", "/user", user).await;
+        let work = call(app, "POST", "/list", json!({"name": "Work"})).await.1;
+        let work = work["id"].as_str().unwrap().to_string();
+        let lists = call(app, "GET", "/list", Value::Null).await.1;
+        let inbox = lists
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|l| l["inbox"] == true)
+            .unwrap()["id"]
+            .clone();
+        let rows = json!([{"title": "Write report"}]);
+        call(app, "POST", &format!("/import?list_id={work}"), rows).await;
+        let todos = call(app, "GET", &format!("/todo?list_id={work}"), Value::Null)
+            .await
+            .1;
+        let report = todos[0]["id"].as_str().unwrap().to_string();
+
+        // Clients sign in with an app password:
+        let created = call(app, "POST", "/app-password", json!({"name": "Phone"}))
+            .await
+            .1;
+        let password = created["password"].as_str().unwrap();
+        let listed = call(app, "GET", "/app-password", Value::Null).await.1;
+        assert_eq!(listed[0]["name"], "Phone");
+        assert!(listed[0].get("password").is_none());
+        let wrong = format!("Basic {}", BASE64.encode("a@example.com:nope"));
+        let (status, headers, _) =
+            send(app, "PROPFIND", "/dav/", &[("authorization", &wrong)], "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(headers.contains_key(header::WWW_AUTHENTICATE));
+        // (The scheme is case-insensitive.)
+        let auth = format!(
+            "basic {}",
+            BASE64.encode(format!("a@example.com:{password}"))
+        );
+        let auth = ("authorization", auth.as_str());
+
+        let (status, headers, _) = send(app, "GET", "/.well-known/caldav", &[], "").await;
+        assert_eq!(status, StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(headers[header::LOCATION], "/dav/");
+        let props = r#"<d:propfind xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+            <d:prop><d:current-user-principal/><c:calendar-home-set/></d:prop>
+            </d:propfind>"#;
+        let (status, _, xml) = send(
+            app,
+            "PROPFIND",
+            "/dav/principal/",
+            &[auth, ("depth", "0")],
+            props,
+        )
+        .await;
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        assert!(xml.contains("<C:calendar-home-set><D:href>/dav/calendars/</D:href>"));
+
+        let (_, _, xml) = send(app, "PROPFIND", "/dav/calendars/", &[auth], "").await;
+        let calendar = format!("/dav/calendars/{work}/");
+        assert!(
+            xml.contains(&format!("<D:href>{calendar}</D:href>")),
+            "{xml}"
+        );
+        assert!(xml.contains("<D:displayname>Work</D:displayname>"));
+        assert!(xml.contains("<D:collection/><C:calendar/>"));
+        let (_, _, xml) = send(app, "PROPFIND", &calendar, &[auth], "").await;
+        let report_href = format!("{calendar}{report}.ics");
+        assert!(xml.contains(&format!("<D:href>{report_href}</D:href>")));
+        let token = sync_token(&xml);
+
+        // Objects keep the UIDs that clients give them:
+        let bob = format!("{calendar}call%20bob@1.ics");
+        let new = [auth, ("if-none-match", "*")];
+        let (status, headers, _) = send(app, "PUT", &bob, &new, &vtodo("call bob@1", "")).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let etag = headers[header::ETAG].to_str().unwrap().to_string();
+        let (status, _, _) = send(app, "PUT", &bob, &new, &vtodo("call bob@1", "")).await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        let (status, _, _) = send(app, "PUT", &bob, &[auth], &vtodo("other", "")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, headers, ics) = send(app, "GET", &bob, &[auth], "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::ETAG], etag.as_str());
+        assert!(ics.contains("UID:call bob@1\r\n"));
+        let todos = call(app, "GET", &format!("/todo?list_id={work}"), Value::Null)
+            .await
+            .1;
+        assert_eq!(todos.as_array().unwrap().len(), 2);
+
+        let done = vtodo("call bob@1", "STATUS:COMPLETED\r\n");
+        let stale = [auth, ("if-match", "\"stale\"")];
+        let (status, _, _) = send(app, "PUT", &bob, &stale, &done).await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        let (status, headers, _) =
+            send(app, "PUT", &bob, &[auth, ("if-match", &etag)], &done).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let etag = headers[header::ETAG].to_str().unwrap().to_string();
+        let completed = call(app, "GET", "/todo?completed=true", Value::Null)
+            .await
+            .1;
+        assert_eq!(completed[0]["title"], "Call Bob");
+
+        let query = r#"<c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+            <d:prop><d:getetag/></d:prop>
+            <c:filter><c:comp-filter name="VCALENDAR"><c:comp-filter name="VTODO">
+              <c:prop-filter name="COMPLETED"><c:is-not-defined/></c:prop-filter>
+            </c:comp-filter></c:comp-filter></c:filter>
+            </c:calendar-query>"#;
+        let (status, _, xml) = send(app, "REPORT", &calendar, &[auth], query).await;
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        assert!(xml.contains(&report_href) && !xml.contains(&bob));
+        let multiget = format!(
+            r#"<c:calendar-multiget xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+            <d:prop><d:getetag/><c:calendar-data/></d:prop>
+            <d:href>{bob}</d:href><d:href>{calendar}missing.ics</d:href>
+            </c:calendar-multiget>"#
+        );
+        let (_, _, xml) = send(app, "REPORT", &calendar, &[auth], &multiget).await;
+        assert!(xml.contains("SUMMARY:Call Bob"));
+        assert!(xml.contains("missing.ics</D:href><D:status>HTTP/1.1 404 Not Found</D:status>"));
+
+        // Moving a todo out of the list and deleting one both show up as
+        // removals since the earlier sync token:
+        call(
+            app,
+            "PUT",
+            &format!("/todo/{report}/list"),
+            json!({"list_id": inbox}),
+        )
+        .await;
+        let (status, _, _) = send(app, "DELETE", &bob, &stale, "").await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        let (status, _, _) = send(app, "DELETE", &bob, &[auth, ("if-match", &etag)], "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _, _) = send(app, "GET", &bob, &[auth], "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let sync = |token: &str| {
+            format!(
+                r#"<d:sync-collection xmlns:d="DAV:"><d:sync-token>{token}</d:sync-token>
+                <d:prop><d:getetag/></d:prop></d:sync-collection>"#
+            )
+        };
+        let (status, _, xml) = send(app, "REPORT", &calendar, &[auth], &sync(&token)).await;
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        for removed in [&report_href, &bob] {
+            let status = format!("{removed}</D:href><D:status>HTTP/1.1 404 Not Found");
+            assert!(xml.contains(&status), "{xml}");
+        }
+        let (status, _, xml) = send(app, "REPORT", &calendar, &[auth], &sync("data:,1")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(xml.contains("<D:valid-sync-token/>"));
+
+        let id = listed[0]["id"].as_str().unwrap();
+        let (status, _, _) = send(
+            app,
+            "DELETE",
+            &format!("/app-password/{id}"),
+            &[TEST_USER],
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _, _) = send(app, "PROPFIND", "/dav/", &[auth], "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,332 @@
+use std::fmt::Write;
+
+use axum::http::StatusCode;
+use roxmltree::{Document, Node};
+
+pub const DAV: &str = "DAV:";
+pub const CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+pub const CALENDARSERVER: &str = "http://calendarserver.org/ns/";
+pub const APPLE: &str = "http://apple.com/ns/ical/";
+
+/// The prefixes responses use for these namespaces.
+const PREFIXES: &[(&str, &str)] = &[
+    (DAV, "D"),
+    (CALDAV, "C"),
+    (CALENDARSERVER, "CS"),
+    (APPLE, "A"),
+];
+
+/// A property name: its namespace and local name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PropName {
+    pub ns: String,
+    pub local: String,
+}
+
+impl PropName {
+    fn of(node: Node) -> Self {
+        PropName {
+            ns: node.tag_name().namespace().unwrap_or_default().to_string(),
+            local: node.tag_name().name().to_string(),
+        }
+    }
+
+    /// An empty element of this name.
+    fn empty_element(&self) -> String {
+        match prefix(&self.ns) {
+            Some(prefix) => format!("<{prefix}:{}/>", self.local),
+            None => format!("<X:{} xmlns:X=\"{}\"/>", self.local, escape(&self.ns)),
+        }
+    }
+
+    fn element(&self, value: &str) -> String {
+        match prefix(&self.ns) {
+            Some(prefix) => format!("<{prefix}:{0}>{value}</{prefix}:{0}>", self.local),
+            None => format!(
+                "<X:{0} xmlns:X=\"{1}\">{value}</X:{0}>",
+                self.local,
+                escape(&self.ns)
+            ),
+        }
+    }
+}
+
+fn prefix(ns: &str) -> Option<&'static str> {
+    PREFIXES.iter().find(|(n, _)| *n == ns).map(|(_, p)| *p)
+}
+
+/// Escape text for XML content or attribute values.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `<D:href>` of a path.
+pub fn href(path: &str) -> String {
+    format!("<D:href>{}</D:href>", escape(path))
+}
+
+/// Which properties a request asks for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PropRequest {
+    /// `allprop` (or `propname`, which is answered the same way).
+    All,
+    Props(Vec<PropName>),
+}
+
+/// A `REPORT` request.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Report {
+    /// The objects of a calendar that match a filter: whether it wants
+    /// `VTODO`s at all, and whether only pending ones.
+    CalendarQuery {
+        props: PropRequest,
+        todos: bool,
+        pending_only: bool,
+    },
+    /// The objects at these hrefs.
+    CalendarMultiget {
+        props: PropRequest,
+        hrefs: Vec<String>,
+    },
+    /// What changed since a sync token (empty for everything).
+    SyncCollection {
+        props: PropRequest,
+        sync_token: String,
+    },
+}
+
+fn parse(body: &str) -> Result<Document<'_>, String> {
+    Document::parse(body).map_err(|e| format!("invalid XML: {e}"))
+}
+
+fn is(node: &Node, ns: &str, name: &str) -> bool {
+    node.is_element() && node.tag_name().namespace() == Some(ns) && node.tag_name().name() == name
+}
+
+fn child<'a, 'input>(node: &Node<'a, 'input>, ns: &str, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|c| is(c, ns, name))
+}
+
+/// The properties asked for by the `prop`, `allprop` or `propname`
+/// child of `node`. No such child means all properties.
+fn prop_request(node: &Node) -> PropRequest {
+    match child(node, DAV, "prop") {
+        Some(prop) => PropRequest::Props(
+            prop.children()
+                .filter(|c| c.is_element())
+                .map(PropName::of)
+                .collect(),
+        ),
+        None => PropRequest::All,
+    }
+}
+
+/// Parse a `PROPFIND` body, where an empty body asks for all properties.
+pub fn parse_propfind(body: &str) -> Result<PropRequest, String> {
+    if body.trim().is_empty() {
+        return Ok(PropRequest::All);
+    }
+    let doc = parse(body)?;
+    let root = doc.root_element();
+    if !is(&root, DAV, "propfind") {
+        return Err("expected a DAV:propfind element".to_string());
+    }
+    Ok(prop_request(&root))
+}
+
+pub fn parse_report(body: &str) -> Result<Report, String> {
+    let doc = parse(body)?;
+    let root = doc.root_element();
+    let props = prop_request(&root);
+    if is(&root, CALDAV, "calendar-query") {
+        // Only the component filters (and a filter for todos that are not
+        // completed) are applied; other filters return a superset.
+        let vcalendar = child(&root, CALDAV, "filter")
+            .and_then(|f| child(&f, CALDAV, "comp-filter"))
+            .filter(|c| c.attribute("name") == Some("VCALENDAR"));
+        let components: Vec<Node> = vcalendar
+            .iter()
+            .flat_map(|c| c.children())
+            .filter(|c| is(c, CALDAV, "comp-filter"))
+            .collect();
+        let vtodo = components
+            .iter()
+            .find(|c| c.attribute("name") == Some("VTODO"));
+        let pending_only = vtodo.is_some_and(|vtodo| {
+            vtodo.children().any(|f| {
+                is(&f, CALDAV, "prop-filter")
+                    && f.attribute("name") == Some("COMPLETED")
+                    && child(&f, CALDAV, "is-not-defined").is_some()
+            })
+        });
+        Ok(Report::CalendarQuery {
+            props,
+            todos: components.is_empty() || vtodo.is_some(),
+            pending_only,
+        })
+    } else if is(&root, CALDAV, "calendar-multiget") {
+        let hrefs = root
+            .children()
+            .filter(|c| is(c, DAV, "href"))
+            .map(|c| c.text().unwrap_or_default().trim().to_string())
+            .collect();
+        Ok(Report::CalendarMultiget { props, hrefs })
+    } else if is(&root, DAV, "sync-collection") {
+        let sync_token = child(&root, DAV, "sync-token")
+            .and_then(|t| t.text())
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        Ok(Report::SyncCollection { props, sync_token })
+    } else {
+        Err(format!("unsupported report {}", root.tag_name().name()))
+    }
+}
+
+/// A `207 Multi-Status` response body.
+#[derive(Debug, Default)]
+pub struct Multistatus {
+    responses: String,
+}
+
+impl Multistatus {
+    /// The found (with their values, as XML) and missing properties of an
+    /// href.
+    pub fn response(&mut self, path: &str, found: &[(PropName, String)], missing: &[PropName]) {
+        self.responses.push_str("<D:response>");
+        self.responses.push_str(&href(path));
+        if !found.is_empty() {
+            self.responses.push_str("<D:propstat><D:prop>");
+            for (name, value) in found {
+                if value.is_empty() {
+                    self.responses.push_str(&name.empty_element());
+                } else {
+                    self.responses.push_str(&name.element(value));
+                }
+            }
+            self.responses
+                .push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat>");
+        }
+        if !missing.is_empty() {
+            self.responses.push_str("<D:propstat><D:prop>");
+            for name in missing {
+                self.responses.push_str(&name.empty_element());
+            }
+            self.responses
+                .push_str("</D:prop><D:status>HTTP/1.1 404 Not Found</D:status></D:propstat>");
+        }
+        self.responses.push_str("</D:response>");
+    }
+
+    /// A response with just a status, e.g. for a missing or removed href.
+    pub fn status(&mut self, path: &str, status: StatusCode) {
+        let _ = write!(
+            self.responses,
+            "<D:response>{}<D:status>HTTP/1.1 {status}</D:status></D:response>",
+            href(path)
+        );
+    }
+
+    /// The document, with a sync token for `sync-collection` reports.
+    pub fn finish(self, sync_token: Option<&str>) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus");
+        for (ns, prefix) in PREFIXES {
+            let _ = write!(xml, " xmlns:{prefix}=\"{ns}\"");
+        }
+        xml.push('>');
+        xml.push_str(&self.responses);
+        if let Some(token) = sync_token {
+            let _ = write!(xml, "<D:sync-token>{}</D:sync-token>", escape(token));
+        }
+        xml.push_str("</D:multistatus>\n");
+        xml
+    }
+}
+
+/// A `DAV:error` body with a failed precondition, e.g. `valid-sync-token`.
+pub fn error(ns: &str, precondition: &str) -> String {
+    let name = PropName {
+        ns: ns.to_string(),
+        local: precondition.to_string(),
+    };
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <D:error xmlns:D=\"DAV:\" xmlns:C=\"{CALDAV}\">{}</D:error>\n",
+        name.empty_element()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_requests() {
+        let propfind = r#"<?xml version="1.0"?>
+            <d:propfind xmlns:d="DAV:" xmlns:x="http://example.com/">
+              <d:prop><d:displayname/><x:custom/></d:prop>
+            </d:propfind>"#;
+        let PropRequest::Props(props) = parse_propfind(propfind).unwrap() else {
+            panic!("expected props");
+        };
+        assert_eq!(props[0].local, "displayname");
+        assert_eq!(props[1].ns, "http://example.com/");
+        assert_eq!(parse_propfind("").unwrap(), PropRequest::All);
+
+        let query = r#"<c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+              <d:prop><d:getetag/></d:prop>
+              <c:filter><c:comp-filter name="VCALENDAR"><c:comp-filter name="VTODO">
+                <c:prop-filter name="COMPLETED"><c:is-not-defined/></c:prop-filter>
+              </c:comp-filter></c:comp-filter></c:filter>
+            </c:calendar-query>"#;
+        let Report::CalendarQuery {
+            todos,
+            pending_only,
+            ..
+        } = parse_report(query).unwrap()
+        else {
+            panic!("expected a calendar query");
+        };
+        assert!(todos && pending_only);
+        let events = query.replace("VTODO", "VEVENT");
+        assert!(matches!(
+            parse_report(&events).unwrap(),
+            Report::CalendarQuery { todos: false, .. }
+        ));
+        assert!(parse_report("<d:foo xmlns:d=\"DAV:\"/>").is_err());
+        assert!(parse_report("<unclosed>").is_err());
+    }
+
+    #[test]
+    fn writes_multistatus() {
+        let mut ms = Multistatus::default();
+        let name = |ns: &str, local: &str| PropName {
+            ns: ns.to_string(),
+            local: local.to_string(),
+        };
+        ms.response(
+            "/dav/a&b/",
+            &[(name(DAV, "displayname"), escape("R&D"))],
+            &[name("http://example.com/", "custom")],
+        );
+        ms.status("/dav/gone.ics", StatusCode::NOT_FOUND);
+        let xml = ms.finish(Some("data:,1"));
+        assert!(xml.contains("<D:href>/dav/a&amp;b/</D:href>"));
+        assert!(xml.contains("<D:displayname>R&amp;D</D:displayname>"));
+        assert!(xml.contains("<X:custom xmlns:X=\"http://example.com/\"/>"));
+        assert!(xml.contains("<D:status>HTTP/1.1 404 Not Found</D:status>"));
+        assert!(xml.contains("<D:sync-token>data:,1</D:sync-token>"));
+        assert!(Document::parse(&xml).is_ok());
+    }
+}
@@ -0,0 +1,226 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    audit::{self, Change},
+    errors::{internal_error, not_found_error},
+    extract::{Authorize, Origin},
+    ical,
+    models::{audit::Entity, ids::ListId},
+    repository::TodoFilter,
+    token, AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    // this router is responsible for everything under `/calendar`
+    Router::<AppState>::new().route("/token", post(create_token).delete(delete_token))
+}
+
+/// A new calendar feed token, which is only ever shown once.
+#[derive(Debug, Serialize)]
+struct FeedToken {
+    token: String,
+    /// The feed's path, to append to this server's address.
+    path: String,
+}
+
+/// Create a secret token for the caller's iCalendar feed, replacing
+/// (and so revoking) any previous one.
+async fn create_token(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+) -> Result<(StatusCode, Json<FeedToken>), (StatusCode, String)> {
+    let token = token::generate();
+    state
+        .users
+        .set_calendar_token(user.id, &token::digest(&token))
+        .await
+        .map_err(internal_error)?;
+    let change = Change::created(
+        Entity::CalendarToken,
+        user.id.0,
+        &json!({"user_id": user.id}),
+    );
+    audit::record(&state, &origin, [change]).await;
+    let path = format!("/todo.ics?token={token}");
+    Ok((StatusCode::CREATED, Json(FeedToken { token, path })))
+}
+
+/// Revoke the caller's calendar feed token.
+async fn delete_token(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if state
+        .users
+        .delete_calendar_token(user.id)
+        .await
+        .map_err(internal_error)?
+    {
+        let change = Change::deleted(
+            Entity::CalendarToken,
+            user.id.0,
+            &json!({"user_id": user.id}),
+        );
+        audit::record(&state, &origin, [change]).await;
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(not_found_error("No calendar token"))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct FeedParams {
+    token: String,
+    /// Also emit a `VEVENT` for each due date.
+    #[serde(default)]
+    events: bool,
+    completed: Option<bool>,
+    list_id: Option<ListId>,
+}
+
+/// The iCalendar feed of a user's todos (as `VTODO`s), authenticated by
+/// their secret feed token rather than a header, since calendar apps
+/// can only be given a URL.
+pub(super) async fn feed(
+    State(state): State<AppState>,
+    Query(params): Query<FeedParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let user = state
+        .users
+        .find_by_calendar_token(&token::digest(&params.token))
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| not_found_error("No such calendar feed"))?;
+    let todos = state
+        .todos
+        .list(TodoFilter {
+            user_id: Some(user.id),
+            completed: params.completed,
+            list_id: params.list_id,
+            ..Default::default()
+        })
+        .await
+        .map_err(internal_error)?;
+
+    let mut components: Vec<_> = todos
+        .iter()
+        .map(|t| ical::vtodo(t, &t.id.0.to_string()))
+        .collect();
+    if params.events {
+        components.extend(todos.iter().filter_map(ical::vevent));
+    }
+    Ok((
+        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        ical::calendar(components).to_string(),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::{
+        db::Database,
+        routes::{call, send, test_app_with, TEST_USER},
+    };
+
+    const ICAL: [(&str, &str); 2] = [TEST_USER, ("content-type", "text/calendar")];
+
+    /// Two todos: one due on a date that repeats monthly, and one done.
+    const ICS: &str = "BEGIN:VCALENDAR\r\n\
+        VERSION:2.0\r\n\
+        PRODID:-//Example//EN\r\n\
+        BEGIN:VTODO\r\n\
+        UID:1@example.com\r\n\
+        SUMMARY:Pay rent\\, water\r\n\
+        DESCRIPTION:Transfer to the landlord\\nbefore noon\r\n\
+        DUE;VALUE=DATE:20300201\r\n\
+        RRULE:FREQ=MONTHLY\r\n\
+        END:VTODO\r\n\
+        BEGIN:VTODO\r\n\
+        UID:2@example.com\r\n\
+        SUMMARY:File the taxes for the year\r\n\
+        \x20 before the deadline\r\n\
+        STATUS:COMPLETED\r\n\
+        END:VTODO\r\n\
+        END:VCALENDAR\r\n";
+
+    crate::db::backend_test!(todos_round_trip_through_icalendar);
+
+    async fn todos_round_trip_through_icalendar(db: &Database) {
+        let app = &test_app_with(db);
+        let user = json!({"email": "a@example.com", "display_name": "A"});
+        assert_eq!(
+            call(app, "POST", "/user", user).await.0,
+            StatusCode::CREATED
+        );
+
+        let bad = ICS.replace("20300201", "2030-02-01");
+        let (status, _, report) = send(app, "POST", "/import/ics", &ICAL, &bad).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(report.contains("invalid DUE"), "{report}");
+        let (status, ..) = send(app, "POST", "/import/ics", &ICAL, "nope").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, ..) = send(app, "POST", "/import/ics", &ICAL, ICS).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (_, todos) = call(app, "GET", "/todo", Value::Null).await;
+        let rent = todos
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|t| t["title"] == "Pay rent, water")
+            .unwrap();
+        assert_eq!(rent["notes"], "Transfer to the landlord\nbefore noon");
+        assert_eq!(rent["due_at"], "2030-02-01T00:00:00Z");
+        assert_eq!(rent["rrule"], "FREQ=MONTHLY");
+        let (_, done) = call(app, "GET", "/todo?completed=true", Value::Null).await;
+        assert_eq!(
+            done[0]["title"],
+            "File the taxes for the year before the deadline"
+        );
+
+        let (status, ..) = send(app, "GET", "/todo.ics?token=nope", &[], "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, token) = call(app, "POST", "/calendar/token", Value::Null).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let feed = token["path"].as_str().unwrap().to_string();
+        let (status, headers, ics) = send(app, "GET", &feed, &[], "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            headers[header::CONTENT_TYPE],
+            "text/calendar; charset=utf-8"
+        );
+        assert_eq!(ics.matches("BEGIN:VTODO").count(), 2);
+        assert!(ics.contains("SUMMARY:Pay rent\\, water\r\n"));
+        assert!(ics.contains("DUE:20300201T000000Z\r\n"));
+        assert!(ics.contains("STATUS:COMPLETED\r\n"));
+        assert!(!ics.contains("BEGIN:VEVENT"));
+
+        let uri = format!("{feed}&events=true&completed=false");
+        let (_, _, ics) = send(app, "GET", &uri, &[], "").await;
+        assert_eq!(ics.matches("BEGIN:VTODO").count(), 1);
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 1);
+        assert!(ics.contains("DTSTART:20300201T000000Z\r\n"));
+
+        // A new token revokes the old one:
+        call(app, "POST", "/calendar/token", Value::Null).await;
+        let (status, ..) = send(app, "GET", &feed, &[], "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call(app, "DELETE", "/calendar/token", Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = call(app, "DELETE", "/calendar/token", Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,74 @@
+use axum::{
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+/// The strong ETag of a version of a todo or user.
+fn etag(version: i64) -> String {
+    format!("\"{version}\"")
+}
+
+/// Whether a conditional header lists the ETag of `version` (or is `*`).
+/// Weak tags (`W/"..."`) only match with `weak` comparison, as for
+/// `If-None-Match`.
+fn matches(headers: &HeaderMap, name: HeaderName, version: i64, weak: bool) -> Option<bool> {
+    let value = headers.get(name)?.to_str().ok()?;
+    let etag = etag(version);
+    Some(value.split(',').map(str::trim).any(|tag| {
+        let tag = match tag.strip_prefix("W/") {
+            Some(tag) if weak => tag,
+            _ => tag,
+        };
+        tag == "*" || tag == etag
+    }))
+}
+
+/// Check a write's `If-Match` header, if any, against the record's
+/// current version.
+pub(super) fn check_if_match(
+    headers: &HeaderMap,
+    version: i64,
+    what: &str,
+) -> Result<(), (StatusCode, String)> {
+    match matches(headers, header::IF_MATCH, version, false) {
+        Some(false) => Err(precondition_failed(what)),
+        _ => Ok(()),
+    }
+}
+
+/// The error for a write that lost a race with another one, after its
+/// `If-Match` header (if any) was checked: 412 if the client sent one,
+/// and otherwise 409, as it should just try again.
+pub(super) fn changed_error(headers: &HeaderMap, what: &str) -> (StatusCode, String) {
+    if headers.contains_key(header::IF_MATCH) {
+        precondition_failed(what)
+    } else {
+        (
+            StatusCode::CONFLICT,
+            format!("The {what} was changed by another request"),
+        )
+    }
+}
+
+fn precondition_failed(what: &str) -> (StatusCode, String) {
+    (
+        StatusCode::PRECONDITION_FAILED,
+        format!("The {what} has changed"),
+    )
+}
+
+/// Respond with a record at `version`, and its ETag.
+pub(super) fn with_etag(version: i64, body: impl IntoResponse) -> Response {
+    let etag = HeaderValue::from_str(&etag(version)).expect("a quoted number");
+    ([(header::ETAG, etag)], body).into_response()
+}
+
+/// Respond to a GET like [`with_etag`], or with 304 if the request's
+/// `If-None-Match` header shows that the client already has this version.
+pub(super) fn versioned(headers: &HeaderMap, version: i64, body: impl IntoResponse) -> Response {
+    if matches(headers, header::IF_NONE_MATCH, version, true) == Some(true) {
+        with_etag(version, StatusCode::NOT_MODIFIED)
+    } else {
+        with_etag(version, body)
+    }
+}
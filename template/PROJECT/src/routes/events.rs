@@ -0,0 +1,155 @@
+use std::{convert::Infallible, time::Duration};
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::sse::{self, KeepAlive, Sse},
+};
+use futures_util::{stream, Stream};
+use serde::Deserialize;
+
+use crate::{events::Received, extract::Authorize, AppState};
+
+/// How often an idle stream gets a comment, to keep proxies from closing
+/// it.
+const HEARTBEAT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize)]
+pub(super) struct StreamParams {
+    /// Where to resume from, for clients that cannot send `Last-Event-ID`.
+    last_event_id: Option<u64>,
+}
+
+/// A stream of server-sent events for the caller's todo and list changes
+/// as they happen, each with its id, its event name (e.g. `todo.updated`)
+/// and the todo or list as data.
+///
+/// A `Last-Event-ID` header (or `last_event_id` parameter) resumes after
+/// that event. When that is no longer possible, or the client falls too
+/// far behind, it gets a `reset` event and should reload its data.
+pub(super) async fn stream(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    headers: HeaderMap,
+    Query(params): Query<StreamParams>,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, Infallible>>>, (StatusCode, String)> {
+    let last_id = match headers.get("last-event-id") {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|v| v.trim().parse().ok())
+                .ok_or_else(|| (StatusCode::BAD_REQUEST, "Invalid Last-Event-ID".to_string()))?,
+        ),
+        None => params.last_event_id,
+    };
+    let subscription = state.events.subscribe(user.id, last_id);
+    let events = stream::unfold(subscription, |mut subscription| async move {
+        let event = match subscription.next().await? {
+            Received::Change(change) => sse::Event::default()
+                .id(change.id.to_string())
+                .event(change.event.name())
+                .data(change.data.to_string()),
+            Received::Missed => sse::Event::default().event("reset").data("{}"),
+        };
+        Some((Ok(event), subscription))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::new().interval(HEARTBEAT)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{
+        body::{Body, BodyDataStream},
+        http::{header, Request, StatusCode},
+    };
+    use futures_util::StreamExt;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use crate::routes::{call, test_app};
+
+    /// Open the event stream, resuming after `last_id` if given.
+    async fn open(app: &axum::Router, last_id: Option<&str>) -> BodyDataStream {
+        let mut req = Request::builder()
+            .uri("/events")
+            .header("x-forwarded-user", "a@example.com");
+        if let Some(id) = last_id {
+            req = req.header("last-event-id", id);
+        }
+        let res = app
+            .clone()
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "text/event-stream");
+        res.into_body().into_data_stream()
+    }
+
+    /// The next event's fields, by name.
+    async fn next_event(events: &mut BodyDataStream) -> Vec<(String, String)> {
+        let mut text = String::new();
+        while !text.contains("\n\n") {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), events.next())
+                .await
+                .expect("an event")
+                .unwrap()
+                .unwrap();
+            text.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        text.lines()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.to_string(), value.trim_start().to_string()))
+            .collect()
+    }
+
+    fn field<'a>(event: &'a [(String, String)], name: &str) -> &'a str {
+        &event.iter().find(|(n, _)| n == name).unwrap().1
+    }
+
+    #[tokio::test]
+    async fn events_stream_changes() {
+        let (app, _dir) = test_app().await;
+        let user = json!({"email": "a@example.com", "display_name": "A"});
+        assert_eq!(
+            call(&app, "POST", "/user", user).await.0,
+            StatusCode::CREATED
+        );
+        let mut events = open(&app, None).await;
+
+        let (status, _) = call(&app, "POST", "/import", json!([{"title": "mow"}])).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let created = next_event(&mut events).await;
+        assert_eq!(field(&created, "event"), "todo.created");
+        let todo: Value = serde_json::from_str(field(&created, "data")).unwrap();
+        assert_eq!(todo["title"], "mow");
+
+        let uri = format!("/todo/{}/completed", todo["id"].as_str().unwrap());
+        call(&app, "PUT", &uri, json!({"completed": true})).await;
+        let completed = next_event(&mut events).await;
+        assert_eq!(field(&completed, "event"), "todo.completed");
+        let (status, _) = call(&app, "POST", "/list", json!({"name": "Garden"})).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let list = next_event(&mut events).await;
+        assert_eq!(field(&list, "event"), "list.created");
+
+        // Resuming replays what came after the last event seen:
+        let mut resumed = open(&app, Some(field(&created, "id"))).await;
+        assert_eq!(next_event(&mut resumed).await, completed);
+        assert_eq!(next_event(&mut resumed).await, list);
+        let mut reset = open(&app, Some("1000000")).await;
+        assert_eq!(field(&next_event(&mut reset).await, "event"), "reset");
+
+        let req = Request::builder()
+            .uri("/events")
+            .header("x-forwarded-user", "a@example.com")
+            .header("last-event-id", "latest")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,80 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use futures_util::TryStreamExt;
+use serde::Deserialize;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::{
+    extract::Authorize,
+    prelude::*,
+    repository::TodoFilter,
+    transfer::{Encoder, Format},
+    AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    // this router is responsible for everything under `/export`
+    Router::<AppState>::new().route("/", get(export))
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportParams {
+    #[serde(default)]
+    format: Format,
+    /// Only todos with the tag of this name.
+    tag: Option<String>,
+}
+
+/// Stream all of the caller's todos (optionally only those with a tag)
+/// as JSON, CSV or NDJSON.
+async fn export(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    Query(params): Query<ExportParams>,
+) -> Response {
+    let format = params.format;
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes, io::Error>>(16);
+
+    tokio::spawn(async move {
+        let mut encoder = Encoder::new(format);
+        let mut rows = state.todos.stream(TodoFilter {
+            user_id: Some(user.id),
+            tag: params.tag,
+            ..Default::default()
+        });
+
+        loop {
+            let chunk = match rows.try_next().await {
+                Ok(Some(todo)) => encoder.record(&todo).map_err(io::Error::other),
+                Ok(None) => break,
+                Err(e) => Err(io::Error::other(e)),
+            };
+            let failed = chunk.is_err();
+            if tx.send(chunk.map(Bytes::from)).await.is_err() || failed {
+                if failed {
+                    error!("todo export for {} failed", user.email);
+                }
+                return;
+            }
+        }
+        let _ = tx.send(Ok(Bytes::from(encoder.finish()))).await;
+    });
+
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"todos.{}\"", format.extension()),
+            ),
+        ],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+        .into_response()
+}
@@ -0,0 +1,253 @@
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Change},
+    errors::internal_error,
+    events,
+    extract::{Authorize, Origin},
+    ical,
+    models::{
+        audit::Entity,
+        ids::{ListId, TodoId},
+        todo::{ImportTodo, Todo},
+        user::User,
+        webhook::Event,
+        FIRST_VERSION,
+    },
+    prelude::*,
+    recurrence::RRule,
+    transfer::{decode, Format, ImportReport, RowError},
+    AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    // this router is responsible for everything under `/import`
+    Router::<AppState>::new()
+        .route("/", post(import))
+        .route("/ics", post(import_ics))
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportParams {
+    format: Option<Format>,
+    #[serde(default)]
+    dry_run: bool,
+    /// The caller's list to import into (default: the parent's list, or
+    /// else their inbox).
+    list_id: Option<ListId>,
+    /// Import the todos as subtasks of this todo of the caller's.
+    parent_id: Option<TodoId>,
+}
+
+/// Import todos for the caller from JSON, CSV or NDJSON.
+async fn import(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Query(params): Query<ImportParams>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<ImportReport>), (StatusCode, String)> {
+    let format = params
+        .format
+        .or_else(|| {
+            headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(Format::from_content_type)
+        })
+        .unwrap_or_default();
+    let rows = decode::<ImportTodo>(format, &body).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    import_rows(&state, &origin, &user, &params, rows).await
+}
+
+/// Import the `VTODO`s of an iCalendar file for the caller, one row per
+/// `VTODO`.
+async fn import_ics(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Query(params): Query<ImportParams>,
+    body: Bytes,
+) -> Result<(StatusCode, Json<ImportReport>), (StatusCode, String)> {
+    let rows = std::str::from_utf8(&body)
+        .map_err(|e| e.to_string())
+        .and_then(ical::import_todos)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    import_rows(&state, &origin, &user, &params, rows).await
+}
+
+/// Validate every row and insert them inside one transaction, which is
+/// only committed if no row failed and this is not a dry run.
+async fn import_rows(
+    state: &AppState,
+    origin: &Origin,
+    user: &User,
+    params: &ImportParams,
+    rows: Vec<Result<ImportTodo, String>>,
+) -> Result<(StatusCode, Json<ImportReport>), (StatusCode, String)> {
+    let parent = match params.parent_id {
+        Some(id) => Some(super::todo::check_parent(state, user.id, id, None, 1).await?),
+        None => None,
+    };
+    let list_id = match (params.list_id, &parent) {
+        (Some(id), _) => super::list::owned_list(state, user.id, id).await?.id,
+        (None, Some(parent)) => parent.list_id,
+        (None, None) => super::list::inbox(state, user.id).await?.id,
+    };
+
+    let mut errors = Vec::new();
+    let mut todos = Vec::new();
+    // The row number of each entry in `todos`:
+    let mut todo_rows = Vec::new();
+    let now = Utc::now();
+
+    for (i, row) in rows.iter().enumerate() {
+        match row {
+            Ok(todo) if todo.title.trim().is_empty() => errors.push(RowError {
+                row: i + 1,
+                error: "title must not be empty".to_string(),
+            }),
+            Ok(todo) => {
+                let rrule = match todo.rrule.as_deref().filter(|r| !r.trim().is_empty()) {
+                    Some(rule) => match RRule::from_str(rule) {
+                        Ok(rule) => Some(rule.to_string()),
+                        Err(error) => {
+                            errors.push(RowError { row: i + 1, error });
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+                todos.push(Todo {
+                    id: TodoId(Uuid::new_v4()),
+                    user_id: user.id,
+                    list_id,
+                    parent_id: params.parent_id,
+                    title: todo.title.clone(),
+                    notes: todo.notes.clone(),
+                    completed: todo.completed,
+                    due_at: todo.due_at,
+                    rrule,
+                    repeat_from_completion: todo.repeat_from_completion,
+                    created_at: now,
+                    updated_at: now,
+                    version: FIRST_VERSION,
+                    deleted_at: None,
+                });
+                todo_rows.push(i + 1);
+            }
+            Err(e) => errors.push(RowError {
+                row: i + 1,
+                error: e.clone(),
+            }),
+        }
+    }
+
+    let dry_run = params.dry_run || !errors.is_empty();
+    let failed = state
+        .todos
+        .insert_many(&todos, dry_run)
+        .await
+        .map_err(internal_error)?;
+    errors.extend(failed.into_iter().map(|(i, error)| RowError {
+        row: todo_rows[i],
+        error,
+    }));
+    errors.sort_by_key(|e| e.row);
+    let commit = errors.is_empty() && !params.dry_run;
+    if commit {
+        let changes = todos
+            .iter()
+            .map(|todo| Change::created(Entity::Todo, todo.id.0, todo));
+        audit::record(state, origin, changes).await;
+        for todo in &todos {
+            events::publish(state, user.id, Event::TodoCreated, todo).await;
+        }
+    }
+
+    let status = if !errors.is_empty() {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else if commit {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+
+    Ok((
+        status,
+        Json(ImportReport {
+            dry_run: params.dry_run,
+            rows: rows.len(),
+            imported: if commit { rows.len() } else { 0 },
+            errors,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use crate::{
+        db::Database,
+        routes::{call, send, test_app_with, TEST_USER},
+    };
+
+    crate::db::backend_test!(import_then_export);
+
+    async fn import_then_export(db: &Database) {
+        let app = &test_app_with(db);
+        let user = json!({"email": "a@example.com", "display_name": "A"});
+        assert_eq!(
+            call(app, "POST", "/user", user).await.0,
+            StatusCode::CREATED
+        );
+
+        let csv = "title,notes,completed,due_at\nfirst,,false,\nsecond,some notes,true,2030-01-01T00:00:00Z\n,,false,\n";
+        let (status, _, body) = send(app, "POST", "/import?format=csv", &[TEST_USER], csv).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{body}");
+        assert!(body.contains(r#""row":3"#), "{body}");
+
+        let csv = "title,notes,completed,due_at\nfirst,,false,\nsecond,some notes,true,2030-01-01T00:00:00Z\n";
+        let (status, ..) = send(
+            app,
+            "POST",
+            "/import?format=csv&dry_run=true",
+            &[TEST_USER],
+            csv,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, ..) = send(app, "POST", "/import?format=csv", &[TEST_USER], csv).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, _, body) = send(app, "GET", "/export?format=ndjson", &[TEST_USER], "").await;
+        assert_eq!(status, StatusCode::OK);
+        let lines: Vec<serde_json::Value> = body
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["title"], "second");
+        assert_eq!(lines[1]["completed"], true);
+        assert_eq!(lines[1]["due_at"], "2030-01-01T00:00:00Z");
+    }
+
+    #[tokio::test]
+    async fn export_requires_registered_user() {
+        let app = test_app_with(&Database::memory(Default::default()));
+        let (status, ..) = send(&app, "GET", "/export", &[TEST_USER], "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,303 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+
+use crate::{
+    audit::{self, Change},
+    errors::{internal_error, not_found_error},
+    events,
+    extract::{Authorize, Origin},
+    models::{
+        audit::Entity,
+        ids::{ListId, UserId},
+        list::{normalize_color, normalize_list_name, CreateList, TodoList, UpdateList},
+        webhook::Event,
+    },
+    AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    // this router is responsible for everything under `/list`
+    Router::<AppState>::new()
+        .route("/", get(list_lists).post(create_list))
+        .route(
+            "/{list_id}",
+            get(get_list).patch(update_list).delete(delete_list),
+        )
+}
+
+/// The caller's list `id`, or 404 if it belongs to someone else.
+pub(super) async fn owned_list(
+    state: &AppState,
+    user_id: UserId,
+    id: ListId,
+) -> Result<TodoList, (StatusCode, String)> {
+    match state.lists.get(id).await.map_err(internal_error)? {
+        Some(list) if list.user_id == user_id => Ok(list),
+        _ => Err(not_found_error(format!("No list {}", id.0))),
+    }
+}
+
+/// The caller's inbox list.
+pub(super) async fn inbox(
+    state: &AppState,
+    user_id: UserId,
+) -> Result<TodoList, (StatusCode, String)> {
+    state
+        .lists
+        .inbox(user_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| internal_error("user has no inbox list"))
+}
+
+fn bad_request(e: String) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, e)
+}
+
+#[derive(Debug, Deserialize)]
+struct ListParams {
+    archived: Option<bool>,
+}
+
+/// The caller's lists ordered by position.
+async fn list_lists(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    Query(params): Query<ListParams>,
+) -> Result<Json<Vec<TodoList>>, (StatusCode, String)> {
+    let lists = state
+        .lists
+        .list(user.id, params.archived)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(lists))
+}
+
+/// Create a list after the caller's other lists.
+async fn create_list(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Json(payload): Json<CreateList>,
+) -> Result<(StatusCode, Json<TodoList>), (StatusCode, String)> {
+    let name = normalize_list_name(&payload.name).map_err(bad_request)?;
+    let color = match payload.color.as_deref() {
+        Some(color) => normalize_color(color).map_err(bad_request)?,
+        None => None,
+    };
+    let position = state
+        .lists
+        .list(user.id, None)
+        .await
+        .map_err(internal_error)?
+        .iter()
+        .map(|l| l.position + 1)
+        .max()
+        .unwrap_or(0);
+    let list = state
+        .lists
+        .insert(&TodoList::new(user.id, &name, color, position))
+        .await
+        .map_err(internal_error)?;
+    audit::record(
+        &state,
+        &origin,
+        [Change::created(Entity::List, list.id.0, &list)],
+    )
+    .await;
+    events::publish(&state, user.id, Event::ListCreated, &list).await;
+    Ok((StatusCode::CREATED, Json(list)))
+}
+
+async fn get_list(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    Path(id): Path<ListId>,
+) -> Result<Json<TodoList>, (StatusCode, String)> {
+    owned_list(&state, user.id, id).await.map(Json)
+}
+
+/// Rename, recolor, (un)archive or reorder a list. The inbox cannot be
+/// archived.
+async fn update_list(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<ListId>,
+    Json(payload): Json<UpdateList>,
+) -> Result<Json<TodoList>, (StatusCode, String)> {
+    let before = owned_list(&state, user.id, id).await?;
+    let mut list = before.clone();
+    if let Some(name) = payload.name {
+        list.name = normalize_list_name(&name).map_err(bad_request)?;
+    }
+    if let Some(color) = payload.color {
+        list.color = normalize_color(&color).map_err(bad_request)?;
+    }
+    if let Some(archived) = payload.archived {
+        if archived && list.inbox {
+            return Err((
+                StatusCode::CONFLICT,
+                "The inbox cannot be archived".to_string(),
+            ));
+        }
+        list.archived = archived;
+    }
+    if let Some(position) = payload.position {
+        list.position = position;
+    }
+    let list = state
+        .lists
+        .update(&list)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| not_found_error(format!("No list {}", id.0)))?;
+    let change = Change::updated(Entity::List, id.0, &before, &list);
+    audit::record(&state, &origin, [change]).await;
+    events::publish(&state, user.id, Event::ListUpdated, &list).await;
+    Ok(Json(list))
+}
+
+/// What happens to a deleted list's todos.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OnDelete {
+    /// Move them to the inbox.
+    #[default]
+    Move,
+    Delete,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteParams {
+    #[serde(default)]
+    todos: OnDelete,
+}
+
+/// Move a list to the trash, moving its todos to the inbox
+/// (`?todos=move`, the default) or trashing them too (`?todos=delete`).
+/// The inbox itself cannot be deleted.
+async fn delete_list(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<ListId>,
+    Query(params): Query<DeleteParams>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let list = owned_list(&state, user.id, id).await?;
+    if list.inbox {
+        return Err((
+            StatusCode::CONFLICT,
+            "The inbox cannot be deleted".to_string(),
+        ));
+    }
+    let move_to = match params.todos {
+        OnDelete::Move => Some(inbox(&state, user.id).await?.id),
+        OnDelete::Delete => None,
+    };
+    state
+        .lists
+        .delete(id, move_to)
+        .await
+        .map_err(internal_error)?;
+    audit::record(
+        &state,
+        &origin,
+        [Change::deleted(Entity::List, id.0, &list)],
+    )
+    .await;
+    events::publish(&state, user.id, Event::ListDeleted, &list).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::{json, Value};
+
+    use crate::{
+        db::Database,
+        routes::{call, test_app_with},
+    };
+
+    crate::db::backend_test!(lists_group_todos);
+
+    async fn lists_group_todos(db: &Database) {
+        let app = &test_app_with(db);
+        let user = json!({"email": "a@example.com", "display_name": "A"});
+        assert_eq!(
+            call(app, "POST", "/user", user).await.0,
+            StatusCode::CREATED
+        );
+        let (_, lists) = call(app, "GET", "/list", Value::Null).await;
+        assert_eq!(lists.as_array().unwrap().len(), 1);
+        assert_eq!(lists[0]["inbox"], true);
+        let inbox = lists[0]["id"].as_str().unwrap().to_string();
+
+        let (status, _) = call(
+            app,
+            "POST",
+            "/list",
+            json!({"name": "Work", "color": "red"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let work = json!({"name": " Work ", "color": "#AABBCC"});
+        let (status, work) = call(app, "POST", "/list", work).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(work["name"], "Work");
+        assert_eq!(work["color"], "#aabbcc");
+        assert_eq!(work["position"], 1);
+        let work_uri = format!("/list/{}", work["id"].as_str().unwrap());
+
+        // Imports go to the inbox unless a list is given:
+        let todos = json!([{"title": "dishes"}]);
+        call(app, "POST", "/import", todos).await;
+        let uri = format!("/import?list_id={}", work["id"].as_str().unwrap());
+        call(app, "POST", &uri, json!([{"title": "report"}])).await;
+        let (_, in_inbox) = call(app, "GET", &format!("/todo?list_id={inbox}"), Value::Null).await;
+        assert_eq!(in_inbox.as_array().unwrap().len(), 1);
+        assert_eq!(in_inbox[0]["title"], "dishes");
+        let dishes = in_inbox[0]["id"].as_str().unwrap();
+
+        let move_uri = format!("/todo/{dishes}/list");
+        let (status, moved) = call(app, "PUT", &move_uri, json!({"list_id": work["id"]})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(moved["list_id"], work["id"]);
+        let uri = format!("/todo?list_id={}", work["id"].as_str().unwrap());
+        let (_, in_work) = call(app, "GET", &uri, Value::Null).await;
+        assert_eq!(in_work.as_array().unwrap().len(), 2);
+
+        let inbox_uri = format!("/list/{inbox}");
+        let archive = json!({"archived": true});
+        let (status, _) = call(app, "PATCH", &inbox_uri, archive.clone()).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = call(app, "DELETE", &inbox_uri, Value::Null).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, archived) = call(app, "PATCH", &work_uri, archive).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(archived["archived"], true);
+        let (_, active) = call(app, "GET", "/list?archived=false", Value::Null).await;
+        assert_eq!(active.as_array().unwrap().len(), 1);
+
+        // Deleting a list moves its todos to the inbox by default:
+        let (status, _) = call(app, "DELETE", &work_uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, in_inbox) = call(app, "GET", &format!("/todo?list_id={inbox}"), Value::Null).await;
+        assert_eq!(in_inbox.as_array().unwrap().len(), 2);
+
+        let (_, home) = call(app, "POST", "/list", json!({"name": "Home"})).await;
+        call(app, "PUT", &move_uri, json!({"list_id": home["id"]})).await;
+        let uri = format!("/list/{}?todos=delete", home["id"].as_str().unwrap());
+        let (status, _) = call(app, "DELETE", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, todos) = call(app, "GET", "/todo", Value::Null).await;
+        assert_eq!(todos.as_array().unwrap().len(), 1);
+        assert_eq!(todos[0]["title"], "report");
+    }
+}
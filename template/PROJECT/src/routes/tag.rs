@@ -0,0 +1,193 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, patch},
+    Json, Router,
+};
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Change},
+    errors::{internal_error, not_found_error},
+    extract::{Authorize, Origin},
+    models::{
+        audit::Entity,
+        ids::{TagId, UserId},
+        tag::{normalize_tag_name, Tag, TagCount, TagName},
+    },
+    AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    // this router is responsible for everything under `/tag`
+    Router::<AppState>::new()
+        .route("/", get(list_tags).post(create_tag))
+        .route("/{tag_id}", patch(rename_tag).delete(delete_tag))
+}
+
+/// The caller's tag `id`, or 404 if it belongs to someone else.
+async fn owned_tag(
+    state: &AppState,
+    user_id: UserId,
+    id: TagId,
+) -> Result<Tag, (StatusCode, String)> {
+    match state.tags.get(id).await.map_err(internal_error)? {
+        Some(tag) if tag.user_id == user_id => Ok(tag),
+        _ => Err(not_found_error(format!("No tag {}", id.0))),
+    }
+}
+
+/// Validate a tag name, rejecting it with 409 if the user already has
+/// another tag by that name.
+async fn available_name(
+    state: &AppState,
+    user_id: UserId,
+    name: &str,
+) -> Result<String, (StatusCode, String)> {
+    let name = normalize_tag_name(name).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    match state
+        .tags
+        .find_by_name(user_id, &name)
+        .await
+        .map_err(internal_error)?
+    {
+        Some(_) => Err((StatusCode::CONFLICT, format!("Tag '{name}' already exists"))),
+        None => Ok(name),
+    }
+}
+
+/// The caller's tags with how many todos carry each.
+async fn list_tags(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+) -> Result<Json<Vec<TagCount>>, (StatusCode, String)> {
+    let tags = state.tags.list(user.id).await.map_err(internal_error)?;
+    Ok(Json(tags))
+}
+
+async fn create_tag(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Json(payload): Json<TagName>,
+) -> Result<(StatusCode, Json<Tag>), (StatusCode, String)> {
+    let name = available_name(&state, user.id, &payload.name).await?;
+    let tag = state
+        .tags
+        .insert(&Tag {
+            id: TagId(Uuid::new_v4()),
+            user_id: user.id,
+            name,
+            created_at: Utc::now(),
+        })
+        .await
+        .map_err(internal_error)?;
+    audit::record(
+        &state,
+        &origin,
+        [Change::created(Entity::Tag, tag.id.0, &tag)],
+    )
+    .await;
+    Ok((StatusCode::CREATED, Json(tag)))
+}
+
+async fn rename_tag(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<TagId>,
+    Json(payload): Json<TagName>,
+) -> Result<Json<Tag>, (StatusCode, String)> {
+    let tag = owned_tag(&state, user.id, id).await?;
+    if normalize_tag_name(&payload.name).as_deref() == Ok(tag.name.as_str()) {
+        return Ok(Json(tag));
+    }
+    let name = available_name(&state, user.id, &payload.name).await?;
+    let renamed = state
+        .tags
+        .rename(id, &name)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| not_found_error(format!("No tag {}", id.0)))?;
+    let change = Change::updated(Entity::Tag, id.0, &tag, &renamed);
+    audit::record(&state, &origin, [change]).await;
+    Ok(Json(renamed))
+}
+
+/// Delete a tag, removing it from all of its todos.
+async fn delete_tag(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<TagId>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let tag = owned_tag(&state, user.id, id).await?;
+    state.tags.delete(id).await.map_err(internal_error)?;
+    audit::record(&state, &origin, [Change::deleted(Entity::Tag, id.0, &tag)]).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::{json, Value};
+
+    use crate::{
+        db::Database,
+        routes::{call, test_app_with},
+    };
+
+    crate::db::backend_test!(tag_todos_and_filter);
+
+    async fn tag_todos_and_filter(db: &Database) {
+        let app = &test_app_with(db);
+        let user = json!({"email": "a@example.com", "display_name": "A"});
+        assert_eq!(
+            call(app, "POST", "/user", user).await.0,
+            StatusCode::CREATED
+        );
+        let todos = json!([{"title": "dishes"}, {"title": "report"}]);
+        assert_eq!(
+            call(app, "POST", "/import", todos).await.0,
+            StatusCode::CREATED
+        );
+        let (_, todos) = call(app, "GET", "/todo", Value::Null).await;
+        let dishes = todos[0]["id"].as_str().unwrap();
+
+        let uri = format!("/todo/{dishes}/tags");
+        let (status, home) = call(app, "POST", &uri, json!({"name": " home "})).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(home["name"], "home");
+        let (status, _) = call(app, "POST", &uri, json!({"name": "home"})).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = call(app, "POST", "/tag", json!({"name": "work"})).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = call(app, "POST", "/tag", json!({"name": "work"})).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (_, tags) = call(app, "GET", "/tag", Value::Null).await;
+        assert_eq!(
+            tags,
+            json!([
+                {"id": home["id"], "name": "home", "todos": 1},
+                {"id": tags[1]["id"], "name": "work", "todos": 0},
+            ])
+        );
+        let (_, tagged) = call(app, "GET", "/todo?tag=home", Value::Null).await;
+        assert_eq!(tagged.as_array().unwrap().len(), 1);
+        assert_eq!(tagged[0]["title"], "dishes");
+
+        let home_uri = format!("/tag/{}", home["id"].as_str().unwrap());
+        let (status, _) = call(app, "PATCH", &home_uri, json!({"name": "work"})).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, renamed) = call(app, "PATCH", &home_uri, json!({"name": "house"})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(renamed["name"], "house");
+
+        let (status, _) = call(app, "DELETE", &home_uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, tags) = call(app, "GET", &uri, Value::Null).await;
+        assert_eq!(tags, json!([]));
+    }
+}
@@ -0,0 +1,806 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    routing::{delete, get, put},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::conditional;
+use crate::{
+    audit::{self, Change},
+    errors::{internal_error, not_found_error},
+    events,
+    extract::{Authorize, Origin},
+    models::{
+        audit::Entity,
+        ids::{ListId, TagId, TodoId, UserId},
+        tag::{normalize_tag_name, Tag, TagName, TodoTag},
+        todo::{
+            tree_height, Completed, MoveTodo, SetCompleted, SetParent, SetRecurrence, Todo,
+            TodoNode, UpdateTodo, MAX_TODO_DEPTH,
+        },
+        webhook::Event,
+    },
+    prelude::*,
+    recurrence::{self, RRule},
+    repository::TodoFilter,
+    AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    // this router is responsible for everything under `/todo`
+    Router::<AppState>::new()
+        .route("/", get(list_todos))
+        .route(
+            "/{todo_id}",
+            get(get_todo).patch(update_todo).delete(delete_todo),
+        )
+        .route("/{todo_id}/completed", put(set_completed))
+        .route("/{todo_id}/list", put(move_todo))
+        .route("/{todo_id}/parent", put(set_parent))
+        .route("/{todo_id}/recurrence", put(set_recurrence))
+        .route("/{todo_id}/occurrences", get(list_occurrences))
+        .route("/{todo_id}/tags", get(list_todo_tags).post(add_todo_tag))
+        .route("/{todo_id}/tags/{tag_id}", delete(remove_todo_tag))
+}
+
+/// The caller's todo `id`, or 404 if it belongs to someone else.
+pub(super) async fn owned_todo(
+    state: &AppState,
+    user_id: UserId,
+    id: TodoId,
+) -> Result<Todo, (StatusCode, String)> {
+    match state.todos.get(id).await.map_err(internal_error)? {
+        Some(todo) if todo.user_id == user_id => Ok(todo),
+        _ => Err(not_found_error(format!("No todo {}", id.0))),
+    }
+}
+
+/// Check that the caller may nest `height` levels of todos (the todo
+/// `moving` and its subtasks, if moving an existing todo) under their todo
+/// `parent_id`, returning the parent.
+///
+/// Rejects parents that are `moving` itself or one of its subtasks, and
+/// nesting deeper than [`MAX_TODO_DEPTH`] levels.
+pub(super) async fn check_parent(
+    state: &AppState,
+    user_id: UserId,
+    parent_id: TodoId,
+    moving: Option<TodoId>,
+    height: usize,
+) -> Result<Todo, (StatusCode, String)> {
+    let parent = owned_todo(state, user_id, parent_id).await?;
+    let mut level = 1;
+    let mut ancestor = Some(parent.clone());
+    while let Some(todo) = ancestor {
+        if Some(todo.id) == moving {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "A todo cannot be a subtask of itself or of its own subtasks".to_string(),
+            ));
+        }
+        if level + height > MAX_TODO_DEPTH {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Subtasks can only be nested {MAX_TODO_DEPTH} levels deep"),
+            ));
+        }
+        ancestor = match todo.parent_id {
+            Some(id) => state.todos.get(id).await.map_err(internal_error)?,
+            None => None,
+        };
+        level += 1;
+    }
+    Ok(parent)
+}
+
+#[derive(Debug, Deserialize)]
+struct ListParams {
+    completed: Option<bool>,
+    list_id: Option<ListId>,
+    /// Only todos with the tag of this name.
+    tag: Option<String>,
+}
+
+/// The caller's todos in creation order.
+async fn list_todos(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    Query(params): Query<ListParams>,
+) -> Result<Json<Vec<Todo>>, (StatusCode, String)> {
+    let todos = state
+        .todos
+        .list(TodoFilter {
+            user_id: Some(user.id),
+            completed: params.completed,
+            list_id: params.list_id,
+            tag: params.tag,
+        })
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(todos))
+}
+
+/// A todo with its subtasks at every level, and their progress. Its
+/// ETag is the todo's own version, which changes to subtasks leave alone.
+async fn get_todo(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    Path(id): Path<TodoId>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    owned_todo(&state, user.id, id).await?;
+    let subtree = state.todos.subtree(id).await.map_err(internal_error)?;
+    let node =
+        TodoNode::tree(id, subtree).ok_or_else(|| not_found_error(format!("No todo {}", id.0)))?;
+    Ok(conditional::versioned(
+        &headers,
+        node.todo.version,
+        Json(node),
+    ))
+}
+
+/// Change a todo's title, notes or due date (`null` clears the latter
+/// two), if it still matches any `If-Match` header.
+async fn update_todo(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<TodoId>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateTodo>,
+) -> Result<Response, (StatusCode, String)> {
+    let before = owned_todo(&state, user.id, id).await?;
+    conditional::check_if_match(&headers, before.version, "todo")?;
+    let mut todo = before.clone();
+    if let Some(title) = payload.title {
+        let title = title.trim();
+        if title.is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "title must not be empty".to_string(),
+            ));
+        }
+        todo.title = title.to_string();
+    }
+    if let Some(notes) = payload.notes {
+        todo.notes = notes.filter(|n| !n.is_empty());
+    }
+    if let Some(due_at) = payload.due_at {
+        todo.due_at = due_at;
+    }
+    let todo = state
+        .todos
+        .update(&todo)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| conditional::changed_error(&headers, "todo"))?;
+    let change = Change::updated(Entity::Todo, id.0, &before, &todo);
+    audit::record(&state, &origin, [change]).await;
+    events::publish(&state, user.id, Event::TodoUpdated, &todo).await;
+    Ok(conditional::with_etag(todo.version, Json(todo)))
+}
+
+/// Move a todo and its subtasks to the trash, if it still matches any
+/// `If-Match` header.
+async fn delete_todo(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<TodoId>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let todo = owned_todo(&state, user.id, id).await?;
+    conditional::check_if_match(&headers, todo.version, "todo")?;
+    let deleted = state
+        .todos
+        .delete(id, Some(todo.version))
+        .await
+        .map_err(internal_error)?;
+    if !deleted {
+        return Err(conditional::changed_error(&headers, "todo"));
+    }
+    audit::record(
+        &state,
+        &origin,
+        [Change::deleted(Entity::Todo, id.0, &todo)],
+    )
+    .await;
+    events::publish(&state, user.id, Event::TodoDeleted, &todo).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Complete or reopen a todo, along with all of its subtasks if
+/// `cascade` is set. Completing a recurring todo creates its next
+/// occurrence, which is returned as `next`.
+async fn set_completed(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<TodoId>,
+    Json(payload): Json<SetCompleted>,
+) -> Result<Json<Completed>, (StatusCode, String)> {
+    let (completed, cascade) = (payload.completed, payload.cascade);
+    complete(&state, &origin, user.id, id, completed, cascade)
+        .await
+        .map(Json)
+}
+
+/// Complete or reopen the caller's todo `id` (and its subtasks, with
+/// `cascade`), scheduling its next occurrence if it recurs.
+///
+/// Nothing is written (or audited) if there is nothing to change, and the
+/// write only goes ahead if the todo has not changed since it was read,
+/// so that two requests completing it at once cannot both schedule the
+/// next occurrence: the one that loses gets a 409 to try again.
+pub(super) async fn complete(
+    state: &AppState,
+    origin: &Origin,
+    user_id: UserId,
+    id: TodoId,
+    completed: bool,
+    cascade: bool,
+) -> Result<Completed, (StatusCode, String)> {
+    let before = owned_todo(state, user_id, id).await?;
+    let unchanged = if cascade {
+        let subtree = state.todos.subtree(id).await.map_err(internal_error)?;
+        subtree.iter().all(|todo| todo.completed == completed)
+    } else {
+        before.completed == completed
+    };
+    if unchanged {
+        return Ok(Completed {
+            todo: before,
+            next: None,
+        });
+    }
+    let version = Some(before.version);
+    let todo = if cascade {
+        state
+            .todos
+            .set_subtree_completed(id, completed, version)
+            .await
+    } else {
+        state.todos.set_completed(id, completed, version).await
+    };
+    let todo = todo.map_err(internal_error)?.ok_or_else(|| {
+        (
+            StatusCode::CONFLICT,
+            "The todo was changed by another request".to_string(),
+        )
+    })?;
+    let completed = todo.completed && !before.completed;
+    let event = if completed {
+        Event::TodoCompleted
+    } else {
+        Event::TodoUpdated
+    };
+    events::publish(state, user_id, event, &todo).await;
+    let next = if completed {
+        recurrence::schedule_next(state.todos.as_ref(), state.tags.as_ref(), &todo)
+            .await
+            .map_err(internal_error)?
+    } else {
+        None
+    };
+    let mut changes = vec![Change::updated(Entity::Todo, id.0, &before, &todo)];
+    changes.extend(
+        next.iter()
+            .map(|next| Change::created(Entity::Todo, next.id.0, next)),
+    );
+    audit::record(state, origin, changes).await;
+    if let Some(next) = &next {
+        events::publish(state, user_id, Event::TodoCreated, next).await;
+    }
+    Ok(Completed { todo, next })
+}
+
+/// Make a todo recur by an RFC 5545 RRULE, from its due date or (with
+/// `repeat_from_completion`) from when it is completed, or stop it
+/// recurring with a `null` rule.
+async fn set_recurrence(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<TodoId>,
+    Json(payload): Json<SetRecurrence>,
+) -> Result<Json<Todo>, (StatusCode, String)> {
+    let before = owned_todo(&state, user.id, id).await?;
+    let rrule = match payload.rrule.as_deref() {
+        Some(rule) => Some(
+            RRule::from_str(rule)
+                .map_err(|e| (StatusCode::BAD_REQUEST, e))?
+                .to_string(),
+        ),
+        None => None,
+    };
+    let todo = state
+        .todos
+        .set_recurrence(id, rrule.as_deref(), payload.repeat_from_completion)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| not_found_error(format!("No todo {}", id.0)))?;
+    updated(&state, &origin, &before, todo).await
+}
+
+/// Audit a changed todo and send a `todo.updated` event for it, and
+/// return it.
+async fn updated(
+    state: &AppState,
+    origin: &Origin,
+    before: &Todo,
+    todo: Todo,
+) -> Result<Json<Todo>, (StatusCode, String)> {
+    let change = Change::updated(Entity::Todo, todo.id.0, before, &todo);
+    audit::record(state, origin, [change]).await;
+    events::publish(state, todo.user_id, Event::TodoUpdated, &todo).await;
+    Ok(Json(todo))
+}
+
+#[derive(Debug, Deserialize)]
+struct OccurrenceParams {
+    count: Option<usize>,
+}
+
+/// How many occurrences to preview by default.
+const DEFAULT_OCCURRENCES: usize = 5;
+/// The most occurrences to preview at once.
+const MAX_OCCURRENCES: usize = 100;
+
+/// The due dates of a recurring todo's next `count` occurrences, as if
+/// it were completed now. Empty if it does not recur.
+async fn list_occurrences(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    Path(id): Path<TodoId>,
+    Query(params): Query<OccurrenceParams>,
+) -> Result<Json<Vec<DateTime<Utc>>>, (StatusCode, String)> {
+    let todo = owned_todo(&state, user.id, id).await?;
+    let count = params
+        .count
+        .unwrap_or(DEFAULT_OCCURRENCES)
+        .min(MAX_OCCURRENCES);
+    let Some(rule) = todo.rrule.as_deref() else {
+        return Ok(Json(Vec::new()));
+    };
+    let rule = RRule::from_str(rule).map_err(internal_error)?;
+    let start = recurrence::series_start(&todo, Utc::now());
+    Ok(Json(rule.after(start).take(count).collect()))
+}
+
+/// Make a todo a subtask of another of the caller's todos, or a
+/// top-level todo again (with a `null` parent).
+async fn set_parent(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<TodoId>,
+    Json(payload): Json<SetParent>,
+) -> Result<Json<Todo>, (StatusCode, String)> {
+    let before = owned_todo(&state, user.id, id).await?;
+    if let Some(parent_id) = payload.parent_id {
+        let subtree = state.todos.subtree(id).await.map_err(internal_error)?;
+        check_parent(&state, user.id, parent_id, Some(id), tree_height(&subtree)).await?;
+    }
+    let todo = state
+        .todos
+        .set_parent(id, payload.parent_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| not_found_error(format!("No todo {}", id.0)))?;
+    updated(&state, &origin, &before, todo).await
+}
+
+/// Move a todo to another of the caller's lists.
+async fn move_todo(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<TodoId>,
+    Json(payload): Json<MoveTodo>,
+) -> Result<Json<Todo>, (StatusCode, String)> {
+    move_to_list(&state, &origin, user.id, id, payload.list_id)
+        .await
+        .map(Json)
+}
+
+/// Move the caller's todo `id` to their list `list_id`.
+pub(super) async fn move_to_list(
+    state: &AppState,
+    origin: &Origin,
+    user_id: UserId,
+    id: TodoId,
+    list_id: ListId,
+) -> Result<Todo, (StatusCode, String)> {
+    let before = owned_todo(state, user_id, id).await?;
+    super::list::owned_list(state, user_id, list_id).await?;
+    let todo = state
+        .todos
+        .set_list(id, list_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| not_found_error(format!("No todo {}", id.0)))?;
+    let change = Change::updated(Entity::Todo, id.0, &before, &todo);
+    audit::record(state, origin, [change]).await;
+    events::publish(state, user_id, Event::TodoUpdated, &todo).await;
+    Ok(todo)
+}
+
+async fn list_todo_tags(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    Path(id): Path<TodoId>,
+) -> Result<Json<Vec<Tag>>, (StatusCode, String)> {
+    owned_todo(&state, user.id, id).await?;
+    let tags = state.tags.for_todo(id).await.map_err(internal_error)?;
+    Ok(Json(tags))
+}
+
+/// Tag a todo by tag name, creating the tag if the caller has none by
+/// that name. Responds 201 if the todo gained the tag, 200 if it already
+/// had it.
+async fn add_todo_tag(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<TodoId>,
+    Json(payload): Json<TagName>,
+) -> Result<(StatusCode, Json<Tag>), (StatusCode, String)> {
+    let todo = owned_todo(&state, user.id, id).await?;
+    let name = normalize_tag_name(&payload.name).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let mut changes = Vec::new();
+    let tag = match state
+        .tags
+        .find_by_name(user.id, &name)
+        .await
+        .map_err(internal_error)?
+    {
+        Some(tag) => tag,
+        None => {
+            let tag = state
+                .tags
+                .insert(&Tag {
+                    id: TagId(Uuid::new_v4()),
+                    user_id: user.id,
+                    name,
+                    created_at: Utc::now(),
+                })
+                .await
+                .map_err(internal_error)?;
+            changes.push(Change::created(Entity::Tag, tag.id.0, &tag));
+            tag
+        }
+    };
+    let added = state
+        .tags
+        .add_to_todo(id, tag.id)
+        .await
+        .map_err(internal_error)?;
+    if added {
+        changes.push(todo_tag_change(Change::created, id, tag.id));
+    }
+    audit::record(&state, &origin, changes).await;
+    let status = if added {
+        events::publish(&state, user.id, Event::TodoUpdated, &todo).await;
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Json(tag)))
+}
+
+async fn remove_todo_tag(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path((id, tag_id)): Path<(TodoId, TagId)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let todo = owned_todo(&state, user.id, id).await?;
+    if state
+        .tags
+        .remove_from_todo(id, tag_id)
+        .await
+        .map_err(internal_error)?
+    {
+        let change = todo_tag_change(Change::deleted, id, tag_id);
+        audit::record(&state, &origin, [change]).await;
+        events::publish(&state, user.id, Event::TodoUpdated, &todo).await;
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(not_found_error(format!(
+            "Todo {} has no tag {}",
+            id.0, tag_id.0
+        )))
+    }
+}
+
+/// The audited change of a todo gaining or losing a tag.
+fn todo_tag_change(
+    change: fn(Entity, String, &TodoTag) -> Change,
+    todo_id: TodoId,
+    tag_id: TagId,
+) -> Change {
+    let id = format!("{}/{}", todo_id.0, tag_id.0);
+    change(Entity::TodoTag, id, &TodoTag { todo_id, tag_id })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::{json, Value};
+
+    use crate::{
+        db::Database,
+        models::todo::MAX_TODO_DEPTH,
+        routes::{call, call_with, test_app_with, TEST_USER},
+    };
+
+    /// Import one todo (under `parent`, if given) and return its id.
+    async fn add(app: &axum::Router, title: &str, parent: Option<&str>) -> (StatusCode, String) {
+        let uri = match parent {
+            Some(id) => format!("/import?parent_id={id}"),
+            None => "/import".to_string(),
+        };
+        let (status, _) = call(app, "POST", &uri, json!([{ "title": title }])).await;
+        let (_, todos) = call(app, "GET", "/todo", Value::Null).await;
+        (
+            status,
+            titled(&todos, title)["id"].as_str().unwrap().to_string(),
+        )
+    }
+
+    /// The todo with this title (creation order is only to the second).
+    fn titled<'a>(todos: &'a Value, title: &str) -> &'a Value {
+        let todos = todos.as_array().unwrap();
+        todos.iter().find(|t| t["title"] == title).unwrap()
+    }
+
+    crate::db::backend_test!(subtasks_nest_and_roll_up);
+
+    async fn subtasks_nest_and_roll_up(db: &Database) {
+        let app = &test_app_with(db);
+        let user = json!({"email": "a@example.com", "display_name": "A"});
+        assert_eq!(
+            call(app, "POST", "/user", user).await.0,
+            StatusCode::CREATED
+        );
+        let (_, root) = add(app, "move house", None).await;
+        let (status, pack) = add(app, "pack", Some(&root)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (_, books) = add(app, "books", Some(&pack)).await;
+        add(app, "call movers", Some(&root)).await;
+
+        let uri = format!("/todo/{books}/completed");
+        let (status, _) = call(app, "PUT", &uri, json!({"completed": true})).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, tree) = call(app, "GET", &format!("/todo/{root}"), Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(tree["progress"], json!({"completed": 1, "total": 3}));
+        let packing = titled(&tree["subtasks"], "pack");
+        assert_eq!(packing["progress"], json!({"completed": 1, "total": 1}));
+        assert_eq!(packing["subtasks"][0]["title"], "books");
+        let calling = titled(&tree["subtasks"], "call movers");
+        assert_eq!(calling["progress"], json!({"completed": 0, "total": 0}));
+
+        // No cycles:
+        let uri = format!("/todo/{root}/parent");
+        let (status, _) = call(app, "PUT", &uri, json!({ "parent_id": books })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = call(app, "PUT", &uri, json!({ "parent_id": root })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Nothing deeper than MAX_TODO_DEPTH:
+        let mut deepest = books.clone();
+        for level in 4..=MAX_TODO_DEPTH {
+            deepest = add(app, &format!("level {level}"), Some(&deepest)).await.1;
+        }
+        let uri = format!("/import?parent_id={deepest}");
+        let (status, _) = call(app, "POST", &uri, json!([{"title": "too deep"}])).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let uri = format!("/todo/{pack}/parent");
+        let movers = calling["id"].as_str().unwrap();
+        let (status, _) = call(app, "PUT", &uri, json!({ "parent_id": movers })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, moved) = call(app, "PUT", &uri, json!({ "parent_id": null })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(moved["parent_id"], Value::Null);
+        let (status, _) = call(app, "PUT", &uri, json!({ "parent_id": root })).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Completing with cascade completes every subtask:
+        let uri = format!("/todo/{root}/completed");
+        let done = json!({"completed": true, "cascade": true});
+        let (status, _) = call(app, "PUT", &uri, done).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, pending) = call(app, "GET", "/todo?completed=false", Value::Null).await;
+        assert_eq!(pending, json!([]));
+    }
+
+    crate::db::backend_test!(recurring_todos_repeat);
+
+    async fn recurring_todos_repeat(db: &Database) {
+        let app = &test_app_with(db);
+        let user = json!({"email": "a@example.com", "display_name": "A"});
+        assert_eq!(
+            call(app, "POST", "/user", user).await.0,
+            StatusCode::CREATED
+        );
+        let bad = json!([{"title": "x", "rrule": "FREQ=HOURLY"}]);
+        let (status, report) = call(app, "POST", "/import", bad).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(report["errors"][0]["row"], 1);
+        // A Sunday, then Mondays and Thursdays, three times in all:
+        let plants = json!([{
+            "title": "water plants",
+            "due_at": "2030-01-06T09:00:00Z",
+            "rrule": "rrule:freq=weekly;byday=mo,th;count=3",
+        }]);
+        assert_eq!(
+            call(app, "POST", "/import", plants).await.0,
+            StatusCode::CREATED
+        );
+        let (_, todos) = call(app, "GET", "/todo", Value::Null).await;
+        let first = todos[0]["id"].as_str().unwrap().to_string();
+        assert_eq!(todos[0]["rrule"], "FREQ=WEEKLY;COUNT=3;BYDAY=MO,TH");
+        let tags_uri = format!("/todo/{first}/tags");
+        call(app, "POST", &tags_uri, json!({"name": "garden"})).await;
+
+        let uri = format!("/todo/{first}/occurrences?count=10");
+        let (status, upcoming) = call(app, "GET", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            upcoming,
+            json!(["2030-01-07T09:00:00Z", "2030-01-10T09:00:00Z"])
+        );
+
+        let uri = format!("/todo/{first}/completed");
+        let (status, done) = call(app, "PUT", &uri, json!({"completed": true})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(done["completed"], true);
+        let next = &done["next"];
+        assert_eq!(next["completed"], false);
+        assert_eq!(next["due_at"], "2030-01-07T09:00:00Z");
+        assert_eq!(next["rrule"], "FREQ=WEEKLY;COUNT=2;BYDAY=MO,TH");
+        let next = next["id"].as_str().unwrap().to_string();
+        let (_, tags) = call(app, "GET", &format!("/todo/{next}/tags"), Value::Null).await;
+        assert_eq!(tags[0]["name"], "garden");
+        // Completing it again changes nothing, so does not repeat it again:
+        let (_, again) = call(app, "PUT", &uri, json!({"completed": true})).await;
+        assert_eq!(again["next"], Value::Null);
+        assert_eq!(again["version"], done["version"]);
+
+        let uri = format!("/todo/{next}/completed");
+        let (_, done) = call(app, "PUT", &uri, json!({"completed": true})).await;
+        assert_eq!(done["next"]["due_at"], "2030-01-10T09:00:00Z");
+        let last = done["next"]["id"].as_str().unwrap().to_string();
+        let uri = format!("/todo/{last}/completed");
+        let (_, done) = call(app, "PUT", &uri, json!({"completed": true})).await;
+        assert_eq!(done["next"], Value::Null);
+
+        let uri = format!("/todo/{last}/recurrence");
+        let (status, _) = call(app, "PUT", &uri, json!({"rrule": "FREQ=DAILY;BYSETPOS=1"})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let daily = json!({"rrule": "FREQ=DAILY", "repeat_from_completion": true});
+        let (status, todo) = call(app, "PUT", &uri, daily).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(todo["rrule"], "FREQ=DAILY");
+        assert_eq!(todo["repeat_from_completion"], true);
+        let (_, todo) = call(app, "PUT", &uri, json!({ "rrule": null })).await;
+        assert_eq!(todo["rrule"], Value::Null);
+        let uri = format!("/todo/{last}/occurrences");
+        let (_, upcoming) = call(app, "GET", &uri, Value::Null).await;
+        assert_eq!(upcoming, json!([]));
+
+        // Completing it twice at once only repeats it once:
+        let fish = json!([{
+            "title": "feed fish",
+            "due_at": "2030-01-06T09:00:00Z",
+            "rrule": "FREQ=DAILY",
+        }]);
+        call(app, "POST", "/import", fish).await;
+        let (_, todos) = call(app, "GET", "/todo?completed=false", Value::Null).await;
+        let fish = titled(&todos, "feed fish")["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let uri = format!("/todo/{fish}/completed");
+        let done = json!({"completed": true});
+        let (a, b) = tokio::join!(
+            call(app, "PUT", &uri, done.clone()),
+            call(app, "PUT", &uri, done.clone())
+        );
+        assert!(a.0 == StatusCode::OK || b.0 == StatusCode::OK);
+        let nexts = [a.1, b.1].iter().filter(|r| r["next"].is_object()).count();
+        assert_eq!(nexts, 1);
+        let (_, todos) = call(app, "GET", "/todo?completed=false", Value::Null).await;
+        let pending = todos.as_array().unwrap().iter();
+        assert_eq!(pending.filter(|t| t["title"] == "feed fish").count(), 1);
+    }
+
+    crate::db::backend_test!(todo_writes_check_etags);
+
+    async fn todo_writes_check_etags(db: &Database) {
+        let app = &test_app_with(db);
+        let user = json!({"email": "a@example.com", "display_name": "A"});
+        assert_eq!(
+            call(app, "POST", "/user", user).await.0,
+            StatusCode::CREATED
+        );
+        let (_, id) = add(app, "mow", None).await;
+        let uri = format!("/todo/{id}");
+        let (status, headers, todo) = call_with(app, "GET", &uri, &[TEST_USER], Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["etag"], "\"1\"");
+        assert_eq!(todo["version"], 1);
+        let (status, _, _) = call_with(
+            app,
+            "GET",
+            &uri,
+            &[TEST_USER, ("if-none-match", "\"1\"")],
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+
+        let patch = json!({"title": "mow the lawn", "notes": "front and back"});
+        let (status, headers, todo) = call_with(
+            app,
+            "PATCH",
+            &uri,
+            &[TEST_USER, ("if-match", "\"1\"")],
+            patch,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["etag"], "\"2\"");
+        assert_eq!(todo["title"], "mow the lawn");
+        assert_eq!(todo["notes"], "front and back");
+        // Any other change bumps the version too:
+        call(
+            app,
+            "PUT",
+            &format!("{uri}/completed"),
+            json!({"completed": true}),
+        )
+        .await;
+        let stale = [TEST_USER, ("if-match", "\"2\"")];
+        let (status, _, _) = call_with(app, "PATCH", &uri, &stale, json!({"notes": null})).await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        let (status, _, _) = call_with(app, "DELETE", &uri, &stale, Value::Null).await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        let (status, _, _) = call_with(
+            app,
+            "GET",
+            &uri,
+            &[TEST_USER, ("if-none-match", "\"2\"")],
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _, todo) =
+            call_with(app, "PATCH", &uri, &[TEST_USER], json!({"notes": null})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            (&todo["notes"], &todo["version"]),
+            (&Value::Null, &json!(4))
+        );
+        let (status, _, _) = call_with(
+            app,
+            "DELETE",
+            &uri,
+            &[TEST_USER, ("if-match", "\"4\"")],
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(
+            call(app, "GET", &uri, Value::Null).await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+}
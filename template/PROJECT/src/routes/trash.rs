@@ -0,0 +1,243 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Response,
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Serialize;
+
+use crate::{
+    audit::{self, Change},
+    errors::{internal_error, not_found_error},
+    events,
+    extract::{Authorize, Origin},
+    models::{
+        audit::Entity,
+        ids::{ListId, TodoId},
+        list::TodoList,
+        todo::Todo,
+        webhook::Event,
+    },
+    repository::TodoFilter,
+    AppState,
+};
+
+use super::conditional;
+
+pub fn router() -> Router<AppState> {
+    // this router is responsible for everything under `/trash`
+    Router::<AppState>::new()
+        .route("/", get(list_trash))
+        .route("/list/{list_id}/restore", post(restore_list))
+        .route("/todo/{todo_id}/restore", post(restore_todo))
+}
+
+#[derive(Debug, Serialize)]
+struct Trash {
+    lists: Vec<TodoList>,
+    todos: Vec<Todo>,
+}
+
+/// The caller's trashed lists and todos, most recently trashed first.
+/// They are purged for good once the retention period has passed.
+async fn list_trash(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+) -> Result<Json<Trash>, (StatusCode, String)> {
+    let lists = state.trash.lists(user.id).await.map_err(internal_error)?;
+    let todos = state.trash.todos(user.id).await.map_err(internal_error)?;
+    Ok(Json(Trash { lists, todos }))
+}
+
+/// Restore a list from the trash, along with the todos that were
+/// trashed with it.
+async fn restore_list(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<ListId>,
+) -> Result<Json<TodoList>, (StatusCode, String)> {
+    let list = state
+        .trash
+        .restore_list(user.id, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| not_found_error(format!("No list {} in the trash", id.0)))?;
+    events::publish(&state, user.id, Event::ListRestored, &list).await;
+    let todos = state
+        .todos
+        .list(TodoFilter {
+            list_id: Some(list.id),
+            ..Default::default()
+        })
+        .await
+        .map_err(internal_error)?;
+    let todos: Vec<&Todo> = todos.iter().filter(|t| t.user_id == user.id).collect();
+    let changes = todos
+        .iter()
+        .map(|todo| Change::restored(Entity::Todo, todo.id.0, todo));
+    let list_change = Change::restored(Entity::List, list.id.0, &list);
+    audit::record(&state, &origin, std::iter::once(list_change).chain(changes)).await;
+    for todo in todos {
+        events::publish(&state, user.id, Event::TodoRestored, todo).await;
+    }
+    Ok(Json(list))
+}
+
+/// Restore a todo from the trash, along with the subtasks that were
+/// trashed with it. It goes to the inbox if its list is still in the
+/// trash, and to the top level if its parent is.
+async fn restore_todo(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<TodoId>,
+) -> Result<Response, (StatusCode, String)> {
+    let todo = state
+        .trash
+        .restore_todo(user.id, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| not_found_error(format!("No todo {} in the trash", id.0)))?;
+    let subtree = state.todos.subtree(id).await.map_err(internal_error)?;
+    let changes = subtree
+        .iter()
+        .map(|todo| Change::restored(Entity::Todo, todo.id.0, todo));
+    audit::record(&state, &origin, changes).await;
+    for todo in &subtree {
+        events::publish(&state, user.id, Event::TodoRestored, todo).await;
+    }
+    Ok(conditional::with_etag(todo.version, Json(todo)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use chrono::{Duration, Utc};
+    use serde_json::{json, Value};
+
+    use crate::{
+        db::Database,
+        events::{Received, Subscription},
+        models::ids::UserId,
+        routes::{call, test_app_with_state, test_state},
+    };
+
+    fn titles(todos: &Value) -> Vec<&str> {
+        let mut titles: Vec<&str> = todos
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["title"].as_str().unwrap())
+            .collect();
+        titles.sort();
+        titles
+    }
+
+    /// The names of the events published so far.
+    fn published(subscription: &mut Subscription) -> Vec<&'static str> {
+        let mut events = Vec::new();
+        while let Some(Some(Received::Change(change))) =
+            futures_util::FutureExt::now_or_never(subscription.next())
+        {
+            events.push(change.event.name());
+        }
+        events
+    }
+
+    crate::db::backend_test!(trash_restores_and_purges);
+
+    async fn trash_restores_and_purges(db: &Database) {
+        let state = test_state(db);
+        let app = test_app_with_state(state.clone());
+        let user = json!({"email": "a@example.com", "display_name": "A"});
+        let (status, user) = call(&app, "POST", "/user", user).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let user_id = UserId(user["id"].as_str().unwrap().parse().unwrap());
+        let (_, garden) = call(&app, "POST", "/list", json!({"name": "Garden"})).await;
+        let garden_id = garden["id"].as_str().unwrap();
+        let uri = format!("/import?list_id={garden_id}");
+        call(&app, "POST", &uri, json!([{"title": "mow"}])).await;
+        let (_, todos) = call(&app, "GET", "/todo", Value::Null).await;
+        let mow = todos[0]["id"].as_str().unwrap().to_string();
+        let uri = format!("/import?parent_id={mow}");
+        call(&app, "POST", &uri, json!([{"title": "edges"}])).await;
+        call(&app, "POST", "/import", json!([{"title": "dishes"}])).await;
+
+        // Trashing a todo trashes its subtasks, and hides both:
+        let (status, _) = call(&app, "DELETE", &format!("/todo/{mow}"), Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, todos) = call(&app, "GET", "/todo", Value::Null).await;
+        assert_eq!(titles(&todos), ["dishes"]);
+        let (status, _) = call(&app, "GET", &format!("/todo/{mow}"), Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, trash) = call(&app, "GET", "/trash", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(titles(&trash["todos"]), ["edges", "mow"]);
+        assert!(trash["todos"][0]["deleted_at"].is_string());
+
+        let mut events = state.events.subscribe(user_id, None);
+        let uri = format!("/trash/todo/{mow}/restore");
+        let (status, restored) = call(&app, "POST", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(restored["deleted_at"], Value::Null);
+        assert_eq!(published(&mut events), ["todo.restored", "todo.restored"]);
+        let (status, _) = call(&app, "POST", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, todos) = call(&app, "GET", "/todo", Value::Null).await;
+        assert_eq!(titles(&todos), ["dishes", "edges", "mow"]);
+
+        // A list comes back with the todos trashed along with it:
+        let uri = format!("/list/{garden_id}?todos=delete");
+        let (status, _) = call(&app, "DELETE", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = call(&app, "GET", &format!("/list/{garden_id}"), Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, trash) = call(&app, "GET", "/trash", Value::Null).await;
+        assert_eq!(trash["lists"][0]["name"], "Garden");
+        assert_eq!(titles(&trash["todos"]), ["edges", "mow"]);
+        published(&mut events);
+        let uri = format!("/trash/list/{garden_id}/restore");
+        let (status, restored) = call(&app, "POST", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(restored["name"], "Garden");
+        assert_eq!(
+            published(&mut events),
+            ["list.restored", "todo.restored", "todo.restored"]
+        );
+        let uri = format!("/todo?list_id={garden_id}");
+        let (_, todos) = call(&app, "GET", &uri, Value::Null).await;
+        assert_eq!(titles(&todos), ["edges", "mow"]);
+
+        // A todo whose list is still in the trash comes back to the inbox:
+        call(&app, "DELETE", &format!("/todo/{mow}"), Value::Null).await;
+        let uri = format!("/list/{garden_id}?todos=delete");
+        call(&app, "DELETE", &uri, Value::Null).await;
+        let uri = format!("/trash/todo/{mow}/restore");
+        let (_, restored) = call(&app, "POST", &uri, Value::Null).await;
+        assert_ne!(restored["list_id"], garden["id"]);
+        let (_, lists) = call(&app, "GET", "/list", Value::Null).await;
+        assert_eq!(lists.as_array().unwrap().len(), 1);
+        assert_eq!(restored["list_id"], lists[0]["id"]);
+
+        // The purge deletes what was trashed before its cutoff for good:
+        call(&app, "DELETE", &format!("/todo/{mow}"), Value::Null).await;
+        let purged = state
+            .trash
+            .purge(Utc::now() - Duration::days(1))
+            .await
+            .unwrap();
+        assert_eq!(purged, 0);
+        let purged = state
+            .trash
+            .purge(Utc::now() + Duration::seconds(1))
+            .await
+            .unwrap();
+        assert_eq!(purged, 3);
+        let (_, trash) = call(&app, "GET", "/trash", Value::Null).await;
+        assert_eq!(trash, json!({"lists": [], "todos": []}));
+        let (_, todos) = call(&app, "GET", "/todo", Value::Null).await;
+        assert_eq!(titles(&todos), ["dishes"]);
+    }
+}
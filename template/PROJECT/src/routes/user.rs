@@ -1,49 +1,237 @@
-use crate::models::user::User;
-use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
-use chrono::Utc;
-use uuid::Uuid;
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    routing::{get, post},
+    Json, Router,
+};
 
+use super::conditional;
 use crate::{
-    errors::internal_error,
+    audit::{self, Change},
+    errors::{internal_error, not_found_error},
+    events,
+    extract::{Admin, Authorize, Origin},
     models::{
+        audit::Entity,
         ids::UserId,
-        user::{CreateUser, PublicUser},
+        user::{CreateUser, PublicUser, UpdateUser, User},
+        webhook::Event,
     },
     AppState,
 };
 
 pub fn router() -> Router<AppState> {
-    Router::<AppState>::new().route("/", post(create_user))
-    //.route("/{user_id}", get(get_user))
+    Router::<AppState>::new()
+        .route("/", post(create_user))
+        .route(
+            "/{user_id}",
+            get(get_user).patch(update_user).delete(delete_user),
+        )
+}
+
+/// The caller, if they are user `id`, or 404.
+fn own_account(user: User, id: UserId) -> Result<User, (StatusCode, String)> {
+    if user.id == id {
+        Ok(user)
+    } else {
+        Err(not_found_error(format!("No user {}", id.0)))
+    }
 }
 
+/// 409 if a user (outside the trash) is registered for `email`.
+pub(super) async fn check_email_free(
+    state: &AppState,
+    email: &str,
+) -> Result<(), (StatusCode, String)> {
+    match state.users.find_by_email(email).await {
+        Ok(None) => Ok(()),
+        Ok(Some(_)) => Err((
+            StatusCode::CONFLICT,
+            format!("A user is already registered for {email}"),
+        )),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+/// Register the caller. Only admins may register anyone else.
 async fn create_user(
     State(state): State<AppState>,
+    origin: Origin,
+    admin: Result<Admin, (StatusCode, String)>,
     Json(payload): Json<CreateUser>,
 ) -> Result<(StatusCode, Json<PublicUser>), (StatusCode, String)> {
-    let id = UserId(Uuid::new_v4());
-    let now = Utc::now();
-    let created_at = now.timestamp();
-
-    let user = sqlx::query_as!(
-        User,
-        r#"
-        INSERT INTO users (id, email, display_name, created_at)
-        VALUES (?, ?, ?, ?)
-        RETURNING
-          id             as "id: UserId",
-          email,
-          display_name,
-          created_at
-        "#,
-        id,
-        payload.email,
-        payload.display_name,
-        created_at
+    match origin.actor.as_deref() {
+        None => return Err((StatusCode::UNAUTHORIZED, "Unauthenticated".to_string())),
+        Some(email) if email != payload.email && admin.is_err() => {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Only admins may register other users".to_string(),
+            ))
+        }
+        Some(_) => {}
+    }
+    check_email_free(&state, &payload.email).await?;
+    let user = state
+        .users
+        .insert(&User::new(&payload.email, &payload.display_name))
+        .await
+        .map_err(internal_error)?;
+    let user = PublicUser::from(user);
+    audit::record(
+        &state,
+        &origin,
+        [Change::created(Entity::User, user.id.0, &user)],
     )
-    .fetch_one(&state.db)
-    .await
-    .map_err(internal_error)?;
+    .await;
+    events::publish(&state, user.id, Event::UserCreated, &user).await;
+
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+async fn get_user(
+    Authorize { user, .. }: Authorize,
+    Path(id): Path<UserId>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let user = own_account(user, id)?;
+    Ok(conditional::versioned(
+        &headers,
+        user.version,
+        Json(PublicUser::from(user)),
+    ))
+}
+
+/// Change the caller's display name, if they still match any `If-Match`
+/// header.
+async fn update_user(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<UserId>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateUser>,
+) -> Result<Response, (StatusCode, String)> {
+    let user = own_account(user, id)?;
+    let before = PublicUser::from(user.clone());
+    conditional::check_if_match(&headers, user.version, "user")?;
+    let display_name = match payload.display_name {
+        Some(name) if name.trim().is_empty() => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "display_name must not be empty".to_string(),
+            ))
+        }
+        Some(name) => name.trim().to_string(),
+        None => user.display_name,
+    };
+    let user = state
+        .users
+        .set_display_name(user.id, &display_name, Some(user.version))
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| conditional::changed_error(&headers, "user"))?;
+    let user = PublicUser::from(user);
+    let change = Change::updated(Entity::User, id.0, &before, &user);
+    audit::record(&state, &origin, [change]).await;
+    events::publish(&state, user.id, Event::UserUpdated, &user).await;
+    Ok(conditional::with_etag(user.version, Json(user)))
+}
+
+/// Move the caller's account with all of their data to the trash (from
+/// which only `user restore` brings it back), if they still match any
+/// `If-Match` header.
+async fn delete_user(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<UserId>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let user = own_account(user, id)?;
+    conditional::check_if_match(&headers, user.version, "user")?;
+    let deleted = state
+        .users
+        .delete(user.id, Some(user.version))
+        .await
+        .map_err(internal_error)?;
+    if !deleted {
+        return Err(conditional::changed_error(&headers, "user"));
+    }
+    let user = PublicUser::from(user);
+    audit::record(
+        &state,
+        &origin,
+        [Change::deleted(Entity::User, id.0, &user)],
+    )
+    .await;
+    events::publish(&state, user.id, Event::UserDeleted, &user).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::{json, Value};
+
+    use crate::{
+        db::Database,
+        routes::{call, call_with, test_app_with, TEST_USER},
+    };
+
+    fn new_user(email: &str) -> Value {
+        json!({"email": email, "display_name": "A"})
+    }
+
+    #[tokio::test]
+    async fn create_user_rejects_duplicate_email() {
+        let app = test_app_with(&Database::memory(Default::default()));
+
+        let (status, user) = call(&app, "POST", "/user", new_user("a@example.com")).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(user["email"], "a@example.com");
+
+        let (status, _) = call(&app, "POST", "/user", new_user("a@example.com")).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    crate::db::backend_test!(trashed_emails_can_register_again);
+
+    async fn trashed_emails_can_register_again(db: &Database) {
+        let app = &test_app_with(db);
+        let (status, user) = call(app, "POST", "/user", new_user("a@example.com")).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let uri = format!("/user/{}", user["id"].as_str().unwrap());
+        let (status, _) = call(app, "DELETE", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, again) = call(app, "POST", "/user", new_user("a@example.com")).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_ne!(again["id"], user["id"]);
+    }
+
+    #[tokio::test]
+    async fn user_writes_check_etags() {
+        let app = test_app_with(&Database::memory(Default::default()));
+        let (_, user) = call(&app, "POST", "/user", new_user("a@example.com")).await;
+        let uri = format!("/user/{}", user["id"].as_str().unwrap());
+
+        let (status, headers, _) = call_with(&app, "GET", &uri, &[TEST_USER], Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["etag"], "\"1\"");
+        let cached = [TEST_USER, ("if-none-match", "\"1\"")];
+        let (status, ..) = call_with(&app, "GET", &uri, &cached, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
 
-    Ok((StatusCode::CREATED, Json(user.into())))
+        let rename = json!({"display_name": "B"});
+        let stale = [TEST_USER, ("if-match", "\"1\"")];
+        let (status, headers, _) = call_with(&app, "PATCH", &uri, &stale, rename).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["etag"], "\"2\"");
+        let (status, ..) = call_with(&app, "DELETE", &uri, &stale, Value::Null).await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        let current = [TEST_USER, ("if-match", "\"2\"")];
+        let (status, ..) = call_with(&app, "DELETE", &uri, &current, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
 }
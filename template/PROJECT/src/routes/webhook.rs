@@ -0,0 +1,369 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::Utc;
+use serde::Deserialize;
+
+use crate::{
+    audit::{self, Change},
+    errors::{internal_error, not_found_error},
+    extract::{Authorize, Origin},
+    models::{
+        audit::Entity,
+        ids::{DeliveryId, UserId, WebhookId},
+        webhook::{
+            normalize_webhook_url, CreateWebhook, Delivery, EventFilter, NewWebhook, Webhook,
+        },
+    },
+    token, webhook, AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    // this router is responsible for everything under `/webhook`
+    Router::<AppState>::new()
+        .route("/", get(list_webhooks).post(create_webhook))
+        .route("/{webhook_id}", get(get_webhook).delete(delete_webhook))
+        .route("/{webhook_id}/deliveries", get(list_deliveries))
+        .route(
+            "/{webhook_id}/deliveries/{delivery_id}/redeliver",
+            post(redeliver),
+        )
+}
+
+/// The caller's webhook `id`, or 404 if it belongs to someone else (or
+/// is global).
+async fn owned_webhook(
+    state: &AppState,
+    user_id: UserId,
+    id: WebhookId,
+) -> Result<Webhook, (StatusCode, String)> {
+    match state.webhooks.get(id).await.map_err(internal_error)? {
+        Some(webhook) if webhook.user_id == Some(user_id) => Ok(webhook),
+        _ => Err(not_found_error(format!("No webhook {}", id.0))),
+    }
+}
+
+/// The caller's webhooks (without their secrets).
+async fn list_webhooks(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+) -> Result<Json<Vec<Webhook>>, (StatusCode, String)> {
+    let webhooks = state
+        .webhooks
+        .list(Some(user.id))
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(webhooks))
+}
+
+/// Register a webhook for events about the caller's data. The secret its
+/// payloads are signed with is only returned this once.
+async fn create_webhook(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Json(payload): Json<CreateWebhook>,
+) -> Result<(StatusCode, Json<NewWebhook>), (StatusCode, String)> {
+    let url = normalize_webhook_url(&payload.url).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let events = EventFilter::new(&payload.events).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    webhook::check_destination(&url, state.allow_local_webhooks)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let secret = token::generate();
+    let webhook = state
+        .webhooks
+        .insert(&Webhook::new(Some(user.id), &url, events, &secret))
+        .await
+        .map_err(internal_error)?;
+    let change = Change::created(Entity::Webhook, webhook.id.0, &webhook);
+    audit::record(&state, &origin, [change]).await;
+    Ok((StatusCode::CREATED, Json(NewWebhook { webhook, secret })))
+}
+
+async fn get_webhook(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    Path(id): Path<WebhookId>,
+) -> Result<Json<Webhook>, (StatusCode, String)> {
+    owned_webhook(&state, user.id, id).await.map(Json)
+}
+
+/// Delete a webhook along with its delivery log.
+async fn delete_webhook(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path(id): Path<WebhookId>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let webhook = owned_webhook(&state, user.id, id).await?;
+    if state.webhooks.delete(id).await.map_err(internal_error)? {
+        let change = Change::deleted(Entity::Webhook, id.0, &webhook);
+        audit::record(&state, &origin, [change]).await;
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(not_found_error(format!("No webhook {}", id.0)))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DeliveryParams {
+    limit: Option<i64>,
+}
+
+/// How many deliveries to list by default.
+const DEFAULT_DELIVERIES: i64 = 50;
+/// The most deliveries to list at once.
+const MAX_DELIVERIES: i64 = 500;
+
+/// A webhook's latest deliveries, newest first: pending ones, delivered
+/// ones, and failed ones that ran out of attempts.
+async fn list_deliveries(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    Path(id): Path<WebhookId>,
+    Query(params): Query<DeliveryParams>,
+) -> Result<Json<Vec<Delivery>>, (StatusCode, String)> {
+    owned_webhook(&state, user.id, id).await?;
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_DELIVERIES)
+        .clamp(1, MAX_DELIVERIES);
+    let deliveries = state
+        .webhooks
+        .deliveries(id, limit)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(deliveries))
+}
+
+/// Send a delivery again, with a fresh set of attempts, whether it was
+/// delivered or failed.
+async fn redeliver(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    Path((id, delivery_id)): Path<(WebhookId, DeliveryId)>,
+) -> Result<(StatusCode, Json<Delivery>), (StatusCode, String)> {
+    owned_webhook(&state, user.id, id).await?;
+    let delivery = match state
+        .webhooks
+        .get_delivery(delivery_id)
+        .await
+        .map_err(internal_error)?
+    {
+        Some(delivery) if delivery.webhook_id == id => delivery,
+        _ => return Err(not_found_error(format!("No delivery {}", delivery_id.0))),
+    };
+    let retried = Delivery {
+        attempts: 0,
+        next_attempt_at: Some(Utc::now()),
+        delivered_at: None,
+        ..delivery.clone()
+    };
+    let retried = state
+        .webhooks
+        .update_delivery(&retried)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| not_found_error(format!("No delivery {}", delivery_id.0)))?;
+    let change = Change::updated(Entity::Delivery, delivery_id.0, &delivery, &retried);
+    audit::record(&state, &origin, [change]).await;
+    let delivery = retried;
+    Ok((StatusCode::ACCEPTED, Json(delivery)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex,
+    };
+
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+    use serde_json::{json, Value};
+
+    use crate::{
+        db::Database,
+        routes::{call_as, test_app_with, test_app_with_state, test_state},
+        webhook, AppState,
+    };
+
+    /// A local HTTP server that records the requests it gets and answers
+    /// them with a settable status code.
+    #[derive(Clone, Default)]
+    struct Receiver {
+        requests: Arc<Mutex<Vec<(HeaderMap, String)>>>,
+        status: Arc<AtomicU16>,
+    }
+
+    impl Receiver {
+        /// Start receiving, returning the URL to post to.
+        async fn start(&self) -> String {
+            self.status.store(200, Ordering::SeqCst);
+            async fn receive(
+                State(receiver): State<Receiver>,
+                headers: HeaderMap,
+                body: Bytes,
+            ) -> StatusCode {
+                let body = String::from_utf8(body.to_vec()).unwrap();
+                receiver.requests.lock().unwrap().push((headers, body));
+                StatusCode::from_u16(receiver.status.load(Ordering::SeqCst)).unwrap()
+            }
+            let app = Router::new()
+                .route("/hook", post(receive))
+                .with_state(self.clone());
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            format!("http://{addr}/hook")
+        }
+
+        fn received(&self) -> Vec<(HeaderMap, String)> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    crate::db::backend_test!(webhooks_deliver_signed_events);
+
+    async fn webhooks_deliver_signed_events(db: &Database) {
+        // The receiver is local, which only ALLOW_LOCAL_WEBHOOKS permits:
+        let app = test_app_with_state(AppState {
+            allow_local_webhooks: true,
+            ..test_state(db)
+        });
+        let webhooks = &db.repos.webhooks;
+        let client = webhook::client(true);
+        let receiver = Receiver::default();
+        let url = receiver.start().await;
+        let a = "a@example.com";
+        for email in [a, "b@example.com"] {
+            let user = json!({"email": email, "display_name": "A"});
+            let (status, _) = call_as(&app, email, "POST", "/user", user).await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+
+        let bad = json!({"url": "ftp://example.com", "events": ["todo.created"]});
+        let (status, _) = call_as(&app, a, "POST", "/webhook", bad).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let bad = json!({"url": url, "events": ["todo.eaten"]});
+        let (status, _) = call_as(&app, a, "POST", "/webhook", bad).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let strict = test_app_with(db);
+        for local in [
+            url.as_str(),
+            "http://10.1.2.3/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://localhost/hook",
+        ] {
+            let hook = json!({"url": local, "events": ["todo.created"]});
+            let (status, _) = call_as(&strict, a, "POST", "/webhook", hook).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{local}");
+        }
+        let hook = json!({"url": url, "events": ["todo.created", "todo.completed"]});
+        let (status, hook) = call_as(&app, a, "POST", "/webhook", hook).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let id = hook["id"].as_str().unwrap();
+        let secret = hook["secret"].as_str().unwrap();
+        let (_, list) = call_as(&app, a, "GET", "/webhook", Value::Null).await;
+        assert_eq!(list.as_array().unwrap().len(), 1);
+        assert!(list[0].get("secret").is_none());
+
+        // Events are queued in the outbox and delivered, signed, later:
+        let todos = json!([{"title": "water plants"}]);
+        let (status, _) = call_as(&app, a, "POST", "/import", todos).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(receiver.received().is_empty());
+        let sent = webhook::deliver_due(webhooks.as_ref(), &client, true).await;
+        assert_eq!(sent.unwrap(), 1);
+        let (headers, body) = receiver.received().pop().unwrap();
+        assert_eq!(headers[webhook::EVENT_HEADER], "todo.created");
+        assert_eq!(
+            headers[webhook::SIGNATURE_HEADER].to_str().unwrap(),
+            webhook::signature(secret, &body)
+        );
+        let payload: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(payload["event"], "todo.created");
+        assert_eq!(
+            payload["id"].as_str().unwrap(),
+            headers[webhook::DELIVERY_HEADER]
+        );
+        assert_eq!(payload["data"]["title"], "water plants");
+        let todo_id = payload["data"]["id"].as_str().unwrap().to_string();
+
+        // A failed delivery is retried later, not right away:
+        receiver.status.store(500, Ordering::SeqCst);
+        let uri = format!("/todo/{todo_id}/completed");
+        let (status, _) = call_as(&app, a, "PUT", &uri, json!({"completed": true})).await;
+        assert_eq!(status, StatusCode::OK);
+        let sent = webhook::deliver_due(webhooks.as_ref(), &client, true).await;
+        assert_eq!(sent.unwrap(), 1);
+        let sent = webhook::deliver_due(webhooks.as_ref(), &client, true).await;
+        assert_eq!(sent.unwrap(), 0);
+        assert_eq!(receiver.received().len(), 2);
+        let uri = format!("/webhook/{id}/deliveries");
+        let (status, log) = call_as(&app, a, "GET", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(log[0]["event"], "todo.completed");
+        assert_eq!(log[0]["attempts"], 1);
+        assert_eq!(log[0]["last_status"], 500);
+        assert!(log[0]["next_attempt_at"].is_string() && log[0]["delivered_at"].is_null());
+        assert_eq!(log[1]["event"], "todo.created");
+        assert!(log[1]["delivered_at"].is_string() && log[1]["next_attempt_at"].is_null());
+
+        // ...or can be sent again by hand:
+        receiver.status.store(204, Ordering::SeqCst);
+        let delivery = log[0]["id"].as_str().unwrap();
+        let uri = format!("/webhook/{id}/deliveries/{delivery}/redeliver");
+        let (status, _) = call_as(&app, "b@example.com", "POST", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call_as(&app, a, "POST", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let sent = webhook::deliver_due(webhooks.as_ref(), &client, true).await;
+        assert_eq!(sent.unwrap(), 1);
+        let uri = format!("/webhook/{id}/deliveries?limit=1");
+        let (_, log) = call_as(&app, a, "GET", &uri, Value::Null).await;
+        assert_eq!(log.as_array().unwrap().len(), 1);
+        assert_eq!(log[0]["last_status"], 204);
+        assert!(log[0]["delivered_at"].is_string());
+
+        // Unsubscribed events and other users' changes are not sent:
+        let todos = json!([{"title": "b's todo"}]);
+        call_as(&app, "b@example.com", "POST", "/import", todos).await;
+        let uri = format!("/todo/{todo_id}/completed");
+        call_as(&app, a, "PUT", &uri, json!({"completed": false})).await;
+        let sent = webhook::deliver_due(webhooks.as_ref(), &client, true).await;
+        assert_eq!(sent.unwrap(), 0);
+
+        // Without ALLOW_LOCAL_WEBHOOKS, local URLs are refused when delivering too:
+        let todos = json!([{"title": "feed cat"}]);
+        call_as(&app, a, "POST", "/import", todos).await;
+        let strict_client = webhook::client(false);
+        let sent = webhook::deliver_due(webhooks.as_ref(), &strict_client, false).await;
+        assert_eq!(sent.unwrap(), 1);
+        assert_eq!(receiver.received().len(), 3);
+        let uri = format!("/webhook/{id}/deliveries?limit=1");
+        let (_, log) = call_as(&app, a, "GET", &uri, Value::Null).await;
+        let error = log[0]["last_error"].as_str().unwrap();
+        assert!(error.contains("local or private"), "{error}");
+
+        let uri = format!("/webhook/{id}");
+        let (status, _) = call_as(&app, "b@example.com", "DELETE", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call_as(&app, a, "DELETE", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let uri = format!("/webhook/{id}/deliveries");
+        let (status, _) = call_as(&app, a, "GET", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -1,28 +1,46 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 use axum::{
     extract::ConnectInfo,
-    http::{HeaderMap, StatusCode},
+    http::{header, HeaderMap, HeaderName, StatusCode},
     response::IntoResponse,
     routing::get,
     Extension, Router,
 };
 
 use crate::{
-    middleware::{AuthenticatedUser, ClientIp},
+    middleware::{
+        AuthenticatedGroups, AuthenticatedName, AuthenticatedUser, BearerToken, ClientIp, RealUser,
+    },
     AppState,
 };
 
-pub fn router() -> Router<AppState> {
-    // this router is responsible for everything under `/hello`
-    Router::<AppState>::new().route("/", get(whoami_default))
+/// Headers besides the standard credential ones whose values are not
+/// echoed: those the trusted proxy vouches for the user with.
+#[derive(Clone, Debug)]
+pub struct SecretHeaders(pub Arc<[HeaderName]>);
+
+pub fn router(secret_headers: SecretHeaders) -> Router<AppState> {
+    // this router is responsible for everything under `/whoami`
+    Router::<AppState>::new()
+        .route("/", get(whoami_default))
+        .layer(Extension(secret_headers))
 }
 
 async fn whoami_default(
-    user: Option<Extension<AuthenticatedUser>>,
+    // The user, and the admin acting as them (if any).
+    (user, real_user): (
+        Option<Extension<AuthenticatedUser>>,
+        Option<Extension<RealUser>>,
+    ),
     client_ip: Option<Extension<ClientIp>>,
+    groups: Option<Extension<AuthenticatedGroups>>,
+    name: Option<Extension<AuthenticatedName>>,
+    bearer: Option<Extension<BearerToken>>,
     ConnectInfo(peer): ConnectInfo<SocketAddr>,
-    headers: HeaderMap,
+    // The request's headers, and which of them to redact.
+    (headers, Extension(SecretHeaders(secret_headers))): (HeaderMap, Extension<SecretHeaders>),
 ) -> impl IntoResponse {
     let email = match user {
         Some(Extension(AuthenticatedUser(email))) => email,
@@ -34,14 +52,39 @@ async fn whoami_default(
         .map(|Extension(ClientIp(ip))| ip)
         .unwrap_or(peer_ip);
 
+    let mut claims = String::new();
+    if let Some(Extension(RealUser(real))) = real_user {
+        claims.push_str(&format!("real_user={real}\n"));
+    }
+    if let Some(Extension(AuthenticatedGroups(groups))) = groups {
+        claims.push_str(&format!("groups={}\n", groups.join(",")));
+    }
+    if let Some(Extension(AuthenticatedName(name))) = name {
+        claims.push_str(&format!("name={name}\n"));
+    }
+
+    let api_token = match bearer {
+        Some(Extension(BearerToken(id))) => format!("api_token={}\n", id.0),
+        None => String::new(),
+    };
+
     let mut hdr_lines = String::new();
     for (name, value) in headers.iter() {
-        let val_str = value.to_str().unwrap_or("<non-utf8>");
+        // Don't echo credentials, which would end up in scripts' logs.
+        let secret = name == header::AUTHORIZATION
+            || name == header::PROXY_AUTHORIZATION
+            || name == header::COOKIE
+            || secret_headers.contains(name);
+        let val_str = if secret {
+            "<redacted>"
+        } else {
+            value.to_str().unwrap_or("<non-utf8>")
+        };
         hdr_lines.push_str(&format!("{name}: {val_str}\n"));
     }
 
     let body =
-        format!("identity:\nuser={email}\npeer_ip={peer_ip}\nclient_ip={client_ip}\n\nheaders:\n{hdr_lines}");
+        format!("identity:\nuser={email}\n{claims}{api_token}peer_ip={peer_ip}\nclient_ip={client_ip}\n\nheaders:\n{hdr_lines}");
 
     (StatusCode::OK, body)
 }
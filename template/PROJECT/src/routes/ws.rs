@@ -0,0 +1,557 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    http::StatusCode,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{
+    audit,
+    errors::{internal_error, not_found_error},
+    events::{self, Change, Received},
+    extract::{Authorize, Origin},
+    models::{
+        audit::Entity,
+        ids::{ListId, TodoId},
+        todo::Todo,
+        user::User,
+        webhook::Event,
+        FIRST_VERSION,
+    },
+    repository::TodoFilter,
+    AppState,
+};
+
+/// The largest message a client may send.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+/// How long a client may take to accept a message before it is dropped.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often an idle connection gets a ping, to keep proxies from
+/// closing it.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A message from the client. Its `id` field, if any, is echoed in the
+/// reply.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Action {
+    /// Get a list with its todos, and events for its changes from now on.
+    Subscribe {
+        list_id: ListId,
+    },
+    Unsubscribe {
+        list_id: ListId,
+    },
+    Mutate(Mutation),
+}
+
+/// A change to make to one of the caller's todos.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Mutation {
+    Create {
+        list_id: ListId,
+        title: String,
+        notes: Option<String>,
+        due_at: Option<DateTime<Utc>>,
+    },
+    /// Change a todo's title and notes (an empty string clears them).
+    Update {
+        todo_id: TodoId,
+        title: Option<String>,
+        notes: Option<String>,
+    },
+    Complete {
+        todo_id: TodoId,
+        completed: bool,
+    },
+    Move {
+        todo_id: TodoId,
+        list_id: ListId,
+    },
+    Delete {
+        todo_id: TodoId,
+    },
+}
+
+/// A message to the client.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    /// A request succeeded, with its result.
+    Ack { id: Value, data: Value },
+    Error {
+        id: Value,
+        status: u16,
+        message: String,
+    },
+    /// A change to a subscribed list or one of its todos.
+    Event {
+        event_id: u64,
+        event: &'static str,
+        data: Value,
+    },
+    /// Some events were missed, so subscriptions should be renewed.
+    Reset,
+}
+
+/// A WebSocket for subscribing to the caller's lists and changing their
+/// todos, with JSON messages.
+pub(super) async fn connect(
+    State(state): State<AppState>,
+    Authorize { user, .. }: Authorize,
+    origin: Origin,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.max_message_size(MAX_MESSAGE_SIZE)
+        .on_upgrade(move |socket| serve(socket, state, user, origin))
+}
+
+/// What a connection is subscribed to.
+struct Connection {
+    state: AppState,
+    user: User,
+    /// Where the connection came from, for the audit log.
+    origin: Origin,
+    lists: HashSet<ListId>,
+    /// The todos known to be in subscribed lists, so that clients hear
+    /// about those moving out too.
+    todos: HashMap<TodoId, ListId>,
+}
+
+async fn serve(mut socket: WebSocket, state: AppState, user: User, origin: Origin) {
+    let mut subscription = state.events.subscribe(user.id, None);
+    let mut connection = Connection {
+        state,
+        user,
+        origin,
+        lists: HashSet::new(),
+        todos: HashMap::new(),
+    };
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    ping.tick().await;
+    loop {
+        // Requests are handled one at a time and replies awaited, so a
+        // client that does not read its replies stops being read from.
+        let reply = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => Some(connection.handle(&text).await),
+                Some(Ok(Message::Binary(_))) => {
+                    Some(error(Value::Null, bad_request("Expected a text message")))
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                // Pings are answered by the socket itself.
+                Some(Ok(_)) => None,
+            },
+            received = subscription.next() => match received {
+                Some(Received::Change(change)) => connection.event(&change),
+                Some(Received::Missed) => Some(Reply::Reset),
+                None => {
+                    let close = CloseFrame {
+                        code: close_code::AWAY,
+                        reason: "Server is shutting down".into(),
+                    };
+                    let _ = tokio::time::timeout(
+                        SEND_TIMEOUT,
+                        socket.send(Message::Close(Some(close))),
+                    )
+                    .await;
+                    return;
+                }
+            },
+            _ = ping.tick() => {
+                if socket.send(Message::Ping(Default::default())).await.is_err() {
+                    return;
+                }
+                None
+            }
+        };
+        let Some(reply) = reply else { continue };
+        if !send(&mut socket, &reply).await {
+            return;
+        }
+    }
+}
+
+/// Send a reply, giving up on clients that do not take it in time.
+async fn send(socket: &mut WebSocket, reply: &Reply) -> bool {
+    let text = serde_json::to_string(reply).expect("replies serialize");
+    matches!(
+        tokio::time::timeout(SEND_TIMEOUT, socket.send(Message::Text(text.into()))).await,
+        Ok(Ok(()))
+    )
+}
+
+fn error(id: Value, (status, message): (StatusCode, String)) -> Reply {
+    Reply::Error {
+        id,
+        status: status.as_u16(),
+        message,
+    }
+}
+
+fn bad_request(message: impl Into<String>) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, message.into())
+}
+
+/// For a todo changed by another request while being changed.
+fn changed_error() -> (StatusCode, String) {
+    (
+        StatusCode::CONFLICT,
+        "The todo was changed by another request".to_string(),
+    )
+}
+
+/// A field of event data, if it is there and of the right type.
+fn field<T: DeserializeOwned>(data: &Value, name: &str) -> Option<T> {
+    serde_json::from_value(data.get(name)?.clone()).ok()
+}
+
+fn to_value(data: impl Serialize) -> Value {
+    serde_json::to_value(data).expect("models serialize")
+}
+
+impl Connection {
+    async fn handle(&mut self, text: &str) -> Reply {
+        let message: Value = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(e) => return error(Value::Null, bad_request(format!("Invalid JSON: {e}"))),
+        };
+        let id = message.get("id").cloned().unwrap_or_default();
+        let action = match serde_json::from_value(message) {
+            Ok(action) => action,
+            Err(e) => return error(id, bad_request(format!("Invalid message: {e}"))),
+        };
+        match self.act(action).await {
+            Ok(data) => Reply::Ack { id, data },
+            Err(e) => error(id, e),
+        }
+    }
+
+    async fn act(&mut self, action: Action) -> Result<Value, (StatusCode, String)> {
+        let state = &self.state;
+        let user_id = self.user.id;
+        match action {
+            Action::Subscribe { list_id } => {
+                let list = super::list::owned_list(state, user_id, list_id).await?;
+                let todos = state
+                    .todos
+                    .list(TodoFilter {
+                        user_id: Some(user_id),
+                        list_id: Some(list.id),
+                        ..Default::default()
+                    })
+                    .await
+                    .map_err(internal_error)?;
+                self.lists.insert(list.id);
+                self.todos.extend(todos.iter().map(|t| (t.id, t.list_id)));
+                Ok(json!({ "list": list, "todos": todos }))
+            }
+            Action::Unsubscribe { list_id } => {
+                if !self.lists.remove(&list_id) {
+                    return Err(not_found_error(format!(
+                        "Not subscribed to list {}",
+                        list_id.0
+                    )));
+                }
+                self.todos.retain(|_, id| *id != list_id);
+                Ok(Value::Null)
+            }
+            Action::Mutate(mutation) => self.mutate(mutation).await,
+        }
+    }
+
+    async fn mutate(&self, mutation: Mutation) -> Result<Value, (StatusCode, String)> {
+        let state = &self.state;
+        let origin = &self.origin;
+        let user_id = self.user.id;
+        match mutation {
+            Mutation::Create {
+                list_id,
+                title,
+                notes,
+                due_at,
+            } => {
+                let list = super::list::owned_list(state, user_id, list_id).await?;
+                let title = title.trim();
+                if title.is_empty() {
+                    return Err(bad_request("title must not be empty"));
+                }
+                let now = Utc::now();
+                let todo = Todo {
+                    id: TodoId(Uuid::new_v4()),
+                    user_id,
+                    list_id: list.id,
+                    parent_id: None,
+                    title: title.to_string(),
+                    notes: notes.filter(|n| !n.is_empty()),
+                    completed: false,
+                    due_at,
+                    rrule: None,
+                    repeat_from_completion: false,
+                    created_at: now,
+                    updated_at: now,
+                    version: FIRST_VERSION,
+                    deleted_at: None,
+                };
+                let failed = state
+                    .todos
+                    .insert_many(std::slice::from_ref(&todo), false)
+                    .await
+                    .map_err(internal_error)?;
+                if let Some((_, e)) = failed.into_iter().next() {
+                    return Err(bad_request(e));
+                }
+                let change = audit::Change::created(Entity::Todo, todo.id.0, &todo);
+                audit::record(state, origin, [change]).await;
+                events::publish(state, user_id, Event::TodoCreated, &todo).await;
+                Ok(to_value(todo))
+            }
+            Mutation::Update {
+                todo_id,
+                title,
+                notes,
+            } => {
+                let before = super::todo::owned_todo(state, user_id, todo_id).await?;
+                let mut todo = before.clone();
+                if let Some(title) = title {
+                    let title = title.trim();
+                    if title.is_empty() {
+                        return Err(bad_request("title must not be empty"));
+                    }
+                    todo.title = title.to_string();
+                }
+                if let Some(notes) = notes {
+                    todo.notes = Some(notes).filter(|n| !n.is_empty());
+                }
+                let todo = state
+                    .todos
+                    .update(&todo)
+                    .await
+                    .map_err(internal_error)?
+                    .ok_or_else(changed_error)?;
+                let change = audit::Change::updated(Entity::Todo, todo.id.0, &before, &todo);
+                audit::record(state, origin, [change]).await;
+                events::publish(state, user_id, Event::TodoUpdated, &todo).await;
+                Ok(to_value(todo))
+            }
+            Mutation::Complete { todo_id, completed } => {
+                super::todo::complete(state, origin, user_id, todo_id, completed, false)
+                    .await
+                    .map(to_value)
+            }
+            Mutation::Move { todo_id, list_id } => {
+                super::todo::move_to_list(state, origin, user_id, todo_id, list_id)
+                    .await
+                    .map(to_value)
+            }
+            Mutation::Delete { todo_id } => {
+                let todo = super::todo::owned_todo(state, user_id, todo_id).await?;
+                let deleted = state
+                    .todos
+                    .delete(todo.id, Some(todo.version))
+                    .await
+                    .map_err(internal_error)?;
+                if !deleted {
+                    return Err(changed_error());
+                }
+                let change = audit::Change::deleted(Entity::Todo, todo.id.0, &todo);
+                audit::record(state, origin, [change]).await;
+                events::publish(state, user_id, Event::TodoDeleted, &todo).await;
+                Ok(to_value(todo))
+            }
+        }
+    }
+
+    /// The event to send for a change, if it concerns a subscribed list
+    /// or a todo in one (or one that just left one).
+    fn event(&mut self, change: &Change) -> Option<Reply> {
+        let relevant = match change.event {
+            Event::ListCreated | Event::ListUpdated | Event::ListDeleted | Event::ListRestored => {
+                let list_id: ListId = field(&change.data, "id")?;
+                if change.event == Event::ListDeleted && self.lists.remove(&list_id) {
+                    self.todos.retain(|_, id| *id != list_id);
+                    true
+                } else {
+                    self.lists.contains(&list_id)
+                }
+            }
+            Event::TodoCreated
+            | Event::TodoUpdated
+            | Event::TodoCompleted
+            | Event::TodoDeleted
+            | Event::TodoRestored => {
+                let todo_id: TodoId = field(&change.data, "id")?;
+                let list_id: ListId = field(&change.data, "list_id")?;
+                let subscribed = self.lists.contains(&list_id);
+                let known = if subscribed && change.event != Event::TodoDeleted {
+                    self.todos.insert(todo_id, list_id)
+                } else {
+                    self.todos.remove(&todo_id)
+                };
+                subscribed || known.is_some()
+            }
+            Event::UserCreated | Event::UserUpdated | Event::UserDeleted | Event::UserRestored => {
+                false
+            }
+        };
+        relevant.then(|| Reply::Event {
+            event_id: change.id,
+            event: change.event.name(),
+            data: change.data.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::StatusCode;
+    use futures_util::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::{
+        tungstenite::{client::IntoClientRequest, protocol::frame::coding::CloseCode, Message},
+        MaybeTlsStream, WebSocketStream,
+    };
+
+    use crate::routes::{call, test_app_with_state, test_state};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn send(client: &mut Client, message: Value) {
+        client
+            .send(Message::text(message.to_string()))
+            .await
+            .unwrap();
+    }
+
+    /// The next message from the server, other than pings.
+    async fn next(client: &mut Client) -> Message {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), client.next())
+                .await
+                .expect("a message")
+                .unwrap()
+                .unwrap();
+            if !matches!(message, Message::Ping(_) | Message::Pong(_)) {
+                return message;
+            }
+        }
+    }
+
+    async fn next_json(client: &mut Client) -> Value {
+        match next(client).await {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("expected text, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn ws_subscribes_and_mutates() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_url = format!("sqlite://{}", dir.path().join("test.db").display());
+        let db = crate::db::connect_pools(&crate::db::DbConfig::new(&db_url))
+            .await
+            .unwrap();
+        db.migrate().await.unwrap();
+        let state = test_state(&db);
+        let app = test_app_with_state(state.clone());
+        let user = json!({"email": "a@example.com", "display_name": "A"});
+        assert_eq!(
+            call(&app, "POST", "/user", user).await.0,
+            StatusCode::CREATED
+        );
+        let (_, list) = call(&app, "POST", "/list", json!({"name": "Garden"})).await;
+        let (_, lists) = call(&app, "GET", "/list", Value::Null).await;
+        let inbox = lists
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|l| l["inbox"] == true)
+            .unwrap()["id"]
+            .clone();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, app.clone()).into_future());
+        let mut req = format!("ws://{addr}/ws").into_client_request().unwrap();
+        req.headers_mut()
+            .insert("x-forwarded-user", "a@example.com".parse().unwrap());
+        let (mut client, _) = tokio_tungstenite::connect_async(req).await.unwrap();
+
+        send(
+            &mut client,
+            json!({"id": 1, "type": "subscribe", "list_id": list["id"]}),
+        )
+        .await;
+        let ack = next_json(&mut client).await;
+        assert_eq!(ack["type"], "ack");
+        assert_eq!(ack["id"], 1);
+        assert_eq!(ack["data"]["list"]["name"], "Garden");
+        assert_eq!(ack["data"]["todos"], json!([]));
+
+        let create = json!({
+            "id": "c", "type": "mutate", "op": "create", "list_id": list["id"], "title": "mow",
+        });
+        send(&mut client, create).await;
+        let ack = next_json(&mut client).await;
+        assert_eq!((&ack["type"], &ack["id"]), (&json!("ack"), &json!("c")));
+        let todo = ack["data"].clone();
+        assert_eq!(todo["title"], "mow");
+        let event = next_json(&mut client).await;
+        assert_eq!(event["type"], "event");
+        assert_eq!(event["event"], "todo.created");
+        assert_eq!(event["data"], todo);
+
+        // Changes made elsewhere arrive too, including moves out of the list:
+        let uri = format!("/todo/{}/completed", todo["id"].as_str().unwrap());
+        call(&app, "PUT", &uri, json!({"completed": true})).await;
+        assert_eq!(next_json(&mut client).await["event"], "todo.completed");
+        let moving = json!({
+            "type": "mutate", "op": "move", "todo_id": todo["id"], "list_id": inbox,
+        });
+        send(&mut client, moving).await;
+        assert_eq!(next_json(&mut client).await["type"], "ack");
+        let event = next_json(&mut client).await;
+        assert_eq!(event["event"], "todo.updated");
+        assert_eq!(event["data"]["list_id"], inbox);
+        // ...but not those to todos in lists it is not subscribed to.
+        let rename =
+            json!({"type": "mutate", "op": "update", "todo_id": todo["id"], "title": "rake"});
+        send(&mut client, rename).await;
+        let ack = next_json(&mut client).await;
+        assert_eq!(ack["data"]["title"], "rake");
+
+        client.send(Message::text("{")).await.unwrap();
+        let error = next_json(&mut client).await;
+        assert_eq!(
+            (&error["type"], &error["status"]),
+            (&json!("error"), &json!(400))
+        );
+        let unknown = json!({
+            "id": 2, "type": "subscribe", "list_id": "00000000-0000-0000-0000-000000000000",
+        });
+        send(&mut client, unknown).await;
+        let error = next_json(&mut client).await;
+        assert_eq!((&error["id"], &error["status"]), (&json!(2), &json!(404)));
+
+        state.events.close();
+        match next(&mut client).await {
+            Message::Close(Some(frame)) => assert_eq!(frame.code, CloseCode::Away),
+            other => panic!("expected a close frame, got {other:?}"),
+        }
+    }
+}
@@ -1,42 +1,136 @@
 use std::net::SocketAddr;
-
-use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use std::sync::Arc;
+use std::time::Duration;
 
 use crate::{
+    backup::{self, BackupConfig},
+    db::{self, Database, DbConfig, Repositories},
+    events::EventBus,
+    extract::RoleGroups,
+    jwt::{self, JwtConfig, JwtVerifier},
     middleware::{TrustedForwardedForConfig, TrustedHeaderAuthConfig},
+    oidc::{OidcClient, OidcConfig},
     prelude::*,
+    repository::memory::MemoryRepository,
     routes::router,
-    AppState,
+    session::{self, SessionConfig},
+    trash, webhook, AppState,
 };
 
+/// How long live connections get to close once the server has stopped.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Settings for what the app does, beyond where it listens and keeps
+/// its data.
+#[derive(Clone, Debug)]
+pub struct AppConfig {
+    /// How long deleted users, lists and todos stay in the trash.
+    pub trash_retention: Duration,
+    /// The emails of the users who are admins, whatever their role.
+    pub admins: Vec<String>,
+    pub role_groups: RoleGroups,
+    /// How to verify JWT assertions from the proxy, if it sends them.
+    pub jwt: Option<JwtConfig>,
+    /// How to sign users in with an OpenID Connect issuer, if at all.
+    pub oidc: Option<OidcConfig>,
+    pub sessions: SessionConfig,
+    /// Whether webhooks may be delivered to local and private addresses.
+    pub allow_local_webhooks: bool,
+}
+
 /// Run the HTTP server until shutdown.
+///
+/// Without a `db_cfg`, data is only kept in memory (`serve --ephemeral`).
 pub async fn run(
     addr: SocketAddr,
+    db_cfg: Option<DbConfig>,
+    migrate: bool,
+    backup_cfg: Option<BackupConfig>,
     user_cfg: TrustedHeaderAuthConfig,
     fwd_cfg: TrustedForwardedForConfig,
+    app_cfg: AppConfig,
 ) -> anyhow::Result<()> {
-    let db_url = std::env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite:data.db".to_string());
-    info!("DATABASE_URL={db_url}");
-    let db: SqlitePool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect(&db_url)
-        .await?;
-    info!("Loaded database connection pool");
-    // Optional but recommended for SQLite:
-    sqlx::query("PRAGMA foreign_keys = ON;")
-        .execute(&db)
-        .await?;
-    sqlx::query("PRAGMA journal_mode = WAL;")
-        .execute(&db)
-        .await?;
-
-    // Run migrations
-    sqlx::migrate!().run(&db).await?;
+    let db = match db_cfg {
+        Some(cfg) => {
+            info!("DATABASE_URL={}", cfg.url);
+            db::connect_pools(&cfg).await?
+        }
+        None => {
+            warn!("Using ephemeral in-memory storage; all data is lost on shutdown");
+            Database::memory(MemoryRepository::default())
+        }
+    };
+
+    if migrate {
+        db.migrate().await?;
+    } else {
+        info!("Skipping database migrations (--no-migrate)");
+    }
+
+    if let Some(cfg) = backup_cfg {
+        match db.sqlite() {
+            Some(pool) => {
+                tokio::spawn(backup::run_schedule(pool.clone(), cfg));
+            }
+            None => warn!("Scheduled backups are only supported for SQLite; ignoring BACKUP_DIR"),
+        }
+    }
+
+    let jwt = match app_cfg.jwt {
+        Some(cfg) => {
+            let verifier = Arc::new(JwtVerifier::new(cfg).await?);
+            tokio::spawn(jwt::run(verifier.jwks()));
+            Some(verifier)
+        }
+        None => None,
+    };
+    let oidc = match app_cfg.oidc {
+        Some(cfg) => {
+            let client = Arc::new(OidcClient::discover(cfg).await?);
+            tokio::spawn(jwt::run(client.jwks()));
+            Some(client)
+        }
+        None => None,
+    };
 
     // Shared state
-    let state = AppState { db };
+    let Repositories {
+        users,
+        lists,
+        todos,
+        tags,
+        caldav,
+        trash,
+        webhooks,
+        audit,
+    } = db.repos;
+    let state = AppState {
+        users,
+        lists,
+        todos,
+        tags,
+        caldav,
+        trash,
+        webhooks,
+        audit,
+        events: EventBus::default(),
+        admins: app_cfg.admins.into(),
+        role_groups: Arc::new(app_cfg.role_groups),
+        oidc,
+        sessions: app_cfg.sessions,
+        logins: Default::default(),
+        allow_local_webhooks: app_cfg.allow_local_webhooks,
+    };
+
+    tokio::spawn(webhook::run(
+        state.webhooks.clone(),
+        app_cfg.allow_local_webhooks,
+    ));
+    tokio::spawn(trash::run(state.trash.clone(), app_cfg.trash_retention));
+    tokio::spawn(session::run(state.users.clone()));
 
-    let app = router(user_cfg, fwd_cfg).with_state(state);
+    let events = state.events.clone();
+    let app = router(user_cfg, fwd_cfg, jwt, &state).with_state(state);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
     let bound_addr = listener.local_addr()?;
@@ -46,9 +140,26 @@ pub async fn run(
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
     )
-    .with_graceful_shutdown(shutdown_signal())
+    .with_graceful_shutdown({
+        let events = events.clone();
+        async move {
+            shutdown_signal().await;
+            // End event streams, which would otherwise keep the server
+            // waiting.
+            events.close();
+        }
+    })
     .await?;
 
+    // WebSocket connections outlive the server's own connections; give
+    // them a moment to send their close frames.
+    if tokio::time::timeout(SHUTDOWN_GRACE, events.drained())
+        .await
+        .is_err()
+    {
+        warn!("Some live connections did not close in time");
+    }
+
     Ok(())
 }
 
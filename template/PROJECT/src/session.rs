@@ -0,0 +1,86 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::{header, HeaderMap};
+use chrono::Utc;
+
+use crate::{models::session::Session, prelude::*, repository::UserRepository, token};
+
+/// The cookie carrying a browser's session secret.
+pub const SESSION_COOKIE: &str = "session";
+
+/// How often expired sessions are deleted.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Settings for the cookie sessions of users who sign in with the app
+/// itself, rather than through the proxy.
+#[derive(Clone, Debug)]
+pub struct SessionConfig {
+    /// How long a session lasts after signing in.
+    pub lifetime: Duration,
+    /// Whether cookies are only sent over HTTPS. Only turn this off to
+    /// try things out over plain HTTP.
+    pub secure: bool,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            lifetime: Duration::from_secs(7 * 24 * 60 * 60),
+            secure: true,
+        }
+    }
+}
+
+impl SessionConfig {
+    /// A `Set-Cookie` value for a cookie only sent back to `path`, and
+    /// hidden from scripts and other sites' requests.
+    pub fn cookie(&self, name: &str, value: &str, path: &str, max_age: Duration) -> String {
+        let secure = if self.secure { "; Secure" } else { "" };
+        format!(
+            "{name}={value}; Path={path}; Max-Age={}; HttpOnly; SameSite=Lax{secure}",
+            max_age.as_secs()
+        )
+    }
+
+    /// A `Set-Cookie` value removing the cookie.
+    pub fn clear_cookie(&self, name: &str, path: &str) -> String {
+        self.cookie(name, "", path, Duration::ZERO)
+    }
+
+    /// Start a session for `email`, returning the `Set-Cookie` value for
+    /// it.
+    pub async fn start(&self, users: &dyn UserRepository, email: &str) -> anyhow::Result<String> {
+        let secret = token::generate();
+        let session = Session::new(email, chrono::Duration::from_std(self.lifetime)?);
+        users
+            .insert_session(&session, &token::digest(&secret))
+            .await?;
+        Ok(self.cookie(SESSION_COOKIE, &secret, "/", self.lifetime))
+    }
+}
+
+/// The value of the request's cookie called `name`, if any.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(n, _)| *n == name)
+        .map(|(_, value)| value.trim_matches('"'))
+}
+
+/// Delete expired sessions every hour until the server stops.
+pub async fn run(users: Arc<dyn UserRepository>) {
+    let mut ticker = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        ticker.tick().await;
+        match users.purge_sessions(Utc::now()).await {
+            Ok(0) => {}
+            Ok(purged) => debug!("Deleted {purged} expired sessions"),
+            Err(e) => error!("Deleting expired sessions failed: {e:#}"),
+        }
+    }
+}
@@ -0,0 +1,14 @@
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// A new random secret token: 64 hex characters carrying 244 random bits
+/// (from two v4 UUIDs).
+pub fn generate() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// The SHA-256 hex digest of a token, which is what gets stored in place
+/// of the token itself.
+pub fn digest(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,231 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    models::{
+        list::TodoList,
+        tag::{Tag, TodoTag},
+        todo::{Subtask, Todo},
+        user::User,
+    },
+    prelude::*,
+};
+
+/// Serialization formats for bulk export and import.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Csv,
+    Ndjson,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Csv => "text/csv",
+            Format::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::Ndjson => "ndjson",
+        }
+    }
+
+    /// Guess the format from a Content-Type header value.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        match mime {
+            "application/json" => Some(Format::Json),
+            "text/csv" => Some(Format::Csv),
+            "application/x-ndjson" | "application/jsonl" => Some(Format::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "ndjson" => Ok(Format::Ndjson),
+            other => Err(format!("unsupported format: {other}")),
+        }
+    }
+}
+
+/// Encodes records one at a time, so large exports can be streamed.
+pub struct Encoder {
+    format: Format,
+    count: usize,
+}
+
+impl Encoder {
+    pub fn new(format: Format) -> Self {
+        Self { format, count: 0 }
+    }
+
+    /// Encode the next record, including any leading separator or header.
+    pub fn record<T: Serialize>(&mut self, record: &T) -> anyhow::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        match self.format {
+            Format::Json => {
+                buf.extend_from_slice(if self.count == 0 { b"[\n" } else { b",\n" });
+                serde_json::to_writer(&mut buf, record)?;
+            }
+            Format::Ndjson => {
+                serde_json::to_writer(&mut buf, record)?;
+                buf.push(b'\n');
+            }
+            Format::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(self.count == 0)
+                    .from_writer(buf);
+                writer.serialize(record)?;
+                buf = writer.into_inner().map_err(|e| e.into_error())?;
+            }
+        }
+        self.count += 1;
+        Ok(buf)
+    }
+
+    /// Encode whatever must follow the last record.
+    pub fn finish(self) -> Vec<u8> {
+        match (self.format, self.count) {
+            (Format::Json, 0) => b"[]\n".to_vec(),
+            (Format::Json, _) => b"\n]\n".to_vec(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Decode every record in `data`, keeping a separate result per row so
+/// that errors can be reported individually.
+///
+/// Returns an error only if the document as a whole cannot be read.
+pub fn decode<T: DeserializeOwned>(
+    format: Format,
+    data: &[u8],
+) -> Result<Vec<Result<T, String>>, String> {
+    match format {
+        Format::Json => {
+            let values: Vec<serde_json::Value> =
+                serde_json::from_slice(data).map_err(|e| format!("invalid JSON array: {e}"))?;
+            Ok(values
+                .into_iter()
+                .map(|v| serde_json::from_value(v).map_err(|e| e.to_string()))
+                .collect())
+        }
+        Format::Ndjson => {
+            let text = std::str::from_utf8(data).map_err(|e| format!("invalid UTF-8: {e}"))?;
+            Ok(text
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| serde_json::from_str(line).map_err(|e| e.to_string()))
+                .collect())
+        }
+        Format::Csv => {
+            let mut reader = csv::Reader::from_reader(data);
+            Ok(reader
+                .deserialize()
+                .map(|r| r.map_err(|e| e.to_string()))
+                .collect())
+        }
+    }
+}
+
+/// A rejected row of an import (1-based).
+#[derive(Debug, Serialize)]
+pub struct RowError {
+    pub row: usize,
+    pub error: String,
+}
+
+/// Outcome of an import.
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    /// Number of rows read from the input.
+    pub rows: usize,
+    /// Number of rows written (zero on dry runs or when any row failed).
+    pub imported: usize,
+    pub errors: Vec<RowError>,
+}
+
+/// One record of a whole-database dump.
+///
+/// Dumps list every user, then lists, todos (without their parents),
+/// subtasks, tags and finally the tags on each todo, so they can be
+/// imported in order without violating foreign keys.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DumpRecord {
+    User(User),
+    List(TodoList),
+    Todo(Todo),
+    Subtask(Subtask),
+    Tag(Tag),
+    TodoTag(TodoTag),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Row {
+        name: String,
+        count: Option<i64>,
+    }
+
+    fn encode(format: Format, rows: &[Row]) -> Vec<u8> {
+        let mut encoder = Encoder::new(format);
+        let mut buf = Vec::new();
+        for row in rows {
+            buf.extend(encoder.record(row).unwrap());
+        }
+        buf.extend(encoder.finish());
+        buf
+    }
+
+    #[test]
+    fn round_trip_all_formats() {
+        let rows = vec![
+            Row {
+                name: "a".into(),
+                count: Some(1),
+            },
+            Row {
+                name: "b, with comma".into(),
+                count: None,
+            },
+        ];
+        for format in [Format::Json, Format::Csv, Format::Ndjson] {
+            let decoded: Vec<Row> = decode(format, &encode(format, &rows))
+                .unwrap()
+                .into_iter()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(decoded, rows, "{format:?}");
+            assert!(decode::<Row>(format, &encode(format, &[]))
+                .unwrap()
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn decode_reports_bad_rows() {
+        let data = b"{\"name\":\"ok\"}\n{\"count\":2}\n";
+        let decoded = decode::<Row>(Format::Ndjson, data).unwrap();
+        assert!(decoded[0].is_ok());
+        assert!(decoded[1].as_ref().unwrap_err().contains("name"));
+    }
+}
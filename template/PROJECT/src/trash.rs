@@ -0,0 +1,27 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::{prelude::*, repository::TrashRepository};
+
+/// How often the trash is checked for expired items.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Delete everything that has been in the trash for longer than
+/// `retention`, every hour until the server stops.
+pub async fn run(trash: Arc<dyn TrashRepository>, retention: Duration) {
+    let Ok(retention) = chrono::Duration::from_std(retention) else {
+        error!("Trash retention is out of range; not purging");
+        return;
+    };
+    let mut ticker = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        ticker.tick().await;
+        match trash.purge(Utc::now() - retention).await {
+            Ok(0) => {}
+            Ok(purged) => info!("Purged {purged} items from the trash"),
+            Err(e) => error!("Purging the trash failed: {e:#}"),
+        }
+    }
+}
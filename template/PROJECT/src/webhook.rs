@@ -0,0 +1,346 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use futures_util::TryStreamExt;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::{
+    models::{
+        ids::UserId,
+        webhook::{Delivery, Event},
+    },
+    prelude::*,
+    repository::WebhookRepository,
+};
+
+/// How many times a delivery is attempted before it is given up on.
+pub const MAX_ATTEMPTS: i64 = 8;
+/// The wait before the first retry, which doubles with every retry after.
+const RETRY_BASE: chrono::Duration = chrono::Duration::seconds(30);
+/// How often the outbox is checked for due deliveries.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How many due deliveries are sent per check.
+const BATCH_SIZE: i64 = 100;
+/// How many deliveries are sent at once.
+const CONCURRENCY: usize = 10;
+/// How long to wait for a webhook to respond.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature-256";
+
+/// The signature header value of a payload: `sha256=` and the hex
+/// HMAC-SHA256 of the payload, keyed with the webhook's secret.
+pub fn signature(secret: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(payload.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// When to retry a delivery that has failed `attempts` times, or `None`
+/// once it has run out of attempts.
+pub fn retry_delay(attempts: i64) -> Option<chrono::Duration> {
+    if attempts >= MAX_ATTEMPTS {
+        return None;
+    }
+    Some(RETRY_BASE * 2i32.pow(attempts.clamp(1, MAX_ATTEMPTS) as u32 - 1))
+}
+
+/// Queue an event about a user's data for every webhook subscribed to it,
+/// with `data` as the payload's `data`.
+///
+/// The deliveries are written to the outbox and sent by [`run`], so a
+/// slow or failing webhook never holds up the request that caused the
+/// event. Failing to queue them is logged rather than failing the request.
+pub async fn notify(
+    webhooks: &dyn WebhookRepository,
+    user_id: UserId,
+    event: Event,
+    data: &impl Serialize,
+) {
+    if let Err(e) = queue(webhooks, user_id, event, data).await {
+        error!("Failed to queue webhook deliveries of {event}: {e:#}");
+    }
+}
+
+async fn queue(
+    webhooks: &dyn WebhookRepository,
+    user_id: UserId,
+    event: Event,
+    data: &impl Serialize,
+) -> anyhow::Result<()> {
+    let subscribers = webhooks.subscribers(user_id).await?;
+    if !subscribers.iter().any(|w| w.events.matches(event)) {
+        return Ok(());
+    }
+    let data = serde_json::to_value(data)?;
+    let deliveries: Vec<Delivery> = subscribers
+        .iter()
+        .filter(|w| w.events.matches(event))
+        .map(|w| Delivery::new(w.id, event, &data))
+        .collect();
+    webhooks.insert_deliveries(&deliveries).await
+}
+
+/// Whether `ip` is on this host or a private network: loopback,
+/// RFC 1918, shared (CGNAT), link-local (which includes cloud metadata
+/// services at 169.254.169.254) and unique local IPv6 addresses, among
+/// others. Webhooks are not delivered to these unless
+/// `ALLOW_LOCAL_WEBHOOKS` is set, so they cannot be used to reach
+/// services behind the firewall.
+pub fn is_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || a == 0
+                || (a == 100 && b & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_local(IpAddr::V4(v4)),
+            None => {
+                let first = ip.segments()[0];
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80
+            }
+        },
+    }
+}
+
+/// The addresses `host` resolves to, or an error if any of them is
+/// [local](is_local).
+async fn public_addrs(host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("cannot resolve {host}: {e}"))?
+        .collect();
+    match addrs.iter().find(|a| is_local(a.ip())) {
+        Some(addr) => Err(format!(
+            "{host} resolves to {}, a local or private address",
+            addr.ip()
+        )),
+        None => Ok(addrs),
+    }
+}
+
+/// Check that a webhook URL points at a public address, resolving its
+/// host, unless local addresses are allowed.
+pub async fn check_destination(url: &str, allow_local: bool) -> Result<(), String> {
+    if allow_local {
+        return Ok(());
+    }
+    let url = reqwest::Url::parse(url).map_err(|e| format!("invalid url: {e}"))?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let host = url.host_str().ok_or("url must have a host")?;
+    match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) if is_local(ip) => Err(format!("{ip} is a local or private address")),
+        Ok(_) => Ok(()),
+        Err(_) => public_addrs(host, port).await.map(drop),
+    }
+}
+
+/// Resolves webhook hosts like the system resolver does, but fails for
+/// hosts with local addresses, so a host cannot be pointed at one between
+/// [`check_destination`] and connecting to it.
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs = public_addrs(name.as_str(), 0).await?;
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// The HTTP client webhooks are delivered with. It does not follow
+/// redirects, so a delivery only counts when the webhook's URL itself
+/// accepts it, and unless `allow_local` is set it does not connect to
+/// local or private addresses.
+pub fn client(allow_local: bool) -> reqwest::Client {
+    let builder = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .user_agent("${APP}-webhook");
+    let builder = if allow_local {
+        builder
+    } else {
+        builder.dns_resolver(Arc::new(PublicResolver))
+    };
+    builder.build().expect("webhook HTTP client")
+}
+
+/// Send the deliveries that are due, returning how many were attempted.
+///
+/// A 2xx response marks a delivery delivered. Anything else, including a
+/// URL that [`check_destination`] refuses, is recorded and the delivery
+/// retried with exponential backoff, up to [`MAX_ATTEMPTS`] attempts.
+/// Up to [`CONCURRENCY`] deliveries are sent at once, so a slow webhook
+/// holds up the others for [`REQUEST_TIMEOUT`] at most.
+pub async fn deliver_due(
+    webhooks: &dyn WebhookRepository,
+    client: &reqwest::Client,
+    allow_local: bool,
+) -> anyhow::Result<usize> {
+    let due = webhooks.due_deliveries(Utc::now(), BATCH_SIZE).await?;
+    let count = due.len();
+    futures_util::stream::iter(due.into_iter().map(Ok))
+        .try_for_each_concurrent(CONCURRENCY, |delivery| {
+            attempt(webhooks, client, allow_local, delivery)
+        })
+        .await?;
+    Ok(count)
+}
+
+/// Send a delivery, and record how it went.
+async fn attempt(
+    webhooks: &dyn WebhookRepository,
+    client: &reqwest::Client,
+    allow_local: bool,
+    mut delivery: Delivery,
+) -> anyhow::Result<()> {
+    let Some(webhook) = webhooks.get(delivery.webhook_id).await? else {
+        return Ok(());
+    };
+    // The client's timeout does not cover checking the destination.
+    let response = tokio::time::timeout(REQUEST_TIMEOUT, async {
+        check_destination(&webhook.url, allow_local).await?;
+        send(client, &webhook.url, &webhook.secret, &delivery).await
+    })
+    .await
+    .unwrap_or_else(|_| Err(format!("timed out after {REQUEST_TIMEOUT:?}")));
+
+    let now = Utc::now();
+    delivery.attempts += 1;
+    delivery.last_attempt_at = Some(now);
+    let delivered = match response {
+        Ok(status) => {
+            delivery.last_status = Some(i64::from(status.as_u16()));
+            delivery.last_error = (!status.is_success()).then(|| format!("HTTP {status}"));
+            status.is_success()
+        }
+        Err(e) => {
+            delivery.last_status = None;
+            delivery.last_error = Some(e);
+            false
+        }
+    };
+    if delivered {
+        delivery.delivered_at = Some(now);
+        delivery.next_attempt_at = None;
+    } else {
+        delivery.next_attempt_at = retry_delay(delivery.attempts).map(|delay| now + delay);
+        match delivery.next_attempt_at {
+            Some(at) => debug!(
+                "Webhook delivery {} failed ({}), retrying at {at}",
+                delivery.id.0,
+                delivery.last_error.as_deref().unwrap_or_default()
+            ),
+            None => warn!(
+                "Webhook delivery {} failed ({}), giving up after {} attempts",
+                delivery.id.0,
+                delivery.last_error.as_deref().unwrap_or_default(),
+                delivery.attempts
+            ),
+        }
+    }
+    webhooks.update_delivery(&delivery).await?;
+    Ok(())
+}
+
+/// Post a delivery's payload to `url`, returning the response's status.
+async fn send(
+    client: &reqwest::Client,
+    url: &str,
+    secret: &str,
+    delivery: &Delivery,
+) -> Result<reqwest::StatusCode, String> {
+    client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, &delivery.event)
+        .header(DELIVERY_HEADER, delivery.id.0.to_string())
+        .header(SIGNATURE_HEADER, signature(secret, &delivery.payload))
+        .body(delivery.payload.clone())
+        .send()
+        .await
+        .map(|response| response.status())
+        .map_err(|e| e.to_string())
+}
+
+/// Send due deliveries from the outbox until the server stops.
+pub async fn run(webhooks: Arc<dyn WebhookRepository>, allow_local: bool) {
+    let client = client(allow_local);
+    let mut ticker = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = deliver_due(webhooks.as_ref(), &client, allow_local).await {
+            error!("Webhook delivery failed: {e:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_payloads() {
+        assert_eq!(
+            signature("key", "The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_local_destinations() {
+        for ip in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fc00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(is_local(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["203.0.113.7", "8.8.8.8", "2001:db8::1"] {
+            assert!(!is_local(ip.parse().unwrap()), "{ip}");
+        }
+        assert!(check_destination("http://[::1]:8080/", false)
+            .await
+            .is_err());
+        assert!(check_destination("http://localhost/", false).await.is_err());
+        assert!(check_destination("http://localhost/", true).await.is_ok());
+        assert!(check_destination("https://203.0.113.7/", false)
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        assert_eq!(retry_delay(1), Some(chrono::Duration::seconds(30)));
+        assert_eq!(retry_delay(2), Some(chrono::Duration::seconds(60)));
+        assert_eq!(retry_delay(7), Some(chrono::Duration::seconds(30 * 64)));
+        assert_eq!(retry_delay(MAX_ATTEMPTS), None);
+    }
+}